@@ -60,6 +60,17 @@ impl FrameCsg {
         frame[FramePos::PosSeq as usize] = seq;
     }
 
+    /// 按控制码、地址、AFN、SEQ 和数据单元组成完整报文，长度域和校验和自动计算
+    pub fn build_frame(ctrl: u8, adress: &[u8], msa: u8, afn: u8, seq: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; FramePos::PosData as usize];
+        Self::init_frame(ctrl, afn, adress, msa, seq, &mut frame);
+        frame.extend_from_slice(data);
+        Self::set_frame_len(frame.len() - FramePos::PosCtrl as usize, &mut frame);
+        let cs = FrameFun::calculate_cs(&frame[FramePos::PosCtrl as usize..]);
+        frame.extend_from_slice(&[cs, 0x16]);
+        frame
+    }
+
     pub fn get_meter_task_len(frame: &[u8]) -> usize {
         let pos = 26;
         let mut len = 27;
@@ -1075,7 +1086,7 @@ impl FrameCsg {
         let left_length = if with_time { total_len - 6 } else { total_len };
         while total_len > pos {
             if pos + 4 > total_len {
-                println!("ddddd pos + 4:{:?} total_len {:?}", pos + 4,total_len);
+                println!("ddddd pos + 4:{:?} total_len {:?}", pos + 4, total_len);
                 if pos != 0 {
                     return true;
                 }
@@ -1100,13 +1111,15 @@ impl FrameCsg {
                     } else {
                         sub_length_cont.parse::<usize>().unwrap()
                     };
-                    println!("sub_length:{:?} pos{:?} with_time{:?}", sub_length,pos,with_time);
+                    println!(
+                        "sub_length:{:?} pos{:?} with_time{:?}",
+                        sub_length, pos, with_time
+                    );
                     pos += sub_length + 6;
                     pos += 5;
                     if (left_length - 6) % (sub_length + 5) == 0 {
                         return false;
                     }
-                    
                 } else {
                     println!("aaaaa");
                     return true;
@@ -3921,7 +3934,13 @@ impl FrameCsg {
 
                 pos += sub_length;
                 num += 1;
-                info!("num:{:?} length{:?} pos{:?} item_count * pncount{:?}", num, length, pos, item_count * pncount);
+                info!(
+                    "num:{:?} length{:?} pos{:?} item_count * pncount{:?}",
+                    num,
+                    length,
+                    pos,
+                    item_count * pncount
+                );
                 if length - pos == 16
                     || length - pos == 22
                     || ((num == (item_count * pncount)) && (length - pos >= 16))
@@ -4615,7 +4634,10 @@ impl FrameCsg {
             if let Some(mut data_item_elem) = data_item_elem {
                 if dir == 1 && prm == 0 {
                     let frame_result: Vec<String> = Vec::new();
-                    info!("dir:{:?} data_item_elem{:?} data_segment{:?}", dir,data_item_elem, data_segment);
+                    info!(
+                        "dir:{:?} data_item_elem{:?} data_segment{:?}",
+                        dir, data_item_elem, data_segment
+                    );
                     let sub_length_cont = data_item_elem.get_child_text("length").unwrap();
                     (sub_length, sub_datament) = if sub_length_cont.to_uppercase() == "UNKNOWN" {
                         let sub_length = Self::calculate_item_length(
@@ -4643,7 +4665,10 @@ impl FrameCsg {
                         );
                         (sub_length, new_datament)
                     };
-                    info!("sub_length {:?} new_datament:{:?}", sub_length,sub_datament);
+                    info!(
+                        "sub_length {:?} new_datament:{:?}",
+                        sub_length, sub_datament
+                    );
                     data_item_elem.update_value("length", sub_length.to_string());
                     item_data = FrameAnalisyic::prase_data(
                         &mut data_item_elem,
@@ -4655,8 +4680,7 @@ impl FrameCsg {
                     );
                 } else {
                     let sub_length_cont = data_item_elem.get_child_text("length").unwrap();
-                    (sub_length, sub_datament) = if sub_length_cont.to_uppercase() == "UNKNOWN"
-                    {
+                    (sub_length, sub_datament) = if sub_length_cont.to_uppercase() == "UNKNOWN" {
                         let sub_length = Self::calculate_item_length(
                             &mut data_item_elem,
                             &data_segment[pos + 4..],
@@ -4682,7 +4706,10 @@ impl FrameCsg {
                         );
                         (sub_length, new_datament)
                     };
-                    info!("sub_length {:?} new_datament:{:?}", sub_length,sub_datament);
+                    info!(
+                        "sub_length {:?} new_datament:{:?}",
+                        sub_length, sub_datament
+                    );
                     data_item_elem.update_value("length", sub_length.to_string());
                     item_data = FrameAnalisyic::prase_data(
                         &mut data_item_elem,
@@ -4692,7 +4719,6 @@ impl FrameCsg {
                         index + pos + 4,
                         Some(dir),
                     );
-
                 }
                 let name = data_item_elem.get_child_text("name").unwrap();
                 let dis_data_identifier = format!("数据标识编码：[{}]-{}", data_item, name);
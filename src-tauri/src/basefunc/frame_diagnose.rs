@@ -0,0 +1,630 @@
+use crate::basefunc::frame_645::Frame645;
+use crate::basefunc::frame_cco::FrameCCO;
+use crate::basefunc::frame_csg::FrameCsg;
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_moudle::FrameMoudle;
+use crate::basefunc::frame_speecial::SpcialFrame;
+use crate::basefunc::frame_tctask::TCMeterTask;
+use crate::basefunc::protocol::{FrameAnalisyic, ProtocolInfo};
+use crate::config::xmlconfig::ProtocolConfigManager;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::panic::{self, AssertUnwindSafe};
+
+const FRAME_START: u8 = 0x68;
+const FRAME_END: u8 = 0x16;
+// 模块协议的数据标识以 EC 开头
+const MOUDLE_ITEM_MASK: u64 = 0xEC000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueLevel {
+    /// 导致识别器拒绝该报文
+    Error,
+    /// 不影响识别，但报文可能有误，如校验和错误、数据标识未配置
+    Warning,
+}
+
+/// 诊断发现的一个问题
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameIssue {
+    pub level: IssueLevel,
+    /// 问题所在的字节位置，与报文整体有关（如长度不足）时为空
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+/// 一个协议识别器的诊断结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectorReport {
+    /// 协议名称，与解析结果中的 protocol 相同
+    pub protocol: String,
+    /// 识别函数，如 is_csg_frame
+    pub detector: String,
+    /// 识别函数的实际判断结果
+    pub accepted: bool,
+    pub issues: Vec<FrameIssue>,
+}
+
+/// 报文诊断结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameDiagnosis {
+    pub frame: String,
+    pub length: usize,
+    /// 实际使用的协议，与正常解析相同，按识别顺序取第一个通过的识别器，都不通过时为 Unknown
+    pub protocol: String,
+    /// 所有识别器的结果，按解析时的识别顺序排列
+    pub detectors: Vec<DetectorReport>,
+    /// 解析中途出错或发生异常时的错误信息
+    pub error: Option<String>,
+    /// 解析停止的字节位置，即第一个未被解析结果覆盖的字节；完整解析或未识别时为空
+    pub stopped_at: Option<usize>,
+    /// 一句话的诊断结论
+    pub summary: String,
+    /// 解析停止前已得到的结果
+    pub data: Vec<Value>,
+}
+
+/// 诊断报文：运行全部协议识别器并给出各自拒绝的原因，再按正常流程解析，
+/// 找出解析停止的位置
+pub fn diagnose_frame(frame: &[u8], region: &str) -> FrameDiagnosis {
+    let detectors = vec![
+        report(
+            ProtocolInfo::ProtocolCSG13,
+            "is_csg_frame",
+            || FrameCsg::is_csg_frame(frame),
+            check_csg(frame, region),
+        ),
+        report(
+            ProtocolInfo::ProtocolDLT64507,
+            "is_dlt645_frame",
+            || Frame645::is_dlt645_frame(frame),
+            check_645(frame, region),
+        ),
+        report(
+            ProtocolInfo::ProtocolCSG16,
+            "is_cco_frame",
+            || FrameCCO::is_cco_frame(frame),
+            check_cco(frame, false),
+        ),
+        report(
+            ProtocolInfo::ProtocolMoudle,
+            "is_moudle_frame",
+            || FrameMoudle::is_moudle_frame(frame),
+            check_cco(frame, true),
+        ),
+        report(
+            ProtocolInfo::ProtocolMS,
+            "is_meter_task",
+            || TCMeterTask::is_meter_task(frame),
+            check_meter_task(frame),
+        ),
+        report(
+            ProtocolInfo::ProtocolHis,
+            "is_special_frame",
+            || SpcialFrame::is_special_frame(frame, region),
+            check_special(frame, region),
+        ),
+    ];
+
+    let mut data = Vec::new();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        FrameAnalisyic::process_frame_into(frame, region, &mut data)
+    }));
+    let (protocol, error) = match result {
+        Ok(result) => result,
+        Err(payload) => {
+            // 识别器与解析使用相同的识别顺序，异常前已识别出的协议即第一个通过的识别器
+            let protocol = detectors
+                .iter()
+                .find(|detector| detector.accepted)
+                .map(|detector| detector.protocol.clone())
+                .unwrap_or_else(|| "Unknown".to_string());
+            (
+                protocol,
+                Some(format!(
+                    "解析时发生异常: {}",
+                    FrameFun::panic_message(&payload)
+                )),
+            )
+        }
+    };
+
+    let stopped_at = if protocol == "Unknown" {
+        None
+    } else {
+        first_uncovered(&data, frame.len())
+    };
+    let summary = summarize(&protocol, &detectors, error.as_deref(), stopped_at);
+    FrameDiagnosis {
+        frame: FrameFun::get_data_str_with_space(frame),
+        length: frame.len(),
+        protocol,
+        detectors,
+        error,
+        stopped_at,
+        summary,
+        data,
+    }
+}
+
+fn report(
+    protocol: ProtocolInfo,
+    detector: &str,
+    accept: impl FnOnce() -> bool + panic::UnwindSafe,
+    mut issues: Vec<FrameIssue>,
+) -> DetectorReport {
+    // 部分识别函数在报文过短时会越界，按拒绝处理
+    let accepted = match panic::catch_unwind(accept) {
+        Ok(accepted) => accepted,
+        Err(payload) => {
+            issues.push(error(
+                None,
+                format!("识别时发生异常: {}", FrameFun::panic_message(&payload)),
+            ));
+            false
+        }
+    };
+    if !accepted && !issues.iter().any(|issue| issue.level == IssueLevel::Error) {
+        issues.push(error(None, "未通过识别，原因未知".to_string()));
+    }
+    DetectorReport {
+        protocol: protocol.name().to_string(),
+        detector: detector.to_string(),
+        accepted,
+        issues,
+    }
+}
+
+// 与 FrameCsg::is_csg_frame 的判断顺序一致，另外检查校验和与数据标识
+fn check_csg(data: &[u8], region: &str) -> Vec<FrameIssue> {
+    let mut issues = Vec::new();
+    if data.len() < 24 {
+        issues.push(too_short(data.len(), 24));
+        return issues;
+    }
+    // 带 84 字节自定义头时，南网报文从头之后开始
+    let base = if data.len() > 84 && FrameCsg::is_contoine_custom_head(&data[..84]) {
+        84
+    } else {
+        0
+    };
+    let frame = &data[base..];
+    // 固定部分到数据标识为止共 22 字节，之后还有校验和与结束符
+    if frame.len() < 24 {
+        issues.push(error(
+            Some(base),
+            format!("自定义头之后只有 {} 字节，至少需要 24 字节", frame.len()),
+        ));
+        return issues;
+    }
+    check_byte(&mut issues, frame, base, 0, FRAME_START, "起始符");
+    check_byte(&mut issues, frame, base, 5, FRAME_START, "第二个起始符");
+    if frame[1..3] != frame[3..5] {
+        issues.push(error(
+            Some(base + 1),
+            format!(
+                "两个长度域不一致: {} 与 {}",
+                FrameFun::get_data_str_with_space(&frame[1..3]),
+                FrameFun::get_data_str_with_space(&frame[3..5])
+            ),
+        ));
+    }
+    let length = u16::from_le_bytes([frame[1], frame[2]]) as usize;
+    if length + 8 != frame.len() {
+        issues.push(length_mismatch(base + 1, length, length + 8, frame.len()));
+    }
+    check_byte(
+        &mut issues,
+        frame,
+        base,
+        frame.len() - 1,
+        FRAME_END,
+        "结束符",
+    );
+    if !issues.is_empty() {
+        return issues;
+    }
+
+    let len = frame.len();
+    check_cs(
+        &mut issues,
+        frame[len - 2],
+        &frame[6..len - 2],
+        base + len - 2,
+    );
+    // 读数据类的 AFN 在 DA 之后为数据标识
+    let afn = frame[14];
+    if matches!(afn, 0x0A | 0x0C | 0x0D) {
+        let item = FrameFun::get_data_str_reverser(&frame[18..22]);
+        let dir = frame[6] >> 7;
+        check_item(
+            &mut issues,
+            &item,
+            ProtocolInfo::ProtocolCSG13.name(),
+            region,
+            dir,
+            base + 18,
+        );
+    }
+    issues
+}
+
+// 与 Frame645::is_dlt645_frame 的判断顺序一致，另外检查校验和与数据标识
+fn check_645(frame: &[u8], region: &str) -> Vec<FrameIssue> {
+    let mut issues = Vec::new();
+    let len = frame.len();
+    if len < 12 {
+        issues.push(too_short(len, 12));
+        return issues;
+    }
+    let start = FrameFun::get_frame_fe_count(frame);
+    if len < start + 12 {
+        issues.push(error(
+            Some(start),
+            format!(
+                "{} 字节 FE 前导之后只有 {} 字节，645 报文至少 12 字节",
+                start,
+                len - start
+            ),
+        ));
+        return issues;
+    }
+    check_byte(&mut issues, frame, 0, start, FRAME_START, "起始符");
+    check_byte(&mut issues, frame, 0, len - 1, FRAME_END, "结束符");
+    check_byte(
+        &mut issues,
+        frame,
+        0,
+        start + 7,
+        FRAME_START,
+        "第二个起始符",
+    );
+    let length = frame[start + 9] as usize;
+    if len != length + 12 + start {
+        issues.push(length_mismatch(start + 9, length, length + 12 + start, len));
+    }
+    if !issues.is_empty() {
+        return issues;
+    }
+
+    check_cs(&mut issues, frame[len - 2], &frame[start..len - 2], len - 2);
+    // 读、写数据和读后续数据的数据域以数据标识开头，异常应答没有数据标识
+    let control = frame[start + 8];
+    if matches!(control & 0x1F, 0x11 | 0x12 | 0x14) && control & 0x40 == 0 && length >= 4 {
+        let item = FrameFun::get_data_str_delete_33h_reverse(&frame[start + 10..start + 14]);
+        check_item(
+            &mut issues,
+            &item,
+            ProtocolInfo::ProtocolDLT64507.name(),
+            region,
+            control >> 7,
+            start + 10,
+        );
+    }
+    issues
+}
+
+// 南网16（CCO）与模块协议帧结构相同，按数据标识是否以 EC 开头区分
+fn check_cco(frame: &[u8], moudle: bool) -> Vec<FrameIssue> {
+    let mut issues = Vec::new();
+    let len = frame.len();
+    if len < 3 {
+        issues.push(too_short(len, 3));
+        return issues;
+    }
+    check_byte(&mut issues, frame, 0, 0, FRAME_START, "起始符");
+    check_byte(&mut issues, frame, 0, len - 1, FRAME_END, "结束符");
+    if !issues.is_empty() {
+        return issues;
+    }
+    if len < 4 {
+        issues.push(too_short(len, 4));
+        return issues;
+    }
+    // 控制域 ADD 位为 0 时没有地址域，数据标识紧跟在 AFN、SEQ 之后
+    let add = (frame[3] >> 5) & 0x01;
+    if moudle || add == 0 {
+        if len < 10 {
+            issues.push(too_short(len, 10));
+            return issues;
+        }
+        let item = FrameFun::bintodecimal(&frame[6..10]);
+        let is_moudle_item = item & MOUDLE_ITEM_MASK == MOUDLE_ITEM_MASK;
+        if moudle && !is_moudle_item {
+            issues.push(error(
+                Some(6),
+                format!("数据标识 {:08X} 不是模块协议的 EC 开头标识", item),
+            ));
+        } else if !moudle && is_moudle_item {
+            issues.push(error(
+                Some(6),
+                format!("数据标识 {:08X} 以 EC 开头，属于模块协议", item),
+            ));
+        }
+    }
+    let length = u16::from_le_bytes([frame[1], frame[2]]) as usize;
+    if length != len {
+        issues.push(length_mismatch(1, length, length, len));
+    }
+    if issues.is_empty() && len >= 5 {
+        check_cs(&mut issues, frame[len - 2], &frame[3..len - 2], len - 2);
+    }
+    issues
+}
+
+// 与 TCMeterTask::is_meter_task 的判断一致
+fn check_meter_task(frame: &[u8]) -> Vec<FrameIssue> {
+    let mut issues = Vec::new();
+    if frame.len() <= 26 {
+        issues.push(too_short(frame.len(), 27));
+        return issues;
+    }
+    for (pos, expected) in [(0, 0x01), (4, 0x51), (9, 0x51), (14, 0x51), (25, 0x5C)] {
+        check_byte(&mut issues, frame, 0, pos, expected, "任务内容");
+    }
+    issues
+}
+
+// 与 SpcialFrame::is_special_frame 的判断一致
+fn check_special(frame: &[u8], region: &str) -> Vec<FrameIssue> {
+    let mut issues = Vec::new();
+    if frame.len() < 6 {
+        issues.push(too_short(frame.len(), 6));
+        return issues;
+    }
+    let item = FrameFun::get_data_str_reverser(&frame[2..6]);
+    if ProtocolConfigManager::get_config_xml(
+        &item,
+        ProtocolInfo::ProtocolCSG13.name(),
+        region,
+        Some(1),
+    )
+    .is_none()
+    {
+        issues.push(error(
+            Some(2),
+            format!("数据标识 {} 未在南网配置中找到", item),
+        ));
+    }
+    issues
+}
+
+fn check_byte(
+    issues: &mut Vec<FrameIssue>,
+    frame: &[u8],
+    base: usize,
+    pos: usize,
+    expected: u8,
+    name: &str,
+) {
+    if frame[pos] != expected {
+        issues.push(error(
+            Some(base + pos),
+            format!("{}应为 {:02X}，实际为 {:02X}", name, expected, frame[pos]),
+        ));
+    }
+}
+
+fn check_cs(issues: &mut Vec<FrameIssue>, actual: u8, data: &[u8], offset: usize) {
+    let expected = FrameFun::calculate_cs(data);
+    if expected != actual {
+        issues.push(FrameIssue {
+            level: IssueLevel::Warning,
+            offset: Some(offset),
+            message: format!("校验和应为 {:02X}，实际为 {:02X}", expected, actual),
+        });
+    }
+}
+
+fn check_item(
+    issues: &mut Vec<FrameIssue>,
+    item: &str,
+    protocol: &str,
+    region: &str,
+    dir: u8,
+    offset: usize,
+) {
+    if ProtocolConfigManager::get_config_xml(item, protocol, region, Some(dir)).is_none() {
+        issues.push(FrameIssue {
+            level: IssueLevel::Warning,
+            offset: Some(offset),
+            message: format!("数据标识 {} 未在 {} 配置中找到", item, protocol),
+        });
+    }
+}
+
+fn too_short(len: usize, min: usize) -> FrameIssue {
+    error(
+        None,
+        format!("报文只有 {} 字节，至少需要 {} 字节", len, min),
+    )
+}
+
+fn length_mismatch(offset: usize, field: usize, expected: usize, actual: usize) -> FrameIssue {
+    error(
+        Some(offset),
+        format!(
+            "长度域为 {}，报文应为 {} 字节，实际为 {} 字节",
+            field, expected, actual
+        ),
+    )
+}
+
+fn error(offset: Option<usize>, message: String) -> FrameIssue {
+    FrameIssue {
+        level: IssueLevel::Error,
+        offset,
+        message,
+    }
+}
+
+// 解析结果中各项的 position 为 [起始, 结束)，找出第一个没有被覆盖的字节
+fn first_uncovered(data: &[Value], len: usize) -> Option<usize> {
+    let mut covered = vec![false; len];
+    mark_covered(data, &mut covered);
+    covered.iter().position(|covered| !covered)
+}
+
+fn mark_covered(data: &[Value], covered: &mut [bool]) {
+    for item in data {
+        if let Some([start, end]) = item["position"]
+            .as_array()
+            .and_then(|position| position.get(..2))
+        {
+            if let (Some(start), Some(end)) = (start.as_u64(), end.as_u64()) {
+                let end = (end as usize).min(covered.len());
+                for covered in covered.iter_mut().take(end).skip(start as usize) {
+                    *covered = true;
+                }
+            }
+        }
+        if let Some(children) = item["children"].as_array() {
+            mark_covered(children, covered);
+        }
+    }
+}
+
+fn summarize(
+    protocol: &str,
+    detectors: &[DetectorReport],
+    error: Option<&str>,
+    stopped_at: Option<usize>,
+) -> String {
+    if protocol == "Unknown" {
+        // 第一个错误位置最靠后的识别器最接近报文的实际格式
+        let closest = detectors
+            .iter()
+            .filter_map(|detector| {
+                let issue = detector
+                    .issues
+                    .iter()
+                    .find(|issue| issue.level == IssueLevel::Error)?;
+                Some((detector, issue))
+            })
+            .max_by_key(|(_, issue)| issue.offset.map_or(0, |offset| offset + 1));
+        return match closest {
+            Some((detector, issue)) => format!(
+                "未识别为任何协议，最接近 {}：{}{}",
+                detector.protocol,
+                offset_text(issue.offset),
+                issue.message
+            ),
+            None => "未识别为任何协议".to_string(),
+        };
+    }
+    let warnings: Vec<&FrameIssue> = detectors
+        .iter()
+        .filter(|detector| detector.protocol == protocol)
+        .flat_map(|detector| &detector.issues)
+        .collect();
+    let mut summary = match (error, stopped_at) {
+        (Some(error), Some(offset)) => {
+            format!(
+                "按 {} 解析，在第 {} 字节处停止：{}",
+                protocol, offset, error
+            )
+        }
+        (Some(error), None) => format!("按 {} 解析出错：{}", protocol, error),
+        (None, Some(offset)) => {
+            format!("按 {} 解析，从第 {} 字节起未能解析", protocol, offset)
+        }
+        (None, None) => format!("按 {} 解析完成", protocol),
+    };
+    for issue in warnings {
+        summary.push_str(&format!("；{}{}", offset_text(issue.offset), issue.message));
+    }
+    summary
+}
+
+fn offset_text(offset: Option<usize>) -> String {
+    offset
+        .map(|offset| format!("第 {} 字节", offset))
+        .map(|text| text + " ")
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hex(text: &str) -> Vec<u8> {
+        FrameFun::get_frame_list_from_str(text)
+    }
+
+    fn levels(issues: &[FrameIssue]) -> Vec<(IssueLevel, Option<usize>)> {
+        issues
+            .iter()
+            .map(|issue| (issue.level, issue.offset))
+            .collect()
+    }
+
+    #[test]
+    fn dlt645_checksum_is_warning_and_length_is_error() {
+        // 读通信地址，没有数据标识，不需要查配置
+        let frame = hex("FE FE 68 AA AA AA AA AA AA 68 13 00 DF 16");
+        assert!(check_645(&frame, "南网").is_empty());
+
+        let mut bad_cs = frame.clone();
+        bad_cs[12] = 0x00;
+        assert_eq!(
+            levels(&check_645(&bad_cs, "南网")),
+            [(IssueLevel::Warning, Some(12))]
+        );
+
+        let mut bad_length = frame.clone();
+        bad_length[11] = 0x01;
+        assert_eq!(
+            levels(&check_645(&bad_length, "南网")),
+            [(IssueLevel::Error, Some(11))]
+        );
+    }
+
+    #[test]
+    fn csg_reports_mismatched_length_fields() {
+        let frame = hex("68 10 00 10 00 68 C9 01 44 00 01 00 00 00 02 70 00 00 00 00 00 E0 61 16");
+        assert!(check_csg(&frame, "南网").is_empty());
+
+        let mut bad = frame.clone();
+        bad[3] = 0x11;
+        assert_eq!(
+            levels(&check_csg(&bad, "南网")),
+            [(IssueLevel::Error, Some(1))]
+        );
+        assert_eq!(
+            levels(&check_csg(&frame[..20], "南网")),
+            [(IssueLevel::Error, None)]
+        );
+    }
+
+    #[test]
+    fn stop_offset_is_first_byte_not_covered() {
+        let data = vec![
+            json!({ "position": [0, 4] }),
+            json!({ "position": [4, 10], "children": [{ "position": [10, 12] }] }),
+        ];
+        assert_eq!(first_uncovered(&data, 12), None);
+        assert_eq!(first_uncovered(&data, 16), Some(12));
+        assert_eq!(first_uncovered(&[], 4), Some(0));
+    }
+
+    #[test]
+    fn unknown_frame_summary_names_closest_protocol() {
+        let detector = |protocol: &str, offset: Option<usize>| DetectorReport {
+            protocol: protocol.to_string(),
+            detector: String::new(),
+            accepted: false,
+            issues: vec![error(offset, "结束符错误".to_string())],
+        };
+        let detectors = [detector("CSG13", None), detector("DLT/645-2007", Some(13))];
+        assert_eq!(
+            summarize("Unknown", &detectors, None, None),
+            "未识别为任何协议，最接近 DLT/645-2007：第 13 字节 结束符错误"
+        );
+        assert_eq!(
+            summarize("CSG13", &[], None, Some(20)),
+            "按 CSG13 解析，从第 20 字节起未能解析"
+        );
+    }
+}
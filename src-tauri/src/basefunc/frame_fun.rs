@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::error::Error;
 extern crate regex;
@@ -248,7 +249,7 @@ impl FrameFun {
         // 转换为大端序：反转字节顺序
         let mut array = bcd_array.to_vec();
         array.reverse();
-        
+
         // 调用现有的 bin_to_decimal 函数处理转换后的数据
         Self::bin_to_decimal(&array, decimal_places, need_delete, sign, judge_ff)
     }
@@ -493,6 +494,33 @@ impl FrameFun {
             .collect()
     }
 
+    /// 去掉空白后按十六进制解码，如 "68 01 16"；出错时说明原因，不会 panic
+    pub fn decode_hex_str(text: &str) -> Result<Vec<u8>, String> {
+        let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+        hex::decode(&compact).map_err(|e| match e {
+            hex::FromHexError::InvalidHexCharacter { c, index } => {
+                format!(
+                    "包含非十六进制字符 '{}'（去掉空白后第 {} 个字符）",
+                    c,
+                    index + 1
+                )
+            }
+            hex::FromHexError::OddLength => "十六进制字符数为奇数".to_string(),
+            other => other.to_string(),
+        })
+    }
+
+    /// catch_unwind 捕获的 panic 信息
+    pub fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "未知错误".to_string()
+        }
+    }
+
     pub fn extract_bits(start_bit: usize, end_bit: usize, value: u32) -> String {
         println!(
             "start_bit: {:?}, end_bit: {:?}, value: {:?}",
@@ -0,0 +1,291 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::protocol::modbus::ModbusParser;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// 按哪种协议重新计算报文的长度域和校验
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixupProtocol {
+    /// 按帧结构识别南网、DL/T 645、DL/T 698.45
+    #[default]
+    Auto,
+    /// 南网：两个长度域和 CS
+    Csg,
+    /// DL/T 645：数据长度 L 和 CS
+    Dlt645,
+    /// DL/T 698.45：长度域、HCS 和 FCS
+    Dlt698,
+    /// Modbus RTU：末尾两字节的 CRC
+    ModbusRtu,
+    /// Modbus TCP：MBAP 头中的长度
+    ModbusTcp,
+    /// 不修改
+    None,
+}
+
+/// 修正时改写的一个字段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameChange {
+    /// 字段在报文中的起始位置，包含 FE 前导
+    pub offset: usize,
+    pub field: String,
+    /// 修改前的内容（十六进制）
+    pub old: String,
+    pub new: String,
+}
+
+/// 报文修正结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameRepair {
+    /// 修正后的报文（十六进制）
+    pub frame: String,
+    /// 实际使用的协议
+    pub protocol: FixupProtocol,
+    /// FE 前导的字节数，前导原样保留，长度和校验从 68 开始计算
+    pub preamble: usize,
+    /// 改写的字段，报文本身正确时为空
+    pub changes: Vec<FrameChange>,
+}
+
+/// 修正手工编辑过的报文：识别协议，按报文实际长度改写长度域并重新计算校验
+///
+/// 识别不出协议时返回错误，Modbus 报文需要指定协议
+pub fn repair_frame(
+    frame: &[u8],
+    protocol: FixupProtocol,
+) -> Result<FrameRepair, Box<dyn Error + Send + Sync>> {
+    let mut fixed = frame.to_vec();
+    let mut changes = Vec::new();
+    let protocol = fix(&mut fixed, protocol, &mut changes)?;
+    if protocol == FixupProtocol::Auto {
+        return Err("无法识别报文协议，请指定协议".into());
+    }
+    let preamble = match protocol {
+        FixupProtocol::Csg | FixupProtocol::Dlt645 | FixupProtocol::Dlt698 => {
+            FrameFun::get_frame_fe_count(frame)
+        }
+        _ => 0,
+    };
+    Ok(FrameRepair {
+        frame: FrameFun::get_data_str_with_space(&fixed),
+        protocol,
+        preamble,
+        changes,
+    })
+}
+
+/// 按协议重新计算完整报文的长度域和校验，返回实际使用的协议
+///
+/// 帧结构不符合指定协议时返回错误；Auto 识别不出协议时不修改报文
+pub fn fix_frame(
+    frame: &mut [u8],
+    protocol: FixupProtocol,
+) -> Result<FixupProtocol, Box<dyn Error + Send + Sync>> {
+    fix(frame, protocol, &mut Vec::new())
+}
+
+fn fix(
+    frame: &mut [u8],
+    protocol: FixupProtocol,
+    changes: &mut Vec<FrameChange>,
+) -> Result<FixupProtocol, Box<dyn Error + Send + Sync>> {
+    let protocol = match protocol {
+        FixupProtocol::Auto => detect_protocol(frame),
+        protocol => protocol,
+    };
+    let len = frame.len();
+    // 南网、645、698 允许带 FE 前导
+    let start = FrameFun::get_frame_fe_count(frame);
+    match protocol {
+        FixupProtocol::Csg => {
+            if len < start + 20 || frame[start] != 0x68 || frame[start + 5] != 0x68 {
+                return Err("不是南网帧结构：68 L L L L 68 ... CS 16".into());
+            }
+            let length = ((len - start - 8) as u16).to_le_bytes();
+            set_field(frame, start + 1, &length, "长度域 L", changes);
+            set_field(frame, start + 3, &length, "长度域 L（重复）", changes);
+            let cs = FrameFun::calculate_cs(&frame[start + 6..len - 2]);
+            set_field(frame, len - 2, &[cs], "校验和 CS", changes);
+        }
+        FixupProtocol::Dlt645 => {
+            if len < start + 12 || frame[start] != 0x68 || frame[start + 7] != 0x68 {
+                return Err("不是 645 帧结构：68 A0..A5 68 C L DATA CS 16".into());
+            }
+            let length = len - start - 12;
+            if length > u8::MAX as usize {
+                return Err(format!("645 数据域过长: {} 字节", length).into());
+            }
+            set_field(frame, start + 9, &[length as u8], "数据长度 L", changes);
+            let cs = FrameFun::calculate_cs(&frame[start..len - 2]);
+            set_field(frame, len - 2, &[cs], "校验和 CS", changes);
+        }
+        FixupProtocol::Dlt698 => {
+            if len < start + 5 || frame[start] != 0x68 {
+                return Err("不是 698 帧结构：68 L L C AF SA CA HCS APDU FCS 16".into());
+            }
+            let sa_len = (frame[start + 4] & 0x0F) as usize + 1;
+            let hcs_pos = start + 5 + sa_len + 1;
+            if len < hcs_pos + 2 + 3 {
+                return Err("698 报文长度不足".into());
+            }
+            let length = len - start - 2;
+            if length > 0x3FFF {
+                return Err(format!("698 报文过长: {} 字节", len).into());
+            }
+            // 长度域高 2 位为长度单位，保留原值
+            let length = length as u16 | (u16::from(frame[start + 2] & 0xC0) << 8);
+            set_field(frame, start + 1, &length.to_le_bytes(), "长度域 L", changes);
+            let hcs = fcs16(&frame[start + 1..hcs_pos]);
+            set_field(frame, hcs_pos, &hcs.to_le_bytes(), "帧头校验 HCS", changes);
+            let fcs = fcs16(&frame[start + 1..len - 3]);
+            set_field(frame, len - 3, &fcs.to_le_bytes(), "帧校验 FCS", changes);
+        }
+        FixupProtocol::ModbusRtu => {
+            if len < 4 {
+                return Err("Modbus RTU 报文长度不足".into());
+            }
+            let crc = ModbusParser::calculate_crc(&frame[..len - 2]);
+            set_field(frame, len - 2, &crc.to_le_bytes(), "CRC", changes);
+        }
+        FixupProtocol::ModbusTcp => {
+            if len < 8 {
+                return Err("Modbus TCP 报文长度不足".into());
+            }
+            let length = ((len - 6) as u16).to_be_bytes();
+            set_field(frame, 4, &length, "MBAP 长度", changes);
+        }
+        FixupProtocol::Auto | FixupProtocol::None => {}
+    }
+    Ok(protocol)
+}
+
+// 写入字段，内容有变化时记录修改
+fn set_field(
+    frame: &mut [u8],
+    offset: usize,
+    value: &[u8],
+    field: &str,
+    changes: &mut Vec<FrameChange>,
+) {
+    let target = &mut frame[offset..offset + value.len()];
+    if target == value {
+        return;
+    }
+    changes.push(FrameChange {
+        offset,
+        field: field.to_string(),
+        old: FrameFun::get_data_str_with_space(target),
+        new: FrameFun::get_data_str_with_space(value),
+    });
+    target.copy_from_slice(value);
+}
+
+// 按起始符位置识别协议，长度和校验未知，不能作为判断依据
+fn detect_protocol(frame: &[u8]) -> FixupProtocol {
+    let len = frame.len();
+    let start = FrameFun::get_frame_fe_count(frame);
+    if len < start + 12 || frame[start] != 0x68 || frame[len - 1] != 0x16 {
+        return FixupProtocol::Auto;
+    }
+    if len >= start + 20 && frame[start + 5] == 0x68 {
+        FixupProtocol::Csg
+    } else if frame[start + 7] == 0x68 {
+        FixupProtocol::Dlt645
+    } else {
+        FixupProtocol::Dlt698
+    }
+}
+
+// DL/T 698.45 帧校验（CRC-16/X-25）
+fn fcs16(data: &[u8]) -> u16 {
+    FrameFun::ppp_fcs16(0xFFFF, data) ^ 0xFFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        FrameFun::get_frame_list_from_str(text)
+    }
+
+    #[test]
+    fn fixes_dlt645_length_and_checksum() {
+        // 读 00010000，数据长度和 CS 都写错
+        let repair = repair_frame(
+            &hex("68 11 11 11 11 11 11 68 11 02 33 33 34 33 00 16"),
+            FixupProtocol::Auto,
+        )
+        .unwrap();
+        assert_eq!(repair.protocol, FixupProtocol::Dlt645);
+        assert_eq!(
+            repair.frame,
+            "68 11 11 11 11 11 11 68 11 04 33 33 34 33 18 16"
+        );
+        let fields: Vec<_> = repair
+            .changes
+            .iter()
+            .map(|c| (c.offset, c.new.as_str()))
+            .collect();
+        assert_eq!(fields, [(9, "04"), (14, "18")]);
+    }
+
+    #[test]
+    fn fixes_dlt698_checksums_behind_preamble() {
+        // 读通信地址 40010200，HCS 和 FCS 都写错
+        let frame = hex("FE FE FE FE 68 17 00 43 05 11 11 11 11 11 11 00 00 00 \
+             05 01 00 40 01 02 00 00 00 00 16");
+        let repair = repair_frame(&frame, FixupProtocol::Auto).unwrap();
+        assert_eq!(repair.protocol, FixupProtocol::Dlt698);
+        assert_eq!(repair.preamble, 4);
+        assert_eq!(
+            repair.frame,
+            "FE FE FE FE 68 17 00 43 05 11 11 11 11 11 11 00 EB 26 \
+             05 01 00 40 01 02 00 00 ED 03 16"
+        );
+        let fields: Vec<_> = repair.changes.iter().map(|c| c.offset).collect();
+        assert_eq!(fields, [16, 26]);
+
+        // 修正后的报文不再改动
+        let mut fixed = hex(&repair.frame);
+        assert_eq!(
+            fix_frame(&mut fixed, FixupProtocol::Auto).unwrap(),
+            FixupProtocol::Dlt698
+        );
+        assert_eq!(FrameFun::get_data_str_with_space(&fixed), repair.frame);
+    }
+
+    #[test]
+    fn fixes_csg_length_and_checksum() {
+        let repair = repair_frame(
+            &hex("68 00 00 00 00 68 4A 01 02 03 04 05 06 00 0C 60 00 00 01 00 00 16"),
+            FixupProtocol::Auto,
+        )
+        .unwrap();
+        assert_eq!(repair.protocol, FixupProtocol::Csg);
+        assert_eq!(
+            repair.frame,
+            "68 0E 00 0E 00 68 4A 01 02 03 04 05 06 00 0C 60 00 00 01 00 CC 16"
+        );
+        assert_eq!(repair.changes.len(), 3);
+    }
+
+    #[test]
+    fn detects_protocol_by_start_bytes() {
+        assert_eq!(
+            detect_protocol(&hex("68 AA AA AA AA AA AA 68 13 00 DF 16")),
+            FixupProtocol::Dlt645
+        );
+        // 不以 16 结尾、长度不足时识别不出协议
+        assert_eq!(
+            detect_protocol(&hex("68 AA AA AA AA AA AA 68 13 00 DF")),
+            FixupProtocol::Auto
+        );
+        assert_eq!(
+            detect_protocol(&hex("01 03 00 00 00 01 84 0A")),
+            FixupProtocol::Auto
+        );
+        assert!(repair_frame(&hex("01 03 00 00 00 01 84 0A"), FixupProtocol::Auto).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::basefunc::frame_fun::FrameFun;
+
+const FRAME_START: u8 = 0x68;
+const FRAME_END: u8 = 0x16;
+const FRAME_WAKEUP: u8 = 0xFE;
+
+// 单帧允许的最大长度，超过该长度的候选帧头视为误判
+const MAX_FRAME_LEN: usize = 8192;
+
+/// 在字节流中查找帧的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameSearch {
+    /// 找到完整帧，`start` 为帧起始位置（含唤醒符），`len` 为帧长度
+    Complete { start: usize, len: usize },
+    /// 在 `start` 处存在可能的帧头，但数据尚不完整
+    Incomplete { start: usize },
+    /// 缓冲区中不存在可能的帧头
+    NotFound,
+}
+
+// 单一协议在某个 0x68 位置上的判定结果
+enum Candidate {
+    Match(usize),
+    NeedMore,
+    NoMatch,
+}
+
+/// 字节流分帧器，用于把 TCP 流、串口数据等连续字节切分为完整报文
+///
+/// 支持南网13（两个 0x68、长度域重复）、DL/T 645（含 FE 唤醒符）
+/// 以及南网16/模块（两字节总长度）三种帧格式
+#[derive(Debug, Default, Clone)]
+pub struct FrameStream {
+    buffer: Vec<u8>,
+}
+
+impl FrameStream {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// 追加接收到的数据
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// 缓冲区中尚未成帧的数据长度
+    pub fn pending_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// 取出下一帧，返回 (帧前被跳过的无效数据, 帧数据)
+    ///
+    /// 数据不足以判断时返回 `None`，已缓存的数据保持不变
+    pub fn next_frame(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        match Self::find_frame(&self.buffer) {
+            FrameSearch::Complete { start, len } => {
+                let skipped: Vec<u8> = self.buffer.drain(..start).collect();
+                let frame: Vec<u8> = self.buffer.drain(..len).collect();
+                Some((skipped, frame))
+            }
+            _ => None,
+        }
+    }
+
+    /// 取出缓冲区中剩余的全部数据（流结束时调用）
+    pub fn take_remaining(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    /// 把一段连续字节切分为帧，无法成帧的数据单独作为一段
+    pub fn split(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut stream = Self::new();
+        stream.push(data);
+        let mut frames = Vec::new();
+        while let Some((skipped, frame)) = stream.next_frame() {
+            if !skipped.is_empty() {
+                frames.push(skipped);
+            }
+            frames.push(frame);
+        }
+        let rest = stream.take_remaining();
+        if !rest.is_empty() {
+            frames.push(rest);
+        }
+        frames
+    }
+
+    /// 在数据中查找第一个完整帧
+    ///
+    /// 同一位置按南网13、645、南网16 的优先级判定，优先级高的协议数据不足时
+    /// 不接受优先级低的匹配；南网16 的帧头只有起始符和长度域，容易误判，
+    /// 数据不足时继续查找后面的完整帧
+    pub fn find_frame(data: &[u8]) -> FrameSearch {
+        let mut pending = None;
+        for pos in 0..data.len() {
+            if data[pos] != FRAME_START {
+                continue;
+            }
+
+            let frame = &data[pos..];
+            let checks: [fn(&[u8]) -> Candidate; 2] = [Self::check_csg13, Self::check_645];
+            let candidate = match checks
+                .iter()
+                .map(|check| check(frame))
+                .find(|c| !matches!(c, Candidate::NoMatch))
+            {
+                Some(candidate) => candidate,
+                None => match Self::check_csg16(frame) {
+                    Candidate::NeedMore => {
+                        pending.get_or_insert(pos);
+                        continue;
+                    }
+                    candidate => candidate,
+                },
+            };
+
+            match candidate {
+                Candidate::Match(len) => {
+                    // 645 报文前的唤醒符归入同一帧
+                    let wakeup = data[..pos]
+                        .iter()
+                        .rev()
+                        .take_while(|&&b| b == FRAME_WAKEUP)
+                        .count();
+                    return FrameSearch::Complete {
+                        start: pos - wakeup,
+                        len: len + wakeup,
+                    };
+                }
+                Candidate::NeedMore => {
+                    return FrameSearch::Incomplete {
+                        start: pending.unwrap_or(pos),
+                    };
+                }
+                Candidate::NoMatch => {}
+            }
+        }
+        match pending {
+            Some(start) => FrameSearch::Incomplete { start },
+            None => FrameSearch::NotFound,
+        }
+    }
+
+    // 68 L1 L2 L1 L2 68 ... CS 16，总长度 = L + 8
+    fn check_csg13(data: &[u8]) -> Candidate {
+        if data.len() < 6 {
+            return Candidate::NeedMore;
+        }
+        if data[5] != FRAME_START || data[1] != data[3] || data[2] != data[4] {
+            return Candidate::NoMatch;
+        }
+        let total = (((data[2] as usize) << 8) | data[1] as usize) + 8;
+        Self::check_end(data, total)
+    }
+
+    // 68 A0..A5 68 C L DATA CS 16，总长度 = L + 12
+    fn check_645(data: &[u8]) -> Candidate {
+        if data.len() < 10 {
+            return if data.len() > 7 && data[7] != FRAME_START {
+                Candidate::NoMatch
+            } else {
+                Candidate::NeedMore
+            };
+        }
+        if data[7] != FRAME_START {
+            return Candidate::NoMatch;
+        }
+        let total = data[9] as usize + 12;
+        Self::check_end(data, total)
+    }
+
+    // 68 LL LL C ... CS 16，长度域为整帧长度
+    fn check_csg16(data: &[u8]) -> Candidate {
+        if data.len() < 3 {
+            return Candidate::NeedMore;
+        }
+        let total = ((data[2] as usize) << 8) | data[1] as usize;
+        if total < 6 {
+            return Candidate::NoMatch;
+        }
+        match Self::check_end(data, total) {
+            // 校验和为控制域到校验和之前各字节的累加和
+            Candidate::Match(len) if data[len - 2] != FrameFun::calculate_cs(&data[3..len - 2]) => {
+                Candidate::NoMatch
+            }
+            candidate => candidate,
+        }
+    }
+
+    fn check_end(data: &[u8], total: usize) -> Candidate {
+        if total > MAX_FRAME_LEN {
+            return Candidate::NoMatch;
+        }
+        if data.len() < total {
+            return Candidate::NeedMore;
+        }
+        if data[total - 1] == FRAME_END {
+            Candidate::Match(total)
+        } else {
+            Candidate::NoMatch
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 68 L L L L 68 用户数据 CS 16
+    fn csg13(user: &[u8]) -> Vec<u8> {
+        let len = (user.len() as u16).to_le_bytes();
+        let mut frame = vec![FRAME_START, len[0], len[1], len[0], len[1], FRAME_START];
+        frame.extend_from_slice(user);
+        frame.push(FrameFun::calculate_cs(user));
+        frame.push(FRAME_END);
+        frame
+    }
+
+    // 68 LL LL 控制域及数据 CS 16，长度域为整帧长度
+    fn csg16(body: &[u8]) -> Vec<u8> {
+        let len = (body.len() as u16 + 5).to_le_bytes();
+        let mut frame = vec![FRAME_START, len[0], len[1]];
+        frame.extend_from_slice(body);
+        frame.push(FrameFun::calculate_cs(body));
+        frame.push(FRAME_END);
+        frame
+    }
+
+    #[test]
+    fn waits_for_csg13_tail_instead_of_cutting_at_csg16_length() {
+        // 长度域按南网16 理解为 20 字节，且第 20 字节为 16、校验和也吻合
+        let mut user = vec![0x4A, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x0C, 0x60];
+        user.extend_from_slice(&[0x00, 0x00, 0x00, 0x16]);
+        user.extend_from_slice(&[0x00, 0x00, 0xFF, 0x00, 0x00, 0x00]);
+        user[12] = FrameFun::calculate_cs(&[&[0x14, 0x00, FRAME_START][..], &user[..12]].concat());
+        let frame = csg13(&user);
+        assert_eq!(frame.len(), 28);
+        assert_eq!(frame[19], FRAME_END);
+
+        let mut stream = FrameStream::new();
+        stream.push(&frame[..20]);
+        assert_eq!(stream.next_frame(), None);
+        stream.push(&frame[20..]);
+        assert_eq!(stream.next_frame(), Some((Vec::new(), frame)));
+        assert_eq!(stream.pending_len(), 0);
+    }
+
+    #[test]
+    fn stray_start_byte_does_not_hold_back_later_frames() {
+        // 孤立的 68 按南网16 理解为 4096 字节的帧
+        let stray = [FRAME_START, 0x00, 0x10];
+        let frame = csg13(&[0x4A, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x0C, 0x60]);
+
+        let mut stream = FrameStream::new();
+        stream.push(&stray);
+        stream.push(&frame[..8]);
+        assert_eq!(stream.next_frame(), None);
+        stream.push(&frame[8..]);
+        assert_eq!(stream.next_frame(), Some((stray.to_vec(), frame)));
+        assert_eq!(stream.next_frame(), None);
+    }
+
+    #[test]
+    fn csg16_requires_checksum() {
+        let frame = csg16(&[0x41, 0x00, 0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(
+            FrameStream::find_frame(&frame),
+            FrameSearch::Complete {
+                start: 0,
+                len: frame.len()
+            }
+        );
+
+        let mut corrupted = frame.clone();
+        let cs = corrupted.len() - 2;
+        corrupted[cs] ^= 0xFF;
+        assert_eq!(FrameStream::find_frame(&corrupted), FrameSearch::NotFound);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_repair::{fix_frame, FixupProtocol};
+use crate::config::appconfig::{load_config_value, set_config_value};
+use chrono::{Datelike, Local, Timelike};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Mutex;
+
+// 南网帧序列域的首帧、末帧标志
+const MASK_FIR: u8 = 0x40;
+const MASK_FIN: u8 = 0x20;
+
+// 模板在应用配置中的位置
+const TEMPLATE_SECTION: &str = "frame";
+const TEMPLATE_KEY: &str = "templates";
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\{([^}]*)\}").unwrap());
+
+// 各模板的 ${seq} 序号，按模板名称区分
+static SEQUENCES: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// ${counter:NAME} 计数器，所有模板共用
+static COUNTERS: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 报文模板
+///
+/// text 为十六进制报文，可以包含以下占位符，发送前展开：
+/// - `${seq}`：本模板的发送序号，每次发送加 1；`${seq:2}` 指定字节数（高位在前），
+///   `${seq:csg}` 为南网帧序列域 SEQ（FIR、FIN 置位，低 4 位为序号）
+/// - `${now}`、`${now:bcd}`：当前时间，BCD 码 ss mm hh DD MM YY（与 645 广播校时相同）；
+///   `${now:bcd:mmhhDD}` 按给定顺序输出，可用 ss mm hh DD MM YY WW（星期）；
+///   `${now:698}` 为 698 的 date_time_s
+/// - `${addr}`：变量 addr，按书写顺序填写地址，帧中按低字节在前倒序；
+///   `${addr:csg}` 把 6 字节地址的前后 3 字节分别倒序，与南网的 A1、A2 对应
+/// - `${counter:NAME}`：命名计数器，所有模板共用，每次展开加 1，同一帧中取同一个值；
+///   `${counter:NAME:2}` 指定字节数，`${counter:NAME:2:le}` 低字节在前
+/// - `${rand}`、`${rand:4}`：随机字节
+/// - `${NAME}`：其他变量，值为十六进制，原样填入
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameTemplate {
+    pub name: String,
+    pub text: String,
+    /// 展开后按哪种协议重新计算长度域和校验，识别不出协议时不修正
+    pub protocol: FixupProtocol,
+    /// 变量的默认值（十六进制），发送时指定的变量优先
+    pub vars: HashMap<String, String>,
+    pub description: String,
+}
+
+impl FrameTemplate {
+    /// 读取保存的全部模板，按名称排序
+    pub fn load_all() -> Vec<FrameTemplate> {
+        Self::load_map().into_values().collect()
+    }
+
+    pub fn load(name: &str) -> Option<FrameTemplate> {
+        Self::load_map().remove(name)
+    }
+
+    /// 保存模板，同名模板被覆盖
+    pub fn save(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("模板名称不能为空".to_string());
+        }
+        let mut templates = Self::load_map();
+        templates.insert(self.name.clone(), self.clone());
+        Self::save_map(&templates)
+    }
+
+    /// 删除模板，模板不存在时返回 false
+    pub fn delete(name: &str) -> Result<bool, String> {
+        let mut templates = Self::load_map();
+        if templates.remove(name).is_none() {
+            return Ok(false);
+        }
+        SEQUENCES.lock().unwrap().remove(name);
+        Self::save_map(&templates)?;
+        Ok(true)
+    }
+
+    /// 展开模板用于发送，序号和计数器加 1
+    pub fn expand(
+        &self,
+        vars: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.render(vars, true)
+    }
+
+    /// 按下一次发送的内容展开模板，不改变序号和计数器
+    pub fn preview(
+        &self,
+        vars: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.render(vars, false)
+    }
+
+    fn render(
+        &self,
+        vars: &HashMap<String, String>,
+        advance: bool,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let mut frame = Vec::new();
+        let mut last = 0;
+        // 同一模板中的 ${seq}、同名 ${counter} 取同一个值
+        let mut seq = None;
+        let mut counters = HashMap::new();
+        for caps in PLACEHOLDER.captures_iter(&self.text) {
+            let whole = caps.get(0).unwrap();
+            frame.extend(parse_hex(&self.text[last..whole.start()])?);
+            last = whole.end();
+
+            let placeholder = caps.get(1).unwrap().as_str().trim();
+            let mut parts = placeholder.split(':');
+            let name = parts.next().unwrap_or_default();
+            let args: Vec<&str> = parts.collect();
+            let bytes = match name {
+                "seq" => {
+                    let value =
+                        *seq.get_or_insert_with(|| next_value(&SEQUENCES, &self.name, advance));
+                    match args.first().copied() {
+                        Some("csg") => vec![MASK_FIR | MASK_FIN | (value & 0x0F) as u8],
+                        width => to_bytes(value, parse_width(width)?, false),
+                    }
+                }
+                "now" => now_bytes(&args)?,
+                "addr" => {
+                    let addr = parse_hex(self.var(vars, "addr")?)?;
+                    match args.first().copied() {
+                        Some("csg") if addr.len() == 6 => addr[..3]
+                            .iter()
+                            .rev()
+                            .chain(addr[3..].iter().rev())
+                            .copied()
+                            .collect(),
+                        Some("csg") => return Err("南网地址应为 6 字节".into()),
+                        _ => addr.into_iter().rev().collect(),
+                    }
+                }
+                "counter" => {
+                    let counter = args.first().ok_or("计数器需要名称，如 ${counter:meter}")?;
+                    let value = *counters
+                        .entry(*counter)
+                        .or_insert_with(|| next_value(&COUNTERS, counter, advance));
+                    let little_endian = args.get(2).is_some_and(|order| *order == "le");
+                    to_bytes(value, parse_width(args.get(1).copied())?, little_endian)
+                }
+                "rand" => {
+                    let width = parse_width(args.first().copied())?;
+                    let mut bytes = Vec::with_capacity(width);
+                    while bytes.len() < width {
+                        bytes.extend(uuid::Uuid::new_v4().as_bytes());
+                    }
+                    bytes.truncate(width);
+                    bytes
+                }
+                _ if args.is_empty() => parse_hex(self.var(vars, name)?)?,
+                _ => return Err(format!("不支持的占位符: ${{{}}}", placeholder).into()),
+            };
+            frame.extend(bytes);
+        }
+        frame.extend(parse_hex(&self.text[last..])?);
+        if frame.is_empty() {
+            return Err("模板展开后报文为空".into());
+        }
+        fix_frame(&mut frame, self.protocol)?;
+        Ok(frame)
+    }
+
+    fn var<'a>(
+        &'a self,
+        vars: &'a HashMap<String, String>,
+        name: &str,
+    ) -> Result<&'a str, Box<dyn Error + Send + Sync>> {
+        vars.get(name)
+            .or_else(|| self.vars.get(name))
+            .map(String::as_str)
+            .ok_or_else(|| format!("缺少变量 {}", name).into())
+    }
+
+    fn load_map() -> BTreeMap<String, FrameTemplate> {
+        load_config_value(TEMPLATE_SECTION, TEMPLATE_KEY)
+            .and_then(|value| serde_json::from_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_map(templates: &BTreeMap<String, FrameTemplate>) -> Result<(), String> {
+        let value = serde_json::to_string(templates).map_err(|e| e.to_string())?;
+        set_config_value(TEMPLATE_SECTION, TEMPLATE_KEY, &value)
+    }
+}
+
+/// 按名称读取并展开模板
+pub fn expand_template(
+    name: &str,
+    vars: &HashMap<String, String>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    FrameTemplate::load(name)
+        .ok_or_else(|| format!("模板不存在: {}", name))?
+        .expand(vars)
+}
+
+// 返回序号的当前值，advance 为 true 时加 1
+fn next_value(values: &Mutex<HashMap<String, u64>>, key: &str, advance: bool) -> u64 {
+    let mut values = values.lock().unwrap();
+    let value = values.entry(key.to_string()).or_insert(0);
+    let current = *value;
+    if advance {
+        *value = value.wrapping_add(1);
+    }
+    current
+}
+
+fn parse_width(arg: Option<&str>) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    match arg {
+        None | Some("") => Ok(1),
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(width) if (1..=8).contains(&width) => Ok(width),
+            _ => Err(format!("字节数应为 1~8: {}", arg).into()),
+        },
+    }
+}
+
+// 取数值的低 width 字节
+fn to_bytes(value: u64, width: usize, little_endian: bool) -> Vec<u8> {
+    let bytes = value.to_le_bytes();
+    let mut bytes = bytes[..width].to_vec();
+    if !little_endian {
+        bytes.reverse();
+    }
+    bytes
+}
+
+fn now_bytes(args: &[&str]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let now = Local::now();
+    let bcd = |value: u32| (((value / 10) % 10) << 4 | (value % 10)) as u8;
+    match args {
+        [] | ["bcd"] => Ok(vec![
+            bcd(now.second()),
+            bcd(now.minute()),
+            bcd(now.hour()),
+            bcd(now.day()),
+            bcd(now.month()),
+            bcd(now.year() as u32 % 100),
+        ]),
+        ["bcd", format] if format.len().is_multiple_of(2) && !format.is_empty() => {
+            let mut bytes = Vec::new();
+            for i in (0..format.len()).step_by(2) {
+                let value = match format.get(i..i + 2) {
+                    Some("ss") => now.second(),
+                    Some("mm") => now.minute(),
+                    Some("hh") => now.hour(),
+                    Some("DD") => now.day(),
+                    Some("MM") => now.month(),
+                    Some("YY") => now.year() as u32 % 100,
+                    Some("WW") => now.weekday().num_days_from_sunday(),
+                    _ => return Err(format!("不支持的时间格式: {}", format).into()),
+                };
+                bytes.push(bcd(value));
+            }
+            Ok(bytes)
+        }
+        ["698"] => {
+            let mut bytes = (now.year() as u16).to_be_bytes().to_vec();
+            bytes.extend([
+                now.month() as u8,
+                now.day() as u8,
+                now.hour() as u8,
+                now.minute() as u8,
+                now.second() as u8,
+            ]);
+            Ok(bytes)
+        }
+        _ => Err(format!("不支持的时间格式: {}", args.join(":")).into()),
+    }
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    FrameFun::decode_hex_str(text)
+        .map_err(|e| format!("不是合法的十六进制 {}: {}", text.trim(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 序号按模板名称区分，各测试使用不同的名称
+    fn template(name: &str, text: &str) -> FrameTemplate {
+        FrameTemplate {
+            name: name.to_string(),
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fixes_dlt645_length_and_checksum_after_substitution() {
+        // 长度和 CS 先写 00，替换地址和数据标识后重新计算
+        let mut template = template("test-645", "68 ${addr} 68 11 00 ${di} 00 16");
+        template.vars = vars(&[("addr", "000000000001")]);
+        let frame = template.expand(&vars(&[("di", "33333433")])).unwrap();
+        assert_eq!(
+            FrameFun::get_data_str_with_space(&frame),
+            "68 01 00 00 00 00 00 68 11 04 33 33 34 33 B3 16"
+        );
+    }
+
+    #[test]
+    fn csg_sequence_advances_and_checksum_follows() {
+        let template = template(
+            "test-csg",
+            "68 00 00 00 00 68 4A ${addr:csg} 00 0C ${seq:csg} 00 00 01 00 00 16",
+        );
+        let vars = vars(&[("addr", "004401000001")]);
+        let first = "68 0E 00 0E 00 68 4A 01 44 00 01 00 00 00 0C 60 00 00 01 00 FD 16";
+        let second = "68 0E 00 0E 00 68 4A 01 44 00 01 00 00 00 0C 61 00 00 01 00 FE 16";
+
+        // 预览不改变序号
+        let preview = template.preview(&vars).unwrap();
+        assert_eq!(FrameFun::get_data_str_with_space(&preview), first);
+        let frame = template.expand(&vars).unwrap();
+        assert_eq!(FrameFun::get_data_str_with_space(&frame), first);
+        let frame = template.expand(&vars).unwrap();
+        assert_eq!(FrameFun::get_data_str_with_space(&frame), second);
+    }
+
+    #[test]
+    fn counter_takes_one_value_per_frame() {
+        let mut template = template(
+            "test-counter",
+            "${counter:test-counter:2:le} ${counter:test-counter:2}",
+        );
+        template.protocol = FixupProtocol::None;
+        let frame = template.expand(&HashMap::new()).unwrap();
+        assert_eq!(frame, [0x00, 0x00, 0x00, 0x00]);
+        let frame = template.expand(&HashMap::new()).unwrap();
+        assert_eq!(frame, [0x01, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn reports_missing_variable_and_unknown_placeholder() {
+        let missing = template("test-missing", "68 ${addr} 16").preview(&HashMap::new());
+        assert!(missing.unwrap_err().to_string().contains("addr"));
+        let unknown = template("test-unknown", "68 ${foo:bar} 16").preview(&HashMap::new());
+        assert!(unknown.unwrap_err().to_string().contains("foo:bar"));
+    }
+}
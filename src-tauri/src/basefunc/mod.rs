@@ -2,9 +2,13 @@
 pub mod frame_645;
 pub mod frame_cco;
 pub mod frame_csg;
+pub mod frame_diagnose;
 pub mod frame_err;
 pub mod frame_fun;
 pub mod frame_moudle;
+pub mod frame_repair;
 pub mod frame_speecial;
+pub mod frame_stream;
 pub mod frame_tctask;
+pub mod frame_template;
 pub mod protocol;
@@ -35,7 +35,7 @@ impl ProtocolInfo {
             ProtocolInfo::ProtocolDLT64507 => "DLT/645-2007",
             ProtocolInfo::ProtocolMoudle => "moudle",
             ProtocolInfo::ProtocolMS => "MS",
-            ProtocolInfo::ProtocolHis => "His"
+            ProtocolInfo::ProtocolHis => "His",
         }
     }
 }
@@ -44,41 +44,110 @@ pub struct FrameAnalisyic;
 
 impl FrameAnalisyic {
     pub fn process_frame(frame: &[u8], region: &str) -> (String, Vec<Value>) {
+        let (protocol, parsed_data, _) = Self::process_frame_with_error(frame, region);
+        (protocol, parsed_data)
+    }
+
+    /// 与 process_frame 相同，另外返回解析中途出错时的错误信息，此时解析结果只包含出错前的部分
+    pub fn process_frame_with_error(
+        frame: &[u8],
+        region: &str,
+    ) -> (String, Vec<Value>, Option<String>) {
         let mut parsed_data: Vec<Value> = Vec::new();
+        let (protocol, error) = Self::process_frame_into(frame, region, &mut parsed_data);
+        (protocol, parsed_data, error)
+    }
+
+    /// 与 process_frame_with_error 相同，但捕获解析中的 panic，此时协议为 Unknown、结果为空，错误信息为 panic 信息
+    pub fn process_frame_safe(frame: &[u8], region: &str) -> (String, Vec<Value>, Option<String>) {
+        std::panic::catch_unwind(|| Self::process_frame_with_error(frame, region)).unwrap_or_else(
+            |e| {
+                (
+                    "Unknown".to_string(),
+                    Vec::new(),
+                    Some(format!("解析出错: {}", FrameFun::panic_message(&e))),
+                )
+            },
+        )
+    }
+
+    /// 解析结果写入 parsed_data，解析中途 panic 时 parsed_data 中保留已解析的部分
+    pub fn process_frame_into(
+        frame: &[u8],
+        region: &str,
+        parsed_data: &mut Vec<Value>,
+    ) -> (String, Option<String>) {
         let mut protocol = String::from("Unknown");
+        let mut error = None;
         if FrameCsg::is_csg_frame(frame) {
-            let result = FrameCsg::analysic_csg_frame_by_afn(frame, &mut parsed_data, 0, region);
+            let result = FrameCsg::analysic_csg_frame_by_afn(frame, parsed_data, 0, region);
             protocol = ProtocolInfo::ProtocolCSG13.name().to_string();
-            match result {
-                Ok(_) => {}
-                Err(_) => {}
-            }
+            error = result.err().map(|e| e.to_string());
         } else if Frame645::is_dlt645_frame(frame) {
             protocol = ProtocolInfo::ProtocolDLT64507.name().to_string();
-            let result = Frame645::analysic_645_frame_by_afn(frame, &mut parsed_data, 0, region);
+            let result = Frame645::analysic_645_frame_by_afn(frame, parsed_data, 0, region);
         } else if FrameCCO::is_cco_frame(frame) {
             protocol = ProtocolInfo::ProtocolCSG16.name().to_string();
-            FrameCCO::analysic_cco_frame_by_afn(frame, &mut parsed_data, 0, region);
+            FrameCCO::analysic_cco_frame_by_afn(frame, parsed_data, 0, region);
         } else if FrameMoudle::is_moudle_frame(frame) {
             protocol = ProtocolInfo::ProtocolMoudle.name().to_string();
-            FrameMoudle::analysic_moudle_frame(frame, &mut parsed_data, 0, region);
+            FrameMoudle::analysic_moudle_frame(frame, parsed_data, 0, region);
         } else if TCMeterTask::is_meter_task(frame) {
             protocol = ProtocolInfo::ProtocolMS.name().to_string();
-            let result = TCMeterTask::analysic_meter_task(frame, &mut parsed_data, 0, region);
-            match result {
-                Ok(_) => {}
-                Err(_) => {}
-            }
+            let result = TCMeterTask::analysic_meter_task(frame, parsed_data, 0, region);
+            error = result.err().map(|e| e.to_string());
         } else if SpcialFrame::is_special_frame(frame, region) {
             protocol = ProtocolInfo::ProtocolHis.name().to_string();
-            let result = SpcialFrame::analysic_special_frame(frame, &mut parsed_data, 0, region);
-            match result {
-                Ok(_) => {}
-                Err(_) => {}
-            }
+            let result = SpcialFrame::analysic_special_frame(frame, parsed_data, 0, region);
+            error = result.err().map(|e| e.to_string());
         }
 
-        (protocol, parsed_data)
+        (protocol, error)
+    }
+
+    /// 只识别报文所属协议，不做解析，识别顺序与 process_frame 一致
+    pub fn detect_protocol(frame: &[u8], region: &str) -> Option<ProtocolInfo> {
+        if FrameCsg::is_csg_frame(frame) {
+            Some(ProtocolInfo::ProtocolCSG13)
+        } else if Frame645::is_dlt645_frame(frame) {
+            Some(ProtocolInfo::ProtocolDLT64507)
+        } else if FrameCCO::is_cco_frame(frame) {
+            Some(ProtocolInfo::ProtocolCSG16)
+        } else if FrameMoudle::is_moudle_frame(frame) {
+            Some(ProtocolInfo::ProtocolMoudle)
+        } else if TCMeterTask::is_meter_task(frame) {
+            Some(ProtocolInfo::ProtocolMS)
+        } else if SpcialFrame::is_special_frame(frame, region) {
+            Some(ProtocolInfo::ProtocolHis)
+        } else {
+            None
+        }
+    }
+
+    /// 提取报文中的设备地址：南网13 为 A1+A2，645 为表地址，均按高字节在前输出
+    pub fn frame_address(frame: &[u8], protocol: &ProtocolInfo) -> Option<String> {
+        match protocol {
+            ProtocolInfo::ProtocolCSG13 => {
+                // 带自定义头的报文，地址在去掉 84 字节头之后
+                let frame = if frame.len() > 84 && FrameCsg::is_contoine_custom_head(&frame[..84]) {
+                    &frame[84..]
+                } else {
+                    frame
+                };
+                let address = frame.get(7..13)?;
+                Some(format!(
+                    "{}{}",
+                    FrameFun::get_data_str_reverser(&address[..3]),
+                    FrameFun::get_data_str_reverser(&address[3..6])
+                ))
+            }
+            ProtocolInfo::ProtocolDLT64507 => {
+                let position = FrameFun::get_frame_fe_count(frame);
+                let address = frame.get(position + 1..position + 7)?;
+                Some(FrameFun::get_data_str_reverser(address))
+            }
+            _ => None,
+        }
     }
     pub fn prase_data(
         data_item_elem: &mut XmlElement,
@@ -499,11 +568,16 @@ impl FrameAnalisyic {
         // 获取所有 `value` 子元素
         let value_elements = data_item_elem.get_items("value");
         let (value_str, element) = Self::find_value_from_elements(&value_elements, &value);
-        
+
         value_name = if value_str.is_none() {
             format!("[{}]: {}", value_name, parse_value.clone())
         } else {
-            format!("[{}]: {}-{}", value_name, parse_value.clone(), value_str.unwrap())
+            format!(
+                "[{}]: {}-{}",
+                value_name,
+                parse_value.clone(),
+                value_str.unwrap()
+            )
         };
         // 获取 color 属性并使用 `.cloned()` 将 Option<&String> 转换为 Option<String>
         color = data_item_elem.get_attribute("color").cloned();
@@ -696,7 +770,9 @@ impl FrameAnalisyic {
             "ASCII" => FrameFun::ascii_to_str(data_segment),
             "PORT" => FrameFun::prase_port(data_segment),
             "IP" => FrameFun::prase_ip_str(data_segment),
-            "BIN_BE" => FrameFun::prase_bin_be_deciml(data_segment, decimal, need_delete, sign, true),
+            "BIN_BE" => {
+                FrameFun::prase_bin_be_deciml(data_segment, decimal, need_delete, sign, true)
+            }
             "NORMAL" => FrameFun::get_data_str(&data_segment, need_delete, true, false),
             _ => return None, // 不支持的类型返回 None
         };
@@ -742,8 +818,7 @@ impl FrameAnalisyic {
                 FrameFun::hex_array_to_int(&data_segment[start_pos..end_pos], need_delete),
             );
             let value_elements = bit_elem.get_items("value");
-            let (value_name, element) =
-                Self::find_value_from_elements(&value_elements, &bit_value);
+            let (value_name, element) = Self::find_value_from_elements(&value_elements, &bit_value);
 
             let bit_id_attr = format!("bit{}", bit_id_attr);
             let name_str = if let Some(name_elem) = bit_name_elem {
@@ -1666,20 +1741,25 @@ impl FrameAnalisyic {
                 if item_singal {
                     result_vec.extend(item_value);
                 } else {
-                    if !item_value.is_empty() && item_value.iter().any(|v| {
-                        if let Some(frame_domain) = v.get("frameDomain") {
-                            if frame_domain.is_string() {
-                                return true;
+                    if !item_value.is_empty()
+                        && item_value.iter().any(|v| {
+                            if let Some(frame_domain) = v.get("frameDomain") {
+                                if frame_domain.is_string() {
+                                    return true;
+                                }
                             }
-                        }
-                        false
-                    }) {
+                            false
+                        })
+                    {
                         // 将 frameDomain 修改为 item_name
                         let mut modified_value = item_value;
                         for v in modified_value.iter_mut() {
                             if let Some(frame_domain) = v.as_object_mut() {
                                 if frame_domain.contains_key("frameDomain") {
-                                    frame_domain.insert("frameDomain".to_string(), json!(item_name.clone()));
+                                    frame_domain.insert(
+                                        "frameDomain".to_string(),
+                                        json!(item_name.clone()),
+                                    );
                                 }
                                 if frame_domain.contains_key("description") {
                                     if let Some(description) = frame_domain.get("description") {
@@ -1688,7 +1768,10 @@ impl FrameAnalisyic {
                                             if let Some(attri_id) = attri_id.as_ref() {
                                                 let pattern = format!("{}_", attri_id);
                                                 let new_desc = desc_str.replace(&pattern, "");
-                                                frame_domain.insert("description".to_string(), json!(new_desc));
+                                                frame_domain.insert(
+                                                    "description".to_string(),
+                                                    json!(new_desc),
+                                                );
                                             }
                                         }
                                     }
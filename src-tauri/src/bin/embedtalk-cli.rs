@@ -0,0 +1,670 @@
+//! EmbedTalk 命令行工具
+//!
+//! 不依赖桌面界面（可不启用 desktop 功能构建），用于在测试台上通过 SSH
+//! 解析报文和日志、组帧、打开通道收发数据以及执行测试场景
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Args, Parser, Subcommand};
+use embedtalk::basefunc::frame_csg::FrameCsg;
+use embedtalk::basefunc::frame_diagnose::{diagnose_frame, IssueLevel};
+use embedtalk::basefunc::frame_fun::FrameFun;
+use embedtalk::basefunc::frame_repair::{repair_frame, FixupProtocol};
+use embedtalk::basefunc::frame_stream::FrameStream;
+use embedtalk::basefunc::frame_template::FrameTemplate;
+use embedtalk::basefunc::protocol::FrameAnalisyic;
+use embedtalk::combridage::{
+    channel_from_params, read_log_file, records_from_log, run_scenario, subscribe_records,
+    CommunicationManager, Message, MessageDirection, Scenario,
+};
+use embedtalk::protocol::build_frame;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, sleep, sleep_until, Duration, Instant};
+
+// 数据在该时间内没有凑成完整帧时按原样输出
+const FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Parser)]
+#[command(name = "embedtalk-cli", version, about = "EmbedTalk 命令行工具")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 解析十六进制报文，未给出报文时从标准输入读取，多帧连续时自动分帧
+    Parse {
+        /// 十六进制报文，可包含空格
+        hex: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// 诊断无法解析的报文：各协议拒绝的原因和解析停止的位置，未给出报文时从标准输入读取
+    Diagnose {
+        hex: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// 解析通信日志文件（支持 .gz 压缩日志）
+    ParseLog {
+        file: PathBuf,
+        /// 只解析指定通道名称的记录
+        #[arg(long)]
+        channel: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// 按 JSON 描述组帧，未给出 JSON 时从标准输入读取
+    Build {
+        /// 协议：csg13、645、modbus 或协议栈中注册的协议名
+        protocol: String,
+        json: Option<String>,
+    },
+    /// 修正手工编辑过的报文，重新计算长度域和校验，未给出报文时从标准输入读取
+    Repair {
+        hex: Option<String>,
+        /// 协议：csg、dlt645、dlt698、modbus_rtu、modbus_tcp，默认按帧结构识别
+        #[arg(long)]
+        protocol: Option<String>,
+        /// 以 JSON 输出修正结果
+        #[arg(long)]
+        json: bool,
+    },
+    /// 打开通道发送报文并实时解析收到的数据，Ctrl-C 退出
+    Channel(ChannelArgs),
+    /// 列出保存的报文模板，给出名称时输出展开后的报文
+    Template {
+        name: Option<String>,
+        /// 展开模板使用的变量，可重复
+        #[arg(long = "var", value_name = "KEY=VALUE")]
+        vars: Vec<String>,
+    },
+    /// 执行 YAML 测试场景，未通过时退出码为 1
+    Scenario {
+        file: String,
+        /// 以 JSON 输出执行报告，逐步结果输出到标准错误
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// 解析使用的省份
+    #[arg(long, default_value = "南网")]
+    region: String,
+    /// 以 JSON 输出解析结果
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ChannelArgs {
+    /// 通道类型：tcpclient、tcpserver、udp、serial、serialserver、
+    /// websocketclient、websocketserver、mqtt、virtual
+    kind: String,
+    /// 通道参数 JSON，字段与界面连接通道时相同
+    #[arg(long)]
+    params: Option<String>,
+    /// 单个通道参数，可重复，覆盖 --params 中的同名字段
+    #[arg(short = 'p', long = "param", value_name = "KEY=VALUE")]
+    param: Vec<String>,
+    /// 连接后发送的十六进制报文，可重复，按顺序发送
+    #[arg(long)]
+    send: Vec<String>,
+    /// 连接后发送的报文模板名称，可重复，排在 --send 之后，每次发送前展开
+    #[arg(long)]
+    template: Vec<String>,
+    /// 展开模板使用的变量，可重复
+    #[arg(long = "var", value_name = "KEY=VALUE")]
+    vars: Vec<String>,
+    /// 循环发送的间隔（毫秒），不指定时只发送一轮
+    #[arg(long)]
+    interval: Option<u64>,
+    /// 循环发送的轮数，不指定时一直发送
+    #[arg(long)]
+    count: Option<u64>,
+    /// 监听时长（秒），不指定时直到 Ctrl-C
+    #[arg(long)]
+    duration: Option<u64>,
+    /// 服务端通道发送的目标客户端 ID
+    #[arg(long)]
+    clientid: Option<String>,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Parse { hex, output } => parse(hex, &output),
+        Command::Diagnose { hex, output } => diagnose(hex, &output),
+        Command::ParseLog {
+            file,
+            channel,
+            output,
+        } => parse_log(&file, channel.as_deref(), &output).await,
+        Command::Build { protocol, json } => build(&protocol, json).await,
+        Command::Repair {
+            hex,
+            protocol,
+            json,
+        } => repair(hex, protocol.as_deref(), json),
+        Command::Channel(args) => channel(args).await,
+        Command::Template { name, vars } => template(name.as_deref(), &vars),
+        Command::Scenario { file, json } => scenario(&file, json).await,
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(hex: Option<String>, output: &OutputArgs) -> Result<(), String> {
+    let text = match hex {
+        Some(text) => text,
+        None => read_stdin()?,
+    };
+    let data = parse_hex(&text)?;
+    let mut results = Vec::new();
+    for frame in FrameStream::split(&data) {
+        let (protocol, items, _) = FrameAnalisyic::process_frame_safe(&frame, &output.region);
+        if output.json {
+            results.push(json!({
+                "frame": FrameFun::get_data_str_with_space(&frame),
+                "protocol": protocol,
+                "result": items,
+            }));
+        } else {
+            print_frame(&frame, &protocol, &items);
+        }
+    }
+    if output.json {
+        print_json(&Value::Array(results));
+    }
+    Ok(())
+}
+
+fn diagnose(hex: Option<String>, output: &OutputArgs) -> Result<(), String> {
+    let text = match hex {
+        Some(text) => text,
+        None => read_stdin()?,
+    };
+    let diagnosis = diagnose_frame(&parse_hex(&text)?, &output.region);
+    if output.json {
+        print_json(&json!(diagnosis));
+        return Ok(());
+    }
+    println!("{}", diagnosis.frame);
+    println!("  {}", diagnosis.summary);
+    for detector in &diagnosis.detectors {
+        let result = if detector.accepted {
+            "通过"
+        } else {
+            "拒绝"
+        };
+        println!(
+            "  {} ({}): {}",
+            detector.protocol, detector.detector, result
+        );
+        for issue in &detector.issues {
+            let level = match issue.level {
+                IssueLevel::Error => "错误",
+                IssueLevel::Warning => "警告",
+            };
+            match issue.offset {
+                Some(offset) => println!("    [{}] 第 {} 字节: {}", level, offset, issue.message),
+                None => println!("    [{}] {}", level, issue.message),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn parse_log(file: &Path, channel: Option<&str>, output: &OutputArgs) -> Result<(), String> {
+    let content = read_log_file(file)
+        .await
+        .map_err(|e| format!("读取日志文件 {} 失败: {}", file.display(), e))?;
+    let records = records_from_log(&content, channel);
+    if records.is_empty() {
+        return Err("日志中没有可解析的报文记录".to_string());
+    }
+    let mut results = Vec::new();
+    for record in &records {
+        let sent = matches!(record.direction(), MessageDirection::Sent);
+        for frame in FrameStream::split(&record.payload_bytes()) {
+            let (protocol, items, _) = FrameAnalisyic::process_frame_safe(&frame, &output.region);
+            if output.json {
+                results.push(frame_json(
+                    record.timestamp(),
+                    sent,
+                    record.channel_name(),
+                    &frame,
+                    &protocol,
+                    items,
+                ));
+            } else {
+                print_header(record.timestamp(), sent, record.channel_name());
+                print_frame(&frame, &protocol, &items);
+            }
+        }
+    }
+    if output.json {
+        print_json(&Value::Array(results));
+    }
+    Ok(())
+}
+
+async fn build(protocol: &str, json: Option<String>) -> Result<(), String> {
+    let text = match json {
+        Some(text) => text,
+        None => read_stdin()?,
+    };
+    let message: Value =
+        serde_json::from_str(&text).map_err(|e| format!("JSON 格式错误: {}", e))?;
+    let frame = match protocol.to_lowercase().as_str() {
+        "csg" | "csg13" => build_csg13(&message)?,
+        "645" | "dlt645" => build_frame("DLT645-2007", &message).await?,
+        "modbus" => build_frame("modbus", &message).await?,
+        _ => build_frame(protocol, &message).await?,
+    };
+    println!("{}", FrameFun::get_data_str_with_space(&frame));
+    Ok(())
+}
+
+// 南网13报文：{"address": "030201060504", "afn": "0C", "data": "..."}，
+// 地址按界面显示的 A1、A2 顺序书写，control 默认 4A，msa 默认 0，seq 默认单帧
+fn build_csg13(message: &Value) -> Result<Vec<u8>, String> {
+    let address = message["address"]
+        .as_str()
+        .ok_or("缺少 address 字段，例如 \"030201060504\"")?;
+    let address = parse_hex(address)?;
+    if address.len() != 6 {
+        return Err(format!("地址应为 6 字节，实际 {} 字节", address.len()));
+    }
+    let mut adress: Vec<u8> = address[..3].iter().rev().copied().collect();
+    adress.extend(address[3..].iter().rev());
+
+    let ctrl = hex_byte(message, "control")?.unwrap_or(0x4A);
+    let afn = hex_byte(message, "afn")?.ok_or("缺少 afn 字段")?;
+    let seq = match hex_byte(message, "seq")? {
+        Some(seq) => seq,
+        None => FrameCsg::get_frame_seq(0, 1, 1, 0),
+    };
+    let msa = message["msa"].as_u64().unwrap_or(0) as u8;
+    let data = match message["data"].as_str() {
+        Some(data) => parse_hex(data)?,
+        None => Vec::new(),
+    };
+    Ok(FrameCsg::build_frame(ctrl, &adress, msa, afn, seq, &data))
+}
+
+// 读取十六进制字符串或数字形式的单字节字段
+fn hex_byte(message: &Value, key: &str) -> Result<Option<u8>, String> {
+    match &message[key] {
+        Value::Null => Ok(None),
+        Value::Number(n) => n
+            .as_u64()
+            .filter(|n| *n <= 0xFF)
+            .map(|n| Some(n as u8))
+            .ok_or_else(|| format!("{} 超出单字节范围", key)),
+        Value::String(text) => u8::from_str_radix(text.trim(), 16)
+            .map(Some)
+            .map_err(|_| format!("{} 不是合法的十六进制字节: {}", key, text)),
+        other => Err(format!("{} 字段格式错误: {}", key, other)),
+    }
+}
+
+fn repair(hex: Option<String>, protocol: Option<&str>, json: bool) -> Result<(), String> {
+    let text = match hex {
+        Some(text) => text,
+        None => read_stdin()?,
+    };
+    let protocol = match protocol {
+        Some(protocol) => serde_json::from_value::<FixupProtocol>(json!(protocol))
+            .map_err(|_| format!("不支持的协议: {}", protocol))?,
+        None => FixupProtocol::Auto,
+    };
+    let repaired = repair_frame(&parse_hex(&text)?, protocol).map_err(|e| e.to_string())?;
+    if json {
+        print_json(&json!(repaired));
+        return Ok(());
+    }
+    println!("{}", repaired.frame);
+    if repaired.changes.is_empty() {
+        println!("  长度和校验正确，无需修改");
+    }
+    for change in &repaired.changes {
+        println!(
+            "  偏移 {} {}: {} -> {}",
+            change.offset, change.field, change.old, change.new
+        );
+    }
+    Ok(())
+}
+
+async fn channel(args: ChannelArgs) -> Result<(), String> {
+    let mut params = match &args.params {
+        Some(text) => {
+            serde_json::from_str(text).map_err(|e| format!("--params 不是合法的 JSON: {}", e))?
+        }
+        None => json!({}),
+    };
+    let fields = params.as_object_mut().ok_or("--params 必须是 JSON 对象")?;
+    for item in &args.param {
+        let (key, value) = item
+            .split_once('=')
+            .ok_or_else(|| format!("参数格式应为 KEY=VALUE: {}", item))?;
+        // 能按 JSON 解析的值（数字、布尔等）保留类型，其余按字符串处理
+        let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+        fields.insert(key.to_string(), value);
+    }
+    if args.kind.eq_ignore_ascii_case("serial") {
+        // 界面总会给出完整串口参数，命令行只要求端口和波特率
+        for (key, value) in [
+            ("databit", json!(8)),
+            ("flowctrl", json!(0)),
+            ("parity", json!("无校验")),
+            ("stopbit", json!(1)),
+        ] {
+            fields.entry(key).or_insert(value);
+        }
+    }
+    let frames = args
+        .send
+        .iter()
+        .map(|text| parse_hex(text))
+        .collect::<Result<Vec<_>, _>>()?;
+    let vars = parse_vars(&args.vars)?;
+    let mut templates = Vec::new();
+    for name in &args.template {
+        let template = FrameTemplate::load(name).ok_or(format!("模板不存在: {}", name))?;
+        template
+            .preview(&vars)
+            .map_err(|e| format!("模板 {} 展开失败: {}", name, e))?;
+        templates.push(template);
+    }
+
+    let (channel_type, options) = channel_from_params(&args.kind, &params)?;
+    // 在打开通道前订阅，避免漏掉最早收到的数据
+    let mut records = subscribe_records();
+    let mut manager = CommunicationManager::new();
+    let channel_id = manager
+        .add_channel_with_options(channel_type.clone(), options)
+        .await
+        .map_err(|e| format!("打开通道失败: {}", e))?;
+    let channel = manager
+        .get_channel(&channel_type)
+        .ok_or("打开通道失败: 通道不存在")?;
+    eprintln!("通道已打开: {}", channel_id);
+
+    let sender = (!frames.is_empty() || !templates.is_empty()).then(|| {
+        let channel = channel.clone();
+        let clientid = args.clientid.clone();
+        let period = args.interval.map(Duration::from_millis);
+        let rounds = args
+            .count
+            .unwrap_or(if period.is_some() { u64::MAX } else { 1 });
+        tokio::spawn(async move {
+            for round in 0..rounds {
+                if round > 0 {
+                    if let Some(period) = period {
+                        sleep(period).await;
+                    }
+                }
+                let expanded = templates.iter().map(|template| template.expand(&vars));
+                for frame in frames.iter().cloned().map(Ok).chain(expanded) {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            eprintln!("模板展开失败: {}", e);
+                            continue;
+                        }
+                    };
+                    let message = Message::new(json!({ "data": frame }));
+                    if let Err(e) = channel.send(&message, clientid.clone()).await {
+                        eprintln!("发送失败: {}", e);
+                    }
+                }
+            }
+        })
+    });
+
+    let deadline = args
+        .duration
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut clients: HashSet<String> = HashSet::new();
+    // 按数据来源和方向分帧，记录最近一次收到数据的时间
+    let mut streams: HashMap<(String, bool), (FrameStream, Instant, DateTime<Utc>)> =
+        HashMap::new();
+    let mut flush_tick = interval(FLUSH_DELAY);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = async {
+                match deadline {
+                    Some(deadline) => sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            } => break,
+            _ = flush_tick.tick() => {
+                for ((source, sent), (stream, last, time)) in streams.iter_mut() {
+                    if stream.pending_len() > 0 && last.elapsed() >= FLUSH_DELAY {
+                        let rest = stream.take_remaining();
+                        print_live(&args.output, *time, *sent, source, &rest);
+                    }
+                }
+            }
+            record = records.recv() => {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(RecvError::Lagged(n)) => {
+                        eprintln!("输出过慢，丢失 {} 条记录", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let source = record.channel_id().to_string();
+                if source != channel_id && !clients.contains(&source) {
+                    clients = channel.client_ids().await.into_iter().collect();
+                    if !clients.contains(&source) {
+                        continue;
+                    }
+                }
+                let sent = matches!(record.direction(), MessageDirection::Sent);
+                let (stream, last, time) = streams
+                    .entry((source.clone(), sent))
+                    .or_insert_with(|| (FrameStream::new(), Instant::now(), record.timestamp()));
+                if stream.pending_len() == 0 {
+                    *time = record.timestamp();
+                }
+                *last = Instant::now();
+                stream.push(&record.payload_bytes());
+                while let Some((skipped, frame)) = stream.next_frame() {
+                    if !skipped.is_empty() {
+                        print_live(&args.output, *time, sent, &source, &skipped);
+                    }
+                    print_live(&args.output, *time, sent, &source, &frame);
+                    *time = record.timestamp();
+                }
+            }
+        }
+    }
+
+    if let Some(sender) = sender {
+        sender.abort();
+    }
+    manager
+        .close(&channel_type)
+        .await
+        .map_err(|e| format!("关闭通道失败: {}", e))?;
+    eprintln!("通道已关闭: {}", channel_id);
+    Ok(())
+}
+
+fn template(name: Option<&str>, vars: &[String]) -> Result<(), String> {
+    let Some(name) = name else {
+        for template in FrameTemplate::load_all() {
+            println!(
+                "{}\t{}\t{}",
+                template.name, template.text, template.description
+            );
+        }
+        return Ok(());
+    };
+    let template = FrameTemplate::load(name).ok_or(format!("模板不存在: {}", name))?;
+    let frame = template
+        .expand(&parse_vars(vars)?)
+        .map_err(|e| format!("模板展开失败: {}", e))?;
+    println!("{}", FrameFun::get_data_str_with_space(&frame));
+    Ok(())
+}
+
+// 解析 KEY=VALUE 形式的模板变量
+fn parse_vars(items: &[String]) -> Result<HashMap<String, String>, String> {
+    items
+        .iter()
+        .map(|item| {
+            item.split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| format!("变量格式应为 KEY=VALUE: {}", item))
+        })
+        .collect()
+}
+
+async fn scenario(file: &str, json: bool) -> Result<(), String> {
+    let scenario = Scenario::load(file).map_err(|e| e.to_string())?;
+    let report = run_scenario(scenario, |step| {
+        if json {
+            eprintln!("{}", step.to_text());
+        } else {
+            println!("{}", step.to_text());
+        }
+    })
+    .await;
+    if json {
+        print_json(&serde_json::to_value(&report).map_err(|e| e.to_string())?);
+    } else {
+        println!("{}", report.summary());
+    }
+    if report.passed {
+        Ok(())
+    } else {
+        Err(format!("场景 {} 未通过", report.name))
+    }
+}
+
+fn read_stdin() -> Result<String, String> {
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| format!("读取标准输入失败: {}", e))?;
+    Ok(text)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let data =
+        FrameFun::decode_hex_str(text).map_err(|e| format!("报文不是合法的十六进制: {}", e))?;
+    if data.is_empty() {
+        return Err("报文为空".to_string());
+    }
+    Ok(data)
+}
+
+fn print_json(value: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+fn direction_text(sent: bool) -> &'static str {
+    if sent {
+        "发送"
+    } else {
+        "接收"
+    }
+}
+
+fn print_header(time: DateTime<Utc>, sent: bool, source: &str) {
+    println!(
+        "[{}] {} {}",
+        time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S%.3f"),
+        direction_text(sent),
+        source
+    );
+}
+
+fn frame_json(
+    time: DateTime<Utc>,
+    sent: bool,
+    source: &str,
+    frame: &[u8],
+    protocol: &str,
+    items: Vec<Value>,
+) -> Value {
+    json!({
+        "time": time.with_timezone(&Local).to_rfc3339(),
+        "direction": direction_text(sent),
+        "channel": source,
+        "frame": FrameFun::get_data_str_with_space(frame),
+        "protocol": protocol,
+        "result": items,
+    })
+}
+
+// 实时监听时 JSON 每帧一行，便于管道处理
+fn print_live(output: &OutputArgs, time: DateTime<Utc>, sent: bool, source: &str, frame: &[u8]) {
+    let (protocol, items, _) = FrameAnalisyic::process_frame_safe(frame, &output.region);
+    if output.json {
+        println!(
+            "{}",
+            frame_json(time, sent, source, frame, &protocol, items)
+        );
+    } else {
+        print_header(time, sent, source);
+        print_frame(frame, &protocol, &items);
+    }
+}
+
+fn print_frame(frame: &[u8], protocol: &str, items: &[Value]) {
+    println!("{}", FrameFun::get_data_str_with_space(frame));
+    if items.is_empty() {
+        println!("  ({} 无法解析)", protocol);
+    } else {
+        println!("  协议: {}", protocol);
+        print_items(items, 1);
+    }
+    println!();
+}
+
+fn print_items(items: &[Value], depth: usize) {
+    for item in items {
+        let indent = "  ".repeat(depth);
+        let domain = item["frameDomain"].as_str().unwrap_or_default();
+        let data = item["data"].as_str().unwrap_or_default();
+        let description = item["description"].as_str().unwrap_or_default();
+        if description.is_empty() || description == data {
+            println!("{}{}: {}", indent, domain, data);
+        } else {
+            println!("{}{}: {}  {}", indent, domain, data, description);
+        }
+        if let Some(children) = item["children"].as_array() {
+            print_items(children, depth + 1);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+pub mod pcap;
+pub mod pcapng_writer;
+pub mod tcp_stream;
+
+pub use pcap::{CapturedPacket, PcapReader};
+pub use pcapng_writer::{PacketDirection, PcapngWriter, SyntheticTcpStream};
+pub use tcp_stream::{StreamDirection, TcpReassembler};
+
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::FrameStream;
+use crate::basefunc::protocol::FrameAnalisyic;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+/// 抓包导入的过滤条件，IP 与端口同时给出时需匹配同一端点
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaptureFilter {
+    pub ip: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl CaptureFilter {
+    fn matches_endpoint(&self, ip: Option<IpAddr>, addr: &SocketAddr) -> bool {
+        ip.is_none_or(|ip| addr.ip() == ip) && self.port.is_none_or(|p| addr.port() == p)
+    }
+
+    fn matches(&self, ip: Option<IpAddr>, src: &SocketAddr, dst: &SocketAddr) -> bool {
+        self.matches_endpoint(ip, src) || self.matches_endpoint(ip, dst)
+    }
+
+    // 解析过滤条件中的 IP，未填写时不按 IP 过滤
+    fn parse_ip(&self) -> Result<Option<IpAddr>, Box<dyn Error + Send + Sync>> {
+        match self.ip.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(ip) => ip
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("过滤条件中的 IP 地址无效: {}", ip).into()),
+        }
+    }
+}
+
+/// 时间线上的一帧报文
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureFrame {
+    pub index: usize,
+    /// 帧首字节的捕获时间（毫秒时间戳）
+    pub timestamp: i64,
+    pub timestamp_us: i64,
+    pub src: String,
+    pub dst: String,
+    pub direction: StreamDirection,
+    pub frame: String,
+    pub protocol: String,
+    pub data: Vec<Value>,
+    pub error: Option<String>,
+}
+
+// 单方向流的分帧状态，记录每段数据的起始偏移以还原帧首字节的时间
+#[derive(Default)]
+struct FramedStream {
+    stream: FrameStream,
+    segments: Vec<(u64, i64)>,
+    pushed: u64,
+    consumed: u64,
+}
+
+impl FramedStream {
+    fn push(&mut self, timestamp_us: i64, data: &[u8]) {
+        self.segments.push((self.pushed, timestamp_us));
+        self.pushed += data.len() as u64;
+        self.stream.push(data);
+    }
+
+    fn timestamp_at(&self, offset: u64) -> i64 {
+        self.segments
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+            .map(|(_, ts)| *ts)
+            .unwrap_or_default()
+    }
+
+    // 取出所有完整帧，返回 (时间戳, 数据, 是否为无法识别的数据)
+    fn drain(&mut self, flush: bool) -> Vec<(i64, Vec<u8>, bool)> {
+        let mut out = Vec::new();
+        while let Some((skipped, frame)) = self.stream.next_frame() {
+            if !skipped.is_empty() {
+                let len = skipped.len() as u64;
+                out.push((self.timestamp_at(self.consumed), skipped, true));
+                self.consumed += len;
+            }
+            let len = frame.len() as u64;
+            out.push((self.timestamp_at(self.consumed), frame, false));
+            self.consumed += len;
+        }
+        if flush {
+            let rest = self.stream.take_remaining();
+            if !rest.is_empty() {
+                let len = rest.len() as u64;
+                out.push((self.timestamp_at(self.consumed), rest, true));
+                self.consumed += len;
+            }
+        }
+        // 只保留仍可能被引用的分段
+        let consumed = self.consumed;
+        if let Some(keep_from) = self
+            .segments
+            .iter()
+            .rposition(|(start, _)| *start <= consumed)
+        {
+            self.segments.drain(..keep_from);
+        }
+        out
+    }
+}
+
+pub struct CaptureImporter;
+
+impl CaptureImporter {
+    /// 读取抓包文件，重组 TCP 流、分帧并逐帧解析
+    pub fn import_file(
+        path: &str,
+        region: &str,
+        filter: &CaptureFilter,
+    ) -> Result<Vec<CaptureFrame>, Box<dyn Error + Send + Sync>> {
+        let content = std::fs::read(path)?;
+        Self::import_bytes(&content, region, filter)
+    }
+
+    pub fn import_bytes(
+        content: &[u8],
+        region: &str,
+        filter: &CaptureFilter,
+    ) -> Result<Vec<CaptureFrame>, Box<dyn Error + Send + Sync>> {
+        let filter_ip = filter.parse_ip()?;
+        let packets = PcapReader::read(content)?;
+        let mut reassembler = TcpReassembler::new();
+        let mut streams = HashMap::new();
+        let mut frames = Vec::new();
+
+        for packet in packets {
+            let Some(segment) = tcp_stream::decode_tcp_segment(packet.linktype, &packet.data)
+            else {
+                continue;
+            };
+            if !filter.matches(filter_ip, &segment.src, &segment.dst) {
+                continue;
+            }
+            // 四元组被新连接复用，旧连接中未成帧的数据原样输出，不与新连接的数据拼接
+            if segment.is_syn() && !segment.is_syn_ack() {
+                for key in [(segment.src, segment.dst), (segment.dst, segment.src)] {
+                    if let Some((direction, stream)) = streams.remove(&key) {
+                        Self::finish_stream(key, direction, stream, &mut frames, region);
+                    }
+                }
+            }
+            let chunks = reassembler.push(packet.timestamp_us, segment);
+            Self::handle_chunks(chunks, &mut streams, &mut frames, region);
+        }
+        let chunks = reassembler.flush();
+        Self::handle_chunks(chunks, &mut streams, &mut frames, region);
+
+        // 抓包结束，输出各流中剩余的不完整数据
+        for (key, (direction, stream)) in streams {
+            Self::finish_stream(key, direction, stream, &mut frames, region);
+        }
+
+        frames.sort_by_key(|f| f.timestamp_us);
+        for (index, frame) in frames.iter_mut().enumerate() {
+            frame.index = index;
+        }
+        Ok(frames)
+    }
+
+    fn handle_chunks(
+        chunks: Vec<tcp_stream::StreamChunk>,
+        streams: &mut HashMap<(SocketAddr, SocketAddr), (StreamDirection, FramedStream)>,
+        frames: &mut Vec<CaptureFrame>,
+        region: &str,
+    ) {
+        for chunk in chunks {
+            let (direction, stream) = streams
+                .entry((chunk.src, chunk.dst))
+                .or_insert_with(|| (chunk.direction, FramedStream::default()));
+            stream.push(chunk.timestamp_us, &chunk.data);
+            for (ts, data, unknown) in stream.drain(false) {
+                frames.push(Self::build_frame(
+                    ts, chunk.src, chunk.dst, *direction, &data, unknown, region,
+                ));
+            }
+        }
+    }
+
+    // 流结束，输出其中剩余的数据
+    fn finish_stream(
+        (src, dst): (SocketAddr, SocketAddr),
+        direction: StreamDirection,
+        mut stream: FramedStream,
+        frames: &mut Vec<CaptureFrame>,
+        region: &str,
+    ) {
+        for (ts, data, unknown) in stream.drain(true) {
+            frames.push(Self::build_frame(
+                ts, src, dst, direction, &data, unknown, region,
+            ));
+        }
+    }
+
+    fn build_frame(
+        timestamp_us: i64,
+        src: SocketAddr,
+        dst: SocketAddr,
+        direction: StreamDirection,
+        data: &[u8],
+        unknown: bool,
+        region: &str,
+    ) -> CaptureFrame {
+        let mut frame = CaptureFrame {
+            index: 0,
+            timestamp: timestamp_us / 1000,
+            timestamp_us,
+            src: src.to_string(),
+            dst: dst.to_string(),
+            direction,
+            frame: FrameFun::get_data_str_with_space(data),
+            protocol: "Unknown".to_string(),
+            data: Vec::new(),
+            error: None,
+        };
+        if unknown {
+            frame.error = Some("无法识别的数据".to_string());
+            return frame;
+        }
+
+        let (protocol, result, error) = FrameAnalisyic::process_frame_safe(data, region);
+        frame.protocol = protocol;
+        frame.data = result;
+        frame.error = error;
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN: u8 = 0x02;
+    const SYN_ACK: u8 = 0x12;
+    const PSH_ACK: u8 = 0x18;
+
+    // 表地址 000000000001 读数据的请求与应答
+    const REQUEST: &str = "68 01 00 00 00 00 00 68 11 04 33 33 34 33 B3 16";
+    const REPLY: &str = "68 01 00 00 00 00 00 68 91 08 33 33 34 33 45 67 89 33 04 16";
+
+    fn hex(text: &str) -> Vec<u8> {
+        FrameFun::get_frame_list_from_str(text)
+    }
+
+    // 原始 IPv4 上的 TCP 段，端点为 (IP 末字节, 端口)
+    fn packet(src: (u8, u16), dst: (u8, u16), seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0];
+        ip.extend_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, src.0, 10, 0, 0, dst.0]);
+        ip.extend_from_slice(&src.1.to_be_bytes());
+        ip.extend_from_slice(&dst.1.to_be_bytes());
+        ip.extend_from_slice(&seq.to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0, 0, 5 << 4, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        ip.extend_from_slice(payload);
+        ip
+    }
+
+    // LINKTYPE_RAW 的 pcap 文件，数据包为 (微秒时间戳, IP 包)
+    fn pcap(packets: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = 0xA1B2_C3D4u32.to_le_bytes().to_vec();
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&101u32.to_le_bytes());
+        for (ts, data) in packets {
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(&ts.to_le_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn summary(frames: &[CaptureFrame]) -> Vec<(i64, StreamDirection, String, bool)> {
+        frames
+            .iter()
+            .map(|f| {
+                (
+                    f.timestamp_us,
+                    f.direction,
+                    f.frame.clone(),
+                    f.error.is_none(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imports_frames_split_across_segments() {
+        let client = (2, 50000);
+        let server = (1, 2404);
+        let request = hex(REQUEST);
+        let reply = hex(REPLY);
+        let file = pcap(&[
+            (10, packet(client, server, 100, SYN, &[])),
+            (20, packet(server, client, 500, SYN_ACK, &[])),
+            (30, packet(client, server, 101, PSH_ACK, &request[..5])),
+            (40, packet(client, server, 106, PSH_ACK, &request[5..])),
+            // 应答前有一个字节的干扰数据
+            (50, packet(server, client, 501, PSH_ACK, &[0xFF])),
+            (60, packet(server, client, 502, PSH_ACK, &reply)),
+        ]);
+
+        let frames =
+            CaptureImporter::import_bytes(&file, "南网", &CaptureFilter::default()).unwrap();
+        assert_eq!(
+            summary(&frames),
+            [
+                (
+                    30,
+                    StreamDirection::ClientToServer,
+                    REQUEST.to_string(),
+                    true
+                ),
+                (50, StreamDirection::ServerToClient, "FF".to_string(), false),
+                (60, StreamDirection::ServerToClient, REPLY.to_string(), true),
+            ]
+        );
+        assert_eq!(frames[0].protocol, "DLT/645-2007");
+        assert_eq!(
+            (frames[0].src.as_str(), frames[0].dst.as_str()),
+            ("10.0.0.2:50000", "10.0.0.1:2404")
+        );
+        assert!(!frames[2].data.is_empty());
+        assert_eq!(
+            frames.iter().map(|f| f.index).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn filters_by_endpoint() {
+        let request = hex(REQUEST);
+        let file = pcap(&[
+            (10, packet((2, 50000), (1, 2404), 1, PSH_ACK, &request)),
+            (20, packet((3, 50001), (1, 502), 1, PSH_ACK, &request)),
+        ]);
+        let filter = |ip: Option<&str>, port: Option<u16>| CaptureFilter {
+            ip: ip.map(str::to_string),
+            port,
+        };
+        let import = |filter: CaptureFilter| {
+            CaptureImporter::import_bytes(&file, "南网", &filter)
+                .unwrap()
+                .iter()
+                .map(|f| f.timestamp_us)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(import(filter(None, Some(502))), [20]);
+        assert_eq!(import(filter(Some("10.0.0.2"), None)), [10]);
+        assert_eq!(import(filter(Some(" "), Some(2404))), [10]);
+        // IP 与端口须匹配同一端点
+        assert!(import(filter(Some("10.0.0.2"), Some(502))).is_empty());
+        assert!(
+            CaptureImporter::import_bytes(&file, "南网", &filter(Some("10.0.0"), None)).is_err()
+        );
+    }
+
+    #[test]
+    fn new_syn_on_reused_tuple_resets_framing() {
+        let client = (2, 50000);
+        let server = (1, 2404);
+        let request = hex(REQUEST);
+        // 旧连接只发出帧的前半部分，新连接复用同一四元组发出后半部分
+        let file = pcap(&[
+            (10, packet(client, server, 100, SYN, &[])),
+            (20, packet(client, server, 101, PSH_ACK, &request[..8])),
+            (30, packet(client, server, 900, SYN, &[])),
+            (40, packet(client, server, 901, PSH_ACK, &request[8..])),
+            (50, packet(client, server, 909, PSH_ACK, &request)),
+        ]);
+
+        let frames =
+            CaptureImporter::import_bytes(&file, "南网", &CaptureFilter::default()).unwrap();
+        let summary = summary(&frames);
+        assert_eq!(
+            summary[0],
+            (
+                20,
+                StreamDirection::ClientToServer,
+                FrameFun::get_data_str_with_space(&request[..8]),
+                false
+            )
+        );
+        assert!(summary[1..].iter().all(|f| f.2 != REQUEST || f.0 == 50));
+        assert_eq!(
+            summary.last().unwrap(),
+            &(
+                50,
+                StreamDirection::ClientToServer,
+                REQUEST.to_string(),
+                true
+            )
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::error::Error;
+
+// pcap 文件魔数（微秒/纳秒精度）
+const PCAP_MAGIC_US: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NS: u32 = 0xA1B2_3C4D;
+
+// pcapng 块类型
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_PB: u32 = 0x0000_0002;
+const PCAPNG_SPB: u32 = 0x0000_0003;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+// IDB 选项
+const OPT_END: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+
+/// 从抓包文件中读取的一个数据包
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    /// 捕获时间（UTC 微秒时间戳）
+    pub timestamp_us: i64,
+    /// 链路层类型（LINKTYPE_*）
+    pub linktype: u32,
+    /// 链路层数据
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Endian {
+    little: bool,
+}
+
+impl Endian {
+    fn u16(&self, b: &[u8]) -> u16 {
+        let v = [b[0], b[1]];
+        if self.little {
+            u16::from_le_bytes(v)
+        } else {
+            u16::from_be_bytes(v)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let v = [b[0], b[1], b[2], b[3]];
+        if self.little {
+            u32::from_le_bytes(v)
+        } else {
+            u32::from_be_bytes(v)
+        }
+    }
+}
+
+// pcapng 接口描述信息
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    linktype: u32,
+    // 每秒的时间戳单位数
+    units_per_sec: u64,
+}
+
+pub struct PcapReader;
+
+impl PcapReader {
+    /// 读取 pcap 或 pcapng 文件内容，自动识别格式
+    pub fn read(content: &[u8]) -> Result<Vec<CapturedPacket>, Box<dyn Error + Send + Sync>> {
+        if content.len() < 4 {
+            return Err("文件过短，不是有效的抓包文件".into());
+        }
+        let magic_le = u32::from_le_bytes([content[0], content[1], content[2], content[3]]);
+        let magic_be = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+        if magic_le == PCAPNG_SHB {
+            Self::read_pcapng(content)
+        } else if magic_le == PCAP_MAGIC_US || magic_le == PCAP_MAGIC_NS {
+            Self::read_pcap(content, Endian { little: true }, magic_le == PCAP_MAGIC_NS)
+        } else if magic_be == PCAP_MAGIC_US || magic_be == PCAP_MAGIC_NS {
+            Self::read_pcap(content, Endian { little: false }, magic_be == PCAP_MAGIC_NS)
+        } else {
+            Err(format!("无法识别的抓包文件格式，魔数: {:08X}", magic_be).into())
+        }
+    }
+
+    fn read_pcap(
+        content: &[u8],
+        endian: Endian,
+        nanosecond: bool,
+    ) -> Result<Vec<CapturedPacket>, Box<dyn Error + Send + Sync>> {
+        if content.len() < 24 {
+            return Err("pcap 文件头不完整".into());
+        }
+        let linktype = endian.u32(&content[20..24]);
+        let mut packets = Vec::new();
+        let mut pos = 24;
+
+        while pos + 16 <= content.len() {
+            let ts_sec = endian.u32(&content[pos..pos + 4]) as i64;
+            let ts_frac = endian.u32(&content[pos + 4..pos + 8]) as i64;
+            let incl_len = endian.u32(&content[pos + 8..pos + 12]) as usize;
+            pos += 16;
+            if pos + incl_len > content.len() {
+                // 文件被截断，丢弃最后一个不完整的包
+                break;
+            }
+            let frac_us = if nanosecond { ts_frac / 1000 } else { ts_frac };
+            packets.push(CapturedPacket {
+                timestamp_us: ts_sec * 1_000_000 + frac_us,
+                linktype,
+                data: content[pos..pos + incl_len].to_vec(),
+            });
+            pos += incl_len;
+        }
+
+        Ok(packets)
+    }
+
+    fn read_pcapng(content: &[u8]) -> Result<Vec<CapturedPacket>, Box<dyn Error + Send + Sync>> {
+        let mut packets = Vec::new();
+        let mut interfaces: Vec<Interface> = Vec::new();
+        let mut endian = Endian { little: true };
+        let mut pos = 0;
+
+        while pos + 12 <= content.len() {
+            // 块类型的数值与字节序无关（SHB 为回文）
+            let block_type = endian.u32(&content[pos..pos + 4]);
+            if block_type == PCAPNG_SHB {
+                let bom = &content[pos + 8..pos + 12];
+                endian = if u32::from_le_bytes([bom[0], bom[1], bom[2], bom[3]])
+                    == PCAPNG_BYTE_ORDER_MAGIC
+                {
+                    Endian { little: true }
+                } else if u32::from_be_bytes([bom[0], bom[1], bom[2], bom[3]])
+                    == PCAPNG_BYTE_ORDER_MAGIC
+                {
+                    Endian { little: false }
+                } else {
+                    return Err("pcapng 字节序标识无效".into());
+                };
+                // 每个节重新定义接口列表
+                interfaces.clear();
+            }
+
+            let block_len = endian.u32(&content[pos + 4..pos + 8]) as usize;
+            if block_len < 12 || !block_len.is_multiple_of(4) || pos + block_len > content.len() {
+                break;
+            }
+            let body = &content[pos + 8..pos + block_len - 4];
+
+            match block_type {
+                PCAPNG_IDB if body.len() >= 8 => {
+                    let linktype = endian.u16(&body[0..2]) as u32;
+                    let units_per_sec = Self::read_tsresol(&body[8..], endian);
+                    interfaces.push(Interface {
+                        linktype,
+                        units_per_sec,
+                    });
+                }
+                PCAPNG_EPB if body.len() >= 20 => {
+                    let if_id = endian.u32(&body[0..4]) as usize;
+                    let ts =
+                        ((endian.u32(&body[4..8]) as u64) << 32) | endian.u32(&body[8..12]) as u64;
+                    let cap_len = endian.u32(&body[12..16]) as usize;
+                    if let (Some(iface), Some(data)) =
+                        (interfaces.get(if_id), body.get(20..20 + cap_len))
+                    {
+                        packets.push(CapturedPacket {
+                            timestamp_us: Self::to_micros(ts, iface.units_per_sec),
+                            linktype: iface.linktype,
+                            data: data.to_vec(),
+                        });
+                    }
+                }
+                PCAPNG_PB if body.len() >= 20 => {
+                    let if_id = endian.u16(&body[0..2]) as usize;
+                    let ts =
+                        ((endian.u32(&body[4..8]) as u64) << 32) | endian.u32(&body[8..12]) as u64;
+                    let cap_len = endian.u32(&body[12..16]) as usize;
+                    if let (Some(iface), Some(data)) =
+                        (interfaces.get(if_id), body.get(20..20 + cap_len))
+                    {
+                        packets.push(CapturedPacket {
+                            timestamp_us: Self::to_micros(ts, iface.units_per_sec),
+                            linktype: iface.linktype,
+                            data: data.to_vec(),
+                        });
+                    }
+                }
+                PCAPNG_SPB if body.len() >= 4 => {
+                    // 简单包块没有时间戳，捕获长度受快照长度限制
+                    let orig_len = endian.u32(&body[0..4]) as usize;
+                    let cap_len = orig_len.min(body.len() - 4);
+                    if let Some(iface) = interfaces.first() {
+                        packets.push(CapturedPacket {
+                            timestamp_us: 0,
+                            linktype: iface.linktype,
+                            data: body[4..4 + cap_len].to_vec(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+
+            pos += block_len;
+        }
+
+        Ok(packets)
+    }
+
+    // 解析 IDB 选项中的 if_tsresol，缺省为微秒
+    fn read_tsresol(options: &[u8], endian: Endian) -> u64 {
+        let mut pos = 0;
+        while pos + 4 <= options.len() {
+            let code = endian.u16(&options[pos..pos + 2]);
+            let len = endian.u16(&options[pos + 2..pos + 4]) as usize;
+            if code == OPT_END {
+                break;
+            }
+            if code == OPT_IF_TSRESOL && len >= 1 && pos + 4 < options.len() {
+                let v = options[pos + 4];
+                let exp = (v & 0x7F) as u32;
+                return if v & 0x80 == 0 {
+                    10u64.checked_pow(exp).unwrap_or(1_000_000)
+                } else {
+                    2u64.checked_pow(exp).unwrap_or(1_000_000)
+                };
+            }
+            pos += 4 + ((len + 3) & !3);
+        }
+        1_000_000
+    }
+
+    fn to_micros(ts: u64, units_per_sec: u64) -> i64 {
+        if units_per_sec == 1_000_000 {
+            ts as i64
+        } else {
+            ((ts as u128) * 1_000_000 / units_per_sec.max(1) as u128) as i64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::PcapngWriter;
+
+    // pcap 文件，数据包为 (秒, 秒内小数部分, 数据)
+    fn pcap(little: bool, magic: u32, packets: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let word = |v: u32| {
+            if little {
+                v.to_le_bytes()
+            } else {
+                v.to_be_bytes()
+            }
+        };
+        let mut file = word(magic).to_vec();
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&word(101));
+        for (sec, frac, data) in packets {
+            file.extend_from_slice(&word(*sec));
+            file.extend_from_slice(&word(*frac));
+            file.extend_from_slice(&word(data.len() as u32));
+            file.extend_from_slice(&word(data.len() as u32));
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let len = (body.len() + 12) as u32;
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&len.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&len.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn reads_pcap_in_both_byte_orders() {
+        let packets: [(u32, u32, &[u8]); 2] = [(1, 500, &[1, 2, 3]), (2, 0, &[4])];
+        for little in [true, false] {
+            let read = PcapReader::read(&pcap(little, PCAP_MAGIC_US, &packets)).unwrap();
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[0].timestamp_us, 1_000_500);
+            assert_eq!(read[0].linktype, 101);
+            assert_eq!(read[0].data, [1, 2, 3]);
+            assert_eq!(read[1].data, [4]);
+        }
+        // 纳秒精度的时间戳换算为微秒
+        let read = PcapReader::read(&pcap(true, PCAP_MAGIC_NS, &[(1, 1_500_999, &[0])])).unwrap();
+        assert_eq!(read[0].timestamp_us, 1_001_500);
+    }
+
+    #[test]
+    fn drops_truncated_packet() {
+        let mut file = pcap(true, PCAP_MAGIC_US, &[(1, 0, &[1, 2]), (2, 0, &[3, 4, 5])]);
+        file.truncate(file.len() - 1);
+        let read = PcapReader::read(&file).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].data, [1, 2]);
+    }
+
+    #[test]
+    fn reads_pcapng_interfaces() {
+        let mut writer = PcapngWriter::new(Vec::new(), "test").unwrap();
+        let raw = writer.add_interface(101, "raw", "").unwrap();
+        let user = writer.add_interface(147, "serial", "串口").unwrap();
+        writer
+            .write_packet(user, 2_000_001, &[0x68, 0x16], None, Some("comment"))
+            .unwrap();
+        writer
+            .write_packet(raw, 1_000_000, &[0x45], None, None)
+            .unwrap();
+        let mut file = writer.into_inner();
+
+        // 纳秒精度的接口（if_tsresol = 9）和简单包块
+        let mut idb = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        idb.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        let mut epb = 2u32.to_le_bytes().to_vec();
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&3_000_000_999u32.to_le_bytes());
+        epb.extend_from_slice(&1u32.to_le_bytes());
+        epb.extend_from_slice(&1u32.to_le_bytes());
+        epb.extend_from_slice(&[0xAA, 0, 0, 0]);
+        file.extend(block(PCAPNG_IDB, &idb));
+        file.extend(block(PCAPNG_EPB, &epb));
+        file.extend(block(PCAPNG_SPB, &[2, 0, 0, 0, 0xBB, 0xCC, 0, 0]));
+
+        let read = PcapReader::read(&file).unwrap();
+        let summary: Vec<_> = read
+            .iter()
+            .map(|p| (p.linktype, p.timestamp_us, p.data.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (147, 2_000_001, vec![0x68, 0x16]),
+                (101, 1_000_000, vec![0x45]),
+                (1, 3_000_000, vec![0xAA]),
+                (101, 0, vec![0xBB, 0xCC]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_files() {
+        assert!(PcapReader::read(&[0xA1]).is_err());
+        assert!(PcapReader::read(&[0; 24]).is_err());
+        assert!(PcapReader::read(&PCAP_MAGIC_US.to_le_bytes()).is_err());
+        // pcapng 字节序标识错误
+        let mut shb = 0u32.to_le_bytes().to_vec();
+        shb.extend_from_slice(&[0; 4]);
+        assert!(PcapReader::read(&block(PCAPNG_SHB, &shb)).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+// pcapng 块类型
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+// 选项代码
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// 链路层类型：原始 IPv4/IPv6
+pub const LINKTYPE_RAW: u16 = 101;
+/// 链路层类型：用户自定义 DLT 0~15（147~162）
+pub const LINKTYPE_USER0: u16 = 147;
+
+/// 报文方向，写入 EPB 的 epb_flags 选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Inbound,
+    Outbound,
+}
+
+/// pcapng 文件写入器，时间戳精度为微秒
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    interface_count: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// 创建写入器并写入节头块
+    pub fn new(mut writer: W, application: &str) -> io::Result<Self> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // 节长度未知
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        Self::push_option(&mut body, OPT_SHB_USERAPPL, application.as_bytes());
+        Self::push_option(&mut body, OPT_END, &[]);
+        Self::write_block(&mut writer, PCAPNG_SHB, &body)?;
+        Ok(Self {
+            writer,
+            interface_count: 0,
+        })
+    }
+
+    /// 添加接口描述块，返回接口编号
+    pub fn add_interface(
+        &mut self,
+        linktype: u16,
+        name: &str,
+        description: &str,
+    ) -> io::Result<u32> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&linktype.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        Self::push_option(&mut body, OPT_IF_NAME, name.as_bytes());
+        if !description.is_empty() {
+            Self::push_option(&mut body, OPT_IF_DESCRIPTION, description.as_bytes());
+        }
+        // 10^-6，即微秒
+        Self::push_option(&mut body, OPT_IF_TSRESOL, &[6]);
+        Self::push_option(&mut body, OPT_END, &[]);
+        Self::write_block(&mut self.writer, PCAPNG_IDB, &body)?;
+        self.interface_count += 1;
+        Ok(self.interface_count - 1)
+    }
+
+    /// 写入增强分组块
+    pub fn write_packet(
+        &mut self,
+        interface_id: u32,
+        timestamp_us: i64,
+        data: &[u8],
+        direction: Option<PacketDirection>,
+        comment: Option<&str>,
+    ) -> io::Result<()> {
+        let ts = timestamp_us.max(0) as u64;
+        let mut body = Vec::with_capacity(data.len() + 48);
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        Self::pad(&mut body);
+
+        let mut has_option = false;
+        if let Some(direction) = direction {
+            let flags: u32 = match direction {
+                PacketDirection::Inbound => 1,
+                PacketDirection::Outbound => 2,
+            };
+            Self::push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+            has_option = true;
+        }
+        if let Some(comment) = comment.filter(|c| !c.is_empty()) {
+            Self::push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+            has_option = true;
+        }
+        if has_option {
+            Self::push_option(&mut body, OPT_END, &[]);
+        }
+        Self::write_block(&mut self.writer, PCAPNG_EPB, &body)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+        body.extend_from_slice(&code.to_le_bytes());
+        body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        body.extend_from_slice(value);
+        Self::pad(body);
+    }
+
+    fn pad(body: &mut Vec<u8>) {
+        while !body.len().is_multiple_of(4) {
+            body.push(0);
+        }
+    }
+
+    fn write_block(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let total_len = (body.len() + 12) as u32;
+        writer.write_all(&block_type.to_le_bytes())?;
+        writer.write_all(&total_len.to_le_bytes())?;
+        writer.write_all(body)?;
+        writer.write_all(&total_len.to_le_bytes())
+    }
+}
+
+/// 为没有真实报文头的 TCP 载荷合成 IP/TCP 头，维护双向序号以便 Wireshark 重组
+#[derive(Debug, Clone)]
+pub struct SyntheticTcpStream {
+    local: SocketAddr,
+    remote: SocketAddr,
+    local_seq: u32,
+    remote_seq: u32,
+    ip_id: u16,
+}
+
+impl SyntheticTcpStream {
+    pub fn new(local: SocketAddr, remote: SocketAddr) -> Self {
+        Self {
+            local,
+            remote,
+            local_seq: 1,
+            remote_seq: 1,
+            ip_id: 0,
+        }
+    }
+
+    /// 生成一个携带载荷的 IP 包，`outbound` 为 true 表示本端发往对端
+    pub fn packet(&mut self, outbound: bool, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, seq, ack) = if outbound {
+            (self.local, self.remote, self.local_seq, self.remote_seq)
+        } else {
+            (self.remote, self.local, self.remote_seq, self.local_seq)
+        };
+        if outbound {
+            self.local_seq = self.local_seq.wrapping_add(payload.len() as u32);
+        } else {
+            self.remote_seq = self.remote_seq.wrapping_add(payload.len() as u32);
+        }
+
+        // TCP 头，PSH|ACK
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend_from_slice(&src.port().to_be_bytes());
+        tcp.extend_from_slice(&dst.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.push(5 << 4);
+        tcp.push(0x18);
+        tcp.extend_from_slice(&0xFFFFu16.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        self.ip_id = self.ip_id.wrapping_add(1);
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                let mut pseudo = Vec::with_capacity(12 + tcp.len());
+                pseudo.extend_from_slice(&s.octets());
+                pseudo.extend_from_slice(&d.octets());
+                pseudo.extend_from_slice(&[0, 6]);
+                pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+                pseudo.extend_from_slice(&tcp);
+                let csum = Self::checksum(&pseudo);
+                tcp[16..18].copy_from_slice(&csum.to_be_bytes());
+
+                let mut ip = Vec::with_capacity(20 + tcp.len());
+                ip.push(0x45);
+                ip.push(0);
+                ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+                ip.extend_from_slice(&self.ip_id.to_be_bytes());
+                ip.extend_from_slice(&0x4000u16.to_be_bytes());
+                ip.push(64);
+                ip.push(6);
+                ip.extend_from_slice(&[0, 0]);
+                ip.extend_from_slice(&s.octets());
+                ip.extend_from_slice(&d.octets());
+                let csum = Self::checksum(&ip);
+                ip[10..12].copy_from_slice(&csum.to_be_bytes());
+                ip.extend_from_slice(&tcp);
+                ip
+            }
+            (s, d) => {
+                let s = Self::to_v6(s);
+                let d = Self::to_v6(d);
+                let mut pseudo = Vec::with_capacity(40 + tcp.len());
+                pseudo.extend_from_slice(&s.octets());
+                pseudo.extend_from_slice(&d.octets());
+                pseudo.extend_from_slice(&(tcp.len() as u32).to_be_bytes());
+                pseudo.extend_from_slice(&[0, 0, 0, 6]);
+                pseudo.extend_from_slice(&tcp);
+                let csum = Self::checksum(&pseudo);
+                tcp[16..18].copy_from_slice(&csum.to_be_bytes());
+
+                let mut ip = Vec::with_capacity(40 + tcp.len());
+                ip.extend_from_slice(&[0x60, 0, 0, 0]);
+                ip.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+                ip.push(6);
+                ip.push(64);
+                ip.extend_from_slice(&s.octets());
+                ip.extend_from_slice(&d.octets());
+                ip.extend_from_slice(&tcp);
+                ip
+            }
+        }
+    }
+
+    fn to_v6(ip: IpAddr) -> Ipv6Addr {
+        match ip {
+            IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+            IpAddr::V6(v6) => v6,
+        }
+    }
+
+    fn checksum(data: &[u8]) -> u16 {
+        let mut sum: u32 = 0;
+        for chunk in data.chunks(2) {
+            let word = if chunk.len() == 2 {
+                u16::from_be_bytes([chunk[0], chunk[1]])
+            } else {
+                u16::from_be_bytes([chunk[0], 0])
+            };
+            sum += word as u32;
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
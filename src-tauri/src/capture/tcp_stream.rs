@@ -0,0 +1,563 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// 链路层类型
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IP_PROTO_TCP: u8 = 6;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+/// 解析出的 TCP 段
+#[derive(Debug, Clone)]
+pub struct TcpSegment {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub flags: u8,
+    pub payload: Vec<u8>,
+}
+
+impl TcpSegment {
+    pub fn is_syn(&self) -> bool {
+        self.flags & TCP_SYN != 0
+    }
+
+    pub fn is_syn_ack(&self) -> bool {
+        self.flags & (TCP_SYN | TCP_ACK) == (TCP_SYN | TCP_ACK)
+    }
+}
+
+/// 相对于 TCP 连接发起方的数据方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StreamDirection {
+    /// 连接发起方（客户端）发往服务端
+    ClientToServer,
+    /// 服务端发往连接发起方
+    ServerToClient,
+}
+
+/// 从链路层数据中解析 TCP 段，非 TCP 或分片的 IP 包返回 `None`
+pub fn decode_tcp_segment(linktype: u32, data: &[u8]) -> Option<TcpSegment> {
+    let (ethertype, ip) = match linktype {
+        LINKTYPE_ETHERNET => {
+            if data.len() < 14 {
+                return None;
+            }
+            let mut ethertype = u16::from_be_bytes([data[12], data[13]]);
+            let mut pos = 14;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                if data.len() < pos + 4 {
+                    return None;
+                }
+                ethertype = u16::from_be_bytes([data[pos + 2], data[pos + 3]]);
+                pos += 4;
+            }
+            (ethertype, &data[pos..])
+        }
+        LINKTYPE_NULL | LINKTYPE_LOOP => {
+            if data.len() < 4 {
+                return None;
+            }
+            // AF_INET 在所有平台上都是 2，AF_INET6 因平台而异，直接看 IP 版本号
+            (0, &data[4..])
+        }
+        LINKTYPE_LINUX_SLL => {
+            if data.len() < 16 {
+                return None;
+            }
+            (u16::from_be_bytes([data[14], data[15]]), &data[16..])
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            if data.len() < 20 {
+                return None;
+            }
+            (u16::from_be_bytes([data[0], data[1]]), &data[20..])
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => (0, data),
+        _ => return None,
+    };
+
+    if ip.is_empty() {
+        return None;
+    }
+    match (ethertype, ip[0] >> 4) {
+        (ETHERTYPE_IPV4, _) | (0, 4) => decode_ipv4(ip),
+        (ETHERTYPE_IPV6, _) | (0, 6) => decode_ipv6(ip),
+        _ => None,
+    }
+}
+
+fn decode_ipv4(ip: &[u8]) -> Option<TcpSegment> {
+    if ip.len() < 20 || ip[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = ((ip[0] & 0x0F) as usize) * 4;
+    let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+    let frag = u16::from_be_bytes([ip[6], ip[7]]);
+    // 不处理 IP 分片（MF 置位或偏移非零）
+    if frag & 0x3FFF != 0 || ip[9] != IP_PROTO_TCP || ihl < 20 {
+        return None;
+    }
+    // 以太网最小帧可能带填充，以 IP 总长度为准
+    let end = if total_len >= ihl && total_len <= ip.len() {
+        total_len
+    } else {
+        ip.len()
+    };
+    let src = IpAddr::V4(Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]));
+    let dst = IpAddr::V4(Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]));
+    decode_tcp(src, dst, ip.get(ihl..end)?)
+}
+
+fn decode_ipv6(ip: &[u8]) -> Option<TcpSegment> {
+    if ip.len() < 40 || ip[0] >> 4 != 6 {
+        return None;
+    }
+    let payload_len = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    let mut next_header = ip[6];
+    let mut src = [0u8; 16];
+    let mut dst = [0u8; 16];
+    src.copy_from_slice(&ip[8..24]);
+    dst.copy_from_slice(&ip[24..40]);
+    let end = (40 + payload_len).min(ip.len());
+    let mut pos = 40;
+
+    // 跳过逐跳、路由、目的选项扩展头
+    while matches!(next_header, 0 | 43 | 60) {
+        if pos + 2 > end {
+            return None;
+        }
+        next_header = ip[pos];
+        pos += (ip[pos + 1] as usize + 1) * 8;
+    }
+    if next_header != IP_PROTO_TCP || pos > end {
+        return None;
+    }
+    decode_tcp(
+        IpAddr::V6(Ipv6Addr::from(src)),
+        IpAddr::V6(Ipv6Addr::from(dst)),
+        &ip[pos..end],
+    )
+}
+
+fn decode_tcp(src: IpAddr, dst: IpAddr, tcp: &[u8]) -> Option<TcpSegment> {
+    if tcp.len() < 20 {
+        return None;
+    }
+    let src_port = u16::from_be_bytes([tcp[0], tcp[1]]);
+    let dst_port = u16::from_be_bytes([tcp[2], tcp[3]]);
+    let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+    let data_offset = ((tcp[12] >> 4) as usize) * 4;
+    if data_offset < 20 || data_offset > tcp.len() {
+        return None;
+    }
+    Some(TcpSegment {
+        src: SocketAddr::new(src, src_port),
+        dst: SocketAddr::new(dst, dst_port),
+        seq,
+        flags: tcp[13],
+        payload: tcp[data_offset..].to_vec(),
+    })
+}
+
+/// 单方向重组后的一段连续数据
+#[derive(Debug, Clone)]
+pub struct StreamChunk {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub direction: StreamDirection,
+    pub timestamp_us: i64,
+    pub data: Vec<u8>,
+}
+
+// 单方向的重组状态
+#[derive(Debug, Default)]
+struct HalfStream {
+    // 下一个期望的序号，未知时为 None
+    next_seq: Option<u32>,
+    // 乱序到达、尚未连续的数据段
+    pending: BTreeMap<u32, (i64, Vec<u8>)>,
+}
+
+impl HalfStream {
+    // 按序号放入数据段，返回可以交付的连续数据
+    fn accept(&mut self, seq: u32, timestamp_us: i64, payload: Vec<u8>) -> Vec<(i64, Vec<u8>)> {
+        let next = *self.next_seq.get_or_insert(seq);
+        let mut ready = Vec::new();
+        let offset = seq.wrapping_sub(next) as i32;
+
+        if offset > 0 {
+            // 前面还有缺失的数据，先缓存
+            self.pending.entry(seq).or_insert((timestamp_us, payload));
+            return ready;
+        }
+
+        // 重传或部分重叠，去掉已交付的部分
+        let overlap = offset.unsigned_abs() as usize;
+        if overlap >= payload.len() {
+            return ready;
+        }
+        let data = payload[overlap..].to_vec();
+        let mut next = next.wrapping_add(data.len() as u32);
+        ready.push((timestamp_us, data));
+
+        // 交付已变为连续的缓存段
+        while let Some((&pending_seq, _)) = self.pending.iter().next() {
+            let offset = pending_seq.wrapping_sub(next) as i32;
+            if offset > 0 {
+                break;
+            }
+            let (ts, payload) = self.pending.remove(&pending_seq).unwrap_or_default();
+            let overlap = offset.unsigned_abs() as usize;
+            if overlap < payload.len() {
+                next = next.wrapping_add((payload.len() - overlap) as u32);
+                ready.push((ts, payload[overlap..].to_vec()));
+            }
+        }
+        self.next_seq = Some(next);
+        ready
+    }
+
+    // 流结束时交付剩余缓存（存在丢包时按序号顺序拼接）
+    fn flush(&mut self) -> Vec<(i64, Vec<u8>)> {
+        std::mem::take(&mut self.pending).into_values().collect()
+    }
+}
+
+// 一条 TCP 连接的重组状态
+#[derive(Debug)]
+struct Connection {
+    client: SocketAddr,
+    server: SocketAddr,
+    to_server: HalfStream,
+    to_client: HalfStream,
+}
+
+/// TCP 流重组器，按连接和方向输出有序的字节段
+#[derive(Debug, Default)]
+pub struct TcpReassembler {
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>,
+}
+
+impl TcpReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 连接键与方向无关
+    fn key(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+        if a <= b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// 处理一个 TCP 段，返回可交付的数据
+    pub fn push(&mut self, timestamp_us: i64, segment: TcpSegment) -> Vec<StreamChunk> {
+        let key = Self::key(segment.src, segment.dst);
+
+        // 新的 SYN 表示连接重建，丢弃旧状态
+        if segment.is_syn() && !segment.is_syn_ack() {
+            self.connections.remove(&key);
+        }
+
+        let connection = self.connections.entry(key).or_insert_with(|| {
+            // 根据握手包确定客户端；中途开始的抓包以端口较大的一方为客户端
+            let src_is_client = if segment.is_syn() {
+                !segment.is_syn_ack()
+            } else {
+                segment.src.port() > segment.dst.port()
+            };
+            if src_is_client {
+                Connection {
+                    client: segment.src,
+                    server: segment.dst,
+                    to_server: HalfStream::default(),
+                    to_client: HalfStream::default(),
+                }
+            } else {
+                Connection {
+                    client: segment.dst,
+                    server: segment.src,
+                    to_server: HalfStream::default(),
+                    to_client: HalfStream::default(),
+                }
+            }
+        });
+
+        let direction = if segment.src == connection.client {
+            StreamDirection::ClientToServer
+        } else {
+            StreamDirection::ServerToClient
+        };
+        let half = match direction {
+            StreamDirection::ClientToServer => &mut connection.to_server,
+            StreamDirection::ServerToClient => &mut connection.to_client,
+        };
+
+        // SYN 占用一个序号
+        let seq = if segment.is_syn() {
+            half.next_seq = Some(segment.seq.wrapping_add(1));
+            segment.seq.wrapping_add(1)
+        } else {
+            segment.seq
+        };
+
+        if segment.payload.is_empty() {
+            return Vec::new();
+        }
+
+        half.accept(seq, timestamp_us, segment.payload)
+            .into_iter()
+            .map(|(ts, data)| StreamChunk {
+                src: segment.src,
+                dst: segment.dst,
+                direction,
+                timestamp_us: ts,
+                data,
+            })
+            .collect()
+    }
+
+    /// 抓包结束时交付所有连接中仍缓存的数据
+    pub fn flush(&mut self) -> Vec<StreamChunk> {
+        let mut chunks = Vec::new();
+        for connection in self.connections.values_mut() {
+            for (ts, data) in connection.to_server.flush() {
+                chunks.push(StreamChunk {
+                    src: connection.client,
+                    dst: connection.server,
+                    direction: StreamDirection::ClientToServer,
+                    timestamp_us: ts,
+                    data,
+                });
+            }
+            for (ts, data) in connection.to_client.flush() {
+                chunks.push(StreamChunk {
+                    src: connection.server,
+                    dst: connection.client,
+                    direction: StreamDirection::ServerToClient,
+                    timestamp_us: ts,
+                    data,
+                });
+            }
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIN: u8 = 0x01;
+    const RST: u8 = 0x04;
+    const PSH_ACK: u8 = 0x18;
+
+    fn client() -> SocketAddr {
+        "192.168.1.10:50000".parse().unwrap()
+    }
+
+    fn server() -> SocketAddr {
+        "192.168.1.1:2404".parse().unwrap()
+    }
+
+    fn segment(
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> TcpSegment {
+        TcpSegment {
+            src,
+            dst,
+            seq,
+            flags,
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn tcp_header(src_port: u16, dst_port: u16, seq: u32, flags: u8) -> Vec<u8> {
+        let mut tcp = Vec::new();
+        tcp.extend_from_slice(&src_port.to_be_bytes());
+        tcp.extend_from_slice(&dst_port.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0, 5 << 4, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        tcp
+    }
+
+    fn ipv4(protocol: u8, frag: u16, tcp: &[u8]) -> Vec<u8> {
+        let mut ip = vec![0x45, 0];
+        ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 1]);
+        ip.extend_from_slice(&frag.to_be_bytes());
+        ip.extend_from_slice(&[64, protocol, 0, 0, 192, 168, 1, 10, 192, 168, 1, 1]);
+        ip.extend_from_slice(tcp);
+        ip
+    }
+
+    fn data(chunks: &[StreamChunk]) -> Vec<(StreamDirection, i64, Vec<u8>)> {
+        chunks
+            .iter()
+            .map(|c| (c.direction, c.timestamp_us, c.data.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_ipv4_over_link_layers() {
+        let mut tcp = tcp_header(50000, 2404, 7, PSH_ACK);
+        tcp.extend_from_slice(&[0x68, 0x16]);
+        let ip = ipv4(IP_PROTO_TCP, 0x4000, &tcp);
+
+        let mut ethernet = vec![0; 12];
+        ethernet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        ethernet.extend_from_slice(&ip);
+        // 以太网最小帧的填充不属于载荷
+        ethernet.extend_from_slice(&[0; 6]);
+        let mut vlan = vec![0; 12];
+        vlan.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        vlan.extend_from_slice(&[0, 100]);
+        vlan.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        vlan.extend_from_slice(&ip);
+        let mut null = 2u32.to_le_bytes().to_vec();
+        null.extend_from_slice(&ip);
+
+        for (linktype, packet) in [
+            (LINKTYPE_ETHERNET, ethernet),
+            (LINKTYPE_ETHERNET, vlan),
+            (LINKTYPE_NULL, null),
+            (LINKTYPE_RAW, ip),
+        ] {
+            let segment = decode_tcp_segment(linktype, &packet).unwrap();
+            assert_eq!((segment.src, segment.dst), (client(), server()));
+            assert_eq!((segment.seq, segment.flags), (7, PSH_ACK));
+            assert_eq!(segment.payload, [0x68, 0x16]);
+        }
+    }
+
+    #[test]
+    fn decodes_ipv6_and_skips_non_tcp() {
+        let tcp = tcp_header(50000, 2404, 1, TCP_SYN);
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        ip.extend_from_slice(&[IP_PROTO_TCP, 64]);
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&tcp);
+        let segment = decode_tcp_segment(LINKTYPE_RAW, &ip).unwrap();
+        assert_eq!(segment.src, "[::1]:50000".parse().unwrap());
+        assert!(segment.is_syn() && !segment.is_syn_ack());
+        assert!(segment.payload.is_empty());
+
+        // UDP、IP 分片和不支持的链路层类型
+        assert!(decode_tcp_segment(LINKTYPE_RAW, &ipv4(17, 0, &tcp)).is_none());
+        assert!(decode_tcp_segment(LINKTYPE_RAW, &ipv4(IP_PROTO_TCP, 0x2000, &tcp)).is_none());
+        assert!(decode_tcp_segment(9999, &ipv4(IP_PROTO_TCP, 0, &tcp)).is_none());
+    }
+
+    #[test]
+    fn reassembles_out_of_order_and_retransmitted_segments() {
+        let mut reassembler = TcpReassembler::new();
+        assert!(reassembler
+            .push(1, segment(client(), server(), 100, TCP_SYN, &[]))
+            .is_empty());
+        // 后一段先到，等前面的数据到达后一起交付
+        assert!(reassembler
+            .push(2, segment(client(), server(), 104, PSH_ACK, b"def"))
+            .is_empty());
+        let chunks = reassembler.push(3, segment(client(), server(), 101, PSH_ACK, b"abc"));
+        assert_eq!(
+            data(&chunks),
+            [
+                (StreamDirection::ClientToServer, 3, b"abc".to_vec()),
+                (StreamDirection::ClientToServer, 2, b"def".to_vec()),
+            ]
+        );
+        // 完全重传的段丢弃，部分重叠的段只交付新数据
+        assert!(reassembler
+            .push(4, segment(client(), server(), 101, PSH_ACK, b"abc"))
+            .is_empty());
+        let chunks = reassembler.push(5, segment(client(), server(), 105, PSH_ACK, b"efgh"));
+        assert_eq!(
+            data(&chunks),
+            [(StreamDirection::ClientToServer, 5, b"gh".to_vec())]
+        );
+    }
+
+    #[test]
+    fn separates_both_directions() {
+        let mut reassembler = TcpReassembler::new();
+        reassembler.push(1, segment(client(), server(), 100, TCP_SYN, &[]));
+        reassembler.push(2, segment(server(), client(), 500, TCP_SYN | TCP_ACK, &[]));
+        let request = reassembler.push(3, segment(client(), server(), 101, PSH_ACK, b"req"));
+        let reply = reassembler.push(4, segment(server(), client(), 501, PSH_ACK, b"rsp"));
+        assert_eq!((request[0].src, request[0].dst), (client(), server()));
+        assert_eq!(request[0].direction, StreamDirection::ClientToServer);
+        assert_eq!((reply[0].src, reply[0].dst), (server(), client()));
+        assert_eq!(
+            data(&reply),
+            [(StreamDirection::ServerToClient, 4, b"rsp".to_vec())]
+        );
+
+        // 没有握手的连接以端口较大的一方为客户端
+        let mut reassembler = TcpReassembler::new();
+        let chunks = reassembler.push(1, segment(server(), client(), 9, PSH_ACK, b"x"));
+        assert_eq!(chunks[0].direction, StreamDirection::ServerToClient);
+    }
+
+    #[test]
+    fn fin_rst_and_reused_connection() {
+        let mut reassembler = TcpReassembler::new();
+        reassembler.push(1, segment(client(), server(), 100, TCP_SYN, &[]));
+        // FIN 段携带的数据照常交付，RST 不产生数据
+        let chunks = reassembler.push(2, segment(client(), server(), 101, FIN | TCP_ACK, b"bye"));
+        assert_eq!(chunks[0].data, b"bye");
+        assert!(reassembler
+            .push(3, segment(server(), client(), 0, RST, &[]))
+            .is_empty());
+
+        // 同一四元组上的新连接从新的初始序号开始，不被当作旧连接的重传
+        reassembler.push(4, segment(client(), server(), 90, TCP_SYN, &[]));
+        let chunks = reassembler.push(5, segment(client(), server(), 91, PSH_ACK, b"again"));
+        assert_eq!(
+            data(&chunks),
+            [(StreamDirection::ClientToServer, 5, b"again".to_vec())]
+        );
+    }
+
+    #[test]
+    fn flush_delivers_data_after_lost_segment() {
+        let mut reassembler = TcpReassembler::new();
+        reassembler.push(1, segment(client(), server(), 100, TCP_SYN, &[]));
+        reassembler.push(2, segment(client(), server(), 101, PSH_ACK, b"a"));
+        // 序号 102 的数据丢失
+        reassembler.push(3, segment(client(), server(), 110, PSH_ACK, b"late"));
+        reassembler.push(4, segment(client(), server(), 103, PSH_ACK, b"b"));
+        let chunks = reassembler.flush();
+        assert_eq!(
+            data(&chunks),
+            [
+                (StreamDirection::ClientToServer, 4, b"b".to_vec()),
+                (StreamDirection::ClientToServer, 3, b"late".to_vec()),
+            ]
+        );
+        assert!(reassembler.flush().is_empty());
+    }
+}
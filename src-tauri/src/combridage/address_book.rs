@@ -0,0 +1,259 @@
+use crate::basefunc::frame_645::Frame645;
+use crate::basefunc::frame_csg::FrameCsg;
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::{FrameSearch, FrameStream};
+use crate::basefunc::protocol::{FrameAnalisyic, ProtocolInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const FRAME_START: u8 = 0x68;
+const FRAME_END: u8 = 0x16;
+
+// 等待成帧的数据上限，超过后只保留末尾部分
+const MAX_PENDING: usize = 4096;
+
+/// 从报文中识别出的终端/电表地址与所在连接
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientAddress {
+    /// 地址，高字节在前的十六进制字符串
+    pub address: String,
+    pub protocol: String,
+    pub client_id: String,
+    pub peer: String,
+    /// 连接建立时间（毫秒）
+    pub connected_at: i64,
+    /// 最近一次收到该地址报文的时间（毫秒）
+    pub last_seen: i64,
+    pub online: bool,
+}
+
+/// TCP 服务端的地址表：按客户端上送报文中的地址（南网13 A1A2、645 表地址、698 SA）
+/// 记录对应的连接，同一终端重连后自动指向新的连接
+#[derive(Debug, Default)]
+pub struct AddressBook {
+    entries: HashMap<String, ClientAddress>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录收到的地址，地址首次出现或换了连接时返回更新后的记录及原来的客户端 ID
+    pub fn learn(
+        &mut self,
+        protocol: &str,
+        address: &str,
+        client_id: &str,
+        peer: &str,
+        connected_at: i64,
+        now: i64,
+    ) -> Option<(ClientAddress, Option<String>)> {
+        let entry = ClientAddress {
+            address: address.to_string(),
+            protocol: protocol.to_string(),
+            client_id: client_id.to_string(),
+            peer: peer.to_string(),
+            connected_at,
+            last_seen: now,
+            online: true,
+        };
+        match self.entries.get_mut(&normalize(address)) {
+            Some(existing) if existing.client_id == client_id => {
+                existing.last_seen = now;
+                existing.online = true;
+                None
+            }
+            Some(existing) => {
+                let previous = std::mem::replace(existing, entry.clone());
+                Some((entry, Some(previous.client_id)))
+            }
+            None => {
+                self.entries.insert(normalize(address), entry.clone());
+                Some((entry, None))
+            }
+        }
+    }
+
+    /// 按地址查找所在连接，地址中的空格、横线等分隔符会被忽略
+    pub fn resolve(&self, address: &str) -> Option<&ClientAddress> {
+        if let Some(entry) = self.entries.get(&normalize(address)) {
+            return Some(entry);
+        }
+        // 终端地址常写作 行政区划码-终端地址（如 4401-0001），两段分别比较并忽略前导 0
+        let (a1, a2) = address.split_once('-')?;
+        let (a1, a2) = (normalize(a1), normalize(a2));
+        self.entries.values().find(|entry| {
+            let (e1, e2) = entry.address.split_at(entry.address.len() / 2);
+            e1.trim_start_matches('0') == a1.trim_start_matches('0')
+                && e2.trim_start_matches('0') == a2.trim_start_matches('0')
+        })
+    }
+
+    /// 连接断开后把该连接上的地址标记为离线，等待终端重连
+    pub fn set_offline(&mut self, client_id: &str) {
+        for entry in self.entries.values_mut() {
+            if entry.client_id == client_id {
+                entry.online = false;
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<ClientAddress> {
+        let mut entries: Vec<ClientAddress> = self.entries.values().cloned().collect();
+        entries.sort_by(|a, b| a.address.cmp(&b.address));
+        entries
+    }
+}
+
+fn normalize(address: &str) -> String {
+    address
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// 在单个连接收到的数据中查找带地址的完整报文，返回 (协议, 地址)
+///
+/// 已识别的报文及之前的数据从 `pending` 中移除，不完整的报文留待下次数据到达后继续识别
+pub fn scan_addresses(pending: &mut Vec<u8>) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    let mut consumed = 0;
+    let mut pos = 0;
+    while pos < pending.len() {
+        if pending[pos] != FRAME_START {
+            pos += 1;
+            continue;
+        }
+        let data = &pending[pos..];
+        if let Some((len, address)) = parse_698(data) {
+            found.push(("DLT/698.45".to_string(), address));
+            pos += len;
+            consumed = pos;
+            continue;
+        }
+        if let FrameSearch::Complete { start: 0, len } = FrameStream::find_frame(data) {
+            let frame = &data[..len];
+            let protocol = if FrameCsg::is_csg_frame(frame) {
+                Some(ProtocolInfo::ProtocolCSG13)
+            } else if Frame645::is_dlt645_frame(frame) {
+                Some(ProtocolInfo::ProtocolDLT64507)
+            } else {
+                None
+            };
+            if let Some(protocol) = protocol {
+                if let Some(address) = FrameAnalisyic::frame_address(frame, &protocol) {
+                    found.push((protocol.name().to_string(), address));
+                }
+                pos += len;
+                consumed = pos;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+
+    pending.drain(..consumed);
+    if pending.len() > MAX_PENDING {
+        pending.drain(..pending.len() - MAX_PENDING);
+    }
+    found
+}
+
+// 68 L L C AF SA CA HCS HCS APDU FCS FCS 16，长度域为去掉起始符和结束符后的长度，
+// AF 低 4 位为服务器地址长度减 1；帧头校验通过才认为是 698 报文
+pub(crate) fn parse_698(data: &[u8]) -> Option<(usize, String)> {
+    if data.len() < 5 {
+        return None;
+    }
+    let length = (u16::from_le_bytes([data[1], data[2]]) & 0x3FFF) as usize;
+    let sa_len = (data[4] & 0x0F) as usize + 1;
+    // 长度域 + 控制域 + AF + SA + CA + HCS + FCS
+    if length < 2 + 1 + 1 + sa_len + 1 + 2 + 2 {
+        return None;
+    }
+    let total = length + 2;
+    if data.len() < total || data[total - 1] != FRAME_END {
+        return None;
+    }
+    let hcs_pos = 5 + sa_len + 1;
+    let hcs = u16::from_le_bytes([data[hcs_pos], data[hcs_pos + 1]]);
+    if fcs16(&data[1..hcs_pos]) != hcs {
+        return None;
+    }
+    Some((total, FrameFun::get_data_str_reverser(&data[5..5 + sa_len])))
+}
+
+// DL/T 698.45 帧校验（CRC-16/X-25）
+fn fcs16(data: &[u8]) -> u16 {
+    FrameFun::ppp_fcs16(0xFFFF, data) ^ 0xFFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        FrameFun::get_frame_list_from_str(text)
+    }
+
+    #[test]
+    fn resolves_csg_terminal_address() {
+        // 南网终端上送的报文，行政区划码 4401、终端地址 0001
+        let mut pending =
+            hex("68 10 00 10 00 68 C9 01 44 00 01 00 00 00 02 70 00 00 00 00 00 E0 61 16");
+        let found = scan_addresses(&mut pending);
+        assert_eq!(found, [("CSG13".to_string(), "004401000001".to_string())]);
+        assert!(pending.is_empty());
+
+        let mut book = AddressBook::new();
+        let (protocol, address) = &found[0];
+        book.learn(protocol, address, "client-1", "127.0.0.1:5000", 1, 2);
+        assert_eq!(book.resolve("4401-0001").unwrap().client_id, "client-1");
+        assert_eq!(
+            book.resolve("00 44 01 00 00 01").unwrap().client_id,
+            "client-1"
+        );
+        assert!(book.resolve("4401-0002").is_none());
+        assert!(book.resolve("4402-0001").is_none());
+    }
+
+    #[test]
+    fn parses_698_server_address_after_header_check() {
+        let frame =
+            hex("68 17 00 43 05 11 11 11 11 11 11 00 EB 26 05 01 00 40 01 02 00 00 ED 03 16");
+        assert_eq!(
+            parse_698(&frame),
+            Some((frame.len(), "111111111111".to_string()))
+        );
+        // 报文未收全
+        assert_eq!(parse_698(&frame[..20]), None);
+        // 帧头校验错误
+        let mut bad = frame.clone();
+        bad[12] = 0x00;
+        assert_eq!(parse_698(&bad), None);
+    }
+
+    #[test]
+    fn reconnect_moves_address_to_new_client() {
+        let mut book = AddressBook::new();
+        assert!(book
+            .learn("CSG13", "004401000001", "client-1", "127.0.0.1:5000", 1, 2)
+            .is_some());
+        // 同一连接再次上送只刷新时间
+        assert!(book
+            .learn("CSG13", "004401000001", "client-1", "127.0.0.1:5000", 1, 3)
+            .is_none());
+
+        book.set_offline("client-1");
+        assert!(!book.resolve("4401-0001").unwrap().online);
+        let (entry, previous) = book
+            .learn("CSG13", "004401000001", "client-2", "127.0.0.1:5001", 4, 5)
+            .unwrap();
+        assert!(entry.online);
+        assert_eq!(previous.as_deref(), Some("client-1"));
+        assert_eq!(book.resolve("4401-0001").unwrap().client_id, "client-2");
+    }
+}
@@ -1,5 +1,5 @@
 use crate::combridage::{ChannelState, CommunicationChannel, Message};
-use crate::global::get_app_handle;
+use crate::global::emit_event;
 use async_trait::async_trait;
 use btleplug::api::{
     Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
@@ -10,7 +10,6 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
-use tauri::Emitter;
 use tokio::sync::Mutex;
 use tokio::time::timeout;
 use uuid::Uuid;
@@ -217,7 +216,6 @@ impl CommunicationChannel for BluetoothChannel {
         &self,
         state: ChannelState,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let app_handle = get_app_handle();
         // 构造断开连接事件的 payload
         let payload = serde_json::json!({
             "channel": "bluetooth",
@@ -226,9 +224,7 @@ impl CommunicationChannel for BluetoothChannel {
 
         println!("Bluetooth channel disconnected. Sending 'channel-state' event...");
         // 发送断开连接事件
-        app_handle
-            .emit("channel-state", serde_json::to_string(&payload)?)
-            .unwrap();
+        emit_event("channel-state", serde_json::to_string(&payload)?).unwrap();
 
         Ok(())
     }
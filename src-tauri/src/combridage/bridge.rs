@@ -0,0 +1,907 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::FrameStream;
+use crate::basefunc::protocol::FrameAnalisyic;
+use crate::combridage::messagemanager::{subscribe_records, MessageDirection, MessageRecord};
+use crate::combridage::{CommunicationChannel, Message};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+// 不完整的数据等待后续字节的最长时间，超时后原样转发
+const FLUSH_DELAY: Duration = Duration::from_millis(100);
+// 时间线保留的最大帧数
+const MAX_TIMELINE: usize = 10000;
+
+/// 桥接中数据的流向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BridgeDirection {
+    /// 通道 A 收到，转发到通道 B
+    AToB,
+    /// 通道 B 收到，转发到通道 A
+    BToA,
+}
+
+/// 规则命中后对帧的处理
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum BridgeAction {
+    /// 丢弃，不转发
+    Drop,
+    /// 延迟后转发，同方向后续的帧随之顺延
+    Delay { delay_ms: u64 },
+    /// 用十六进制数据替换整帧
+    Replace { data: String },
+    /// 从 offset 开始用十六进制数据覆盖，超出帧长时追加
+    Patch { offset: usize, data: String },
+}
+
+/// 故障注入规则，按顺序取第一条匹配的规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeRule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 生效的方向，None 表示两个方向
+    #[serde(default)]
+    pub direction: Option<BridgeDirection>,
+    /// 十六进制匹配模式，?? 匹配任意字节，帧中包含该序列即命中；为空时匹配所有帧
+    #[serde(default)]
+    pub pattern: String,
+    /// 最多生效的次数，None 表示不限
+    #[serde(default)]
+    pub limit: Option<u32>,
+    pub action: BridgeAction,
+    /// 已生效的次数
+    #[serde(default)]
+    pub hits: u32,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BridgeOptions {
+    /// 解析报文使用的地区
+    pub region: String,
+    pub rules: Vec<BridgeRule>,
+    /// 通道 A 为服务端时转发到的客户端，未指定时发给最近收到数据的客户端
+    pub clientid_a: Option<String>,
+    /// 通道 B 为服务端时转发到的客户端，未指定时发给最近收到数据的客户端
+    pub clientid_b: Option<String>,
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        Self {
+            region: "南网".to_string(),
+            rules: Vec::new(),
+            clientid_a: None,
+            clientid_b: None,
+        }
+    }
+}
+
+/// 帧的转发结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BridgeOutcome {
+    Forwarded,
+    Dropped,
+    Delayed,
+    Modified,
+    /// 转发时发送失败
+    Failed,
+    /// 收发记录滞后而丢失了数据，之前缓存的不完整数据已原样转发
+    Gap,
+}
+
+/// 桥接时间线上的一帧，两个方向按收到的先后交错排列
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeFrame {
+    pub index: usize,
+    /// 收到帧首字节的时间（毫秒时间戳）
+    pub timestamp: i64,
+    pub direction: BridgeDirection,
+    /// 收到数据的连接，服务端通道为客户端 ID
+    pub source: String,
+    pub frame: String,
+    /// 实际转发的数据，丢弃时为 None
+    pub forwarded: Option<String>,
+    pub outcome: BridgeOutcome,
+    /// 命中的规则名称，未命名时为规则序号
+    pub rule: Option<String>,
+    pub protocol: String,
+    pub data: Vec<Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BridgeStats {
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    pub dropped: u64,
+    pub delayed: u64,
+    pub modified: u64,
+    pub failed: u64,
+    /// 订阅收发记录滞后而丢失的记录数，丢失的数据未被转发
+    pub lost: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeStatus {
+    pub channel_a: String,
+    pub channel_b: String,
+    pub stats: BridgeStats,
+    pub rules: Vec<BridgeRule>,
+    pub frames: usize,
+}
+
+// 编译后的规则
+struct CompiledRule {
+    rule: BridgeRule,
+    pattern: Vec<Option<u8>>,
+    data: Vec<u8>,
+}
+
+impl CompiledRule {
+    fn new(rule: BridgeRule) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let pattern = parse_pattern(&rule.pattern)
+            .ok_or_else(|| format!("规则 {} 的匹配模式无效: {}", rule.name, rule.pattern))?;
+        let data = match &rule.action {
+            BridgeAction::Replace { data } | BridgeAction::Patch { data, .. } => {
+                parse_hex(data).ok_or_else(|| format!("规则 {} 的数据无效: {}", rule.name, data))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            rule,
+            pattern,
+            data,
+        })
+    }
+
+    fn matches(&self, direction: BridgeDirection, frame: &[u8]) -> bool {
+        if !self.rule.enabled
+            || self.rule.direction.is_some_and(|d| d != direction)
+            || self.rule.limit.is_some_and(|limit| self.rule.hits >= limit)
+        {
+            return false;
+        }
+        pattern_matches(&self.pattern, frame)
+    }
+
+    // 返回转发的数据、结果和延迟
+    fn apply(&self, frame: &[u8]) -> (Option<Vec<u8>>, BridgeOutcome, Option<Duration>) {
+        match &self.rule.action {
+            BridgeAction::Drop => (None, BridgeOutcome::Dropped, None),
+            BridgeAction::Delay { delay_ms } => (
+                Some(frame.to_vec()),
+                BridgeOutcome::Delayed,
+                Some(Duration::from_millis(*delay_ms)),
+            ),
+            BridgeAction::Replace { .. } => {
+                (Some(self.data.clone()), BridgeOutcome::Modified, None)
+            }
+            BridgeAction::Patch { offset, .. } => {
+                let mut data = frame.to_vec();
+                let end = offset + self.data.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[*offset..end].copy_from_slice(&self.data);
+                (Some(data), BridgeOutcome::Modified, None)
+            }
+        }
+    }
+}
+
+// 报文中任意位置出现与模式相同的字节序列即为命中，?? 匹配任意字节，空模式匹配所有报文
+pub(crate) fn pattern_matches(pattern: &[Option<u8>], frame: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+    frame.windows(pattern.len()).any(|window| {
+        window
+            .iter()
+            .zip(pattern)
+            .all(|(byte, expected)| expected.is_none_or(|e| e == *byte))
+    })
+}
+
+// 解析十六进制匹配模式，?? 为通配符
+pub(crate) fn parse_pattern(pattern: &str) -> Option<Vec<Option<u8>>> {
+    let compact: String = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if !compact.len().is_multiple_of(2) {
+        return None;
+    }
+    compact
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            b"??" => Some(None),
+            _ => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16)
+                .ok()
+                .map(Some),
+        })
+        .collect()
+}
+
+pub(crate) fn parse_hex(data: &str) -> Option<Vec<u8>> {
+    FrameFun::decode_hex_str(data).ok()
+}
+
+// 桥接的一端
+struct BridgeSide {
+    channel: Arc<Box<dyn CommunicationChannel>>,
+    channel_id: String,
+    // 服务端通道已知的客户端 ID
+    clients: Mutex<HashSet<String>>,
+    // 指定的或最近收到数据的客户端，转发到这一端时使用
+    target_client: Mutex<Option<String>>,
+    pinned: bool,
+}
+
+impl BridgeSide {
+    fn new(channel: Arc<Box<dyn CommunicationChannel>>, clientid: Option<String>) -> Self {
+        Self {
+            channel_id: channel.get_channel_id(),
+            channel,
+            clients: Mutex::new(HashSet::new()),
+            pinned: clientid.is_some(),
+            target_client: Mutex::new(clientid),
+        }
+    }
+
+    fn owns(&self, channel_id: &str) -> bool {
+        channel_id == self.channel_id || self.clients.lock().unwrap().contains(channel_id)
+    }
+
+    async fn refresh_clients(&self) {
+        let clients = self.channel.client_ids().await;
+        *self.clients.lock().unwrap() = clients.into_iter().collect();
+    }
+
+    fn note_source(&self, channel_id: &str) {
+        if !self.pinned && channel_id != self.channel_id {
+            *self.target_client.lock().unwrap() = Some(channel_id.to_string());
+        }
+    }
+}
+
+// 送入单方向转发队列的内容
+enum Inbound {
+    /// (来源, 时间戳, 数据)
+    Data(String, i64, Vec<u8>),
+    /// 订阅滞后丢失的记录数
+    Gap(u64),
+}
+
+// 桥接任务之间共享的状态
+struct BridgeShared {
+    region: String,
+    rules: Mutex<Vec<CompiledRule>>,
+    timeline: Mutex<VecDeque<BridgeFrame>>,
+    next_index: Mutex<usize>,
+    stats: Mutex<BridgeStats>,
+    on_frame: Box<dyn Fn(&BridgeFrame) + Send + Sync>,
+}
+
+/// 两个通道之间的透明桥接：双向原样转发，并逐帧解析形成统一的时间线
+pub struct Bridge {
+    side_a: Arc<BridgeSide>,
+    side_b: Arc<BridgeSide>,
+    shared: Arc<BridgeShared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Bridge {
+    /// 启动桥接，每处理一帧调用一次 `on_frame`
+    pub fn start<F>(
+        channel_a: Arc<Box<dyn CommunicationChannel>>,
+        channel_b: Arc<Box<dyn CommunicationChannel>>,
+        options: BridgeOptions,
+        on_frame: F,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>>
+    where
+        F: Fn(&BridgeFrame) + Send + Sync + 'static,
+    {
+        // 先订阅，避免错过启动过程中收到的数据
+        Self::start_with(subscribe_records(), channel_a, channel_b, options, on_frame)
+    }
+
+    // 从指定的收发记录订阅中取两端收到的数据
+    fn start_with<F>(
+        receiver: tokio::sync::broadcast::Receiver<MessageRecord>,
+        channel_a: Arc<Box<dyn CommunicationChannel>>,
+        channel_b: Arc<Box<dyn CommunicationChannel>>,
+        options: BridgeOptions,
+        on_frame: F,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>>
+    where
+        F: Fn(&BridgeFrame) + Send + Sync + 'static,
+    {
+        let rules = Self::compile(options.rules)?;
+        let side_a = Arc::new(BridgeSide::new(channel_a, options.clientid_a));
+        let side_b = Arc::new(BridgeSide::new(channel_b, options.clientid_b));
+        if side_a.channel_id == side_b.channel_id {
+            return Err("桥接的两端不能是同一个通道".into());
+        }
+        let shared = Arc::new(BridgeShared {
+            region: options.region,
+            rules: Mutex::new(rules),
+            timeline: Mutex::new(VecDeque::new()),
+            next_index: Mutex::new(0),
+            stats: Mutex::new(BridgeStats::default()),
+            on_frame: Box::new(on_frame),
+        });
+
+        let (tx_a, rx_a) = mpsc::unbounded_channel();
+        let (tx_b, rx_b) = mpsc::unbounded_channel();
+        let handles = vec![
+            tokio::spawn(Self::listen(
+                receiver,
+                side_a.clone(),
+                side_b.clone(),
+                tx_a,
+                tx_b,
+                shared.clone(),
+            )),
+            tokio::spawn(Self::forward(
+                BridgeDirection::AToB,
+                rx_a,
+                side_b.clone(),
+                shared.clone(),
+            )),
+            tokio::spawn(Self::forward(
+                BridgeDirection::BToA,
+                rx_b,
+                side_a.clone(),
+                shared.clone(),
+            )),
+        ];
+        Ok(Self {
+            side_a,
+            side_b,
+            shared,
+            handles,
+        })
+    }
+
+    fn compile(rules: Vec<BridgeRule>) -> Result<Vec<CompiledRule>, Box<dyn Error + Send + Sync>> {
+        rules.into_iter().map(CompiledRule::new).collect()
+    }
+
+    /// 替换故障注入规则，立即对后续的帧生效
+    pub fn set_rules(&self, rules: Vec<BridgeRule>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let rules = Self::compile(rules)?;
+        *self.shared.rules.lock().unwrap() = rules;
+        Ok(())
+    }
+
+    /// 获取序号不小于 `since` 的帧
+    pub fn timeline(&self, since: usize) -> Vec<BridgeFrame> {
+        self.shared
+            .timeline
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|frame| frame.index >= since)
+            .cloned()
+            .collect()
+    }
+
+    pub fn status(&self) -> BridgeStatus {
+        BridgeStatus {
+            channel_a: self.side_a.channel_id.clone(),
+            channel_b: self.side_b.channel_id.clone(),
+            stats: self.shared.stats.lock().unwrap().clone(),
+            rules: self
+                .shared
+                .rules
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| r.rule.clone())
+                .collect(),
+            frames: *self.shared.next_index.lock().unwrap(),
+        }
+    }
+
+    /// 是否使用了指定的通道
+    pub fn uses(&self, channel_id: &str) -> bool {
+        self.side_a.channel_id == channel_id || self.side_b.channel_id == channel_id
+    }
+
+    /// 停止转发，返回最终的统计
+    pub fn stop(self) -> BridgeStats {
+        self.shared.stats.lock().unwrap().clone()
+    }
+
+    // 从收发记录中取出两端收到的数据，分别送入两个方向的转发队列
+    async fn listen(
+        mut receiver: tokio::sync::broadcast::Receiver<MessageRecord>,
+        side_a: Arc<BridgeSide>,
+        side_b: Arc<BridgeSide>,
+        tx_a: mpsc::UnboundedSender<Inbound>,
+        tx_b: mpsc::UnboundedSender<Inbound>,
+        shared: Arc<BridgeShared>,
+    ) {
+        loop {
+            let record = match receiver.recv().await {
+                Ok(record) => record,
+                Err(RecvError::Lagged(n)) => {
+                    // 无法得知丢失的记录属于哪个方向，两个方向都在时间线上标出
+                    eprintln!("桥接订阅消息滞后，丢失 {} 条记录", n);
+                    shared.stats.lock().unwrap().lost += n;
+                    if tx_a.send(Inbound::Gap(n)).is_err() || tx_b.send(Inbound::Gap(n)).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if !matches!(record.direction(), MessageDirection::Received) {
+                continue;
+            }
+            let channel_id = record.channel_id();
+            // 服务端的新客户端不在已知列表中，刷新后再判断
+            if !side_a.owns(channel_id) && !side_b.owns(channel_id) {
+                side_a.refresh_clients().await;
+                side_b.refresh_clients().await;
+            }
+            let (side, tx) = if side_a.owns(channel_id) {
+                (&side_a, &tx_a)
+            } else if side_b.owns(channel_id) {
+                (&side_b, &tx_b)
+            } else {
+                continue;
+            };
+            side.note_source(channel_id);
+            let timestamp = record.content().get_timestamp();
+            if tx
+                .send(Inbound::Data(
+                    channel_id.to_string(),
+                    timestamp,
+                    record.payload_bytes(),
+                ))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    // 单方向的分帧与转发，延迟规则会使同方向后续的帧顺延，保证转发顺序不变
+    async fn forward(
+        direction: BridgeDirection,
+        mut rx: mpsc::UnboundedReceiver<Inbound>,
+        target: Arc<BridgeSide>,
+        shared: Arc<BridgeShared>,
+    ) {
+        let mut stream = FrameStream::new();
+        // 缓冲区中最早数据的来源和时间
+        let mut pending: Option<(String, i64)> = None;
+        let mut deadline = Instant::now();
+        loop {
+            let flush = async {
+                if pending.is_some() {
+                    sleep_until(deadline).await
+                } else {
+                    std::future::pending().await
+                }
+            };
+            tokio::select! {
+                received = rx.recv() => {
+                    let (source, timestamp, data) = match received {
+                        Some(Inbound::Data(source, timestamp, data)) => (source, timestamp, data),
+                        Some(Inbound::Gap(lost)) => {
+                            // 丢失的数据无法补回，缓存的不完整数据不再与之后的数据拼接
+                            if let Some((source, timestamp)) = pending.take() {
+                                let rest = stream.take_remaining();
+                                Self::process(direction, &source, timestamp, &rest, &target, &shared).await;
+                            }
+                            Self::gap(direction, lost, &shared);
+                            continue;
+                        }
+                        None => return,
+                    };
+                    let (first_source, first_timestamp) =
+                        pending.get_or_insert_with(|| (source.clone(), timestamp)).clone();
+                    stream.push(&data);
+                    let mut extracted = false;
+                    while let Some((skipped, frame)) = stream.next_frame() {
+                        extracted = true;
+                        for part in [skipped, frame] {
+                            if !part.is_empty() {
+                                Self::process(direction, &first_source, first_timestamp, &part, &target, &shared).await;
+                            }
+                        }
+                    }
+                    if stream.pending_len() == 0 {
+                        pending = None;
+                    } else {
+                        if extracted {
+                            // 之前缓存的数据已成帧，剩余数据来自本次收到的报文
+                            pending = Some((source, timestamp));
+                        }
+                        deadline = Instant::now() + FLUSH_DELAY;
+                    }
+                }
+                _ = flush => {
+                    let (source, timestamp) = pending.take().unwrap_or_default();
+                    let rest = stream.take_remaining();
+                    Self::process(direction, &source, timestamp, &rest, &target, &shared).await;
+                }
+            }
+        }
+    }
+
+    async fn process(
+        direction: BridgeDirection,
+        source: &str,
+        timestamp: i64,
+        frame: &[u8],
+        target: &BridgeSide,
+        shared: &BridgeShared,
+    ) {
+        let (forwarded, mut outcome, delay, rule) = {
+            let mut rules = shared.rules.lock().unwrap();
+            match rules
+                .iter_mut()
+                .enumerate()
+                .find(|(_, r)| r.matches(direction, frame))
+            {
+                Some((i, rule)) => {
+                    rule.rule.hits += 1;
+                    let (forwarded, outcome, delay) = rule.apply(frame);
+                    // 未命名的规则以序号表示
+                    let name = if rule.rule.name.is_empty() {
+                        format!("规则{}", i + 1)
+                    } else {
+                        rule.rule.name.clone()
+                    };
+                    (forwarded, outcome, delay, Some(name))
+                }
+                None => (Some(frame.to_vec()), BridgeOutcome::Forwarded, None, None),
+            }
+        };
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+
+        let mut error = None;
+        if let Some(data) = &forwarded {
+            let message = Message::new(serde_json::json!({ "data": data }));
+            let clientid = target.target_client.lock().unwrap().clone();
+            if let Err(e) = target.channel.send(&message, clientid).await {
+                error = Some(format!("转发失败: {}", e));
+                outcome = BridgeOutcome::Failed;
+            }
+        }
+
+        let (protocol, parsed, parse_error) =
+            FrameAnalisyic::process_frame_safe(frame, &shared.region);
+        if let Some(parse_error) = parse_error {
+            error.get_or_insert(parse_error);
+        }
+
+        {
+            let mut stats = shared.stats.lock().unwrap();
+            match direction {
+                BridgeDirection::AToB => stats.a_to_b += 1,
+                BridgeDirection::BToA => stats.b_to_a += 1,
+            }
+            match outcome {
+                BridgeOutcome::Dropped => stats.dropped += 1,
+                BridgeOutcome::Delayed => stats.delayed += 1,
+                BridgeOutcome::Modified => stats.modified += 1,
+                BridgeOutcome::Failed => stats.failed += 1,
+                BridgeOutcome::Forwarded | BridgeOutcome::Gap => {}
+            }
+        }
+        Self::append(
+            shared,
+            BridgeFrame {
+                index: 0,
+                timestamp,
+                direction,
+                source: source.to_string(),
+                frame: FrameFun::get_data_str_with_space(frame),
+                forwarded: forwarded.map(|data| FrameFun::get_data_str_with_space(&data)),
+                outcome,
+                rule,
+                protocol,
+                data: parsed,
+                error,
+            },
+        );
+    }
+
+    // 在时间线上标出订阅滞后丢失数据的位置
+    fn gap(direction: BridgeDirection, lost: u64, shared: &BridgeShared) {
+        Self::append(
+            shared,
+            BridgeFrame {
+                index: 0,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                direction,
+                source: String::new(),
+                frame: String::new(),
+                forwarded: None,
+                outcome: BridgeOutcome::Gap,
+                rule: None,
+                protocol: "Unknown".to_string(),
+                data: Vec::new(),
+                error: Some(format!(
+                    "收发记录滞后，丢失 {} 条记录，其中的数据未转发",
+                    lost
+                )),
+            },
+        );
+    }
+
+    // 分配序号后加入时间线
+    fn append(shared: &BridgeShared, mut frame: BridgeFrame) {
+        {
+            let mut next_index = shared.next_index.lock().unwrap();
+            frame.index = *next_index;
+            *next_index += 1;
+        }
+        {
+            let mut timeline = shared.timeline.lock().unwrap();
+            if timeline.len() >= MAX_TIMELINE {
+                timeline.pop_front();
+            }
+            timeline.push_back(frame.clone());
+        }
+        (shared.on_frame)(&frame);
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combridage::VirtualChannel;
+    use tokio::sync::broadcast;
+    use tokio::time::timeout;
+
+    // 68 A0..A5 68 C L 数据(+33) CS 16
+    fn dlt645(control: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x68, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68];
+        frame.push(control);
+        frame.push(data.len() as u8);
+        frame.extend(data.iter().map(|b| b.wrapping_add(0x33)));
+        frame.push(FrameFun::calculate_cs(&frame));
+        frame.push(0x16);
+        frame
+    }
+
+    fn message(data: &[u8]) -> Message {
+        Message::new(serde_json::json!({ "data": data }))
+    }
+
+    async fn recv(channel: &VirtualChannel) -> Option<Vec<u8>> {
+        let message = timeout(Duration::from_secs(2), channel.receive())
+            .await
+            .ok()?
+            .unwrap();
+        let data = message.get_content()["data"].as_array()?.clone();
+        Some(data.iter().map(|b| b.as_u64().unwrap() as u8).collect())
+    }
+
+    fn rule(direction: BridgeDirection, pattern: &str, action: BridgeAction) -> BridgeRule {
+        BridgeRule {
+            name: String::new(),
+            enabled: true,
+            direction: Some(direction),
+            pattern: pattern.to_string(),
+            limit: None,
+            action,
+            hits: 0,
+        }
+    }
+
+    // 终端 ⇄ 通道 A ⇄ 桥接 ⇄ 通道 B ⇄ 主站，返回 (桥接, 通道 A 的 ID, 终端, 主站)
+    async fn bridged(
+        receiver: Option<broadcast::Receiver<MessageRecord>>,
+        rules: Vec<BridgeRule>,
+    ) -> (Bridge, String, VirtualChannel, VirtualChannel) {
+        let (a, device) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let (b, master) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let channel_a = a.get_channel_id();
+        let options = BridgeOptions {
+            rules,
+            ..Default::default()
+        };
+        let bridge = Bridge::start_with(
+            receiver.unwrap_or_else(subscribe_records),
+            Arc::new(Box::new(a)),
+            Arc::new(Box::new(b)),
+            options,
+            |_| {},
+        )
+        .unwrap();
+        (bridge, channel_a, device, master)
+    }
+
+    #[test]
+    fn pattern_wildcards() {
+        let pattern = parse_pattern("68 ?? 16").unwrap();
+        assert!(pattern_matches(&pattern, &[0x00, 0x68, 0x05, 0x16, 0x00]));
+        assert!(!pattern_matches(&pattern, &[0x68, 0x16]));
+        assert!(!pattern_matches(&pattern, &[0x68, 0x05, 0x17]));
+        assert!(pattern_matches(&parse_pattern("").unwrap(), &[0x01]));
+        assert!(parse_pattern("68 1").is_none());
+        assert!(parse_pattern("6G").is_none());
+    }
+
+    #[tokio::test]
+    async fn forwards_both_ways_unchanged() {
+        let (bridge, _, device, master) = bridged(None, Vec::new()).await;
+        let request = dlt645(0x11, &[0x00, 0x00, 0x01, 0x00]);
+        let reply = dlt645(0x91, &[0x00, 0x00, 0x01, 0x00, 0x12, 0x34, 0x56, 0x00]);
+
+        device.send(&message(&request), None).await.unwrap();
+        assert_eq!(recv(&master).await, Some(request.clone()));
+        master.send(&message(&reply), None).await.unwrap();
+        assert_eq!(recv(&device).await, Some(reply));
+        // 无法成帧的数据等待后原样转发
+        device
+            .send(&message(&[0x01, 0x02, 0x03]), None)
+            .await
+            .unwrap();
+        assert_eq!(recv(&master).await, Some(vec![0x01, 0x02, 0x03]));
+
+        let timeline = bridge.timeline(0);
+        let directions: Vec<_> = timeline.iter().map(|f| f.direction).collect();
+        assert_eq!(
+            directions,
+            [
+                BridgeDirection::AToB,
+                BridgeDirection::BToA,
+                BridgeDirection::AToB
+            ]
+        );
+        assert!(timeline.iter().all(
+            |f| f.outcome == BridgeOutcome::Forwarded && f.forwarded.as_ref() == Some(&f.frame)
+        ));
+        let stats = bridge.stop();
+        assert_eq!((stats.a_to_b, stats.b_to_a), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn rules_drop_delay_replace_and_patch() {
+        let mut drop_once = rule(
+            BridgeDirection::AToB,
+            "68 ?? ?? ?? ?? ?? ?? 68 11",
+            BridgeAction::Drop,
+        );
+        drop_once.limit = Some(1);
+        let rules = vec![
+            drop_once,
+            rule(
+                BridgeDirection::AToB,
+                "68 ?? ?? ?? ?? ?? ?? 68 13",
+                BridgeAction::Delay { delay_ms: 150 },
+            ),
+            rule(
+                BridgeDirection::AToB,
+                "68 ?? ?? ?? ?? ?? ?? 68 14",
+                BridgeAction::Patch {
+                    offset: 1,
+                    data: "AA".to_string(),
+                },
+            ),
+            rule(
+                BridgeDirection::BToA,
+                "68 ?? ?? ?? ?? ?? ?? 68 91",
+                BridgeAction::Replace {
+                    data: "01 02".to_string(),
+                },
+            ),
+        ];
+        let (bridge, _, device, master) = bridged(None, rules).await;
+        let read = dlt645(0x11, &[0x00, 0x00, 0x01, 0x00]);
+
+        // 第一次读被丢弃，规则次数用完后正常转发
+        device.send(&message(&read), None).await.unwrap();
+        device.send(&message(&read), None).await.unwrap();
+        assert_eq!(recv(&master).await, Some(read));
+
+        let address = dlt645(0x13, &[]);
+        let started = Instant::now();
+        device.send(&message(&address), None).await.unwrap();
+        assert_eq!(recv(&master).await, Some(address));
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        let write = dlt645(0x14, &[0x01]);
+        device.send(&message(&write), None).await.unwrap();
+        let mut patched = write.clone();
+        patched[1] = 0xAA;
+        assert_eq!(recv(&master).await, Some(patched));
+
+        master
+            .send(&message(&dlt645(0x91, &[0x00])), None)
+            .await
+            .unwrap();
+        assert_eq!(recv(&device).await, Some(vec![0x01, 0x02]));
+
+        let outcomes: Vec<_> = bridge.timeline(0).iter().map(|f| f.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                BridgeOutcome::Dropped,
+                BridgeOutcome::Forwarded,
+                BridgeOutcome::Delayed,
+                BridgeOutcome::Modified,
+                BridgeOutcome::Modified,
+            ]
+        );
+        assert_eq!(bridge.timeline(0)[0].rule.as_deref(), Some("规则1"));
+        let stats = bridge.stop();
+        assert_eq!((stats.dropped, stats.delayed, stats.modified), (1, 1, 2));
+    }
+
+    #[tokio::test]
+    async fn lagged_records_are_marked_as_gap() {
+        // 容量为 2 的订阅，一次收到 4 条记录时丢失最早的 2 条
+        let (tx, receiver) = broadcast::channel(2);
+        let (bridge, channel_a, _device, master) = bridged(Some(receiver), Vec::new()).await;
+        let record = |data: &[u8]| {
+            MessageRecord::new(
+                "virtual".to_string(),
+                channel_a.clone(),
+                String::new(),
+                chrono::Utc::now(),
+                MessageDirection::Received,
+                message(data),
+                None,
+            )
+        };
+        let frame = dlt645(0x13, &[]);
+
+        // 帧的前半部分已缓存，后半部分随丢失的记录一起丢失
+        tx.send(record(&frame[..4])).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        tx.send(record(&frame[4..])).unwrap();
+        tx.send(record(&[0xFF])).unwrap();
+        tx.send(record(&frame)).unwrap();
+        tx.send(record(&frame)).unwrap();
+
+        // 缓存的数据原样转发，不与丢失之后的数据拼接
+        assert_eq!(recv(&master).await, Some(frame[..4].to_vec()));
+        assert_eq!(recv(&master).await, Some(frame.clone()));
+        assert_eq!(recv(&master).await, Some(frame.clone()));
+
+        let gaps: Vec<_> = bridge
+            .timeline(0)
+            .into_iter()
+            .filter(|f| f.outcome == BridgeOutcome::Gap)
+            .map(|f| f.direction)
+            .collect();
+        assert_eq!(gaps.len(), 2);
+        assert!(gaps.contains(&BridgeDirection::AToB) && gaps.contains(&BridgeDirection::BToA));
+        let stats = bridge.stop();
+        assert_eq!((stats.lost, stats.a_to_b), (2, 3));
+    }
+}
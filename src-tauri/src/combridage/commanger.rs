@@ -1,12 +1,22 @@
+use crate::combridage::serial_port::SerialSettings;
 use crate::combridage::BluetoothChannel;
+use crate::combridage::ChannelOptions;
 use crate::combridage::ChannelType;
 use crate::combridage::CommunicationChannel;
 use crate::combridage::Message;
 use crate::combridage::MqttChannel;
+use crate::combridage::MqttConfig;
+use crate::combridage::ReconnectPolicy;
 use crate::combridage::SerialPortChannel;
+use crate::combridage::SerialServerChannel;
 use crate::combridage::TcpClientChannel;
 use crate::combridage::TcpServerChannel;
-use rumqttc::QoS;
+use crate::combridage::UdpChannel;
+use crate::combridage::VirtualChannel;
+use crate::combridage::VirtualEnd;
+use crate::combridage::WebSocketClientChannel;
+use crate::combridage::WebSocketMode;
+use crate::combridage::WebSocketServerChannel;
 use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
@@ -30,65 +40,125 @@ impl CommunicationManager {
         }
     }
 
+    /// 添加通道，使用应用配置中的默认重连策略
     pub async fn add_channel(
         &mut self,
         channel_type: ChannelType,
     ) -> Result<String, Box<dyn Error + Send + Sync>> {
-        let channel: Box<dyn CommunicationChannel> = match &channel_type {
-            ChannelType::TcpClient(ipaddr, port) => {
-                Box::new(TcpClientChannel::new(ipaddr, *port).await?)
-            }
+        let options = ChannelOptions {
+            reconnect: ReconnectPolicy::load(),
+            ..Default::default()
+        };
+        self.add_channel_with_options(channel_type, options).await
+    }
+
+    /// 添加通道并指定重连策略、串口分帧等选项
+    pub async fn add_channel_with_options(
+        &mut self,
+        channel_type: ChannelType,
+        options: ChannelOptions,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let channel = Self::open_channel(&channel_type, options).await?;
+        Ok(self.insert_channel(channel_type, channel))
+    }
+
+    /// 打开通道但不加入管理器，连接可能较慢，调用方无需在此期间持有管理器的锁
+    pub async fn open_channel(
+        channel_type: &ChannelType,
+        options: ChannelOptions,
+    ) -> Result<Box<dyn CommunicationChannel>, Box<dyn Error + Send + Sync>> {
+        let channel: Box<dyn CommunicationChannel> = match channel_type {
+            ChannelType::TcpClient(ipaddr, port) => Box::new(
+                TcpClientChannel::new(ipaddr, *port, options.reconnect, options.tls).await?,
+            ),
             ChannelType::TcpServer(ipaddr, port) => {
-                Box::new(TcpServerChannel::new(ipaddr, *port).await?)
+                Box::new(TcpServerChannel::new(ipaddr, *port, options.tls).await?)
             }
             ChannelType::SerialPort(port, baud_rate, databit, flowctrl, parity, stopbit) => {
-                let databit = match *databit {
-                    5 => tokio_serial::DataBits::Five,
-                    6 => tokio_serial::DataBits::Six,
-                    7 => tokio_serial::DataBits::Seven,
-                    8 => tokio_serial::DataBits::Eight,
-                    _ => tokio_serial::DataBits::Eight,
-                };
-                let flowctrl = match *flowctrl {
-                    0 => tokio_serial::FlowControl::None,
-                    1 => tokio_serial::FlowControl::Software,
-                    2 => tokio_serial::FlowControl::Hardware,
-                    _ => tokio_serial::FlowControl::None,
-                };
-                let parity = match parity.as_str() {
-                    "无校验" => tokio_serial::Parity::None,
-                    "奇校验" => tokio_serial::Parity::Odd,
-                    "偶校验" => tokio_serial::Parity::Even,
-                    _ => tokio_serial::Parity::None,
-                };
-                let stopbits = match *stopbit {
-                    1 => tokio_serial::StopBits::One,
-                    2 => tokio_serial::StopBits::Two,
-                    _ => tokio_serial::StopBits::One,
-                };
+                let (databit, flowctrl, parity, stopbits) =
+                    serial_line(*databit, *flowctrl, parity, *stopbit);
                 Box::new(
-                    SerialPortChannel::new(port, *baud_rate, databit, flowctrl, parity, stopbits)
-                        .await?,
+                    SerialPortChannel::new(
+                        port, *baud_rate, databit, flowctrl, parity, stopbits, options,
+                    )
+                    .await?,
                 )
             }
-            ChannelType::Mqtt(ipaddr, port, username, password, clientid, qos, topic) => {
-                let qos = match *qos {
-                    0 => QoS::AtMostOnce,
-                    1 => QoS::AtLeastOnce,
-                    2 => QoS::ExactlyOnce,
-                    _ => QoS::AtMostOnce,
+            ChannelType::SerialServer(
+                ipaddr,
+                port,
+                baud_rate,
+                databit,
+                flowctrl,
+                parity,
+                stopbit,
+                rfc2217,
+            ) => {
+                let (databit, flowctrl, parity, stopbit) =
+                    serial_line(*databit, *flowctrl, parity, *stopbit);
+                let settings = SerialSettings {
+                    port_name: format!("{}:{}", ipaddr, port),
+                    baud_rate: *baud_rate,
+                    databit,
+                    flowctrl,
+                    parity,
+                    stopbit,
                 };
-
                 Box::new(
-                    MqttChannel::new(ipaddr, *port, username, password, clientid, qos, topic)
-                        .await?,
+                    SerialServerChannel::new(ipaddr, *port, settings, *rfc2217, options).await?,
                 )
             }
+            ChannelType::Mqtt(config) => {
+                Box::new(MqttChannel::new((**config).clone(), options.reconnect).await?)
+            }
             ChannelType::Bluetooth(adapter, device, characteristic_uuid) => {
                 Box::new(BluetoothChannel::new().await?)
             }
+            ChannelType::Udp(ipaddr, port, remote_ip, remote_port, server_mode) => Box::new(
+                UdpChannel::new(
+                    ipaddr,
+                    *port,
+                    remote_ip,
+                    *remote_port,
+                    *server_mode,
+                    options.multicast,
+                )
+                .await?,
+            ),
+            ChannelType::WebSocketClient(url, mode, ping_interval) => Box::new(
+                WebSocketClientChannel::new(
+                    url,
+                    *mode,
+                    *ping_interval,
+                    options.reconnect,
+                    options.tls,
+                )
+                .await?,
+            ),
+            ChannelType::WebSocketServer(ipaddr, port, path, mode, ping_interval) => Box::new(
+                WebSocketServerChannel::new(
+                    ipaddr,
+                    *port,
+                    path,
+                    *mode,
+                    *ping_interval,
+                    options.tls,
+                )
+                .await?,
+            ),
+            ChannelType::Virtual(name, end) => {
+                Box::new(VirtualChannel::open(name, *end, options.impairment).await?)
+            }
         };
+        Ok(channel)
+    }
 
+    /// 把已打开的通道加入管理器，返回通道 ID
+    pub fn insert_channel(
+        &mut self,
+        channel_type: ChannelType,
+        channel: Box<dyn CommunicationChannel>,
+    ) -> String {
         // 生成唯一的通道ID
         let channel_id = channel.get_channel_id();
         // 存储通道和通道ID的映射关系
@@ -98,7 +168,7 @@ impl CommunicationManager {
             .insert(channel_type.clone(), channel_id.clone());
 
         // 返回通道ID
-        Ok(channel_id)
+        channel_id
     }
 
     pub async fn send(
@@ -120,8 +190,13 @@ impl CommunicationManager {
                 ChannelType::TcpClient(_, _) => "tcpclient",
                 ChannelType::TcpServer(_, _) => "tcpserver",
                 ChannelType::SerialPort(_, _, _, _, _, _) => "serial",
-                ChannelType::Mqtt(_, _, _, _, _, _, _) => "mqtt",
+                ChannelType::Mqtt(_) => "mqtt",
                 ChannelType::Bluetooth(_, _, _) => "bluetooth",
+                ChannelType::Udp(_, _, _, _, _) => "udp",
+                ChannelType::SerialServer(..) => "serialserver",
+                ChannelType::WebSocketClient(..) => "websocketclient",
+                ChannelType::WebSocketServer(..) => "websocketserver",
+                ChannelType::Virtual(..) => "virtual",
             };
             let clientid_clone = clientid.clone();
             let channel_id = if let Some(id) = clientid_clone {
@@ -144,6 +219,23 @@ impl CommunicationManager {
         }
     }
 
+    /// 获取通道实例，便于长时间运行的任务在不持有管理器锁的情况下收发
+    pub fn get_channel(
+        &self,
+        channel_type: &ChannelType,
+    ) -> Option<Arc<Box<dyn CommunicationChannel>>> {
+        self.channels.get(channel_type).cloned()
+    }
+
+    /// 从管理器中移除通道并返回，由调用方在不持有管理器锁的情况下关闭
+    pub fn remove_channel(
+        &mut self,
+        channel_type: &ChannelType,
+    ) -> Option<Arc<Box<dyn CommunicationChannel>>> {
+        self.channel_ids.remove(channel_type);
+        self.channels.remove(channel_type)
+    }
+
     pub async fn receive(
         &self,
         channel_type: &ChannelType,
@@ -216,7 +308,7 @@ impl CommunicationManager {
         topic: &str,
         qos: u8,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let ChannelType::Mqtt(_, _, _, _, _, _, _) = channel_type {
+        if let ChannelType::Mqtt(_) = channel_type {
             if let Some(channel) = self.channels.get(channel_type) {
                 channel.subscribe_topic(topic, qos).await
             } else {
@@ -232,7 +324,7 @@ impl CommunicationManager {
         channel_type: &ChannelType,
         topic: &str,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let ChannelType::Mqtt(_, _, _, _, _, _, _) = channel_type {
+        if let ChannelType::Mqtt(_) = channel_type {
             if let Some(channel) = self.channels.get(channel_type) {
                 channel.unsubscribe_topic(topic).await
             } else {
@@ -243,3 +335,204 @@ impl CommunicationManager {
         }
     }
 }
+
+/// 根据界面传入的通道类型和参数生成 ChannelType 及创建选项
+pub fn channel_from_params(
+    channel: &str,
+    values: &serde_json::Value,
+) -> Result<(ChannelType, ChannelOptions), String> {
+    // 根据通道类型和参数创建 ChannelType
+    let channel_type = match channel.to_uppercase().as_str() {
+        "TCPCLIENT" => {
+            let ip = values["ip"].as_str().ok_or("Missing ip")?;
+            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
+            ChannelType::TcpClient(ip.to_string(), port)
+        }
+        "TCPSERVER" => {
+            let ip = values["ip"].as_str().ok_or("Missing ip")?;
+            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
+            ChannelType::TcpServer(ip.to_string(), port)
+        }
+        "UDP" => {
+            let ip = values["ip"].as_str().ok_or("Missing ip")?;
+            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
+            let remote_ip = values["remoteip"].as_str().unwrap_or("");
+            let remote_port = values["remoteport"].as_u64().unwrap_or(0) as u16;
+            let server_mode = values["server"].as_bool().unwrap_or(false);
+            ChannelType::Udp(
+                ip.to_string(),
+                port,
+                remote_ip.to_string(),
+                remote_port,
+                server_mode,
+            )
+        }
+        "SERIALSERVER" => {
+            let ip = values["ip"].as_str().ok_or("Missing ip")?;
+            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
+            let baud_rate = values["baurdate"].as_u64().unwrap_or(9600) as u32;
+            let data_bits = values["databit"].as_u64().unwrap_or(8) as u8;
+            let flow_control = values["flowctrl"].as_u64().unwrap_or(0) as u8;
+            let parity = values["parity"].as_str().unwrap_or("无校验");
+            let stop_bits = values["stopbit"].as_u64().unwrap_or(1) as u8;
+            // 默认使用 RFC 2217 协商串口参数，为 false 时为透传模式
+            let rfc2217 = values["rfc2217"].as_bool().unwrap_or(true);
+            ChannelType::SerialServer(
+                ip.to_string(),
+                port,
+                baud_rate,
+                data_bits,
+                flow_control,
+                parity.to_string(),
+                stop_bits,
+                rfc2217,
+            )
+        }
+        "SERIAL" => {
+            let port = values["comname"].as_str().ok_or("Missing comname")?;
+            let baud_rate = values["baurdate"].as_u64().ok_or("Missing baud rate")? as u32;
+            let data_bits = values["databit"].as_u64().ok_or("Missing data bits")? as u8;
+            let flow_control = values["flowctrl"].as_u64().ok_or("Missing flow control")? as u8;
+            let parity = values["parity"].as_str().ok_or("Missing parity")?;
+            let stop_bits = values["stopbit"].as_u64().ok_or("Missing stop bits")? as u8;
+            ChannelType::SerialPort(
+                port.to_string(),
+                baud_rate,
+                flow_control,
+                data_bits,
+                parity.to_string(),
+                stop_bits,
+            )
+        }
+        "WEBSOCKETCLIENT" => {
+            let url = values["url"].as_str().ok_or("Missing url")?;
+            let mode = websocket_mode(values)?;
+            let ping_interval = values["pinginterval"].as_u64().unwrap_or(30) as u16;
+            ChannelType::WebSocketClient(url.to_string(), mode, ping_interval)
+        }
+        "WEBSOCKETSERVER" => {
+            let ip = values["ip"].as_str().ok_or("Missing ip")?;
+            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
+            // 默认接受任意路径
+            let path = values["path"].as_str().unwrap_or("/");
+            let mode = websocket_mode(values)?;
+            let ping_interval = values["pinginterval"].as_u64().unwrap_or(30) as u16;
+            ChannelType::WebSocketServer(
+                ip.to_string(),
+                port,
+                path.to_string(),
+                mode,
+                ping_interval,
+            )
+        }
+        "MQTT" => {
+            values["ip"].as_str().ok_or("Missing ip")?;
+            values["port"].as_u64().ok_or("Missing port")?;
+            values["clientid"].as_str().ok_or("Missing client ID")?;
+            let config: MqttConfig = serde_json::from_value(values.clone())
+                .map_err(|e| format!("Invalid MQTT config: {}", e))?;
+            ChannelType::Mqtt(Box::new(config))
+        }
+        "VIRTUAL" => {
+            let name = values["name"].as_str().ok_or("Missing name")?;
+            // 未指定端点时打开 A 端
+            let end: VirtualEnd = match values.get("end") {
+                Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+                    .map_err(|e| format!("Invalid virtual end: {}", e))?,
+                _ => VirtualEnd::A,
+            };
+            ChannelType::Virtual(name.to_string(), end)
+        }
+        _ => return Err(format!("Unsupported channel type: {}", channel)),
+    };
+
+    // 可选的断线重连策略，未指定时使用应用配置中的默认策略
+    let reconnect = match values.get("reconnect") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid reconnect policy: {}", e))?,
+        _ => ReconnectPolicy::load(),
+    };
+    // 可选的串口接收分帧配置
+    let framing = match values.get("framing") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid serial framing: {}", e))?,
+        _ => Default::default(),
+    };
+    // 可选的 TLS 配置，只对 TCP 和 WebSocket 的客户端、服务端生效
+    let tls = match values.get("tls") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid TLS config: {}", e))?,
+        _ => Default::default(),
+    };
+    // 可选的 UDP 组播设置
+    let multicast = match values.get("multicast") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid UDP multicast config: {}", e))?,
+        _ => Default::default(),
+    };
+    // 可选的虚拟通道损伤设置
+    let impairment = match values.get("impairment") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid virtual impairment: {}", e))?,
+        _ => Default::default(),
+    };
+
+    Ok((
+        channel_type,
+        ChannelOptions {
+            reconnect,
+            framing,
+            tls,
+            impairment,
+            multicast,
+        },
+    ))
+}
+
+// WebSocket 报文承载方式，未指定时为二进制帧
+fn websocket_mode(values: &serde_json::Value) -> Result<WebSocketMode, String> {
+    match values.get("mode") {
+        Some(value) if !value.is_null() => serde_json::from_value(value.clone())
+            .map_err(|e| format!("Invalid WebSocket mode: {}", e)),
+        _ => Ok(WebSocketMode::default()),
+    }
+}
+
+// 把界面上的数据位、流控、校验位、停止位转换为串口库的参数
+fn serial_line(
+    databit: u8,
+    flowctrl: u8,
+    parity: &str,
+    stopbit: u8,
+) -> (
+    tokio_serial::DataBits,
+    tokio_serial::FlowControl,
+    tokio_serial::Parity,
+    tokio_serial::StopBits,
+) {
+    let databit = match databit {
+        5 => tokio_serial::DataBits::Five,
+        6 => tokio_serial::DataBits::Six,
+        7 => tokio_serial::DataBits::Seven,
+        8 => tokio_serial::DataBits::Eight,
+        _ => tokio_serial::DataBits::Eight,
+    };
+    let flowctrl = match flowctrl {
+        0 => tokio_serial::FlowControl::None,
+        1 => tokio_serial::FlowControl::Software,
+        2 => tokio_serial::FlowControl::Hardware,
+        _ => tokio_serial::FlowControl::None,
+    };
+    let parity = match parity {
+        "无校验" => tokio_serial::Parity::None,
+        "奇校验" => tokio_serial::Parity::Odd,
+        "偶校验" => tokio_serial::Parity::Even,
+        _ => tokio_serial::Parity::None,
+    };
+    let stopbits = match stopbit {
+        1 => tokio_serial::StopBits::One,
+        2 => tokio_serial::StopBits::Two,
+        _ => tokio_serial::StopBits::One,
+    };
+    (databit, flowctrl, parity, stopbits)
+}
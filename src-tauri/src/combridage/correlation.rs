@@ -0,0 +1,524 @@
+use crate::combridage::address_book::parse_698;
+use crate::combridage::serial_framing::modbus_frame_len;
+use crate::combridage::websocket::payload_bytes;
+use crate::combridage::{CommunicationChannel, Message};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+
+const FRAME_START: u8 = 0x68;
+const FRAME_END: u8 = 0x16;
+
+/// 根据请求报文确定的应答匹配条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseKey {
+    /// 南网13：终端地址（A1A2）+ PSEQ，应答方向为终端上行的从动帧
+    Csg { address: [u8; 6], pseq: u8 },
+    /// DL/T 645：表地址 + 控制码功能 + 数据标识，应答控制码 D7 置位；
+    /// 广播地址的请求接受任意地址的应答
+    Dlt645 {
+        address: [u8; 6],
+        function: u8,
+        di: Vec<u8>,
+    },
+    /// Modbus TCP：事务标识 + 单元标识
+    ModbusTcp { transaction_id: u16, unit: u8 },
+    /// Modbus RTU：从站地址 + 功能码，异常应答功能码最高位置位
+    ModbusRtu { unit: u8, function: u8 },
+    /// DL/T 698.45：服务类型 + PIID 中的服务序号
+    Dlt698 { service: [u8; 2], piid: u8 },
+    /// 无法识别协议的请求，接受之后收到的第一条数据
+    Any,
+}
+
+impl ResponseKey {
+    /// 识别请求报文的协议并生成应答匹配条件，无法识别时返回 None
+    pub fn from_request(data: &[u8]) -> Option<Self> {
+        let start = data.iter().position(|&b| b != 0xFE)?;
+        let frame = &data[start..];
+        if frame[0] == FRAME_START {
+            if let Some(key) = Self::csg_request(frame)
+                .or_else(|| Self::dlt645_request(frame))
+                .or_else(|| Self::dlt698_request(frame))
+            {
+                return Some(key);
+            }
+        }
+        Self::modbus_request(data)
+    }
+
+    /// 判断收到的数据是否为该请求的应答；数据中可以带有应答帧之外的内容
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            ResponseKey::ModbusTcp {
+                transaction_id,
+                unit,
+            } => {
+                data.len() >= 8
+                    && u16::from_be_bytes([data[0], data[1]]) == *transaction_id
+                    && data[2..4] == [0, 0]
+                    && data[6] == *unit
+            }
+            ResponseKey::ModbusRtu { unit, function } => {
+                data.len() >= 4
+                    && data[0] == *unit
+                    && data[1] & 0x7F == *function
+                    && modbus_frame_len(data).is_some()
+            }
+            ResponseKey::Any => true,
+            _ => (0..data.len())
+                .filter(|&pos| data[pos] == FRAME_START)
+                .any(|pos| self.matches_frame(&data[pos..])),
+        }
+    }
+
+    // 68 开头的协议，frame 从起始符开始，之后可以有其他数据
+    fn matches_frame(&self, frame: &[u8]) -> bool {
+        match self {
+            ResponseKey::Csg { address, pseq } => {
+                let Some(len) = csg_frame_len(frame) else {
+                    return false;
+                };
+                let control = frame[6];
+                // DIR=1 上行，PRM=0 从动，排除终端主动上报和心跳
+                len >= 16
+                    && control & 0x80 != 0
+                    && control & 0x40 == 0
+                    && frame[7..13] == address[..]
+                    && frame[15] & 0x0F == *pseq
+            }
+            ResponseKey::Dlt645 {
+                address,
+                function,
+                di,
+            } => {
+                if dlt645_frame_len(frame).is_none() {
+                    return false;
+                }
+                let control = frame[8];
+                if control & 0x80 == 0 || control & 0x1F != *function {
+                    return false;
+                }
+                if !is_645_broadcast(address) && frame[1..7] != address[..] {
+                    return false;
+                }
+                // 异常应答（D6 置位）不带数据标识
+                if control & 0x40 != 0 || di.is_empty() {
+                    return true;
+                }
+                frame[9] as usize >= di.len() && frame[10..10 + di.len()] == di[..]
+            }
+            ResponseKey::Dlt698 { service, piid } => {
+                let Some((len, _)) = parse_698(frame) else {
+                    return false;
+                };
+                let apdu = 8 + (frame[4] & 0x0F) as usize + 1;
+                // APDU 之后还有 FCS 和结束符
+                frame[3] & 0x80 != 0
+                    && len >= apdu + 3 + 3
+                    && frame[apdu] == service[0] | 0x80
+                    && frame[apdu + 1] == service[1]
+                    && frame[apdu + 2] & 0x3F == *piid
+            }
+            _ => false,
+        }
+    }
+
+    fn csg_request(frame: &[u8]) -> Option<Self> {
+        let len = csg_frame_len(frame)?;
+        if len < 16 || frame[6] & 0x80 != 0 {
+            return None;
+        }
+        Some(ResponseKey::Csg {
+            address: frame[7..13].try_into().ok()?,
+            pseq: frame[15] & 0x0F,
+        })
+    }
+
+    fn dlt645_request(frame: &[u8]) -> Option<Self> {
+        dlt645_frame_len(frame)?;
+        let control = frame[8];
+        if control & 0x80 != 0 {
+            return None;
+        }
+        let function = control & 0x1F;
+        // 读数据请求带数据标识：2007 版 4 字节，1997 版 2 字节
+        let di_len = match function {
+            0x11 => 4,
+            0x01 => 2,
+            _ => 0,
+        };
+        let di_len = di_len.min(frame[9] as usize);
+        Some(ResponseKey::Dlt645 {
+            address: frame[1..7].try_into().ok()?,
+            function,
+            di: frame[10..10 + di_len].to_vec(),
+        })
+    }
+
+    fn dlt698_request(frame: &[u8]) -> Option<Self> {
+        let (len, _) = parse_698(frame)?;
+        // 服务器地址之后为 CA、HCS，再之后为 APDU，APDU 之后还有 FCS 和结束符
+        let apdu = 8 + (frame[4] & 0x0F) as usize + 1;
+        if frame[3] & 0x80 != 0 || len < apdu + 3 + 3 {
+            return None;
+        }
+        // GET、SET、ACTION、PROXY 请求的第三个字节为 PIID
+        if !matches!(frame[apdu], 0x05 | 0x06 | 0x07 | 0x09) {
+            return None;
+        }
+        Some(ResponseKey::Dlt698 {
+            service: [frame[apdu], frame[apdu + 1]],
+            piid: frame[apdu + 2] & 0x3F,
+        })
+    }
+
+    fn modbus_request(data: &[u8]) -> Option<Self> {
+        // RTU 读请求（如 01 03 00 00 00 02 CRC）同时符合 MBAP 头的格式，CRC 正确时优先按 RTU 处理
+        if modbus_frame_len(data) == Some(data.len()) && data[1] & 0x80 == 0 {
+            return Some(ResponseKey::ModbusRtu {
+                unit: data[0],
+                function: data[1],
+            });
+        }
+        // MBAP 头：事务标识 协议标识(0) 长度 单元标识
+        if data.len() >= 8
+            && data[2..4] == [0, 0]
+            && u16::from_be_bytes([data[4], data[5]]) as usize == data.len() - 6
+        {
+            return Some(ResponseKey::ModbusTcp {
+                transaction_id: u16::from_be_bytes([data[0], data[1]]),
+                unit: data[6],
+            });
+        }
+        None
+    }
+}
+
+// 68 L L L L 68 C A1A2(6) MSA AFN SEQ ... CS 16
+fn csg_frame_len(frame: &[u8]) -> Option<usize> {
+    if frame.len() < 8 || frame[5] != FRAME_START || frame[1..3] != frame[3..5] {
+        return None;
+    }
+    let len = u16::from_le_bytes([frame[1], frame[2]]) as usize + 8;
+    (frame.len() >= len && frame[len - 1] == FRAME_END).then_some(len)
+}
+
+// 68 A0..A5 68 C L DATA CS 16
+fn dlt645_frame_len(frame: &[u8]) -> Option<usize> {
+    if frame.len() < 12 || frame[7] != FRAME_START {
+        return None;
+    }
+    let len = frame[9] as usize + 12;
+    (frame.len() >= len && frame[len - 1] == FRAME_END).then_some(len)
+}
+
+fn is_645_broadcast(address: &[u8; 6]) -> bool {
+    address.iter().all(|&b| b == 0xAA) || address.iter().all(|&b| b == 0x99)
+}
+
+/// 应答的匹配条件，`T` 为收到的数据
+pub trait ResponseMatch<T>: Send {
+    fn matches(&self, response: &T) -> bool;
+}
+
+impl ResponseMatch<Vec<u8>> for ResponseKey {
+    fn matches(&self, response: &Vec<u8>) -> bool {
+        ResponseKey::matches(self, response)
+    }
+}
+
+// 等待应答的请求
+struct Waiter<K, T> {
+    id: u64,
+    key: K,
+    tx: oneshot::Sender<T>,
+}
+
+type Waiters<K, T> = Arc<std::sync::Mutex<Vec<Waiter<K, T>>>>;
+
+/// 请求/应答关联：收到的数据先交给等待中的请求匹配，未匹配的数据照常进入接收队列
+///
+/// 同时等待的多个请求各自超时，按发出顺序优先匹配。默认按报文的协议字段匹配，
+/// MQTT 等按消息属性匹配的通道可以指定自己的匹配条件和数据类型
+pub struct Correlator<K = ResponseKey, T = Vec<u8>> {
+    waiters: Waiters<K, T>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<K, T> Clone for Correlator<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            waiters: self.waiters.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<K, T> Default for Correlator<K, T> {
+    fn default() -> Self {
+        Self {
+            waiters: Arc::new(std::sync::Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<K, T> std::fmt::Debug for Correlator<K, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Correlator")
+            .field("pending", &self.waiters.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// 等待中的应答，drop 时取消等待
+pub struct PendingResponse<K = ResponseKey, T = Vec<u8>> {
+    id: u64,
+    rx: oneshot::Receiver<T>,
+    waiters: Waiters<K, T>,
+}
+
+impl<K, T> PendingResponse<K, T> {
+    /// 等待应答，超时返回错误
+    pub async fn wait(mut self, timeout_secs: u64) -> Result<T, Box<dyn Error + Send + Sync>> {
+        match timeout(Duration::from_secs(timeout_secs), &mut self.rx).await {
+            Ok(Ok(data)) => Ok(data),
+            Ok(Err(_)) => Err("通道已关闭".into()),
+            Err(_) => Err("等待响应超时".into()),
+        }
+    }
+}
+
+impl<K, T> Drop for PendingResponse<K, T> {
+    fn drop(&mut self) {
+        self.waiters.lock().unwrap().retain(|w| w.id != self.id);
+    }
+}
+
+impl<K: ResponseMatch<T>, T> Correlator<K, T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记一个等待中的请求，需在发出请求之前调用，避免应答先于登记到达
+    pub fn expect(&self, key: K) -> PendingResponse<K, T> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().push(Waiter { id, key, tx });
+        PendingResponse {
+            id,
+            rx,
+            waiters: self.waiters.clone(),
+        }
+    }
+
+    /// 收到的数据与等待中的请求匹配；匹配时交给对应请求并返回 None，否则原样返回
+    pub fn offer(&self, data: T) -> Option<T> {
+        let mut waiters = self.waiters.lock().unwrap();
+        let mut data = data;
+        while let Some(index) = waiters.iter().position(|w| w.key.matches(&data)) {
+            let waiter = waiters.remove(index);
+            // 请求已超时放弃时继续匹配下一个
+            match waiter.tx.send(data) {
+                Ok(()) => return None,
+                Err(returned) => data = returned,
+            }
+        }
+        Some(data)
+    }
+}
+
+impl Correlator {
+    /// 发送请求并等待匹配的应答；无法识别协议的请求等待下一条收到的数据
+    pub async fn send_and_wait<C: CommunicationChannel + ?Sized>(
+        &self,
+        channel: &C,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let key = payload_bytes(message)
+            .ok()
+            .and_then(|data| ResponseKey::from_request(&data))
+            .unwrap_or(ResponseKey::Any);
+
+        let pending = self.expect(key);
+        channel.send(message, None).await?;
+        let data = pending.wait(timeout_secs).await?;
+        Ok(Message::new(serde_json::json!({ "data": data })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basefunc::frame_fun::FrameFun;
+    use crate::protocol::modbus::parser::ModbusParser;
+
+    // 68 L L L L 68 C A1A2(6) MSA AFN SEQ 数据 CS 16
+    fn csg13(control: u8, seq: u8, data: &[u8]) -> Vec<u8> {
+        let mut user = vec![control, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x0C, seq];
+        user.extend_from_slice(data);
+        let len = (user.len() as u16).to_le_bytes();
+        let mut frame = vec![FRAME_START, len[0], len[1], len[0], len[1], FRAME_START];
+        frame.extend_from_slice(&user);
+        frame.push(FrameFun::calculate_cs(&user));
+        frame.push(FRAME_END);
+        frame
+    }
+
+    // 68 A0..A5 68 C L 数据(+33) CS 16
+    fn dlt645(control: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![FRAME_START, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, FRAME_START];
+        frame.push(control);
+        frame.push(data.len() as u8);
+        frame.extend(data.iter().map(|b| b.wrapping_add(0x33)));
+        frame.push(FrameFun::calculate_cs(&frame));
+        frame.push(FRAME_END);
+        frame
+    }
+
+    // 68 L L C AF SA(6) CA HCS APDU FCS 16
+    fn dlt698(control: u8, apdu: &[u8]) -> Vec<u8> {
+        let fcs = |data: &[u8]| (FrameFun::ppp_fcs16(0xFFFF, data) ^ 0xFFFF).to_le_bytes();
+        let len = (1 + 2 + 1 + 1 + 6 + 1 + 2 + apdu.len() + 2 + 1 - 2) as u16;
+        let mut frame = vec![FRAME_START];
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(&[control, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        let hcs = fcs(&frame[1..]);
+        frame.extend_from_slice(&hcs);
+        frame.extend_from_slice(apdu);
+        let fcs = fcs(&frame[1..]);
+        frame.extend_from_slice(&fcs);
+        frame.push(FRAME_END);
+        frame
+    }
+
+    fn modbus_rtu(body: &[u8]) -> Vec<u8> {
+        let mut frame = body.to_vec();
+        frame.extend_from_slice(&ModbusParser::calculate_crc(body).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn csg13_matches_address_and_pseq() {
+        let request = csg13(0x4A, 0x61, &[0x00, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        let key = ResponseKey::from_request(&request).unwrap();
+        assert_eq!(
+            key,
+            ResponseKey::Csg {
+                address: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+                pseq: 0x01
+            }
+        );
+
+        let data = [0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x12, 0x34];
+        assert!(key.matches(&csg13(0x88, 0x61, &data)));
+        // 其他请求的应答
+        assert!(!key.matches(&csg13(0x88, 0x62, &data)));
+        // 终端主动上报（PRM=1）
+        assert!(!key.matches(&csg13(0xC8, 0x61, &data)));
+    }
+
+    #[test]
+    fn dlt645_matches_function_and_di_with_reply_bit() {
+        // 前导 FE 之后为读数据请求，数据标识 00 01 00 00
+        let mut request = vec![0xFE; 4];
+        request.extend(dlt645(0x11, &[0x00, 0x00, 0x01, 0x00]));
+        let key = ResponseKey::from_request(&request).unwrap();
+        assert_eq!(
+            key,
+            ResponseKey::Dlt645 {
+                address: [0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+                function: 0x11,
+                di: vec![0x33, 0x33, 0x34, 0x33],
+            }
+        );
+
+        let reply = dlt645(0x91, &[0x00, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00, 0x00]);
+        assert!(key.matches(&reply));
+        // 异常应答不带数据标识
+        assert!(key.matches(&dlt645(0xD1, &[0x02])));
+        // 回显的请求 D7 未置位
+        assert!(!key.matches(&request));
+        // 其他数据标识的应答
+        let other = dlt645(0x91, &[0x00, 0x00, 0x02, 0x00, 0x00, 0x12, 0x00, 0x00]);
+        assert!(!key.matches(&other));
+    }
+
+    #[test]
+    fn dlt698_matches_service_and_piid_after_server_address() {
+        // GET-Request-Normal，PIID=1，OAD 40010200
+        let request = dlt698(0x43, &[0x05, 0x01, 0x01, 0x40, 0x01, 0x02, 0x00, 0x00]);
+        let key = ResponseKey::from_request(&request).unwrap();
+        assert_eq!(
+            key,
+            ResponseKey::Dlt698 {
+                service: [0x05, 0x01],
+                piid: 0x01
+            }
+        );
+
+        let apdu = |piid| {
+            [
+                0x85, 0x01, piid, 0x40, 0x01, 0x02, 0x00, 0x01, 0x09, 0x06, 0x01, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00,
+            ]
+        };
+        assert!(key.matches(&dlt698(0xC3, &apdu(0x01))));
+        assert!(!key.matches(&dlt698(0xC3, &apdu(0x02))));
+    }
+
+    #[test]
+    fn modbus_tcp_matches_transaction_id() {
+        let request = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x02,
+        ];
+        let key = ResponseKey::from_request(&request).unwrap();
+        assert_eq!(
+            key,
+            ResponseKey::ModbusTcp {
+                transaction_id: 1,
+                unit: 1
+            }
+        );
+
+        let mut reply = vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x07, 0x01, 0x03, 0x04];
+        reply.extend_from_slice(&[0x00, 0x0A, 0x00, 0x0B]);
+        assert!(key.matches(&reply));
+        reply[1] = 0x02;
+        assert!(!key.matches(&reply));
+    }
+
+    #[test]
+    fn modbus_rtu_matches_unit_and_function() {
+        let request = modbus_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        let key = ResponseKey::from_request(&request).unwrap();
+        assert_eq!(
+            key,
+            ResponseKey::ModbusRtu {
+                unit: 1,
+                function: 0x03
+            }
+        );
+
+        assert!(key.matches(&modbus_rtu(&[0x01, 0x03, 0x04, 0x00, 0x0A, 0x00, 0x0B])));
+        // 异常应答
+        assert!(key.matches(&modbus_rtu(&[0x01, 0x83, 0x02])));
+        assert!(!key.matches(&modbus_rtu(&[0x02, 0x03, 0x04, 0x00, 0x0A, 0x00, 0x0B])));
+    }
+
+    #[test]
+    fn unmatched_data_is_returned_and_any_takes_next() {
+        let correlator = Correlator::<ResponseKey, Vec<u8>>::new();
+        let request = modbus_rtu(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        let _pending = correlator.expect(ResponseKey::from_request(&request).unwrap());
+        let other = vec![0x12, 0x34];
+        assert_eq!(correlator.offer(other.clone()), Some(other.clone()));
+
+        let _any = correlator.expect(ResponseKey::Any);
+        assert_eq!(correlator.offer(other), None);
+    }
+}
@@ -0,0 +1,921 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::protocol::FrameAnalisyic;
+use crate::combridage::messagemanager::{MessageDirection, MessageRecord};
+use crate::combridage::storage_policy::{
+    compress_closed_logs, dir_usage, prune_archive, safe_file_name, ArchiveWriter, ChannelUsage,
+    RetentionReport, StoragePolicy, StorageUsage, ARCHIVE_DIR,
+};
+use crate::combridage::Message;
+use crate::config::appconfig::GLOBAL_CONFIG_MANAGER;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{
+    SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+};
+use sqlx::{QueryBuilder, Row, Sqlite};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::sync::OnceCell;
+
+// 数据库文件名，位于应用数据目录下
+pub const MESSAGE_DB_FILE: &str = "messages.db";
+
+// 单页最大条数
+const MAX_PAGE_SIZE: u32 = 1000;
+const DEFAULT_PAGE_SIZE: u32 = 100;
+
+// 保留策略每批清理的条数
+const EVICT_BATCH: i64 = 1000;
+// 后台执行保留策略的间隔
+const MAINTENANCE_INTERVAL_SECS: u64 = 3600;
+// 估算一条记录占用的数据量
+const ROW_SIZE: &str = "(length(hex) + length(content) + IFNULL(length(metadata), 0))";
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    channel_type TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    channel_name TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    protocol TEXT,
+    address TEXT,
+    hex TEXT NOT NULL,
+    content TEXT NOT NULL,
+    metadata TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_messages_time ON messages(timestamp);
+CREATE INDEX IF NOT EXISTS idx_messages_channel ON messages(channel_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_messages_channel_name ON messages(channel_name, timestamp);
+CREATE INDEX IF NOT EXISTS idx_messages_direction ON messages(direction, timestamp);
+CREATE INDEX IF NOT EXISTS idx_messages_protocol ON messages(protocol, timestamp);
+CREATE INDEX IF NOT EXISTS idx_messages_address ON messages(address, timestamp);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    hex, content='messages', content_rowid='id'
+);
+CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts(rowid, hex) VALUES (new.id, new.hex);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, hex) VALUES ('delete', old.id, old.hex);
+END;
+CREATE TABLE IF NOT EXISTS migrated_logs (
+    path TEXT PRIMARY KEY,
+    records INTEGER NOT NULL,
+    migrated_at INTEGER NOT NULL
+);
+"#;
+
+static MESSAGE_STORE: OnceCell<MessageStore> = OnceCell::const_new();
+
+/// 消息查询条件，所有条件均可选，时间为毫秒时间戳
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageQuery {
+    pub channel_id: Option<String>,
+    pub channel_name: Option<String>,
+    pub channel_type: Option<String>,
+    pub direction: Option<MessageDirection>,
+    pub protocol: Option<String>,
+    pub address: Option<String>,
+    pub start_time: Option<i64>,
+    pub end_time: Option<i64>,
+    /// 按字节序列全文搜索，如 "68 11 04" 或 "681104"
+    pub hex: Option<String>,
+    /// 页码，从 1 开始
+    pub page: u32,
+    pub page_size: u32,
+    /// 为 true 时按时间倒序
+    pub descending: bool,
+}
+
+/// 数据库中的一条消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: i64,
+    pub protocol: Option<String>,
+    pub address: Option<String>,
+    pub hex: String,
+    #[serde(flatten)]
+    pub record: MessageRecord,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagePage {
+    pub total: i64,
+    pub page: u32,
+    pub page_size: u32,
+    pub messages: Vec<StoredMessage>,
+}
+
+/// .log 文件迁移结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogMigrationReport {
+    pub files: usize,
+    pub records: usize,
+    /// 无法解析而跳过的行数
+    pub skipped_lines: usize,
+    /// 之前已经迁移过的文件数
+    pub already_migrated: usize,
+    /// 读取失败而跳过的文件及原因，下次迁移时会重试
+    pub failed_files: Vec<String>,
+}
+
+/// 基于 SQLite 的消息存储，所有通道共用一个数据库
+///
+/// 直接使用 sqlx 而不是 tauri-plugin-sql，因为 web 服务和命令行没有 Tauri 运行时；
+/// tauri-plugin-sql 也是基于 sqlx 的，桌面版中两者使用同一个 SQLite 实现
+#[derive(Debug, Clone)]
+pub struct MessageStore {
+    pool: SqlitePool,
+}
+
+impl MessageStore {
+    /// 打开（必要时创建）数据库并建表
+    pub async fn open(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .auto_vacuum(SqliteAutoVacuum::Incremental);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await?;
+        sqlx::raw_sql(SCHEMA).execute(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    /// 获取全局存储，首次调用时打开数据库，在后台迁移旧的 .log 文件并定期执行保留策略
+    pub async fn global(base_path: &Path) -> Result<&'static Self, Box<dyn Error + Send + Sync>> {
+        MESSAGE_STORE
+            .get_or_try_init(|| async {
+                let store = Self::open(&base_path.join(MESSAGE_DB_FILE)).await?;
+                let migration_store = store.clone();
+                let base_path = base_path.to_path_buf();
+                tokio::spawn(async move {
+                    match migration_store.migrate_log_files(&base_path).await {
+                        Ok(report) if report.files > 0 => {
+                            tracing::info!("Migrated message logs: {:?}", report)
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("迁移消息日志失败: {:?}", e),
+                    }
+                    loop {
+                        let policy = StoragePolicy::load();
+                        match migration_store.apply_policy(&base_path, &policy).await {
+                            Ok(report) => tracing::info!("Applied storage policy: {:?}", report),
+                            Err(e) => eprintln!("执行存储保留策略失败: {:?}", e),
+                        }
+                        tokio::time::sleep(tokio::time::Duration::from_secs(
+                            MAINTENANCE_INTERVAL_SECS,
+                        ))
+                        .await;
+                    }
+                });
+                Ok(store)
+            })
+            .await
+    }
+
+    /// 在一个事务中批量写入消息
+    pub async fn insert_records(
+        &self,
+        records: &[MessageRecord],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let region = GLOBAL_CONFIG_MANAGER.global_region.get_value();
+        let mut tx = self.pool.begin().await?;
+        for record in records {
+            Self::insert_record(&mut tx, record, &region).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_record(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        record: &MessageRecord,
+        region: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = record.payload_bytes();
+        let (protocol, address) = Self::detect(&payload, region);
+        let metadata = match record.metadata() {
+            Some(metadata) => Some(serde_json::to_string(metadata)?),
+            None => None,
+        };
+        sqlx::query(
+            "INSERT INTO messages (timestamp, channel_type, channel_id, channel_name, direction, \
+             protocol, address, hex, content, metadata) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(record.timestamp().timestamp_millis())
+        .bind(record.channeltype())
+        .bind(record.channel_id())
+        .bind(record.channel_name())
+        .bind(Self::direction_str(record.direction()))
+        .bind(protocol)
+        .bind(address)
+        .bind(FrameFun::get_data_str_with_space(&payload))
+        .bind(serde_json::to_string(record.content())?)
+        .bind(metadata)
+        .execute(&mut **tx)
+        .await?;
+        Ok(())
+    }
+
+    // 识别协议与地址，识别函数对异常数据可能 panic，需要捕获
+    fn detect(payload: &[u8], region: &str) -> (Option<String>, Option<String>) {
+        if payload.is_empty() {
+            return (None, None);
+        }
+        std::panic::catch_unwind(|| {
+            let protocol = FrameAnalisyic::detect_protocol(payload, region)?;
+            let address = FrameAnalisyic::frame_address(payload, &protocol);
+            Some((protocol.name().to_string(), address))
+        })
+        .ok()
+        .flatten()
+        .map_or((None, None), |(protocol, address)| {
+            (Some(protocol), address)
+        })
+    }
+
+    fn direction_str(direction: &MessageDirection) -> &'static str {
+        match direction {
+            MessageDirection::Sent => "Sent",
+            MessageDirection::Received => "Received",
+        }
+    }
+
+    /// 分页查询
+    pub async fn query(
+        &self,
+        query: &MessageQuery,
+    ) -> Result<MessagePage, Box<dyn Error + Send + Sync>> {
+        let page = query.page.max(1);
+        let page_size = match query.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM messages");
+        Self::push_filters(&mut count, query)?;
+        let total: i64 = count.build().fetch_one(&self.pool).await?.try_get(0)?;
+
+        let mut select = QueryBuilder::<Sqlite>::new("SELECT * FROM messages");
+        Self::push_filters(&mut select, query)?;
+        Self::push_order(&mut select, query);
+        select.push(" LIMIT ");
+        select.push_bind(page_size as i64);
+        select.push(" OFFSET ");
+        select.push_bind((page as i64 - 1) * page_size as i64);
+        let rows = select.build().fetch_all(&self.pool).await?;
+
+        Ok(MessagePage {
+            total,
+            page,
+            page_size,
+            messages: rows
+                .iter()
+                .map(Self::row_to_message)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// 不分页地读取所有满足条件的消息，用于导出
+    pub async fn load_records(
+        &self,
+        query: &MessageQuery,
+    ) -> Result<Vec<MessageRecord>, Box<dyn Error + Send + Sync>> {
+        let mut select = QueryBuilder::<Sqlite>::new("SELECT * FROM messages");
+        Self::push_filters(&mut select, query)?;
+        Self::push_order(&mut select, query);
+        let rows = select.build().fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Self::row_to_message(row).map(|m| m.record))
+            .collect()
+    }
+
+    fn push_filters(
+        builder: &mut QueryBuilder<'_, Sqlite>,
+        query: &MessageQuery,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        builder.push(" WHERE 1 = 1");
+        let text_filters = [
+            ("channel_id", &query.channel_id),
+            ("channel_name", &query.channel_name),
+            ("channel_type", &query.channel_type),
+            ("protocol", &query.protocol),
+            ("address", &query.address),
+        ];
+        for (column, value) in text_filters {
+            if let Some(value) = value.as_ref().filter(|v| !v.is_empty()) {
+                builder.push(format!(" AND {} = ", column));
+                builder.push_bind(value.clone());
+            }
+        }
+        if let Some(direction) = &query.direction {
+            builder.push(" AND direction = ");
+            builder.push_bind(Self::direction_str(direction));
+        }
+        if let Some(start) = query.start_time {
+            builder.push(" AND timestamp >= ");
+            builder.push_bind(start);
+        }
+        if let Some(end) = query.end_time {
+            builder.push(" AND timestamp < ");
+            builder.push_bind(end);
+        }
+        if let Some(hex) = query.hex.as_deref().filter(|h| !h.trim().is_empty()) {
+            builder.push(" AND id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ");
+            builder.push_bind(Self::hex_match_expr(hex)?);
+            builder.push(")");
+        }
+        Ok(())
+    }
+
+    fn push_order(builder: &mut QueryBuilder<'_, Sqlite>, query: &MessageQuery) {
+        builder.push(if query.descending {
+            " ORDER BY timestamp DESC, id DESC"
+        } else {
+            " ORDER BY timestamp ASC, id ASC"
+        });
+    }
+
+    // 把十六进制输入转换为 FTS5 短语查询，按字节匹配连续序列
+    fn hex_match_expr(hex: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("无效的十六进制搜索内容: {}", hex).into());
+        }
+        if !digits.len().is_multiple_of(2) {
+            return Err("十六进制搜索内容长度必须为偶数".into());
+        }
+        let bytes = digits
+            .as_bytes()
+            .chunks(2)
+            .map(|c| String::from_utf8_lossy(c).to_uppercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(format!("\"{}\"", bytes))
+    }
+
+    fn row_to_message(
+        row: &sqlx::sqlite::SqliteRow,
+    ) -> Result<StoredMessage, Box<dyn Error + Send + Sync>> {
+        let direction = match row.try_get::<String, _>("direction")?.as_str() {
+            "Sent" => MessageDirection::Sent,
+            _ => MessageDirection::Received,
+        };
+        let content: Message = serde_json::from_str(&row.try_get::<String, _>("content")?)?;
+        let metadata = row
+            .try_get::<Option<String>, _>("metadata")?
+            .map(|m| serde_json::from_str::<HashMap<String, String>>(&m))
+            .transpose()?;
+        let timestamp =
+            chrono::DateTime::from_timestamp_millis(row.try_get("timestamp")?).unwrap_or_default();
+        Ok(StoredMessage {
+            id: row.try_get("id")?,
+            protocol: row.try_get("protocol")?,
+            address: row.try_get("address")?,
+            hex: row.try_get("hex")?,
+            record: MessageRecord::new(
+                row.try_get::<String, _>("channel_type")?,
+                row.try_get::<String, _>("channel_id")?,
+                row.try_get::<String, _>("channel_name")?,
+                timestamp,
+                direction,
+                content,
+                metadata,
+            ),
+        })
+    }
+
+    /// 把 {日期}/{通道}.log 文件导入数据库，已导入的文件不会重复导入
+    pub async fn migrate_log_files(
+        &self,
+        base_path: &Path,
+    ) -> Result<LogMigrationReport, Box<dyn Error + Send + Sync>> {
+        let mut report = LogMigrationReport::default();
+        for path in Self::find_log_files(base_path).await? {
+            let key = path.to_string_lossy().to_string();
+            let migrated = sqlx::query("SELECT 1 FROM migrated_logs WHERE path = ?")
+                .bind(&key)
+                .fetch_optional(&self.pool)
+                .await?;
+            if migrated.is_some() {
+                report.already_migrated += 1;
+                continue;
+            }
+
+            // 单个文件读取失败不影响其他文件；旧版本可能写入过非 UTF-8 内容，按有损方式解码
+            let content = match fs::read(&path).await {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => {
+                    report.failed_files.push(format!("{}: {}", key, e));
+                    continue;
+                }
+            };
+            let mut records = Vec::new();
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                match MessageRecord::from_log_line(line) {
+                    Some(record) => records.push(record),
+                    None => report.skipped_lines += 1,
+                }
+            }
+
+            let region = GLOBAL_CONFIG_MANAGER.global_region.get_value();
+            let mut tx = self.pool.begin().await?;
+            for record in &records {
+                Self::insert_record(&mut tx, record, &region).await?;
+            }
+            sqlx::query("INSERT INTO migrated_logs (path, records, migrated_at) VALUES (?, ?, ?)")
+                .bind(&key)
+                .bind(records.len() as i64)
+                .bind(chrono::Utc::now().timestamp_millis())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            report.files += 1;
+            report.records += records.len();
+        }
+        Ok(report)
+    }
+
+    // 查找 base_path 下各日期目录中的 .log 文件
+    async fn find_log_files(
+        base_path: &Path,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+        let mut files = Vec::new();
+        let Ok(mut dates) = fs::read_dir(base_path).await else {
+            return Ok(files);
+        };
+        while let Some(date) = dates.next_entry().await? {
+            if !date.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut entries = fs::read_dir(date.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) == Some("log") {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// 执行保留策略：清理过期记录及超出总量、单通道上限的最旧记录，清理前可先写入压缩归档，
+    /// 之后按归档的保留天数和大小上限清理归档文件
+    pub async fn apply_policy(
+        &self,
+        base_path: &Path,
+        policy: &StoragePolicy,
+    ) -> Result<RetentionReport, Box<dyn Error + Send + Sync>> {
+        let mut report = RetentionReport::default();
+        let archive = policy
+            .archive_evicted
+            .then(|| ArchiveWriter::new(base_path, policy.archive_rotate_mb));
+
+        if policy.max_age_days > 0 {
+            let cutoff = (chrono::Utc::now() - chrono::Duration::days(policy.max_age_days as i64))
+                .timestamp_millis();
+            report.expired = self
+                .evict_oldest(None, Some(cutoff), None, archive.as_ref(), &mut report)
+                .await?;
+        }
+        if let Some(limit) = policy.max_channel_bytes() {
+            for channel in self.channel_usage().await? {
+                if channel.bytes > limit {
+                    report.over_channel += self
+                        .evict_oldest(
+                            Some(&channel.channel_name),
+                            None,
+                            Some(channel.bytes - limit),
+                            archive.as_ref(),
+                            &mut report,
+                        )
+                        .await?;
+                }
+            }
+        }
+        if let Some(limit) = policy.max_total_bytes() {
+            let total: i64 = sqlx::query_scalar(&format!(
+                "SELECT IFNULL(SUM({}), 0) FROM messages",
+                ROW_SIZE
+            ))
+            .fetch_one(&self.pool)
+            .await?;
+            if total > limit {
+                report.over_total = self
+                    .evict_oldest(
+                        None,
+                        None,
+                        Some(total - limit),
+                        archive.as_ref(),
+                        &mut report,
+                    )
+                    .await?;
+            }
+        }
+        if report.expired + report.over_channel + report.over_total > 0 {
+            sqlx::query("PRAGMA incremental_vacuum")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        if policy.archive_max_age_days > 0 || policy.archive_max_bytes().is_some() {
+            let cutoff = (policy.archive_max_age_days > 0).then(|| {
+                (chrono::Utc::now() - chrono::Duration::days(policy.archive_max_age_days as i64))
+                    .format("%Y-%m-%d")
+                    .to_string()
+            });
+            let max_bytes = policy.archive_max_bytes();
+            let base_path = base_path.to_path_buf();
+            report.removed_archives = tokio::task::spawn_blocking(move || {
+                prune_archive(&base_path, cutoff.as_deref(), max_bytes)
+            })
+            .await??;
+        }
+
+        if policy.compress_closed_days {
+            let migrated: Vec<String> = sqlx::query_scalar("SELECT path FROM migrated_logs")
+                .fetch_all(&self.pool)
+                .await?;
+            let base_path = base_path.to_path_buf();
+            let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+            report.compressed_logs = tokio::task::spawn_blocking(move || {
+                compress_closed_logs(&base_path, &today, &migrated)
+            })
+            .await??;
+        }
+        Ok(report)
+    }
+
+    // 按时间从旧到新删除记录，直到早于 before 的记录删完或释放了 bytes_to_free 的数据量
+    async fn evict_oldest(
+        &self,
+        channel_name: Option<&str>,
+        before: Option<i64>,
+        bytes_to_free: Option<i64>,
+        archive: Option<&ArchiveWriter>,
+        report: &mut RetentionReport,
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let mut removed = 0;
+        let mut freed = 0;
+        loop {
+            let mut select: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+                "SELECT *, {} AS row_size FROM messages WHERE 1 = 1",
+                ROW_SIZE
+            ));
+            if let Some(name) = channel_name {
+                select.push(" AND channel_name = ");
+                select.push_bind(name.to_string());
+            }
+            if let Some(before) = before {
+                select.push(" AND timestamp < ");
+                select.push_bind(before);
+            }
+            select.push(" ORDER BY timestamp ASC, id ASC LIMIT ");
+            select.push_bind(EVICT_BATCH);
+            let rows = select.build().fetch_all(&self.pool).await?;
+
+            let mut ids = Vec::new();
+            let mut records = Vec::new();
+            for row in &rows {
+                if bytes_to_free.is_some_and(|target| freed >= target) {
+                    break;
+                }
+                freed += row.try_get::<i64, _>("row_size")?;
+                ids.push(row.try_get::<i64, _>("id")?);
+                if archive.is_some() {
+                    records.push(Self::row_to_message(row)?.record);
+                }
+            }
+            if ids.is_empty() {
+                break;
+            }
+
+            // 先归档成功再删除，避免数据丢失
+            if let Some(archive) = archive {
+                let archive = archive.clone();
+                report.archived +=
+                    tokio::task::spawn_blocking(move || archive.write(&records)).await??;
+            }
+            let mut delete: QueryBuilder<Sqlite> =
+                QueryBuilder::new("DELETE FROM messages WHERE id IN (");
+            let mut separated = delete.separated(", ");
+            for id in &ids {
+                separated.push_bind(*id);
+            }
+            separated.push_unseparated(")");
+            delete.build().execute(&self.pool).await?;
+
+            removed += ids.len();
+            if ids.len() < rows.len() || (rows.len() as i64) < EVICT_BATCH {
+                break;
+            }
+        }
+        Ok(removed)
+    }
+
+    // 按通道名称统计数据库中的记录
+    async fn channel_usage(&self) -> Result<Vec<ChannelUsage>, Box<dyn Error + Send + Sync>> {
+        let rows = sqlx::query(&format!(
+            "SELECT channel_name, MAX(channel_type) AS channel_type, COUNT(*) AS records, \
+             SUM({}) AS bytes, MIN(timestamp) AS oldest, MAX(timestamp) AS newest \
+             FROM messages GROUP BY channel_name ORDER BY bytes DESC",
+            ROW_SIZE
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(ChannelUsage {
+                    channel_name: row.try_get("channel_name")?,
+                    channel_type: row.try_get("channel_type")?,
+                    records: row.try_get("records")?,
+                    bytes: row.try_get("bytes")?,
+                    oldest: row.try_get("oldest")?,
+                    newest: row.try_get("newest")?,
+                    archive_bytes: 0,
+                })
+            })
+            .collect()
+    }
+
+    /// 统计数据库、归档和旧版日志文件的存储占用，并按通道汇总
+    pub async fn usage(
+        &self,
+        base_path: &Path,
+    ) -> Result<StorageUsage, Box<dyn Error + Send + Sync>> {
+        let mut channels = self.channel_usage().await?;
+        let base = base_path.to_path_buf();
+        let (database_bytes, (archive_bytes, mut archive_channels), (legacy_log_bytes, _)) =
+            tokio::task::spawn_blocking(move || {
+                let database_bytes = ["", "-wal", "-shm"]
+                    .iter()
+                    .filter_map(|suffix| {
+                        std::fs::metadata(base.join(format!("{}{}", MESSAGE_DB_FILE, suffix))).ok()
+                    })
+                    .map(|m| m.len())
+                    .sum::<u64>();
+                (
+                    database_bytes,
+                    dir_usage(&base.join(ARCHIVE_DIR), &[".log.gz"]),
+                    dir_usage(&base, &[".log.gz", ".log"]),
+                )
+            })
+            .await?;
+
+        for channel in channels.iter_mut() {
+            channel.archive_bytes = archive_channels
+                .remove(&safe_file_name(&channel.channel_name))
+                .unwrap_or(0);
+        }
+        // 只剩归档的通道
+        for (channel_name, archive_bytes) in archive_channels {
+            channels.push(ChannelUsage {
+                channel_name,
+                archive_bytes,
+                ..Default::default()
+            });
+        }
+
+        Ok(StorageUsage {
+            database_bytes,
+            records: channels.iter().map(|c| c.records).sum(),
+            record_bytes: channels.iter().map(|c| c.bytes).sum(),
+            archive_bytes,
+            legacy_log_bytes,
+            channels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 内存数据库只存在于单个连接中，连接池保持一个不过期的连接
+    async fn memory_store() -> MessageStore {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::raw_sql(SCHEMA).execute(&pool).await.unwrap();
+        MessageStore { pool }
+    }
+
+    // 表地址末字节为 address 的 645 报文
+    fn dlt645(address: u8, control: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![
+            0x68,
+            address,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x68,
+            control,
+            data.len() as u8,
+        ];
+        frame.extend_from_slice(data);
+        frame.push(FrameFun::calculate_cs(&frame));
+        frame.push(0x16);
+        frame
+    }
+
+    fn record(
+        channel: &str,
+        timestamp: i64,
+        direction: MessageDirection,
+        data: &[u8],
+    ) -> MessageRecord {
+        MessageRecord::new(
+            "tcp".to_string(),
+            format!("id-{}", channel),
+            channel.to_string(),
+            chrono::DateTime::from_timestamp_millis(timestamp).unwrap(),
+            direction,
+            Message::new(serde_json::json!({ "data": data })),
+            None,
+        )
+    }
+
+    fn timestamps(page: &MessagePage) -> Vec<i64> {
+        page.messages
+            .iter()
+            .map(|m| m.record.timestamp().timestamp_millis())
+            .collect()
+    }
+
+    async fn seeded_store() -> MessageStore {
+        let store = memory_store().await;
+        let read = dlt645(0x01, 0x11, &[0x33, 0x33, 0x34, 0x33]);
+        let reply = dlt645(0x01, 0x91, &[0x33, 0x33, 0x34, 0x33, 0x45, 0x67]);
+        let other = dlt645(0x02, 0x11, &[0x33, 0x34, 0x35, 0x36]);
+        store
+            .insert_records(&[
+                record("A", 1000, MessageDirection::Sent, &read),
+                record("A", 2000, MessageDirection::Received, &reply),
+                record("B", 3000, MessageDirection::Sent, &other),
+                record("A", 4000, MessageDirection::Sent, &read),
+                record("B", 5000, MessageDirection::Received, &[0x01, 0x02]),
+            ])
+            .await
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn query_filters_and_pages() {
+        let store = seeded_store().await;
+        let query = |query: MessageQuery| {
+            let store = store.clone();
+            async move { store.query(&query).await.unwrap() }
+        };
+
+        let page = query(MessageQuery::default()).await;
+        assert_eq!(
+            (page.total, page.page, page.page_size),
+            (5, 1, DEFAULT_PAGE_SIZE)
+        );
+        assert_eq!(timestamps(&page), [1000, 2000, 3000, 4000, 5000]);
+        assert_eq!(
+            page.messages[0].hex,
+            "68 01 00 00 00 00 00 68 11 04 33 33 34 33 B3 16"
+        );
+        assert_eq!(page.messages[0].address.as_deref(), Some("000000000001"));
+        assert_eq!(page.messages[4].protocol, None);
+
+        let page = query(MessageQuery {
+            channel_name: Some("A".to_string()),
+            direction: Some(MessageDirection::Sent),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(timestamps(&page), [1000, 4000]);
+
+        let page = query(MessageQuery {
+            address: Some("000000000002".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(timestamps(&page), [3000]);
+        assert_eq!(page.messages[0].record.channel_id(), "id-B");
+
+        let page = query(MessageQuery {
+            protocol: Some("DLT/645-2007".to_string()),
+            start_time: Some(2000),
+            end_time: Some(4000),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(timestamps(&page), [2000, 3000]);
+
+        // 第 2 页，每页 2 条，倒序
+        let page = query(MessageQuery {
+            page: 2,
+            page_size: 2,
+            descending: true,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!((page.total, page.page), (5, 2));
+        assert_eq!(timestamps(&page), [3000, 2000]);
+        let page = query(MessageQuery {
+            page: 4,
+            page_size: 2,
+            ..Default::default()
+        })
+        .await;
+        assert!(page.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn hex_search_matches_byte_sequences() {
+        let store = seeded_store().await;
+        let search = |hex: &str| {
+            let store = store.clone();
+            let query = MessageQuery {
+                hex: Some(hex.to_string()),
+                ..Default::default()
+            };
+            async move { store.query(&query).await }
+        };
+
+        assert_eq!(
+            timestamps(&search("33 34 33").await.unwrap()),
+            [1000, 2000, 4000]
+        );
+        assert_eq!(timestamps(&search("3334 3536").await.unwrap()), [3000]);
+        assert_eq!(timestamps(&search("0102").await.unwrap()), [5000]);
+        // 字节不连续时不匹配
+        assert!(search("33 35").await.unwrap().messages.is_empty());
+        assert!(search("45 6").await.is_err());
+        assert!(search("ZZ").await.is_err());
+
+        let records = store
+            .load_records(&MessageQuery {
+                hex: Some("91".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(matches!(records[0].direction(), MessageDirection::Received));
+    }
+
+    #[tokio::test]
+    async fn migrates_legacy_logs_once() {
+        let base = std::env::temp_dir().join(format!("embedtalk-logs-{}", uuid::Uuid::new_v4()));
+        let day = base.join("2024-01-02");
+        std::fs::create_dir_all(&day).unwrap();
+        let mut content = b"2024-01-02 08:00:00:123 [TCP1] >>> \xE5\x8F\x91\xE9\x80\x81: 68 16\n\
+            not a log line\n\n\
+            2024-01-02 08:00:01:000 [TCP1] <<< \xBD\xD3\xCA\xD5: 01 02 03\n"
+            .to_vec();
+        // 旧版本写入的非 UTF-8 内容
+        content.extend_from_slice(b"\xFF\xFE broken\n");
+        std::fs::write(day.join("TCP1.log"), &content).unwrap();
+        // 无法读取的文件
+        std::fs::create_dir_all(day.join("broken.log")).unwrap();
+
+        let store = memory_store().await;
+        let report = store.migrate_log_files(&base).await.unwrap();
+        assert_eq!(
+            (report.files, report.records, report.skipped_lines),
+            (1, 2, 2)
+        );
+        assert_eq!(report.already_migrated, 0);
+        assert_eq!(report.failed_files.len(), 1);
+        assert!(report.failed_files[0].contains("broken.log"));
+
+        let page = store.query(&MessageQuery::default()).await.unwrap();
+        assert_eq!(timestamps(&page), [1704182400123, 1704182401000]);
+        assert_eq!(page.messages[1].hex, "01 02 03");
+        assert_eq!(page.messages[1].record.channel_name(), "TCP1");
+
+        // 已迁移的文件不再导入，读取失败的文件下次重试
+        let report = store.migrate_log_files(&base).await.unwrap();
+        assert_eq!((report.files, report.already_migrated), (0, 1));
+        assert_eq!(report.failed_files.len(), 1);
+        assert_eq!(
+            store.query(&MessageQuery::default()).await.unwrap().total,
+            2
+        );
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
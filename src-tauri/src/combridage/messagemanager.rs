@@ -1,15 +1,19 @@
+use crate::combridage::message_store::{LogMigrationReport, MessageStore};
+use crate::combridage::pcap_export;
+use crate::combridage::storage_policy::{RetentionReport, StoragePolicy, StorageUsage};
 use crate::combridage::Message;
-use crate::global::get_app_handle;
+use crate::global::emit_event;
+#[cfg(feature = "desktop")]
+use crate::global::try_get_app_handle;
 use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::Emitter;
+#[cfg(feature = "desktop")]
 use tauri::Manager;
-use tokio::fs::{self, OpenOptions};
-use tokio::io::AsyncWriteExt;
 use tokio::sync::{broadcast, Mutex, RwLock};
 use uuid::Uuid;
 
@@ -31,14 +35,182 @@ pub enum MessageDirection {
     Received,
 }
 
+// 日志行中的时间格式
+const LOG_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S:%3f";
+
+// 数据库写入失败时每个通道最多积压的记录数
+const MAX_PENDING_RECORDS: usize = 10000;
+
+// 所有 MessageManager 共用的消息广播，回放等功能通过它订阅各通道的收发记录
+static MESSAGE_BROADCAST: Lazy<broadcast::Sender<MessageRecord>> =
+    Lazy::new(|| broadcast::channel(1024).0);
+
+/// 订阅所有通道的收发记录，不需要 AppHandle
+pub fn subscribe_records() -> broadcast::Receiver<MessageRecord> {
+    MESSAGE_BROADCAST.subscribe()
+}
+
+impl MessageRecord {
+    pub fn new(
+        channeltype: String,
+        channel_id: String,
+        channel_name: String,
+        timestamp: DateTime<Utc>,
+        direction: MessageDirection,
+        content: Message,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Self {
+        Self {
+            channeltype,
+            channel_id,
+            channel_name,
+            timestamp,
+            direction,
+            content,
+            metadata,
+        }
+    }
+
+    pub fn channeltype(&self) -> &str {
+        &self.channeltype
+    }
+
+    pub fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    pub fn channel_name(&self) -> &str {
+        &self.channel_name
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn direction(&self) -> &MessageDirection {
+        &self.direction
+    }
+
+    pub fn content(&self) -> &Message {
+        &self.content
+    }
+
+    pub fn metadata(&self) -> Option<&HashMap<String, String>> {
+        self.metadata.as_ref()
+    }
+
+    /// 提取消息中的原始字节：data 为字节数组时直接使用，
+    /// MQTT 发送消息取 payload 字段，其余按文本处理
+    pub fn payload_bytes(&self) -> Vec<u8> {
+        let content = self.content.get_content();
+        match content.get("data") {
+            Some(serde_json::Value::Array(arr)) => arr
+                .iter()
+                .filter_map(|v| v.as_u64())
+                .map(|v| v as u8)
+                .collect(),
+            Some(serde_json::Value::String(s)) => s.as_bytes().to_vec(),
+            Some(data) => match data.get("payload").and_then(|p| p.as_str()) {
+                Some(payload) => payload.as_bytes().to_vec(),
+                None => data.to_string().into_bytes(),
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// 格式化为一行文本日志，用于归档，格式与 from_log_line 对应
+    pub fn to_log_line(&self) -> String {
+        let content = self.content.get_content();
+        let data = match content.get("data") {
+            Some(serde_json::Value::Array(arr)) => arr
+                .iter()
+                .filter_map(|num| num.as_u64())
+                .map(|num| format!("{:02X}", num))
+                .collect::<Vec<String>>()
+                .join(" "),
+            Some(serde_json::Value::String(s)) => s.clone(),
+            // 对象等其他类型使用单行 JSON，保证一条记录一行
+            Some(data) => data.to_string(),
+            None => String::new(),
+        };
+        let datetime = DateTime::<Utc>::from_timestamp_millis(self.content.get_timestamp())
+            .unwrap_or(self.timestamp);
+        let (direction, direction_str) = match self.direction {
+            MessageDirection::Sent => (">>>", "发送"),
+            MessageDirection::Received => ("<<<", "接收"),
+        };
+        format!(
+            "{} [{}] {} {}: {}",
+            datetime.format(LOG_TIME_FORMAT),
+            self.channel_name,
+            direction,
+            direction_str,
+            data
+        )
+    }
+
+    /// 从旧版 {日期}/{通道}.log 日志行还原消息记录，仅支持十六进制数据行
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        let time_str = line.get(..23)?;
+        let naive = chrono::NaiveDateTime::parse_from_str(time_str, LOG_TIME_FORMAT).ok()?;
+        let timestamp = DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc);
+
+        let rest = line.get(23..)?.strip_prefix(" [")?;
+        let name_end = rest.find("] ")?;
+        let channel_name = &rest[..name_end];
+        let rest = &rest[name_end + 2..];
+        let (direction, rest) = if let Some(r) = rest.strip_prefix(">>> ") {
+            (MessageDirection::Sent, r)
+        } else if let Some(r) = rest.strip_prefix("<<< ") {
+            (MessageDirection::Received, r)
+        } else {
+            return None;
+        };
+        let data_str = rest.split_once(": ").map(|(_, d)| d).unwrap_or("");
+        let data = data_str
+            .split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).ok().filter(|_| b.len() == 2))
+            .collect::<Option<Vec<u8>>>()?;
+
+        let channeltype = if channel_name.starts_with("TCP") {
+            "tcp"
+        } else if channel_name.starts_with("UDP") {
+            "udp"
+        } else if channel_name.starts_with("SerialServer") {
+            "serialserver"
+        } else if channel_name.starts_with("Serial") {
+            "serial"
+        } else if channel_name.starts_with("mqtt") {
+            "mqtt"
+        } else {
+            "unknown"
+        };
+
+        Some(Self {
+            channeltype: channeltype.to_string(),
+            channel_id: channel_name.to_string(),
+            channel_name: channel_name.to_string(),
+            timestamp,
+            direction,
+            content: Message::with_timestamp(
+                serde_json::json!({ "data": data }),
+                timestamp.timestamp_millis(),
+            ),
+            metadata: None,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct MessageManager {
-    app_handle: tauri::AppHandle,
-    base_path: PathBuf,
+    // 是否通知界面
+    notify_ui: bool,
+    // 没有数据目录时不保存到数据库
+    base_path: Option<PathBuf>,
     message_sender: broadcast::Sender<MessageRecord>,
     active_channels: Arc<RwLock<HashMap<String, bool>>>,
     write_queues: Arc<RwLock<HashMap<String, Arc<Mutex<Vec<MessageRecord>>>>>>,
-    message_history: Arc<Mutex<Vec<MessageRecord>>>,
 }
 
 impl std::fmt::Debug for MessageManager {
@@ -50,8 +222,9 @@ impl std::fmt::Debug for MessageManager {
 }
 
 impl MessageManager {
+    #[cfg(feature = "desktop")]
     pub fn new(app_handle: tauri::AppHandle) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let (message_sender, _) = broadcast::channel(100);
+        let message_sender = MESSAGE_BROADCAST.clone();
 
         // 获取应用数据目录
         let base_path = app_handle
@@ -60,42 +233,82 @@ impl MessageManager {
             .map_err(|_| "Failed to get app data directory")?;
         println!("App data directory: {:?}", base_path);
         let manager = Self {
-            app_handle,
-            base_path,
+            notify_ui: true,
+            base_path: Some(base_path),
             message_sender,
             active_channels: Arc::new(RwLock::new(HashMap::new())),
             write_queues: Arc::new(RwLock::new(HashMap::new())),
-            message_history: Arc::new(Mutex::new(Vec::new())),
         };
 
         Ok(manager)
     }
 
-    fn get_storage_path(&self, channel_name: &str) -> PathBuf {
-        let now = Utc::now();
-        let date_folder = now.format("%Y-%m-%d").to_string();
-        // 替换文件名中的非法字符
-        let safe_channel_name =
-            channel_name.replace(&['\\', '/', ':', '*', '?', '"', '<', '>', '|'][..], "_");
+    /// 不保存到数据库、不通知界面的管理器，收发记录只广播给订阅者，用于测试和命令行
+    pub fn detached() -> Self {
+        Self {
+            notify_ui: false,
+            base_path: None,
+            message_sender: MESSAGE_BROADCAST.clone(),
+            active_channels: Arc::new(RwLock::new(HashMap::new())),
+            write_queues: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// 已设置应用句柄时与 new 相同，否则返回 detached 的管理器
+    #[cfg(feature = "desktop")]
+    pub fn from_global() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        match try_get_app_handle() {
+            Some(app_handle) => Self::new(app_handle),
+            None => Ok(Self::detached()),
+        }
+    }
+
+    /// 不带桌面界面构建时总是返回 detached 的管理器
+    #[cfg(not(feature = "desktop"))]
+    pub fn from_global() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(Self::detached())
+    }
+
+    fn base_path(&self) -> Result<&PathBuf, Box<dyn Error + Send + Sync>> {
         self.base_path
-            .join(date_folder)
-            .join(format!("{}.log", safe_channel_name))
+            .as_ref()
+            .ok_or_else(|| "消息存储未启用".into())
+    }
+
+    /// 获取消息数据库，所有 MessageManager 共用同一个实例
+    pub async fn store(&self) -> Result<&'static MessageStore, Box<dyn Error + Send + Sync>> {
+        MessageStore::global(self.base_path()?).await
     }
 
-    async fn ensure_storage_path(
+    /// 把 base_path 下旧的 .log 文件导入消息库
+    pub async fn migrate_log_files(
         &self,
-        channel_name: &str,
-    ) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
-        let path = self.get_storage_path(channel_name);
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).await?;
-        }
-        Ok(path)
+    ) -> Result<LogMigrationReport, Box<dyn Error + Send + Sync>> {
+        self.store()
+            .await?
+            .migrate_log_files(self.base_path()?)
+            .await
+    }
+
+    /// 按策略清理数据库中的记录
+    pub async fn apply_storage_policy(
+        &self,
+        policy: &StoragePolicy,
+    ) -> Result<RetentionReport, Box<dyn Error + Send + Sync>> {
+        self.store()
+            .await?
+            .apply_policy(self.base_path()?, policy)
+            .await
     }
 
-    async fn start_storage_worker(&self, channel_id: String) {
+    /// 存储占用统计
+    pub async fn storage_usage(&self) -> Result<StorageUsage, Box<dyn Error + Send + Sync>> {
+        self.store().await?.usage(self.base_path()?).await
+    }
+
+    async fn start_storage_worker(&self, channel_id: String, base_path: PathBuf) {
         let write_queues = self.write_queues.clone();
-        let base_path = self.base_path.clone();
+        let notify_ui = self.notify_ui;
 
         tokio::spawn(async move {
             loop {
@@ -114,107 +327,37 @@ impl MessageManager {
                     continue;
                 }
 
-                // 获取消息并按照 channel_name 分组
-                let messages_by_channel =
-                    queue_lock.drain(..).fold(HashMap::new(), |mut acc, msg| {
-                        acc.entry(msg.channel_name.clone())
-                            .or_insert_with(Vec::new)
-                            .push(msg);
-                        acc
+                let messages: Vec<MessageRecord> = queue_lock.drain(..).collect();
+                let result = match MessageStore::global(&base_path).await {
+                    Ok(store) => store.insert_records(&messages).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    eprintln!(
+                        "Error writing messages to storage for channel {}: {:?}",
+                        channel_id, e
+                    );
+                    // 写入失败时，将消息放回队列，积压过多时丢弃最旧的记录
+                    queue_lock.splice(0..0, messages);
+                    let dropped = queue_lock.len().saturating_sub(MAX_PENDING_RECORDS);
+                    queue_lock.drain(..dropped);
+
+                    let payload = serde_json::json!({
+                        "channelId": channel_id,
+                        "error": e.to_string(),
+                        "pending": queue_lock.len(),
+                        "dropped": dropped,
                     });
-
-                // 对每个 channel_name 分别写入文件
-                for (channel_name, messages) in messages_by_channel {
-                    let today = Utc::now().format("%Y-%m-%d").to_string();
-                    let safe_channel_name = channel_name
-                        .replace(&['\\', '/', ':', '*', '?', '"', '<', '>', '|'][..], "_");
-                    let path = base_path
-                        .join(today)
-                        .join(format!("{}.log", safe_channel_name));
-
-                    if let Err(e) = Self::batch_write_messages(&path, &messages).await {
-                        eprintln!(
-                            "Error writing messages to storage for channel {}: {:?}",
-                            channel_name, e
-                        );
-                        // 写入失败时，将消息放回队列
-                        queue_lock.extend(messages);
+                    if notify_ui {
+                        if let Err(e) = emit_event("storage-error", payload) {
+                            eprintln!("发送存储错误事件失败: {:?}", e);
+                        }
                     }
                 }
             }
         });
     }
 
-    async fn batch_write_messages(
-        path: &PathBuf,
-        messages: &[MessageRecord],
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).await?;
-        }
-
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .await?;
-
-        for message in messages {
-            let content = message.content.get_content();
-
-            println!("content {:?}", content);
-            // 提取data字段并根据类型进行处理
-            let data = if let Some(data_value) = content.get("data") {
-                match data_value {
-                    // 如果是数组，转换为十六进制字符串
-                    serde_json::Value::Array(arr) => arr
-                        .iter()
-                        .filter_map(|num| num.as_u64())
-                        .map(|num| format!("{:02X}", num))
-                        .collect::<Vec<String>>()
-                        .join(" "),
-                    // 如果是字符串，直接使用
-                    serde_json::Value::String(s) => s.clone(),
-                    // 如果是对象或其他类型，转换为格式化的JSON字符串
-                    _ => serde_json::to_string_pretty(data_value)
-                        .unwrap_or_else(|_| data_value.to_string()),
-                }
-            } else {
-                "".to_string()
-            };
-
-            // 格式化时间
-            let datetime = DateTime::<Utc>::from_timestamp_millis(message.content.timestamp)
-                .unwrap_or(Utc::now());
-            let timestamp = datetime.format("%Y-%m-%d %H:%M:%S:%3f").to_string();
-
-            // 格式化方向
-            let direction = match message.direction {
-                MessageDirection::Sent => ">>>",
-                MessageDirection::Received => "<<<",
-            };
-
-            // 构建日志行
-            let log_line = format!(
-                "{} [{}] {} {}: {}\n",
-                timestamp,
-                message.channel_name, // TODO: 从metadata中获取地址信息
-                direction,
-                if direction == ">>>" {
-                    "发送"
-                } else {
-                    "接收"
-                },
-                data
-            );
-
-            file.write_all(log_line.as_bytes()).await?;
-        }
-
-        file.flush().await?;
-        Ok(())
-    }
-
     pub fn subscribe_to_messages(&self) -> broadcast::Receiver<MessageRecord> {
         self.message_sender.subscribe()
     }
@@ -228,13 +371,18 @@ impl MessageManager {
 
         if !active_channels.contains_key(channel_id) {
             active_channels.insert(channel_id.to_string(), true);
+            // 不保存时没有写入队列
+            let Some(base_path) = self.base_path.clone() else {
+                return Ok(());
+            };
             write_queues.insert(channel_id.to_string(), Arc::new(Mutex::new(Vec::new())));
 
-            // 确保存储路径存在
-            self.ensure_storage_path(channel_id).await?;
+            // 确保数据库已打开
+            self.store().await?;
 
             // 启动该通道的存储工作器
-            self.start_storage_worker(channel_id.to_string()).await;
+            self.start_storage_worker(channel_id.to_string(), base_path)
+                .await;
         }
 
         Ok(())
@@ -278,17 +426,6 @@ impl MessageManager {
             metadata: metadata.clone(),
         };
 
-        // 添加到历史记录
-        let mut history = self.message_history.lock().await;
-        history.push(message_record.clone());
-
-        // 限制历史记录大小
-        if history.len() > 1000 {
-            let split_at = history.len() - 1000;
-            let new_messages = history.split_off(split_at);
-            *history = new_messages;
-        }
-
         // 将消息添加到写入队列
         let write_queues = self.write_queues.read().await;
         if let Some(queue) = write_queues.get(channel_id) {
@@ -307,8 +444,16 @@ impl MessageManager {
             }
         }
 
+        // 实时抓包写入
+        pcap_export::write_live_record(&message_record);
+
+        // 通知订阅者，没有订阅者时忽略
+        let _ = self.message_sender.send(message_record.clone());
+
         // 发送消息事件通知前端
-        let app_handle = get_app_handle();
+        if !self.notify_ui {
+            return Ok(());
+        }
 
         // 创建一个前端可用的消息对象
         let frontend_message = serde_json::json!({
@@ -326,7 +471,7 @@ impl MessageManager {
             frontend_message
         );
         // 发送消息事件
-        match app_handle.emit("message-event", serde_json::to_string(&frontend_message)?) {
+        match emit_event("message-event", serde_json::to_string(&frontend_message)?) {
             Ok(_) => println!("消息事件已发送"),
             Err(e) => eprintln!("发送消息事件失败: {:?}", e),
         }
@@ -344,9 +489,9 @@ impl MessageManager {
             "metadata": record.metadata,
         });
         println!("Notifying UI: {:?}", payload);
-        self.app_handle
-            .emit("message-event", serde_json::to_string(&payload)?)
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?;
+        if self.notify_ui {
+            emit_event("message-event", serde_json::to_string(&payload)?)?;
+        }
         Ok(())
     }
 }
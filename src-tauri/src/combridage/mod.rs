@@ -1,19 +1,74 @@
 // Re-export the channel types
+mod address_book;
 mod bluetooth;
+mod bridge;
 mod commanger;
+mod correlation;
+mod message_store;
 mod messagemanager;
 mod mqtt;
+mod mqtt_session;
+mod pcap_export;
+mod reconnect;
+mod replay;
+mod rfc2217;
+mod scenario;
+mod serial_framing;
 mod serial_port;
+mod serial_server;
+mod storage_policy;
 mod tcp_client;
 mod tcp_server;
+mod timed_send;
+mod tls;
+mod udp;
+mod virtual_channel;
+mod websocket;
+mod websocket_client;
+mod websocket_server;
 
+pub use address_book::{AddressBook, ClientAddress};
 pub use bluetooth::BluetoothChannel;
-pub use commanger::CommunicationManager;
-pub use mqtt::MqttChannel;
+pub use bridge::{
+    Bridge, BridgeAction, BridgeDirection, BridgeFrame, BridgeOptions, BridgeOutcome, BridgeRule,
+    BridgeStats, BridgeStatus,
+};
+pub use commanger::{channel_from_params, CommunicationManager};
+pub use correlation::{Correlator, PendingResponse, ResponseKey};
+pub use message_store::{
+    LogMigrationReport, MessagePage, MessageQuery, MessageStore, StoredMessage,
+};
+pub use messagemanager::{subscribe_records, MessageDirection, MessageManager, MessageRecord};
+pub use mqtt::{MqttChannel, MqttConfig, MqttProperties, MqttVersion, MqttWill};
+pub use pcap_export::{
+    export_records, is_live_capture_active, start_live_capture, stop_live_capture,
+    MessagePcapExporter,
+};
+pub use reconnect::{OutageBuffer, ReconnectPolicy};
+pub use replay::{
+    build_steps, records_from_log, DivergenceKind, ReplayDivergence, ReplayEngine, ReplayOptions,
+    ReplayReport, ReplayStep, ReplayTiming,
+};
+pub use scenario::{
+    run_scenario, Condition, ConditionOp, Scenario, ScenarioReport, ScenarioStep, StandIn,
+    StandInReply, StepAction, StepResult,
+};
 use serde::{Deserialize, Serialize};
+pub use serial_framing::{FrameProtocol, SerialFramer, SerialFraming};
 pub use serial_port::SerialPortChannel;
+pub use serial_server::SerialServerChannel;
+pub use storage_policy::{
+    read_log_file, ChannelUsage, RetentionReport, StoragePolicy, StorageUsage,
+};
 pub use tcp_client::TcpClientChannel;
 pub use tcp_server::TcpServerChannel;
+pub use timed_send::{TimedSendJob, TimedSendOptions, TimedSendStatus};
+pub use tls::{TlsConfig, TlsSession};
+pub use udp::{UdpChannel, UdpMulticast};
+pub use virtual_channel::{set_virtual_impairment, VirtualChannel, VirtualEnd, VirtualImpairment};
+pub use websocket::WebSocketMode;
+pub use websocket_client::WebSocketClientChannel;
+pub use websocket_server::WebSocketServerChannel;
 // Define the CommunicationChannel trait here
 use async_trait::async_trait;
 use serde_json::Value;
@@ -24,6 +79,8 @@ use uuid::Uuid;
 pub enum ChannelState {
     Connected,
     Disconnected,
+    /// 连接断开后正在按重连策略重新连接
+    Reconnecting,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -42,6 +99,19 @@ impl Message {
         }
     }
 
+    // 使用指定的毫秒时间戳创建消息，用于从日志等记录中还原
+    pub fn with_timestamp(content: Value, timestamp: i64) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            content,
+            timestamp,
+        }
+    }
+
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
     // 添加 getter 方法，获取 content 字段
     pub fn get_content(&self) -> &Value {
         &self.content
@@ -56,13 +126,33 @@ impl Message {
     }
 }
 
+/// 创建通道时的可选参数
+#[derive(Clone, Debug, Default)]
+pub struct ChannelOptions {
+    /// 断线重连策略，只对 TCP 客户端、串口和 MQTT 生效
+    pub reconnect: ReconnectPolicy,
+    /// 串口接收分帧配置
+    pub framing: SerialFraming,
+    /// TLS 配置，只对 TCP 和 WebSocket 的客户端、服务端生效
+    pub tls: TlsConfig,
+    /// 虚拟通道本端发出数据的损伤设置
+    pub impairment: VirtualImpairment,
+    /// UDP 通道的组播设置
+    pub multicast: UdpMulticast,
+}
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Debug)]
 pub enum ChannelType {
-    TcpClient(String, u16),                                // Address, port
-    TcpServer(String, u16),                                // Address, port
+    TcpClient(String, u16),                                   // Address, port
+    TcpServer(String, u16),                                   // Address, port
     SerialPort(String, u32, u8, u8, String, u8), // Port name, baud rate, data bits, flowctrl, parity, stop bits
-    Mqtt(String, u16, String, String, String, u8, String), // ip, port, username, password, client id, qos, topic
+    Mqtt(Box<MqttConfig>), // Broker, credentials, subscribe topic, TLS and session options
     Bluetooth(String, String, String), // Device name or MAC address, service UUID, characteristic UUID
+    Udp(String, u16, String, u16, bool), // Local address, local port, default remote address, remote port, server mode
+    SerialServer(String, u16, u32, u8, u8, String, u8, bool), // Server address, port, baud rate, data bits, flowctrl, parity, stop bits, RFC 2217
+    WebSocketClient(String, WebSocketMode, u16), // ws:// or wss:// URL, message mode, ping interval (s)
+    WebSocketServer(String, u16, String, WebSocketMode, u16), // Address, port, path, message mode, ping interval (s)
+    Virtual(String, VirtualEnd),                              // Link name, endpoint
 }
 
 #[async_trait]
@@ -88,4 +178,12 @@ pub trait CommunicationChannel: Send + Sync {
         qos: u8,
     ) -> Result<(), Box<dyn Error + Send + Sync>>;
     async fn unsubscribe_topic(&self, topic: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// 服务端通道当前连接的客户端 ID，其他通道没有客户端
+    async fn client_ids(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// 服务端通道从客户端上送报文中识别出的终端/电表地址，其他通道为空
+    async fn client_addresses(&self) -> Vec<ClientAddress> {
+        Vec::new()
+    }
 }
@@ -1,54 +1,160 @@
+use crate::combridage::correlation::{Correlator, ResponseMatch};
 use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::mqtt_session::{MqttClient, MqttEvent, MqttPublish, MqttSession};
+use crate::combridage::reconnect::{OutageBuffer, ReconnectPolicy};
+use crate::combridage::tls::TlsConfig;
 use crate::combridage::CommunicationChannel;
 use crate::combridage::{ChannelState, Message};
-use crate::global::get_app_handle;
+use crate::global::emit_event;
 use async_trait::async_trait;
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
-use tauri::Emitter;
-use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
 use tokio::time::{sleep, timeout};
 use uuid::Uuid;
 
+/// MQTT 协议版本
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MqttVersion {
+    #[default]
+    #[serde(rename = "3.1.1")]
+    V311,
+    #[serde(rename = "5")]
+    V5,
+}
+
+/// 遗嘱消息，客户端异常断开时由服务器代为发布
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttWill {
+    pub topic: String,
+    pub payload: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// MQTT v5 消息属性，用于请求/应答关联，3.1.1 下忽略
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MqttProperties {
+    /// 应答方发布应答的主题
+    pub response_topic: Option<String>,
+    /// 关联数据，应答中原样带回
+    pub correlation_data: Option<String>,
+    pub content_type: Option<String>,
+    /// 消息有效期（秒）
+    pub message_expiry_interval: Option<u32>,
+    /// 用户属性，允许重复的键
+    pub user_properties: Vec<(String, String)>,
+}
+
+/// MQTT 通道配置，字段名与连接参数一致
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MqttConfig {
+    #[serde(rename = "ip")]
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    #[serde(rename = "clientid")]
+    pub client_id: String,
+    /// 订阅主题的 QoS
+    pub qos: u8,
+    /// 连接后订阅的主题
+    pub topic: String,
+    pub version: MqttVersion,
+    /// 心跳间隔（秒），0 表示不发送心跳
+    pub keep_alive: u16,
+    pub clean_session: bool,
+    pub will: Option<MqttWill>,
+    pub tls: TlsConfig,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            client_id: String::new(),
+            qos: 0,
+            topic: "#".to_string(),
+            version: MqttVersion::V311,
+            keep_alive: 5,
+            clean_session: true,
+            will: None,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MqttMessage {
     topic: String,
     payload: String,
     qos: u8,
     retain: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    properties: Option<MqttProperties>,
+}
+
+// 按 v5 关联数据匹配应答
+struct CorrelationData(String);
+
+impl ResponseMatch<MqttMessage> for CorrelationData {
+    fn matches(&self, response: &MqttMessage) -> bool {
+        response
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.correlation_data.as_deref())
+            == Some(self.0.as_str())
+    }
 }
 
+#[derive(Clone)]
 pub struct MqttChannel {
     channeltype: String,
     channelid: String,
     channel_name: String,
-    client: AsyncClient,
+    client: MqttClient,
     topic: String,
+    qos: u8,
+    // 是否通过 TLS 连接
+    tls: bool,
+    // 当前是否已连接，重连成功后通知发送任务补发缓存的数据
+    connected: Arc<watch::Sender<bool>>,
+    reconnect: ReconnectPolicy,
     shutdown_signal: broadcast::Sender<()>,
     tx_send: mpsc::Sender<MqttMessage>,
     rx_recv: Arc<Mutex<mpsc::Receiver<MqttMessage>>>,
-    topics: Arc<Mutex<HashMap<String, QoS>>>,
+    // 带关联数据的请求等待应答，其他消息照常进入接收队列
+    correlator: Correlator<CorrelationData, MqttMessage>,
+    topics: Arc<Mutex<HashMap<String, u8>>>,
 }
 
 impl MqttChannel {
     pub async fn new(
-        broker: &str,
-        port: u16,
-        username: &str,
-        password: &str,
-        client_id: &str,
-        qos: QoS,
-        topic: &str,
+        config: MqttConfig,
+        reconnect: ReconnectPolicy,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        let mut mqttoptions = MqttOptions::new(client_id, broker, port);
-        mqttoptions.set_keep_alive(Duration::from_secs(5));
-
-        let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
-        client.subscribe(topic, qos).await?;
+        let broker = config.host.clone();
+        let port = config.port;
+        let topic = config.topic.clone();
+        let qos = config.qos;
+
+        // 首次连接失败时直接返回错误，之后的断线按重连策略处理
+        let (client, mut session) = MqttSession::new(&config, 100)?;
+        let MqttEvent::Connected = session.poll().await? else {
+            return Err("MQTT 连接失败".into());
+        };
+        client.subscribe(&topic, qos).await?;
 
         let (shutdown_signal, _) = broadcast::channel(1);
         let (tx_send, rx_send) = mpsc::channel(100); // 发送队列
@@ -59,123 +165,146 @@ impl MqttChannel {
             channelid: "mqtt".to_string() + &Uuid::new_v4().to_string(),
             channel_name: "mqtt".to_string() + &broker.to_string() + ":" + &port.to_string(),
             client: client.clone(),
-            topic: topic.to_string(),
+            topic,
+            qos,
+            tls: config.tls.enabled,
+            connected: Arc::new(watch::channel(true).0),
+            reconnect,
             shutdown_signal,
             tx_send,
             rx_recv: Arc::new(Mutex::new(rx_recv)),
+            correlator: Correlator::new(),
             topics: Arc::new(Mutex::new(HashMap::new())),
         };
 
         // 初始化消息管理器
-        let app_handle = get_app_handle();
-        let message_manager = MessageManager::new(app_handle)?;
+        let message_manager = MessageManager::from_global()?;
         let _ = message_manager
             .register_channel(&format!("{}:{}", broker, port))
             .await;
 
         // 启动发送任务
-        let send_client = client.clone();
-        let channeltype = channel.channeltype.clone();
-        let channelid = channel.channelid.clone();
-        let channel_name = channel.channel_name.clone();
+        let channel_send = channel.clone();
         let message_manager_send = message_manager.clone();
         tokio::spawn(async move {
-            if let Err(e) = Self::send_task(
-                send_client,
-                rx_send,
-                channeltype,
-                channelid,
-                channel_name,
-                message_manager_send,
-            )
-            .await
-            {
+            if let Err(e) = channel_send.send_task(rx_send, message_manager_send).await {
                 eprintln!("MQTT send task error: {}", e);
             }
         });
 
         // 启动接收任务
-        let channeltype = channel.channeltype.clone();
-        let channelid = channel.channelid.clone();
-        let channel_name = channel.channel_name.clone();
+        let channel_recv = channel.clone();
         let message_manager_rec = message_manager.clone();
-        let shutdown = channel.shutdown_signal.subscribe();
         tokio::spawn(async move {
-            if let Err(e) = Self::receive_task(
-                eventloop,
-                tx_recv,
-                channeltype,
-                channelid,
-                channel_name,
-                message_manager_rec,
-                shutdown,
-            )
-            .await
+            if let Err(e) = channel_recv
+                .receive_task(session, tx_recv, message_manager_rec)
+                .await
             {
                 eprintln!("MQTT receive task error: {}", e);
             }
         });
 
+        if let Err(e) = channel.on_statechange(ChannelState::Connected).await {
+            eprintln!("Failed to send connect event: {:?}", e);
+        }
         Ok(channel)
     }
 
-    // 发送任务
+    // 发送任务，断线期间按策略缓存，重连后补发
     async fn send_task(
-        client: AsyncClient,
+        &self,
         mut rx_send: mpsc::Receiver<MqttMessage>,
-        channeltype: String,
-        channelid: String,
-        channel_name: String,
         message_manager: MessageManager,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        while let Some(data) = rx_send.recv().await {
-            client
-                .publish(
-                    &data.topic,
-                    QoSLevel::from(data.qos).into_inner(),
-                    data.retain,
-                    data.payload.as_bytes(),
-                )
-                .await?;
-
-            let payload = serde_json::json!({
-                "data": data,
-                "topic": data.topic,
-                "qos": u8::from(QoSLevel::from(data.qos))
-            });
-            let message = Message::new(payload);
-
-            if let Err(e) = timeout(
-                Duration::from_secs(1),
-                message_manager.record_message(
-                    &channeltype,
-                    &channelid,
-                    &channel_name,
-                    &message,
-                    MessageDirection::Sent,
-                    None,
-                ),
-            )
-            .await
-            {
-                eprintln!("Timeout recording sent message: {:?}", e);
+        let mut connected = self.connected.subscribe();
+        let mut shutdown = self.shutdown_signal.subscribe();
+        let mut pending = OutageBuffer::new(self.reconnect.max_buffered);
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => return Ok(()),
+                changed = connected.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    if !*connected.borrow_and_update() || pending.is_empty() {
+                        continue;
+                    }
+                    let (buffered, dropped) = pending.drain();
+                    if dropped > 0 {
+                        eprintln!("MQTT dropped {} buffered messages during outage", dropped);
+                    }
+                    for data in buffered {
+                        self.publish(data, &message_manager).await?;
+                    }
+                }
+                data = rx_send.recv() => {
+                    let Some(data) = data else {
+                        return Ok(());
+                    };
+                    if *connected.borrow() {
+                        self.publish(data, &message_manager).await?;
+                    } else if self.reconnect.buffers() {
+                        pending.push(data);
+                    } else {
+                        eprintln!("MQTT disconnected, dropped message to {}", data.topic);
+                    }
+                }
             }
         }
+    }
+
+    async fn publish(
+        &self,
+        data: MqttMessage,
+        message_manager: &MessageManager,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.client
+            .publish(MqttPublish {
+                topic: data.topic.clone(),
+                payload: data.payload.clone().into_bytes(),
+                qos: data.qos,
+                retain: data.retain,
+                properties: data.properties.clone(),
+            })
+            .await?;
+
+        let payload = serde_json::json!({
+            "data": data,
+            "topic": data.topic,
+            "qos": u8::from(QoSLevel::from(data.qos))
+        });
+        let message = Message::new(payload);
+
+        if let Err(e) = timeout(
+            Duration::from_secs(1),
+            message_manager.record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &message,
+                MessageDirection::Sent,
+                None,
+            ),
+        )
+        .await
+        {
+            eprintln!("Timeout recording sent message: {:?}", e);
+        }
         Ok(())
     }
 
     // 接收任务
     async fn receive_task(
-        mut eventloop: rumqttc::EventLoop,
+        &self,
+        mut session: MqttSession,
         tx_recv: mpsc::Sender<MqttMessage>,
-        channeltype: String,
-        channelid: String,
-        channel_name: String,
         message_manager: MessageManager,
-        mut shutdown: broadcast::Receiver<()>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut shutdown = self.shutdown_signal.subscribe();
         let mut dropped_messages = 0;
         let mut last_log_time = std::time::Instant::now();
+        // 连续重连失败的次数
+        let mut attempt = 0;
 
         loop {
             tokio::select! {
@@ -183,15 +312,17 @@ impl MqttChannel {
                     println!("MQTT receive task received shutdown signal");
                     return Ok(());
                 }
-                event = eventloop.poll() => {
+                event = session.poll() => {
                     match event {
-                        Ok(Event::Incoming(Packet::Publish(publish))) => {
-                            let received_data = publish.payload.to_vec();
+                        Ok(MqttEvent::Publish(publish)) => {
+                            let received_data = publish.payload;
 
                             let payload = serde_json::json!({
                                 "data": received_data.clone(),
                                 "topic": publish.topic,
-                                "qos": u8::from(QoSLevel::from(publish.qos))
+                                "qos": publish.qos,
+                                "retain": publish.retain,
+                                "properties": publish.properties,
                             });
                             let message = Message::new(payload);
 
@@ -199,9 +330,9 @@ impl MqttChannel {
                             if let Err(e) = timeout(
                                 Duration::from_secs(1),
                                 message_manager.record_message(
-                                    &channeltype,
-                                    &channelid,
-                                    &channel_name,
+                                    &self.channeltype,
+                                    &self.channelid,
+                                    &self.channel_name,
                                     &message,
                                     MessageDirection::Received,
                                     None
@@ -210,10 +341,15 @@ impl MqttChannel {
                                 eprintln!("Timeout recording received message: {:?}", e);
                             }
                             let mqtt_message = MqttMessage {
-                                topic: publish.topic.to_string(),
+                                topic: publish.topic,
                                 payload: String::from_utf8(received_data).unwrap_or_default(),
-                                qos: u8::from(QoSLevel::from(publish.qos)),
-                                retain: publish.retain
+                                qos: publish.qos,
+                                retain: publish.retain,
+                                properties: publish.properties,
+                            };
+
+                            let Some(mqtt_message) = self.correlator.offer(mqtt_message) else {
+                                continue;
                             };
 
                             // 使用 try_send 发送到接收队列
@@ -233,10 +369,31 @@ impl MqttChannel {
                                 }
                             }
                         }
-                        Ok(_) => continue,
+                        Ok(MqttEvent::Connected) => {
+                            // 重连成功，重新订阅主题
+                            println!("MQTT reconnected after {} attempts", attempt);
+                            attempt = 0;
+                            self.resubscribe().await;
+                            self.connected.send_replace(true);
+                            let _ = self.on_statechange(ChannelState::Connected).await;
+                        }
+                        Ok(MqttEvent::Disconnected) => return Ok(()),
                         Err(e) => {
                             eprintln!("MQTT event loop error: {:?}", e);
-                            return Err(Box::new(e));
+                            attempt += 1;
+                            let Some(delay) = self.reconnect.backoff(attempt) else {
+                                self.connected.send_replace(false);
+                                let _ = self.on_statechange(ChannelState::Disconnected).await;
+                                return Err(e);
+                            };
+                            self.connected.send_replace(false);
+                            let _ = self.on_statechange(ChannelState::Reconnecting).await;
+                            println!("MQTT reconnecting in {:?} (attempt {})", delay, attempt);
+                            // 等待后再次 poll，会话会自动重新连接
+                            tokio::select! {
+                                _ = shutdown.recv() => return Ok(()),
+                                _ = sleep(delay) => {}
+                            }
                         }
                     }
                 }
@@ -244,24 +401,41 @@ impl MqttChannel {
         }
     }
 
+    // 重新订阅连接时的主题及之后订阅的主题
+    async fn resubscribe(&self) {
+        let mut topics = vec![(self.topic.clone(), self.qos)];
+        topics.extend(
+            self.topics
+                .lock()
+                .await
+                .iter()
+                .map(|(topic, qos)| (topic.clone(), *qos)),
+        );
+        for (topic, qos) in topics {
+            if let Err(e) = self.client.subscribe(&topic, qos).await {
+                eprintln!("MQTT resubscribe {} failed: {:?}", topic, e);
+            }
+        }
+    }
+
     pub async fn set_subscriber(
         &mut self,
         topic: &str,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        self.client.subscribe(topic, QoS::AtLeastOnce).await?;
+        self.client.subscribe(topic, 1).await?;
         Ok(())
     }
 
     pub async fn disconnect_mqtt(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // 先发送关闭信号，通知所有任务准备关闭
-        let _ = self.shutdown_signal.send(());
+        // 先请求会话发送 DISCONNECT，正常断开时服务器不会发布遗嘱消息
+        // 使用timeout避免永久阻塞，如果出现错误，记录但不返回失败
+        let result = timeout(Duration::from_secs(2), self.client.disconnect()).await;
 
-        // 等待任务正常退出
+        // 等待接收任务处理断开请求，再发送关闭信号通知所有任务退出
         sleep(Duration::from_millis(300)).await;
+        let _ = self.shutdown_signal.send(());
 
-        // 尝试断开MQTT连接，但使用timeout避免永久阻塞
-        // 如果出现错误，记录但不返回失败
-        match timeout(Duration::from_secs(2), self.client.disconnect()).await {
+        match result {
             Ok(result) => {
                 if let Err(e) = result {
                     eprintln!("MQTT断开连接时发生错误，但将继续处理: {}", e);
@@ -334,15 +508,27 @@ impl CommunicationChannel for MqttChannel {
         let payload = content["data"]["payload"]
             .as_str()
             .unwrap_or("default_payload");
-        let qos_level = QoSLevel::from(qos as u8);
         let retain = content["data"]["retain"].as_bool().unwrap_or(false);
+        // v5 属性，3.1.1 连接下发送时忽略
+        let properties = match content["data"].get("properties") {
+            Some(value) if !value.is_null() => Some(
+                serde_json::from_value::<MqttProperties>(value.clone())
+                    .map_err(|e| format!("Invalid MQTT properties: {}", e))?,
+            ),
+            _ => None,
+        };
+
+        if !*self.connected.borrow() && !self.reconnect.buffers() {
+            return Err("MQTT 连接已断开".into());
+        }
 
         self.tx_send
             .send(MqttMessage {
                 topic: topic.to_string(),
                 payload: payload.to_string(),
-                qos: qos as u8,
-                retain: retain,
+                qos: u8::from(QoSLevel::from(qos as u8)),
+                retain,
+                properties,
             })
             .await
             .map_err(|e| {
@@ -369,8 +555,26 @@ impl CommunicationChannel for MqttChannel {
         message: &Message,
         timeout_secs: u64,
     ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let properties = &message.get_content()["data"]["properties"];
+        let correlation = properties["correlationData"].as_str().map(str::to_string);
+        // 应答主题尚未订阅时先订阅，否则收不到应答
+        if let Some(response_topic) = properties["responseTopic"].as_str() {
+            if response_topic != self.topic
+                && !self.topics.lock().await.contains_key(response_topic)
+            {
+                self.subscribe_topic(response_topic, self.qos).await?;
+            }
+        }
+
+        let Some(correlation) = correlation else {
+            self.send(message, None).await?;
+            return tokio::time::timeout(Duration::from_secs(timeout_secs), self.receive()).await?;
+        };
+        // 带关联数据的请求只接受关联数据相同的应答，期间收到的其他消息照常进入接收队列
+        let pending = self.correlator.expect(CorrelationData(correlation));
         self.send(message, None).await?;
-        tokio::time::timeout(Duration::from_secs(timeout_secs), self.receive()).await?
+        let reply = pending.wait(timeout_secs).await?;
+        Ok(Message::new(serde_json::json!({ "data": reply })))
     }
 
     async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -382,18 +586,23 @@ impl CommunicationChannel for MqttChannel {
         &self,
         state: ChannelState,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let app_handle = get_app_handle();
+        let reason = match state {
+            ChannelState::Connected => "MQTT channel connected",
+            ChannelState::Disconnected => "MQTT channel disconnected",
+            ChannelState::Reconnecting => "MQTT channel reconnecting",
+        };
         let payload = serde_json::json!({
             "channeltype": "mqtt",
             "channelId": self.channelid.clone(),
             "state": state,
             "data": {
-                "topic": self.topic
+                "topic": self.topic,
+                "tls": self.tls
             },
-            "reason": "MQTT channel state changed"
+            "reason": reason
         });
 
-        app_handle.emit("channel-state", serde_json::to_string(&payload)?)?;
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
         Ok(())
     }
 
@@ -406,13 +615,9 @@ impl CommunicationChannel for MqttChannel {
         topic: &str,
         qos: u8,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        self.client
-            .subscribe(topic, QoSLevel::from(qos).into_inner())
-            .await?;
-        self.topics
-            .lock()
-            .await
-            .insert(topic.to_string(), QoSLevel::from(qos).into_inner());
+        let qos = u8::from(QoSLevel::from(qos));
+        self.client.subscribe(topic, qos).await?;
+        self.topics.lock().await.insert(topic.to_string(), qos);
         println!("MQTT subscribe topic: {}", topic);
         Ok(())
     }
@@ -424,3 +629,51 @@ impl CommunicationChannel for MqttChannel {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(correlation_data: Option<&str>, payload: &str) -> MqttMessage {
+        MqttMessage {
+            topic: "meter/reply/1".to_string(),
+            payload: payload.to_string(),
+            qos: 1,
+            retain: false,
+            properties: correlation_data.map(|data| MqttProperties {
+                correlation_data: Some(data.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn correlation_data_must_match_exactly() {
+        let key = CorrelationData("req-1".to_string());
+        assert!(key.matches(&reply(Some("req-1"), "")));
+        assert!(!key.matches(&reply(Some("req-10"), "")));
+        assert!(!key.matches(&reply(None, "")));
+        let mut without_data = reply(Some("req-1"), "");
+        without_data.properties = Some(MqttProperties::default());
+        assert!(!key.matches(&without_data));
+    }
+
+    #[tokio::test]
+    async fn reply_is_routed_to_waiter() {
+        let correlator = Correlator::<CorrelationData, MqttMessage>::new();
+        let first = correlator.expect(CorrelationData("req-1".to_string()));
+        let second = correlator.expect(CorrelationData("req-2".to_string()));
+
+        // 不相关的消息继续进入接收队列
+        assert!(correlator.offer(reply(None, "plain")).is_some());
+        assert!(correlator.offer(reply(Some("req-3"), "other")).is_some());
+
+        assert!(correlator.offer(reply(Some("req-2"), "second")).is_none());
+        assert!(correlator.offer(reply(Some("req-1"), "first")).is_none());
+        assert_eq!(second.wait(1).await.unwrap().payload, "second");
+        assert_eq!(first.wait(1).await.unwrap().payload, "first");
+
+        // 应答已被取走，重复的应答不再被拦截
+        assert!(correlator.offer(reply(Some("req-1"), "again")).is_some());
+    }
+}
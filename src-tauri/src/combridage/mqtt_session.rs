@@ -0,0 +1,537 @@
+use crate::combridage::mqtt::{MqttConfig, MqttProperties, MqttVersion};
+use bytes::Bytes;
+use rumqttc::v5;
+use rumqttc::v5::mqttbytes::v5 as packet_v5;
+use rumqttc::{NetworkOptions, TlsConfiguration, Transport};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+
+// 单个报文的最大长度
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+// 建立连接（含 TLS 握手）的超时时间（秒）
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 发布或收到的一条消息
+#[derive(Debug, Clone)]
+pub(crate) struct MqttPublish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+    /// MQTT v5 属性，3.1.1 下忽略
+    pub properties: Option<MqttProperties>,
+}
+
+pub(crate) enum MqttEvent {
+    /// 收到 CONNACK
+    Connected,
+    Publish(MqttPublish),
+    /// 主动断开连接
+    Disconnected,
+}
+
+#[derive(Clone)]
+enum Client {
+    V311(rumqttc::AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+enum EventLoop {
+    V311(Box<rumqttc::EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+// 两个协议版本的事件中会话关心的部分
+enum SessionEvent {
+    ConnAck { session_present: bool },
+    Publish(MqttPublish),
+    SubscribeSent(u16),
+    SubAck(u16, Result<(), String>),
+    DisconnectSent,
+    // 所有客户端句柄已释放
+    Closed,
+    Other,
+}
+
+type SubscribeWaiter = oneshot::Sender<Result<(), String>>;
+
+// 等待 SUBACK 的订阅
+#[derive(Default)]
+struct PendingSubscribes {
+    // 已提交、尚未发出的订阅，与事件循环处理请求的顺序一致
+    queued: VecDeque<SubscribeWaiter>,
+    // 已发出的订阅，按报文标识符索引
+    sent: HashMap<u16, SubscribeWaiter>,
+    // 连接断开时尚未发出的订阅数，新会话中这些请求会被丢弃
+    stale: usize,
+}
+
+impl PendingSubscribes {
+    fn sent(&mut self, pkid: u16) {
+        if let Some(waiter) = self.queued.pop_front() {
+            self.sent.insert(pkid, waiter);
+        }
+        self.stale = self.stale.saturating_sub(1);
+    }
+
+    fn acked(&mut self, pkid: u16, result: Result<(), String>) {
+        if let Some(waiter) = self.sent.remove(&pkid) {
+            let _ = waiter.send(result);
+        }
+    }
+
+    // 已发出的订阅断线后不会重发
+    fn connection_lost(&mut self) {
+        for (_, waiter) in self.sent.drain() {
+            let _ = waiter.send(Err("连接断开，订阅未得到确认".to_string()));
+        }
+        self.stale = self.queued.len();
+    }
+
+    fn connected(&mut self, session_present: bool) {
+        if !session_present {
+            for waiter in self.queued.drain(..self.stale) {
+                let _ = waiter.send(Err("连接断开，订阅请求已丢弃".to_string()));
+            }
+        }
+        self.stale = 0;
+    }
+}
+
+/// 向会话提交请求，可在多个任务间共享
+#[derive(Clone)]
+pub(crate) struct MqttClient {
+    client: Client,
+    subscribes: Arc<Mutex<PendingSubscribes>>,
+    // 保证订阅请求进入事件循环的顺序与登记的顺序一致
+    subscribe_order: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl MqttClient {
+    /// 提交发布请求；QoS 1/2 的消息由事件循环保存，断线重连后重发
+    pub(crate) async fn publish(
+        &self,
+        publish: MqttPublish,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match &self.client {
+            Client::V311(client) => {
+                client
+                    .publish(
+                        publish.topic,
+                        qos_v4(publish.qos),
+                        publish.retain,
+                        publish.payload,
+                    )
+                    .await?
+            }
+            Client::V5(client) => match publish.properties {
+                Some(properties) => {
+                    client
+                        .publish_with_properties(
+                            publish.topic,
+                            qos_v5(publish.qos),
+                            publish.retain,
+                            publish.payload,
+                            publish_properties(properties),
+                        )
+                        .await?
+                }
+                None => {
+                    client
+                        .publish(
+                            publish.topic,
+                            qos_v5(publish.qos),
+                            publish.retain,
+                            publish.payload,
+                        )
+                        .await?
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// 订阅主题并等待 SUBACK，服务器拒绝订阅时返回错误
+    ///
+    /// 需要有任务在调用 `MqttSession::poll`，否则等不到应答
+    pub(crate) async fn subscribe(
+        &self,
+        topic: &str,
+        qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let _order = self.subscribe_order.lock().await;
+            self.subscribes.lock().unwrap().queued.push_back(tx);
+            let result = match &self.client {
+                Client::V311(client) => client
+                    .subscribe(topic, qos_v4(qos))
+                    .await
+                    .map_err(|e| e.to_string()),
+                Client::V5(client) => client
+                    .subscribe(topic, qos_v5(qos))
+                    .await
+                    .map_err(|e| e.to_string()),
+            };
+            if let Err(e) = result {
+                // 请求未进入事件循环，撤销登记
+                self.subscribes.lock().unwrap().queued.pop_back();
+                return Err(format!("MQTT 订阅 {} 失败: {}", topic, e).into());
+            }
+        }
+        match timeout(SUBSCRIBE_TIMEOUT, rx).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(reason))) => Err(format!("MQTT 订阅 {} 失败: {}", topic, reason).into()),
+            Ok(Err(_)) => Err("MQTT 会话已结束".into()),
+            Err(_) => Err(format!("MQTT 订阅 {} 超时", topic).into()),
+        }
+    }
+
+    pub(crate) async fn unsubscribe(
+        &self,
+        topic: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match &self.client {
+            Client::V311(client) => client.unsubscribe(topic).await?,
+            Client::V5(client) => client.unsubscribe(topic).await?,
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn disconnect(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match &self.client {
+            Client::V311(client) => client.disconnect().await?,
+            Client::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+/// MQTT 3.1.1 / 5 客户端会话，基于 rumqttc 的事件循环
+///
+/// 循环调用 `poll`，出错后再次调用即重新连接；未确认的 QoS 1/2 消息在重连后重发
+pub(crate) struct MqttSession {
+    event_loop: EventLoop,
+    subscribes: Arc<Mutex<PendingSubscribes>>,
+    connected: bool,
+}
+
+impl MqttSession {
+    pub(crate) fn new(
+        config: &MqttConfig,
+        capacity: usize,
+    ) -> Result<(MqttClient, MqttSession), Box<dyn Error + Send + Sync>> {
+        if !config.clean_session && config.client_id.is_empty() {
+            return Err("保留会话（clean session 关闭）时必须指定 client id".into());
+        }
+        let transport = match config.tls.client_config(&config.host)? {
+            Some(tls) => Transport::tls_with_config(TlsConfiguration::Rustls(tls)),
+            None => Transport::tcp(),
+        };
+        let mut network = NetworkOptions::new();
+        network.set_tcp_nodelay(true);
+        network.set_connection_timeout(CONNECT_TIMEOUT_SECS);
+        let keep_alive = Duration::from_secs(config.keep_alive as u64);
+
+        let (client, event_loop) = match config.version {
+            MqttVersion::V311 => {
+                let mut options = rumqttc::MqttOptions::new(
+                    config.client_id.as_str(),
+                    config.host.as_str(),
+                    config.port,
+                );
+                options
+                    .set_transport(transport)
+                    .set_keep_alive(keep_alive)
+                    .set_clean_session(config.clean_session)
+                    .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+                if !config.username.is_empty() {
+                    options.set_credentials(config.username.as_str(), config.password.as_str());
+                }
+                if let Some(will) = &config.will {
+                    options.set_last_will(rumqttc::LastWill::new(
+                        will.topic.as_str(),
+                        will.payload.as_bytes(),
+                        qos_v4(will.qos),
+                        will.retain,
+                    ));
+                }
+                let (client, mut event_loop) = rumqttc::AsyncClient::new(options, capacity);
+                event_loop.set_network_options(network);
+                (Client::V311(client), EventLoop::V311(Box::new(event_loop)))
+            }
+            MqttVersion::V5 => {
+                let mut options = v5::MqttOptions::new(
+                    config.client_id.as_str(),
+                    config.host.as_str(),
+                    config.port,
+                );
+                options
+                    .set_transport(transport)
+                    .set_keep_alive(keep_alive)
+                    .set_clean_start(config.clean_session)
+                    .set_max_packet_size(Some(MAX_PACKET_SIZE as u32))
+                    .set_connection_timeout(CONNECT_TIMEOUT_SECS)
+                    .set_network_options(network);
+                if !config.username.is_empty() {
+                    options.set_credentials(config.username.as_str(), config.password.as_str());
+                }
+                if let Some(will) = &config.will {
+                    options.set_last_will(packet_v5::LastWill::new(
+                        will.topic.as_str(),
+                        will.payload.as_bytes(),
+                        qos_v5(will.qos),
+                        will.retain,
+                        None,
+                    ));
+                }
+                let (client, event_loop) = v5::AsyncClient::new(options, capacity);
+                (Client::V5(client), EventLoop::V5(Box::new(event_loop)))
+            }
+        };
+
+        let subscribes = Arc::new(Mutex::new(PendingSubscribes::default()));
+        let client = MqttClient {
+            client,
+            subscribes: subscribes.clone(),
+            subscribe_order: Arc::new(tokio::sync::Mutex::new(())),
+        };
+        let session = Self {
+            event_loop,
+            subscribes,
+            connected: false,
+        };
+        Ok((client, session))
+    }
+
+    pub(crate) async fn poll(&mut self) -> Result<MqttEvent, Box<dyn Error + Send + Sync>> {
+        loop {
+            let event = match self.poll_event().await {
+                Ok(event) => event,
+                Err(e) => {
+                    if std::mem::take(&mut self.connected) {
+                        self.subscribes.lock().unwrap().connection_lost();
+                    }
+                    return Err(e);
+                }
+            };
+            match event {
+                SessionEvent::ConnAck { session_present } => {
+                    self.connected = true;
+                    self.subscribes.lock().unwrap().connected(session_present);
+                    return Ok(MqttEvent::Connected);
+                }
+                SessionEvent::Publish(publish) => return Ok(MqttEvent::Publish(publish)),
+                SessionEvent::SubscribeSent(pkid) => self.subscribes.lock().unwrap().sent(pkid),
+                SessionEvent::SubAck(pkid, result) => {
+                    self.subscribes.lock().unwrap().acked(pkid, result)
+                }
+                SessionEvent::DisconnectSent | SessionEvent::Closed => {
+                    return Ok(MqttEvent::Disconnected)
+                }
+                SessionEvent::Other => {}
+            }
+        }
+    }
+
+    async fn poll_event(&mut self) -> Result<SessionEvent, Box<dyn Error + Send + Sync>> {
+        let event = match &mut self.event_loop {
+            EventLoop::V311(event_loop) => match event_loop.poll().await {
+                Ok(event) => v4_event(event),
+                Err(rumqttc::ConnectionError::RequestsDone) => SessionEvent::Closed,
+                Err(e) => return Err(format!("MQTT 连接错误: {}", e).into()),
+            },
+            EventLoop::V5(event_loop) => match event_loop.poll().await {
+                Ok(event) => v5_event(event),
+                Err(v5::ConnectionError::RequestsDone) => SessionEvent::Closed,
+                Err(e) => return Err(format!("MQTT 连接错误: {}", e).into()),
+            },
+        };
+        Ok(event)
+    }
+}
+
+fn v4_event(event: rumqttc::Event) -> SessionEvent {
+    use rumqttc::{Event, Outgoing, Packet, SubscribeReasonCode};
+    match event {
+        Event::Incoming(Packet::ConnAck(connack)) => SessionEvent::ConnAck {
+            session_present: connack.session_present,
+        },
+        Event::Incoming(Packet::Publish(publish)) => SessionEvent::Publish(MqttPublish {
+            topic: publish.topic,
+            payload: publish.payload.to_vec(),
+            qos: publish.qos as u8,
+            retain: publish.retain,
+            properties: None,
+        }),
+        Event::Incoming(Packet::SubAck(suback)) => {
+            let result = if suback.return_codes.contains(&SubscribeReasonCode::Failure) {
+                Err("服务器拒绝订阅".to_string())
+            } else {
+                Ok(())
+            };
+            SessionEvent::SubAck(suback.pkid, result)
+        }
+        Event::Incoming(Packet::Disconnect) => {
+            eprintln!("MQTT 服务器断开连接");
+            SessionEvent::Other
+        }
+        Event::Outgoing(Outgoing::Subscribe(pkid)) => SessionEvent::SubscribeSent(pkid),
+        Event::Outgoing(Outgoing::Disconnect) => SessionEvent::DisconnectSent,
+        _ => SessionEvent::Other,
+    }
+}
+
+fn v5_event(event: v5::Event) -> SessionEvent {
+    use packet_v5::{Packet, SubscribeReasonCode};
+    use rumqttc::Outgoing;
+    match event {
+        v5::Event::Incoming(Packet::ConnAck(connack)) => SessionEvent::ConnAck {
+            session_present: connack.session_present,
+        },
+        v5::Event::Incoming(Packet::Publish(publish)) => SessionEvent::Publish(MqttPublish {
+            topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+            payload: publish.payload.to_vec(),
+            qos: publish.qos as u8,
+            retain: publish.retain,
+            properties: publish.properties.map(|properties| MqttProperties {
+                response_topic: properties.response_topic,
+                correlation_data: properties
+                    .correlation_data
+                    .map(|data| String::from_utf8_lossy(&data).into_owned()),
+                content_type: properties.content_type,
+                message_expiry_interval: properties.message_expiry_interval,
+                user_properties: properties.user_properties,
+            }),
+        }),
+        v5::Event::Incoming(Packet::SubAck(suback)) => {
+            let rejected: Vec<_> = suback
+                .return_codes
+                .iter()
+                .filter(|code| !matches!(code, SubscribeReasonCode::Success(_)))
+                .collect();
+            let result = if rejected.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("服务器拒绝订阅: {:?}", rejected))
+            };
+            SessionEvent::SubAck(suback.pkid, result)
+        }
+        v5::Event::Incoming(Packet::Disconnect(disconnect)) => {
+            eprintln!("MQTT 服务器断开连接: {:?}", disconnect.reason_code);
+            SessionEvent::Other
+        }
+        v5::Event::Outgoing(Outgoing::Subscribe(pkid)) => SessionEvent::SubscribeSent(pkid),
+        v5::Event::Outgoing(Outgoing::Disconnect) => SessionEvent::DisconnectSent,
+        _ => SessionEvent::Other,
+    }
+}
+
+fn publish_properties(properties: MqttProperties) -> packet_v5::PublishProperties {
+    packet_v5::PublishProperties {
+        response_topic: properties.response_topic,
+        correlation_data: properties
+            .correlation_data
+            .map(|data| Bytes::from(data.into_bytes())),
+        content_type: properties.content_type,
+        message_expiry_interval: properties.message_expiry_interval,
+        user_properties: properties.user_properties,
+        payload_format_indicator: None,
+        topic_alias: None,
+        subscription_identifiers: Vec::new(),
+    }
+}
+
+fn qos_v4(qos: u8) -> rumqttc::QoS {
+    match qos {
+        0 => rumqttc::QoS::AtMostOnce,
+        2 => rumqttc::QoS::ExactlyOnce,
+        _ => rumqttc::QoS::AtLeastOnce,
+    }
+}
+
+fn qos_v5(qos: u8) -> v5::mqttbytes::QoS {
+    match qos {
+        0 => v5::mqttbytes::QoS::AtMostOnce,
+        2 => v5::mqttbytes::QoS::ExactlyOnce,
+        _ => v5::mqttbytes::QoS::AtLeastOnce,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> MqttProperties {
+        MqttProperties {
+            response_topic: Some("meter/reply/1".to_string()),
+            correlation_data: Some("req-1".to_string()),
+            content_type: Some("application/octet-stream".to_string()),
+            message_expiry_interval: Some(30),
+            user_properties: vec![
+                ("meter".to_string(), "000000000001".to_string()),
+                ("meter".to_string(), "000000000002".to_string()),
+            ],
+        }
+    }
+
+    fn incoming(publish: packet_v5::Publish) -> MqttPublish {
+        match v5_event(v5::Event::Incoming(packet_v5::Packet::Publish(publish))) {
+            SessionEvent::Publish(publish) => publish,
+            _ => panic!("不是 PUBLISH 事件"),
+        }
+    }
+
+    #[test]
+    fn v5_properties_round_trip() {
+        let publish = packet_v5::Publish::new(
+            "meter/reply/1",
+            qos_v5(1),
+            Bytes::from_static(b"\x68\x16"),
+            Some(publish_properties(properties())),
+        );
+        let publish = incoming(publish);
+        assert_eq!(publish.topic, "meter/reply/1");
+        assert_eq!(
+            (publish.qos, publish.payload.as_slice()),
+            (1, &b"\x68\x16"[..])
+        );
+        assert_eq!(publish.properties, Some(properties()));
+
+        // 不带属性的消息
+        let publish = incoming(packet_v5::Publish::new("t", qos_v5(0), "x", None));
+        assert_eq!(publish.properties, None);
+    }
+
+    #[test]
+    fn binary_correlation_data_is_decoded_lossily() {
+        let mut properties = publish_properties(MqttProperties::default());
+        properties.correlation_data = Some(Bytes::from_static(b"id-\xFF"));
+        let publish = incoming(packet_v5::Publish::new(
+            "t",
+            qos_v5(2),
+            "x",
+            Some(properties),
+        ));
+        let properties = publish.properties.unwrap();
+        assert_eq!(properties.correlation_data.as_deref(), Some("id-\u{FFFD}"));
+        assert_eq!(properties.response_topic, None);
+        assert_eq!(publish.qos, 2);
+    }
+
+    #[test]
+    fn v311_publish_has_no_properties() {
+        let publish = rumqttc::Publish::new("t", qos_v4(1), "x");
+        match v4_event(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) {
+            SessionEvent::Publish(publish) => {
+                assert_eq!((publish.topic.as_str(), publish.qos), ("t", 1));
+                assert_eq!(publish.properties, None);
+            }
+            _ => panic!("不是 PUBLISH 事件"),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::capture::pcapng_writer::{LINKTYPE_RAW, LINKTYPE_USER0};
+use crate::capture::{PacketDirection, PcapngWriter, SyntheticTcpStream};
+use crate::combridage::messagemanager::{MessageDirection, MessageRecord};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// 串口、MQTT 及其他通道使用的用户自定义链路类型
+const LINKTYPE_SERIAL: u16 = LINKTYPE_USER0;
+const LINKTYPE_MQTT: u16 = LINKTYPE_USER0 + 1;
+const LINKTYPE_OTHER: u16 = LINKTYPE_USER0 + 2;
+
+// 无法获得本端地址时使用的合成端口起点
+const SYNTHETIC_LOCAL_PORT: u16 = 49152;
+
+// 实时写入时刷新文件的间隔
+const LIVE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// 实时写入的 pcapng 文件，由单独的写入线程负责，收发记录时只发送到通道
+static LIVE_CAPTURE: Lazy<Mutex<Option<LiveCapture>>> = Lazy::new(|| Mutex::new(None));
+
+enum LiveCommand {
+    Record(MessageRecord),
+    Stop,
+}
+
+struct LiveCapture {
+    sender: mpsc::Sender<LiveCommand>,
+    handle: JoinHandle<io::Result<()>>,
+}
+
+impl LiveCapture {
+    // 通知写入线程写完剩余记录并刷新，等待其退出
+    fn stop(self) -> io::Result<()> {
+        let _ = self.sender.send(LiveCommand::Stop);
+        self.handle
+            .join()
+            .map_err(|_| io::Error::other("live capture writer panicked"))?
+    }
+}
+
+// 每个通道对应的接口
+struct ChannelInterface {
+    interface_id: u32,
+    tcp: bool,
+}
+
+/// 把 MessageRecord 写为 pcapng：每个通道一个接口，TCP 通道合成 IP/TCP 头，
+/// 串口和 MQTT 使用用户 DLT（147/148）
+pub struct MessagePcapExporter<W: Write> {
+    writer: PcapngWriter<W>,
+    interfaces: HashMap<String, ChannelInterface>,
+    // 合成 TCP 流按 (通道 ID, 对端地址) 区分，TCP 服务端的每个客户端各占一条连接
+    streams: HashMap<(String, SocketAddr), SyntheticTcpStream>,
+}
+
+impl<W: Write> MessagePcapExporter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        Ok(Self {
+            writer: PcapngWriter::new(writer, "EmbedTalk")?,
+            interfaces: HashMap::new(),
+            streams: HashMap::new(),
+        })
+    }
+
+    pub fn write_record(&mut self, record: &MessageRecord) -> io::Result<()> {
+        if !self.interfaces.contains_key(record.channel_id()) {
+            let interface = self.create_interface(record)?;
+            self.interfaces
+                .insert(record.channel_id().to_string(), interface);
+        }
+        let interface = match self.interfaces.get(record.channel_id()) {
+            Some(interface) => interface,
+            None => return Ok(()),
+        };
+
+        let (direction, outbound) = match record.direction() {
+            MessageDirection::Sent => (PacketDirection::Outbound, true),
+            MessageDirection::Received => (PacketDirection::Inbound, false),
+        };
+        let interface_id = interface.interface_id;
+        let payload = record.payload_bytes();
+        let data = if interface.tcp {
+            let (local, remote) = tcp_endpoints(record);
+            // 没有本端地址时每条连接使用不同的合成端口，避免不同对端的报文落在同一连接中
+            let next_port = SYNTHETIC_LOCAL_PORT.wrapping_add(self.streams.len() as u16);
+            self.streams
+                .entry((record.channel_id().to_string(), remote))
+                .or_insert_with(|| {
+                    let local = local.unwrap_or_else(|| {
+                        let ip = match remote.ip() {
+                            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                        };
+                        SocketAddr::new(ip, next_port)
+                    });
+                    SyntheticTcpStream::new(local, remote)
+                })
+                .packet(outbound, &payload)
+        } else {
+            payload
+        };
+        let comment = record
+            .content()
+            .get_content()
+            .get("topic")
+            .or_else(|| record.content().get_content()["data"].get("topic"))
+            .and_then(|t| t.as_str())
+            .map(|t| format!("topic={}", t));
+
+        self.writer.write_packet(
+            interface_id,
+            record.timestamp().timestamp_micros(),
+            &data,
+            Some(direction),
+            comment.as_deref(),
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn create_interface(&mut self, record: &MessageRecord) -> io::Result<ChannelInterface> {
+        let name = record.channel_name();
+        let description = format!("{} {}", record.channeltype(), record.channel_id());
+        if record.channeltype().starts_with("tcp") {
+            let interface_id = self
+                .writer
+                .add_interface(LINKTYPE_RAW, name, &description)?;
+            return Ok(ChannelInterface {
+                interface_id,
+                tcp: true,
+            });
+        }
+
+        let linktype = match record.channeltype() {
+            "serial" => LINKTYPE_SERIAL,
+            "mqtt" => LINKTYPE_MQTT,
+            _ => LINKTYPE_OTHER,
+        };
+        let interface_id = self.writer.add_interface(linktype, name, &description)?;
+        Ok(ChannelInterface {
+            interface_id,
+            tcp: false,
+        })
+    }
+}
+
+// 优先使用记录元数据中的地址，否则从通道名称（"TCP" + 地址）解析对端地址；
+// 没有本端地址时返回 None，由调用方分配合成地址
+fn tcp_endpoints(record: &MessageRecord) -> (Option<SocketAddr>, SocketAddr) {
+    let metadata_addr = |key: &str| {
+        record
+            .metadata()
+            .and_then(|m| m.get(key))
+            .and_then(|a| a.parse::<SocketAddr>().ok())
+    };
+    let addr_str = record
+        .channel_name()
+        .trim_start_matches("TCP")
+        .trim_start_matches("tcp");
+    let remote = metadata_addr("remote_addr")
+        .or_else(|| addr_str.parse::<SocketAddr>().ok())
+        .unwrap_or_else(|| {
+            let port = addr_str
+                .rsplit(':')
+                .next()
+                .and_then(|p| p.parse::<u16>().ok())
+                .unwrap_or(0);
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), port)
+        });
+    (metadata_addr("local_addr"), remote)
+}
+
+/// 把消息记录导出为 pcapng 文件，返回写入的报文数
+pub fn export_records(records: &[MessageRecord], path: &Path) -> io::Result<usize> {
+    let file = File::create(path)?;
+    let mut exporter = MessagePcapExporter::new(BufWriter::new(file))?;
+    for record in records {
+        exporter.write_record(record)?;
+    }
+    exporter.flush()?;
+    Ok(records.len())
+}
+
+/// 开始实时写入，之后所有通道的收发记录都会追加到该文件
+pub fn start_live_capture(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut exporter = MessagePcapExporter::new(BufWriter::new(file))?;
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name("pcapng-live".to_string())
+        .spawn(move || live_writer(&mut exporter, receiver))?;
+    let previous = LIVE_CAPTURE
+        .lock()
+        .map_err(|_| io::Error::other("live capture lock poisoned"))?
+        .replace(LiveCapture { sender, handle });
+    if let Some(previous) = previous {
+        let _ = previous.stop();
+    }
+    Ok(())
+}
+
+/// 停止实时写入，等待已收到的记录写完
+pub fn stop_live_capture() -> io::Result<()> {
+    let live = LIVE_CAPTURE
+        .lock()
+        .map_err(|_| io::Error::other("live capture lock poisoned"))?
+        .take();
+    match live {
+        Some(live) => live.stop(),
+        None => Ok(()),
+    }
+}
+
+pub fn is_live_capture_active() -> bool {
+    LIVE_CAPTURE
+        .lock()
+        .map(|l| l.as_ref().is_some_and(|live| !live.handle.is_finished()))
+        .unwrap_or(false)
+}
+
+// 由 MessageManager::record_message 调用，只把记录交给写入线程，不做文件操作
+pub(crate) fn write_live_record(record: &MessageRecord) {
+    let Ok(mut live) = LIVE_CAPTURE.lock() else {
+        return;
+    };
+    let stopped = live.as_ref().is_some_and(|live| {
+        live.sender
+            .send(LiveCommand::Record(record.clone()))
+            .is_err()
+    });
+    // 写入线程出错退出后不再保留
+    if stopped {
+        *live = None;
+    }
+}
+
+// 写入线程：逐条写入记录，空闲或距上次刷新超过 LIVE_FLUSH_INTERVAL 时刷新，写入失败时退出
+fn live_writer<W: Write>(
+    exporter: &mut MessagePcapExporter<W>,
+    receiver: mpsc::Receiver<LiveCommand>,
+) -> io::Result<()> {
+    let mut last_flush = Instant::now();
+    let mut dirty = false;
+    loop {
+        match receiver.recv_timeout(LIVE_FLUSH_INTERVAL) {
+            Ok(LiveCommand::Record(record)) => {
+                if let Err(e) = exporter.write_record(&record) {
+                    eprintln!("实时抓包写入失败，已停止: {:?}", e);
+                    return Err(e);
+                }
+                dirty = true;
+            }
+            Ok(LiveCommand::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                return exporter.flush();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if dirty && last_flush.elapsed() >= LIVE_FLUSH_INTERVAL {
+            if let Err(e) = exporter.flush() {
+                eprintln!("实时抓包写入失败，已停止: {:?}", e);
+                return Err(e);
+            }
+            dirty = false;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::tcp_stream::decode_tcp_segment;
+    use crate::capture::{PcapReader, TcpReassembler};
+    use crate::combridage::Message;
+    use chrono::{TimeZone, Utc};
+
+    fn record(
+        channel_id: &str,
+        peer: &str,
+        direction: MessageDirection,
+        data: &[u8],
+        ms: i64,
+    ) -> MessageRecord {
+        MessageRecord::new(
+            "tcpserver".to_string(),
+            channel_id.to_string(),
+            format!("TCP{}", peer),
+            Utc.timestamp_millis_opt(ms).unwrap(),
+            direction,
+            Message::new(serde_json::json!({ "data": data })),
+            None,
+        )
+    }
+
+    #[test]
+    fn tcp_server_clients_export_as_separate_streams() {
+        use MessageDirection::{Received, Sent};
+        // 同一服务端通道上两个客户端的报文交替到达，且都分成两段
+        let records = [
+            record(
+                "tcpserver",
+                "192.168.1.10:50001",
+                Received,
+                &[0x68, 0x01],
+                1,
+            ),
+            record(
+                "tcpserver",
+                "192.168.1.11:50002",
+                Received,
+                &[0x68, 0x02],
+                2,
+            ),
+            record("tcpserver", "192.168.1.10:50001", Received, &[0x16], 3),
+            record("tcpserver", "192.168.1.11:50002", Received, &[0x26], 4),
+            record("tcpserver", "192.168.1.10:50001", Sent, &[0xA1], 5),
+            record("tcpserver", "192.168.1.11:50002", Sent, &[0xB1], 6),
+        ];
+        let mut exporter = MessagePcapExporter::new(Vec::new()).unwrap();
+        for record in &records {
+            exporter.write_record(record).unwrap();
+        }
+        let content = exporter.writer.into_inner();
+
+        let mut reassembler = TcpReassembler::new();
+        let mut flows: HashMap<(SocketAddr, SocketAddr), Vec<u8>> = HashMap::new();
+        for packet in PcapReader::read(&content).unwrap() {
+            let segment = decode_tcp_segment(packet.linktype, &packet.data).unwrap();
+            for chunk in reassembler.push(packet.timestamp_us, segment) {
+                flows
+                    .entry((chunk.src, chunk.dst))
+                    .or_default()
+                    .extend(chunk.data);
+            }
+        }
+
+        let a: SocketAddr = "192.168.1.10:50001".parse().unwrap();
+        let b: SocketAddr = "192.168.1.11:50002".parse().unwrap();
+        let mut received: Vec<_> = flows
+            .iter()
+            .filter(|((src, _), _)| *src == a || *src == b)
+            .map(|((src, _), data)| (*src, data.clone()))
+            .collect();
+        received.sort();
+        assert_eq!(
+            received,
+            [(a, vec![0x68, 0x01, 0x16]), (b, vec![0x68, 0x02, 0x26])]
+        );
+        // 每个客户端一条连接，两个方向共 4 条单向流
+        assert_eq!(flows.len(), 4);
+        assert_eq!(
+            flows.iter().find(|((_, dst), _)| *dst == a).unwrap().1,
+            &[0xA1]
+        );
+        assert_eq!(
+            flows.iter().find(|((_, dst), _)| *dst == b).unwrap().1,
+            &[0xB1]
+        );
+    }
+}
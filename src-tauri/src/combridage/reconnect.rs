@@ -0,0 +1,192 @@
+use crate::config::appconfig::{load_config_value, set_config_value};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 策略在应用配置中的位置
+const POLICY_SECTION: &str = "channel";
+const POLICY_KEY: &str = "reconnect";
+
+/// 断线重连策略，适用于 TCP 客户端、MQTT 和串口
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    /// 第一次重连前的等待时间（毫秒）
+    pub initial_backoff_ms: u64,
+    /// 等待时间上限（毫秒），每次失败后翻倍直到上限
+    pub max_backoff_ms: u64,
+    /// 随机抖动比例，0.2 表示在等待时间上下浮动 20%
+    pub jitter: f64,
+    /// 最多重连次数，0 表示不限制
+    pub max_attempts: u32,
+    /// 断线期间发送的数据是否缓存，恢复连接后补发；为 false 时直接返回发送失败
+    pub buffer_while_disconnected: bool,
+    /// 最多缓存的条数，超过后丢弃最早的数据
+    pub max_buffered: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+            jitter: 0.2,
+            max_attempts: 0,
+            buffer_while_disconnected: true,
+            max_buffered: 1000,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// 从应用配置读取默认策略，没有配置时不重连
+    pub fn load() -> Self {
+        load_config_value(POLICY_SECTION, POLICY_KEY)
+            .and_then(|value| serde_json::from_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let value = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        set_config_value(POLICY_SECTION, POLICY_KEY, &value)
+    }
+
+    /// 断线期间新发送的数据是否需要缓存
+    pub fn buffers(&self) -> bool {
+        self.enabled && self.buffer_while_disconnected
+    }
+
+    /// 第 attempt 次（从 1 开始）重连前的等待时间，超过最大次数时返回 None
+    pub fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if !self.enabled || (self.max_attempts > 0 && attempt > self.max_attempts) {
+            return None;
+        }
+        let exp = attempt.saturating_sub(1).min(30);
+        let base = self
+            .initial_backoff_ms
+            .max(1)
+            .saturating_mul(1 << exp)
+            .min(self.max_backoff_ms.max(self.initial_backoff_ms));
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // 用当前时间的纳秒部分作为随机源，只用于错开多个通道的重连时间
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let factor = 1.0 + jitter * ((nanos % 2001) as f64 / 1000.0 - 1.0);
+        Some(Duration::from_millis((base as f64 * factor) as u64))
+    }
+}
+
+/// 断线期间待发送数据的缓存，超过上限时丢弃最早的数据
+#[derive(Debug)]
+pub struct OutageBuffer<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl<T> OutageBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.queue.len() >= self.capacity {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back(item);
+    }
+
+    /// 取出所有缓存的数据及期间丢弃的条数
+    pub fn drain(&mut self) -> (Vec<T>, usize) {
+        let dropped = std::mem::take(&mut self.dropped);
+        (self.queue.drain(..).collect(), dropped)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: u32) -> ReconnectPolicy {
+        ReconnectPolicy {
+            enabled: true,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            jitter: 0.0,
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let policy = policy(0);
+        let waits: Vec<u64> = (1..=6)
+            .map(|attempt| policy.backoff(attempt).unwrap().as_millis() as u64)
+            .collect();
+        assert_eq!(waits, [100, 200, 400, 800, 1000, 1000]);
+        // 次数很大时不会溢出
+        assert_eq!(policy.backoff(u32::MAX), Some(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn backoff_stops_after_max_attempts() {
+        let policy = policy(3);
+        assert!(policy.backoff(3).is_some());
+        assert_eq!(policy.backoff(4), None);
+        let disabled = ReconnectPolicy {
+            enabled: false,
+            ..policy
+        };
+        assert_eq!(disabled.backoff(1), None);
+        assert!(!disabled.buffers());
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_range() {
+        let policy = ReconnectPolicy {
+            jitter: 0.2,
+            ..policy(0)
+        };
+        for _ in 0..100 {
+            let wait = policy.backoff(2).unwrap().as_millis();
+            assert!((160..=240).contains(&wait), "{}", wait);
+        }
+    }
+
+    #[test]
+    fn outage_buffer_drops_oldest() {
+        let mut buffer = OutageBuffer::new(3);
+        for item in 1..=5 {
+            buffer.push(item);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.drain(), (vec![3, 4, 5], 2));
+        assert!(buffer.is_empty());
+        // 丢弃计数在取出后清零
+        buffer.push(6);
+        assert_eq!(buffer.drain(), (vec![6], 0));
+        // 容量至少为 1
+        let mut buffer = OutageBuffer::new(0);
+        buffer.push('a');
+        buffer.push('b');
+        assert_eq!(buffer.drain(), (vec!['b'], 1));
+    }
+}
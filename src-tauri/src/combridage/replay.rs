@@ -0,0 +1,328 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::FrameStream;
+use crate::combridage::messagemanager::{subscribe_records, MessageDirection, MessageRecord};
+use crate::combridage::{CommunicationChannel, Message};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// 回放节奏
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayTiming {
+    /// 按录制时的帧间隔发送，可通过 speed 加速或减速
+    Original,
+    /// 收到与录制时数量相同的应答帧（或超时）后再发送下一帧
+    WaitForResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayOptions {
+    pub timing: ReplayTiming,
+    /// 速度倍数，2.0 表示间隔缩短为原来的一半
+    pub speed: f64,
+    /// 等待应答的最长时间（毫秒）
+    pub response_timeout_ms: u64,
+    /// TCP 服务端通道需要指定发往的客户端
+    pub clientid: Option<String>,
+    /// 是否比较实际应答与录制的应答
+    pub compare_responses: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            timing: ReplayTiming::Original,
+            speed: 1.0,
+            response_timeout_ms: 3000,
+            clientid: None,
+            compare_responses: true,
+        }
+    }
+}
+
+/// 回放的一步：一帧发送数据及录制时紧随其后的应答
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayStep {
+    pub index: usize,
+    /// 相对第一帧发送的时间偏移（毫秒）
+    pub offset_ms: i64,
+    pub payload: Vec<u8>,
+    /// 录制时的应答，按帧切分
+    pub expected: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DivergenceKind {
+    /// 发送失败
+    SendFailed,
+    /// 录制时有应答，回放时没有收到
+    Missing,
+    /// 回放时收到了录制中没有的应答
+    Unexpected,
+    /// 应答内容不同
+    Mismatch,
+}
+
+/// 回放结果与录制不一致的地方
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayDivergence {
+    pub step: usize,
+    pub kind: DivergenceKind,
+    pub sent: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    /// 第一个不同字节的位置
+    pub offset: Option<usize>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub total_steps: usize,
+    pub sent_steps: usize,
+    pub matched_steps: usize,
+    pub divergences: Vec<ReplayDivergence>,
+    pub finished: bool,
+    pub elapsed_ms: u64,
+}
+
+/// 把录制的消息整理为回放步骤：每条发送记录一步，之后到下一条发送记录之前的接收记录为其应答
+pub fn build_steps(records: &[MessageRecord]) -> Vec<ReplayStep> {
+    let mut records: Vec<&MessageRecord> = records.iter().collect();
+    records.sort_by_key(|r| r.timestamp());
+
+    let mut steps: Vec<ReplayStep> = Vec::new();
+    let mut responses: Vec<u8> = Vec::new();
+    let mut first_sent = None;
+    for record in records {
+        match record.direction() {
+            MessageDirection::Sent => {
+                if let Some(step) = steps.last_mut() {
+                    step.expected = FrameStream::split(&std::mem::take(&mut responses));
+                }
+                let timestamp = record.timestamp().timestamp_millis();
+                let first = *first_sent.get_or_insert(timestamp);
+                steps.push(ReplayStep {
+                    index: steps.len(),
+                    offset_ms: timestamp - first,
+                    payload: record.payload_bytes(),
+                    expected: Vec::new(),
+                });
+            }
+            // 第一帧发送之前的接收数据与回放无关
+            MessageDirection::Received if !steps.is_empty() => {
+                responses.extend(record.payload_bytes());
+            }
+            MessageDirection::Received => {}
+        }
+    }
+    if let Some(step) = steps.last_mut() {
+        step.expected = FrameStream::split(&responses);
+    }
+    steps
+}
+
+/// 从 .log 文件内容读取消息记录，可按通道名称过滤
+pub fn records_from_log(content: &str, channel_name: Option<&str>) -> Vec<MessageRecord> {
+    content
+        .lines()
+        .filter_map(MessageRecord::from_log_line)
+        .filter(|r| channel_name.is_none_or(|name| r.channel_name() == name))
+        .collect()
+}
+
+/// 在指定通道上回放录制的发送数据并比较应答
+pub struct ReplayEngine {
+    channel: Arc<Box<dyn CommunicationChannel>>,
+    // 应答记录所属的通道 ID，TCP 服务端为客户端 ID
+    response_channel_id: String,
+    options: ReplayOptions,
+}
+
+impl ReplayEngine {
+    pub fn new(channel: Arc<Box<dyn CommunicationChannel>>, options: ReplayOptions) -> Self {
+        let response_channel_id = options
+            .clientid
+            .clone()
+            .unwrap_or_else(|| channel.get_channel_id());
+        Self {
+            channel,
+            response_channel_id,
+            options,
+        }
+    }
+
+    /// 执行回放，每完成一步调用一次 `on_progress`
+    pub async fn run<F>(&self, steps: &[ReplayStep], mut on_progress: F) -> ReplayReport
+    where
+        F: FnMut(&ReplayReport) + Send,
+    {
+        let mut report = ReplayReport {
+            total_steps: steps.len(),
+            ..Default::default()
+        };
+        // 先订阅，避免错过发送后立即到达的应答
+        let mut receiver = subscribe_records();
+        let speed = if self.options.speed > 0.0 {
+            self.options.speed
+        } else {
+            1.0
+        };
+        let response_timeout = Duration::from_millis(self.options.response_timeout_ms);
+        let started = Instant::now();
+
+        for (i, step) in steps.iter().enumerate() {
+            let message = Message::new(serde_json::json!({ "data": step.payload }));
+            if let Err(e) = self
+                .channel
+                .send(&message, self.options.clientid.clone())
+                .await
+            {
+                report.divergences.push(ReplayDivergence {
+                    step: step.index,
+                    kind: DivergenceKind::SendFailed,
+                    sent: FrameFun::get_data_str_with_space(&step.payload),
+                    expected: None,
+                    actual: None,
+                    offset: None,
+                    message: Some(e.to_string()),
+                });
+                on_progress(&report);
+                // 按原始时间回放时仍等到下一步的发送时间，保持后续步骤的时序
+                if let (ReplayTiming::Original, Some(next)) =
+                    (self.options.timing, steps.get(i + 1))
+                {
+                    sleep_until(started + Self::scaled_gap(next.offset_ms, speed)).await;
+                }
+                continue;
+            }
+            report.sent_steps += 1;
+            let sent_at = Instant::now();
+
+            // 本步应答的收集截止时间
+            let next_gap = steps
+                .get(i + 1)
+                .map(|next| Self::scaled_gap(next.offset_ms - step.offset_ms, speed));
+            let deadline = match (self.options.timing, next_gap) {
+                (ReplayTiming::Original, Some(_)) => {
+                    started + Self::scaled_gap(steps[i + 1].offset_ms, speed)
+                }
+                (ReplayTiming::Original, None) => sent_at + response_timeout,
+                (ReplayTiming::WaitForResponse, gap) if step.expected.is_empty() => {
+                    // 录制时没有应答，按原间隔等待，但不超过应答超时
+                    sent_at + gap.unwrap_or(Duration::ZERO).min(response_timeout)
+                }
+                (ReplayTiming::WaitForResponse, _) => sent_at + response_timeout,
+            };
+            let wait_count = match self.options.timing {
+                ReplayTiming::WaitForResponse => Some(step.expected.len()),
+                ReplayTiming::Original if next_gap.is_none() => Some(step.expected.len()),
+                ReplayTiming::Original => None,
+            };
+
+            let actual = self
+                .collect_responses(&mut receiver, deadline, wait_count)
+                .await;
+            if self.options.compare_responses {
+                let divergences = Self::compare(step, &actual);
+                if divergences.is_empty() {
+                    report.matched_steps += 1;
+                }
+                report.divergences.extend(divergences);
+            }
+            report.elapsed_ms = started.elapsed().as_millis() as u64;
+            on_progress(&report);
+        }
+
+        report.finished = true;
+        report.elapsed_ms = started.elapsed().as_millis() as u64;
+        on_progress(&report);
+        report
+    }
+
+    fn scaled_gap(gap_ms: i64, speed: f64) -> Duration {
+        Duration::from_secs_f64(gap_ms.max(0) as f64 / 1000.0 / speed)
+    }
+
+    // 收集应答直到截止时间，或在收到 wait_count 帧后提前结束
+    async fn collect_responses(
+        &self,
+        receiver: &mut tokio::sync::broadcast::Receiver<MessageRecord>,
+        deadline: Instant,
+        wait_count: Option<usize>,
+    ) -> Vec<Vec<u8>> {
+        let mut stream = FrameStream::new();
+        let mut frames = Vec::new();
+        loop {
+            // 只有完整的帧才计入应答数量
+            while let Some((skipped, frame)) = stream.next_frame() {
+                if !skipped.is_empty() {
+                    frames.push(skipped);
+                }
+                frames.push(frame);
+            }
+            if let Some(count) = wait_count {
+                if count > 0 && frames.len() >= count {
+                    break;
+                }
+            }
+            tokio::select! {
+                _ = sleep_until(deadline) => break,
+                result = receiver.recv() => match result {
+                    Ok(record) => {
+                        if record.channel_id() == self.response_channel_id
+                            && matches!(record.direction(), MessageDirection::Received)
+                        {
+                            stream.push(&record.payload_bytes());
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        eprintln!("回放订阅消息滞后，丢失 {} 条记录", n);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+        let rest = stream.take_remaining();
+        if !rest.is_empty() {
+            frames.push(rest);
+        }
+        frames
+    }
+
+    fn compare(step: &ReplayStep, actual: &[Vec<u8>]) -> Vec<ReplayDivergence> {
+        let sent = FrameFun::get_data_str_with_space(&step.payload);
+        let mut divergences = Vec::new();
+        for i in 0..step.expected.len().max(actual.len()) {
+            let expected = step.expected.get(i);
+            let got = actual.get(i);
+            let (kind, offset) = match (expected, got) {
+                (Some(e), Some(a)) if e == a => continue,
+                (Some(e), Some(a)) => (
+                    DivergenceKind::Mismatch,
+                    Some(
+                        e.iter()
+                            .zip(a.iter())
+                            .position(|(x, y)| x != y)
+                            .unwrap_or(e.len().min(a.len())),
+                    ),
+                ),
+                (Some(_), None) => (DivergenceKind::Missing, None),
+                (None, Some(_)) => (DivergenceKind::Unexpected, None),
+                (None, None) => continue,
+            };
+            divergences.push(ReplayDivergence {
+                step: step.index,
+                kind,
+                sent: sent.clone(),
+                expected: expected.map(|e| FrameFun::get_data_str_with_space(e)),
+                actual: got.map(|a| FrameFun::get_data_str_with_space(a)),
+                offset,
+                message: None,
+            });
+        }
+        divergences
+    }
+}
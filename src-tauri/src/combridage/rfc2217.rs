@@ -0,0 +1,442 @@
+use crate::combridage::serial_port::SerialSettings;
+use std::collections::HashMap;
+use std::error::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout_at, Duration, Instant};
+
+// Telnet 命令
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+// Telnet 选项
+const OPT_BINARY: u8 = 0;
+const OPT_SGA: u8 = 3;
+const OPT_COM_PORT: u8 = 44;
+
+// COM-PORT-OPTION 子命令，服务器应答的子命令号为请求加 100
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const SERVER_OFFSET: u8 = 100;
+
+/// 从 Telnet 数据流中解析出的协商命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TelnetEvent {
+    /// WILL / WONT / DO / DONT
+    Command { command: u8, option: u8 },
+    /// IAC SB option ... IAC SE
+    SubNegotiation { option: u8, data: Vec<u8> },
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum DecodeState {
+    #[default]
+    Data,
+    Iac,
+    Command(u8),
+    Sub,
+    SubIac,
+}
+
+/// Telnet 流解码器，把串口数据与协商命令分开，命令可以跨多次读取
+#[derive(Debug, Default)]
+pub(crate) struct TelnetDecoder {
+    state: DecodeState,
+    sub: Vec<u8>,
+}
+
+impl TelnetDecoder {
+    pub(crate) fn feed(&mut self, input: &[u8], data: &mut Vec<u8>, events: &mut Vec<TelnetEvent>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (DecodeState::Data, IAC) => DecodeState::Iac,
+                (DecodeState::Data, _) => {
+                    data.push(byte);
+                    DecodeState::Data
+                }
+                // IAC IAC 为数据中的 0xFF
+                (DecodeState::Iac, IAC) => {
+                    data.push(IAC);
+                    DecodeState::Data
+                }
+                (DecodeState::Iac, SB) => {
+                    self.sub.clear();
+                    DecodeState::Sub
+                }
+                (DecodeState::Iac, WILL..=DONT) => DecodeState::Command(byte),
+                // NOP、GA 等其他命令直接忽略
+                (DecodeState::Iac, _) => DecodeState::Data,
+                (DecodeState::Command(command), option) => {
+                    events.push(TelnetEvent::Command { command, option });
+                    DecodeState::Data
+                }
+                (DecodeState::Sub, IAC) => DecodeState::SubIac,
+                (DecodeState::Sub, _) => {
+                    self.sub.push(byte);
+                    DecodeState::Sub
+                }
+                (DecodeState::SubIac, IAC) => {
+                    self.sub.push(IAC);
+                    DecodeState::Sub
+                }
+                (DecodeState::SubIac, SE) => {
+                    if let Some((&option, rest)) = self.sub.split_first() {
+                        events.push(TelnetEvent::SubNegotiation {
+                            option,
+                            data: rest.to_vec(),
+                        });
+                    }
+                    DecodeState::Data
+                }
+                // 格式错误的子协商，丢弃
+                (DecodeState::SubIac, _) => DecodeState::Data,
+            };
+        }
+    }
+}
+
+/// 发送的数据中 0xFF 需要转义为 IAC IAC
+pub(crate) fn escape(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+/// 拒绝服务器请求的不支持的选项，已支持的选项在连接时已主动协商，不再应答
+pub(crate) fn reply(command: u8, option: u8) -> Option<Vec<u8>> {
+    let supported = matches!(option, OPT_BINARY | OPT_SGA | OPT_COM_PORT);
+    match command {
+        DO if !supported => Some(vec![IAC, WONT, option]),
+        WILL if !supported => Some(vec![IAC, DONT, option]),
+        _ => None,
+    }
+}
+
+/// 通过 COM-PORT-OPTION 设置的串口参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ComPortSettings {
+    pub baud_rate: u32,
+    pub data_size: u8,
+    /// 1 无校验，2 奇校验，3 偶校验
+    pub parity: u8,
+    /// 1 一位，2 两位
+    pub stop_size: u8,
+    /// 1 无流控，2 软件流控，3 硬件流控
+    pub control: u8,
+}
+
+impl ComPortSettings {
+    pub(crate) fn new(settings: &SerialSettings) -> Self {
+        Self {
+            baud_rate: settings.baud_rate,
+            data_size: match settings.databit {
+                tokio_serial::DataBits::Five => 5,
+                tokio_serial::DataBits::Six => 6,
+                tokio_serial::DataBits::Seven => 7,
+                tokio_serial::DataBits::Eight => 8,
+            },
+            parity: match settings.parity {
+                tokio_serial::Parity::None => 1,
+                tokio_serial::Parity::Odd => 2,
+                tokio_serial::Parity::Even => 3,
+            },
+            stop_size: match settings.stopbit {
+                tokio_serial::StopBits::One => 1,
+                tokio_serial::StopBits::Two => 2,
+            },
+            control: match settings.flowctrl {
+                tokio_serial::FlowControl::None => 1,
+                tokio_serial::FlowControl::Software => 2,
+                tokio_serial::FlowControl::Hardware => 3,
+            },
+        }
+    }
+
+    // 依次设置波特率、数据位、校验位、停止位和流控
+    fn requests(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(com_port_command(
+            SET_BAUDRATE,
+            &self.baud_rate.to_be_bytes(),
+        ));
+        bytes.extend(com_port_command(SET_DATASIZE, &[self.data_size]));
+        bytes.extend(com_port_command(SET_PARITY, &[self.parity]));
+        bytes.extend(com_port_command(SET_STOPSIZE, &[self.stop_size]));
+        bytes.extend(com_port_command(SET_CONTROL, &[self.control]));
+        bytes
+    }
+
+    // 比较服务器应答的实际参数，返回不一致的项
+    fn mismatches(&self, acks: &HashMap<u8, Vec<u8>>) -> Vec<String> {
+        let expected = [
+            (SET_BAUDRATE, "波特率", self.baud_rate),
+            (SET_DATASIZE, "数据位", self.data_size as u32),
+            (SET_PARITY, "校验位", self.parity as u32),
+            (SET_STOPSIZE, "停止位", self.stop_size as u32),
+            (SET_CONTROL, "流控", self.control as u32),
+        ];
+        expected
+            .iter()
+            .filter_map(|(command, name, value)| {
+                let actual = acks
+                    .get(command)?
+                    .iter()
+                    .fold(0u32, |acc, &b| (acc << 8) | b as u32);
+                (actual != *value).then(|| format!("{}: 请求 {}，实际 {}", name, value, actual))
+            })
+            .collect()
+    }
+}
+
+fn com_port_command(command: u8, value: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![IAC, SB, OPT_COM_PORT, command];
+    bytes.extend(escape(value));
+    bytes.extend([IAC, SE]);
+    bytes
+}
+
+/// 与串口服务器协商 COM-PORT-OPTION 并设置串口参数
+///
+/// 返回协商期间收到的串口数据；服务器拒绝该选项或超时未应答时返回错误，
+/// 应答的参数与请求不一致时只打印警告，部分服务器会把波特率调整为最接近的值
+pub(crate) async fn negotiate<S>(
+    stream: &mut S,
+    settings: &ComPortSettings,
+    decoder: &mut TelnetDecoder,
+    wait: Duration,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let requests = [
+        IAC,
+        WILL,
+        OPT_COM_PORT,
+        IAC,
+        WILL,
+        OPT_BINARY,
+        IAC,
+        DO,
+        OPT_BINARY,
+        IAC,
+        WILL,
+        OPT_SGA,
+        IAC,
+        DO,
+        OPT_SGA,
+    ];
+    stream.write_all(&requests).await?;
+
+    let deadline = Instant::now() + wait;
+    let mut accepted = false;
+    let mut acks: HashMap<u8, Vec<u8>> = HashMap::new();
+    let mut data = Vec::new();
+    let mut events = Vec::new();
+    let mut buffer = [0u8; 512];
+    while !accepted || acks.len() < 5 {
+        let n = timeout_at(deadline, stream.read(&mut buffer))
+            .await
+            .map_err(|_| "RFC 2217 协商超时，串口服务器可能未启用 RFC 2217")??;
+        if n == 0 {
+            return Err("串口服务器在 RFC 2217 协商过程中断开连接".into());
+        }
+        decoder.feed(&buffer[..n], &mut data, &mut events);
+        for event in events.drain(..) {
+            match event {
+                TelnetEvent::Command {
+                    command: DO,
+                    option: OPT_COM_PORT,
+                } if !accepted => {
+                    accepted = true;
+                    stream.write_all(&settings.requests()).await?;
+                }
+                TelnetEvent::Command {
+                    command: DONT,
+                    option: OPT_COM_PORT,
+                } => return Err("串口服务器不支持 RFC 2217".into()),
+                TelnetEvent::Command { command, option } => {
+                    if let Some(reply) = reply(command, option) {
+                        stream.write_all(&reply).await?;
+                    }
+                }
+                TelnetEvent::SubNegotiation {
+                    option: OPT_COM_PORT,
+                    data: sub,
+                } => {
+                    if let Some((&command, value)) = sub.split_first() {
+                        // 只关心设置参数的应答，线路状态等通知忽略
+                        if (SERVER_OFFSET + SET_BAUDRATE..=SERVER_OFFSET + SET_CONTROL)
+                            .contains(&command)
+                        {
+                            acks.insert(command - SERVER_OFFSET, value.to_vec());
+                        }
+                    }
+                }
+                TelnetEvent::SubNegotiation { .. } => {}
+            }
+        }
+    }
+
+    for mismatch in settings.mismatches(&acks) {
+        eprintln!("RFC 2217 参数与请求不一致，{}", mismatch);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basefunc::frame_fun::FrameFun;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const SETTINGS: ComPortSettings = ComPortSettings {
+        baud_rate: 9600,
+        data_size: 8,
+        parity: 3,
+        stop_size: 1,
+        control: 1,
+    };
+
+    // 客户端连接后发送的选项协商：WILL COM-PORT、WILL/DO BINARY、WILL/DO SGA
+    const CLIENT_OPTIONS: &str = "FF FB 2C FF FB 00 FF FD 00 FF FB 03 FF FD 03";
+
+    // 读取并核对客户端发来的字节
+    async fn expect_bytes(stream: &mut TcpStream, expected: &str) {
+        let expected = FrameFun::get_frame_list_from_str(expected);
+        let mut actual = vec![0u8; expected.len()];
+        stream.read_exact(&mut actual).await.unwrap();
+        assert_eq!(
+            FrameFun::get_data_str_with_space(&actual),
+            FrameFun::get_data_str_with_space(&expected)
+        );
+    }
+
+    // 在本地端口启动模拟串口服务器，返回地址和服务器任务
+    async fn stand_in<F, Fut>(server: F) -> (String, tokio::task::JoinHandle<()>)
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            server(stream).await;
+        });
+        (address, handle)
+    }
+
+    async fn negotiate_with(address: &str, wait: Duration) -> Result<Vec<u8>, String> {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut decoder = TelnetDecoder::default();
+        negotiate(&mut stream, &SETTINGS, &mut decoder, wait)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    #[tokio::test]
+    async fn negotiates_com_port_settings_with_server() {
+        let (address, server) = stand_in(|mut stream| async move {
+            expect_bytes(&mut stream, CLIENT_OPTIONS).await;
+            // 接受 COM-PORT-OPTION，同时请求一个不支持的选项（终端类型 24）
+            let reply = FrameFun::get_frame_list_from_str("FF FD 2C FF FD 18");
+            stream.write_all(&reply).await.unwrap();
+            // SET-BAUDRATE 9600、SET-DATASIZE 8、SET-PARITY 偶、SET-STOPSIZE 1、SET-CONTROL 无
+            expect_bytes(
+                &mut stream,
+                "FF FA 2C 01 00 00 25 80 FF F0 \
+                 FF FA 2C 02 08 FF F0 \
+                 FF FA 2C 03 03 FF F0 \
+                 FF FA 2C 04 01 FF F0 \
+                 FF FA 2C 05 01 FF F0",
+            )
+            .await;
+            // 拒绝终端类型
+            expect_bytes(&mut stream, "FF FC 18").await;
+            // 逐项应答实际参数，中间夹带串口数据，其中 0xFF 转义为 FF FF
+            let reply = FrameFun::get_frame_list_from_str(
+                "FF FA 2C 65 00 00 25 80 FF F0 \
+                 68 FF FF 16 \
+                 FF FA 2C 66 08 FF F0 \
+                 FF FA 2C 67 03 FF F0 \
+                 FF FA 2C 68 01 FF F0 \
+                 FF FA 2C 69 01 FF F0",
+            );
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let data = negotiate_with(&address, Duration::from_secs(2))
+            .await
+            .unwrap();
+        assert_eq!(data, vec![0x68, 0xFF, 0x16]);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_when_server_refuses_com_port_option() {
+        let (address, server) = stand_in(|mut stream| async move {
+            expect_bytes(&mut stream, CLIENT_OPTIONS).await;
+            let reply = FrameFun::get_frame_list_from_str("FF FE 2C");
+            stream.write_all(&reply).await.unwrap();
+        })
+        .await;
+
+        let result = negotiate_with(&address, Duration::from_secs(2)).await;
+        assert_eq!(result.unwrap_err(), "串口服务器不支持 RFC 2217");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn times_out_when_server_does_not_answer() {
+        let (address, server) = stand_in(|mut stream| async move {
+            expect_bytes(&mut stream, CLIENT_OPTIONS).await;
+            // 不应答，直到客户端断开
+            let mut buffer = [0u8; 64];
+            while stream.read(&mut buffer).await.unwrap_or(0) > 0 {}
+        })
+        .await;
+
+        let result = negotiate_with(&address, Duration::from_millis(200)).await;
+        assert!(result.unwrap_err().contains("协商超时"));
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn decoder_handles_commands_split_across_reads() {
+        let mut decoder = TelnetDecoder::default();
+        let mut data = Vec::new();
+        let mut events = Vec::new();
+        for chunk in ["01 FF", "FF 02 FF FA 2C", "6B 01 FF F0 FF FB", "03 03"] {
+            let chunk = FrameFun::get_frame_list_from_str(chunk);
+            decoder.feed(&chunk, &mut data, &mut events);
+        }
+        assert_eq!(data, vec![0x01, 0xFF, 0x02, 0x03]);
+        assert_eq!(
+            events,
+            vec![
+                TelnetEvent::SubNegotiation {
+                    option: OPT_COM_PORT,
+                    data: vec![0x6B, 0x01],
+                },
+                TelnetEvent::Command {
+                    command: WILL,
+                    option: OPT_SGA,
+                },
+            ]
+        );
+        assert_eq!(escape(&[0x68, 0xFF, 0x16]), vec![0x68, 0xFF, 0xFF, 0x16]);
+    }
+}
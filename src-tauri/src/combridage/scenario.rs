@@ -0,0 +1,1227 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::FrameStream;
+use crate::basefunc::frame_template::expand_template;
+use crate::basefunc::protocol::FrameAnalisyic;
+use crate::combridage::bridge::{parse_hex, parse_pattern, pattern_matches};
+use crate::combridage::{
+    channel_from_params, subscribe_records, ChannelType, CommunicationChannel,
+    CommunicationManager, Message, MessageDirection, MessageRecord,
+};
+use crate::protocol::build_frame;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, timeout_at, Duration, Instant};
+
+// 不完整的数据等待后续数据的时间，超时后按一帧处理（如 Modbus 等非 68 帧）
+const FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+/// 测试场景，从 YAML 文件加载
+///
+/// ```yaml
+/// name: 读当前电能
+/// variables: { addr: "01 02 03 04 05 06" }
+/// stand_ins:
+///   - name: terminal
+///     type: virtual
+///     params: { name: lab, end: B }
+///     replies:
+///       - pattern: "68 ?? ?? ?? ?? 68 4A"
+///         reply: "68 25 00 25 00 68 88 ..."
+/// steps:
+///   - connect: { channel: master, type: virtual, params: { name: lab } }
+///   - send: { frame: "68 ... 16" }
+///   - expect:
+///       protocol: CSG13
+///       timeout_ms: 3000
+///       assert: ["0000FF00.00000000 > 0"]
+///       save: { total: "0000FF00.00000000" }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: String,
+    /// 解析报文使用的地区
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// 初始变量，报文、参数和断言中以 ${name} 引用
+    #[serde(default)]
+    pub variables: HashMap<String, Value>,
+    /// 步骤失败后是否继续执行后续步骤
+    #[serde(default)]
+    pub continue_on_failure: bool,
+    /// 场景开始前打开的模拟设备，收到匹配的报文后自动应答
+    #[serde(default)]
+    pub stand_ins: Vec<StandIn>,
+    pub steps: Vec<ScenarioStep>,
+}
+
+fn default_region() -> String {
+    "南网".to_string()
+}
+
+impl Scenario {
+    pub fn from_yaml(content: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        serde_yaml::from_str(content).map_err(|e| format!("场景格式错误: {}", e).into())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取场景文件 {} 失败: {}", path, e))?;
+        Self::from_yaml(&content)
+    }
+}
+
+/// 模拟设备：按规则应答收到的报文，用于在没有真实设备的环境（如 CI）中运行场景
+#[derive(Debug, Clone, Deserialize)]
+pub struct StandIn {
+    pub name: String,
+    /// 通道类型，与连接通道时的类型相同，如 tcpserver、virtual
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub params: Value,
+    #[serde(default)]
+    pub replies: Vec<StandInReply>,
+}
+
+/// 模拟设备的应答规则，按顺序取第一条匹配的规则
+#[derive(Debug, Clone, Deserialize)]
+pub struct StandInReply {
+    /// 十六进制匹配模式，?? 为通配符
+    pub pattern: String,
+    /// 十六进制应答报文
+    pub reply: String,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// 场景步骤，`name` 为可选的步骤说明
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioStep {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub action: StepAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepAction {
+    /// 连接通道
+    Connect(ConnectStep),
+    /// 发送报文
+    Send(SendStep),
+    /// 等待满足条件的报文
+    Expect(ExpectStep),
+    /// 等待指定毫秒数
+    Wait(u64),
+    /// 设置变量
+    Set(HashMap<String, Value>),
+    /// 重复执行一组步骤
+    Loop(LoopStep),
+    /// 断开通道
+    Disconnect(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectStep {
+    /// 场景中引用该通道的名称
+    pub channel: String,
+    /// 通道类型，如 tcpclient、serial、virtual
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// 与界面连接通道时相同的参数
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendStep {
+    /// 未指定时使用最近连接的通道
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// 十六进制报文
+    #[serde(default)]
+    pub frame: Option<String>,
+    /// 使用协议组帧
+    #[serde(default)]
+    pub build: Option<BuildMessage>,
+    /// 展开保存的报文模板
+    #[serde(default)]
+    pub template: Option<String>,
+    /// 展开模板使用的变量，值可以引用场景变量，如 {"addr": "${addr}"}
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// 服务端通道发送到指定客户端，未指定时发送到全部客户端
+    #[serde(default)]
+    pub clientid: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BuildMessage {
+    /// 协议名称，如 modbus、dlt645
+    pub protocol: String,
+    pub message: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpectStep {
+    /// 未指定时使用最近连接的通道
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// 十六进制匹配模式，?? 为通配符
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// 解析出的协议名称包含该字符串（不区分大小写），如 CSG13、645
+    #[serde(default)]
+    pub protocol: Option<String>,
+    /// 对解析结果的断言
+    #[serde(default)]
+    pub assert: Vec<Condition>,
+    #[serde(default = "default_expect_timeout")]
+    pub timeout_ms: u64,
+    /// 把解析出的数据项的值保存到变量，键为变量名，值为数据项
+    #[serde(default)]
+    pub save: HashMap<String, String>,
+}
+
+fn default_expect_timeout() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoopStep {
+    pub times: u32,
+    /// 保存当前次数的变量，从 1 开始
+    #[serde(default)]
+    pub var: Option<String>,
+    pub steps: Vec<ScenarioStep>,
+}
+
+/// 对解析出的数据项的断言
+///
+/// 可以写成 `"0000FF00.00000000 > 0"`，也可以分别给出 item、op、value。
+/// 数据项按解析树逐级查找，`.` 分隔各级，每级匹配数据项编码（如 00000000）或名称中的文字。
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "ConditionDef")]
+pub struct Condition {
+    pub item: String,
+    pub op: ConditionOp,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConditionOp {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = "contains")]
+    Contains,
+    /// 正则表达式匹配
+    #[serde(rename = "matches")]
+    Matches,
+    /// 数据项存在
+    #[serde(rename = "exists")]
+    Exists,
+}
+
+impl ConditionOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            ConditionOp::Eq => "==",
+            ConditionOp::Ne => "!=",
+            ConditionOp::Gt => ">",
+            ConditionOp::Ge => ">=",
+            ConditionOp::Lt => "<",
+            ConditionOp::Le => "<=",
+            ConditionOp::Contains => "contains",
+            ConditionOp::Matches => "matches",
+            ConditionOp::Exists => "exists",
+        }
+    }
+
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        [
+            ConditionOp::Eq,
+            ConditionOp::Ne,
+            ConditionOp::Gt,
+            ConditionOp::Ge,
+            ConditionOp::Lt,
+            ConditionOp::Le,
+            ConditionOp::Contains,
+            ConditionOp::Matches,
+            ConditionOp::Exists,
+        ]
+        .into_iter()
+        .find(|op| op.symbol() == symbol)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConditionDef {
+    Text(String),
+    Full {
+        item: String,
+        op: ConditionOp,
+        #[serde(default)]
+        value: Value,
+    },
+}
+
+impl TryFrom<ConditionDef> for Condition {
+    type Error = String;
+
+    fn try_from(def: ConditionDef) -> Result<Self, Self::Error> {
+        match def {
+            ConditionDef::Full { item, op, value } => Ok(Condition { item, op, value }),
+            ConditionDef::Text(text) => {
+                let mut parts = text.trim().splitn(3, char::is_whitespace);
+                let item = parts.next().unwrap_or_default().to_string();
+                let op = parts
+                    .next()
+                    .and_then(ConditionOp::from_symbol)
+                    .ok_or_else(|| format!("断言格式错误: {}", text))?;
+                let value = parts.next().map(str::trim).unwrap_or_default();
+                if item.is_empty() || (value.is_empty() && op != ConditionOp::Exists) {
+                    return Err(format!("断言格式错误: {}", text));
+                }
+                Ok(Condition {
+                    item,
+                    op,
+                    value: Value::String(value.to_string()),
+                })
+            }
+        }
+    }
+}
+
+/// 单个步骤的执行结果
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    /// 步骤位置，从 1 开始；循环内的步骤为 "3[2].1"，即第 3 步第 2 次循环的第 1 步
+    pub path: String,
+    pub kind: String,
+    pub name: Option<String>,
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub message: String,
+    /// 发送或匹配到的报文
+    pub frame: Option<String>,
+}
+
+/// 场景执行报告
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub passed_steps: usize,
+    pub failed_steps: usize,
+    pub steps: Vec<StepResult>,
+    pub variables: HashMap<String, String>,
+}
+
+impl StepResult {
+    /// 文本格式的单步结果，不含换行
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} {:<8} {:<10} {:>6} ms  {}{}",
+            if self.passed { "PASS" } else { "FAIL" },
+            self.path,
+            self.kind,
+            self.duration_ms,
+            self.name
+                .as_ref()
+                .map(|name| format!("{}: ", name))
+                .unwrap_or_default(),
+            self.message
+        )
+    }
+}
+
+impl ScenarioReport {
+    /// 文本格式的报告，每步一行，最后一行为汇总
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for step in &self.steps {
+            text.push_str(&step.to_text());
+            text.push('\n');
+        }
+        text.push_str(&self.summary());
+        text.push('\n');
+        text
+    }
+
+    /// 一行汇总：是否通过、通过和失败的步数、耗时
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}：通过 {} 步，失败 {} 步，耗时 {} ms",
+            if self.passed { "PASS" } else { "FAIL" },
+            self.name,
+            self.passed_steps,
+            self.failed_steps,
+            self.duration_ms
+        )
+    }
+}
+
+/// 执行场景，每完成一步调用一次 `on_step`
+pub async fn run_scenario<F>(scenario: Scenario, on_step: F) -> ScenarioReport
+where
+    F: Fn(&StepResult) + Send + Sync,
+{
+    let started = Instant::now();
+    let mut runner = ScenarioRunner {
+        manager: CommunicationManager::new(),
+        channels: HashMap::new(),
+        current: None,
+        variables: scenario
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value_text(value)))
+            .collect(),
+        region: scenario.region.clone(),
+        records: subscribe_records(),
+        results: Vec::new(),
+        stand_ins: Vec::new(),
+        stand_in_types: Vec::new(),
+        continue_on_failure: scenario.continue_on_failure,
+        on_step: &on_step,
+    };
+
+    let mut ready = true;
+    for stand_in in &scenario.stand_ins {
+        let step_started = Instant::now();
+        if let Err(e) = runner.start_stand_in(stand_in).await {
+            runner.finish(
+                format!("S{}", runner.stand_ins.len() + 1),
+                "stand_in",
+                Some(stand_in.name.clone()),
+                step_started,
+                Err(format!("模拟设备启动失败: {}", e)),
+                None,
+            );
+            ready = false;
+            break;
+        }
+    }
+    if ready {
+        runner.run_steps(&scenario.steps, String::new()).await;
+    }
+    runner.shutdown().await;
+
+    let failed_steps = runner.results.iter().filter(|r| !r.passed).count();
+    ScenarioReport {
+        name: scenario.name,
+        passed: failed_steps == 0,
+        duration_ms: started.elapsed().as_millis() as u64,
+        passed_steps: runner.results.len() - failed_steps,
+        failed_steps,
+        steps: runner.results,
+        variables: runner.variables,
+    }
+}
+
+// 积累收到的数据并切分为帧，长时间不完整的数据整体作为一帧
+#[derive(Default)]
+struct FrameCollector {
+    stream: FrameStream,
+    flush_at: Option<Instant>,
+}
+
+impl FrameCollector {
+    fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.stream.push(data);
+        let mut frames = Vec::new();
+        while let Some((skipped, frame)) = self.stream.next_frame() {
+            frames.extend([skipped, frame].into_iter().filter(|part| !part.is_empty()));
+        }
+        self.flush_at = (self.stream.pending_len() > 0).then(|| Instant::now() + FLUSH_DELAY);
+        frames
+    }
+
+    fn flush(&mut self) -> Option<Vec<u8>> {
+        self.flush_at = None;
+        let rest = self.stream.take_remaining();
+        (!rest.is_empty()).then_some(rest)
+    }
+}
+
+// 场景中连接的通道
+struct ScenarioChannel {
+    channel_type: ChannelType,
+    channel: Arc<Box<dyn CommunicationChannel>>,
+    channel_id: String,
+    // 服务端通道已知的客户端 ID
+    clients: HashSet<String>,
+    collector: FrameCollector,
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl ScenarioChannel {
+    fn owns(&self, channel_id: &str) -> bool {
+        channel_id == self.channel_id || self.clients.contains(channel_id)
+    }
+}
+
+struct ScenarioRunner<'a> {
+    manager: CommunicationManager,
+    channels: HashMap<String, ScenarioChannel>,
+    // 最近连接的通道，步骤未指定通道时使用
+    current: Option<String>,
+    variables: HashMap<String, String>,
+    region: String,
+    records: Receiver<MessageRecord>,
+    results: Vec<StepResult>,
+    stand_ins: Vec<JoinHandle<()>>,
+    stand_in_types: Vec<ChannelType>,
+    continue_on_failure: bool,
+    on_step: &'a (dyn Fn(&StepResult) + Send + Sync),
+}
+
+type StepOutcome = Result<String, String>;
+
+impl<'a> ScenarioRunner<'a> {
+    // 返回 false 时停止执行
+    fn run_steps<'s>(
+        &'s mut self,
+        steps: &'s [ScenarioStep],
+        prefix: String,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 's>> {
+        Box::pin(async move {
+            for (index, step) in steps.iter().enumerate() {
+                let path = format!("{}{}", prefix, index + 1);
+                if let StepAction::Loop(step) = &step.action {
+                    for iteration in 1..=step.times {
+                        if let Some(var) = &step.var {
+                            self.variables.insert(var.clone(), iteration.to_string());
+                        }
+                        let prefix = format!("{}[{}].", path, iteration);
+                        if !self.run_steps(&step.steps, prefix).await {
+                            return false;
+                        }
+                    }
+                    continue;
+                }
+
+                let started = Instant::now();
+                let mut frame = None;
+                let (kind, outcome) = match &step.action {
+                    StepAction::Connect(connect) => ("connect", self.connect(connect).await),
+                    StepAction::Send(send) => ("send", self.send(send, &mut frame).await),
+                    StepAction::Expect(expect) => ("expect", self.expect(expect, &mut frame).await),
+                    StepAction::Wait(ms) => {
+                        sleep(Duration::from_millis(*ms)).await;
+                        ("wait", Ok(format!("等待 {} ms", ms)))
+                    }
+                    StepAction::Set(values) => ("set", self.set(values)),
+                    StepAction::Disconnect(channel) => {
+                        ("disconnect", self.disconnect(channel).await)
+                    }
+                    StepAction::Loop(_) => unreachable!(),
+                };
+                let passed = outcome.is_ok();
+                self.finish(path, kind, step.name.clone(), started, outcome, frame);
+                if !passed && !self.continue_on_failure {
+                    return false;
+                }
+            }
+            true
+        })
+    }
+
+    fn finish(
+        &mut self,
+        path: String,
+        kind: &str,
+        name: Option<String>,
+        started: Instant,
+        outcome: StepOutcome,
+        frame: Option<Vec<u8>>,
+    ) {
+        let (passed, message) = match outcome {
+            Ok(message) => (true, message),
+            Err(message) => (false, message),
+        };
+        let result = StepResult {
+            path,
+            kind: kind.to_string(),
+            name,
+            passed,
+            duration_ms: started.elapsed().as_millis() as u64,
+            message,
+            frame: frame.map(|frame| FrameFun::get_data_str_with_space(&frame)),
+        };
+        (self.on_step)(&result);
+        self.results.push(result);
+    }
+
+    // 替换文本中的 ${name} 变量
+    fn substitute(&self, text: &str) -> Result<String, String> {
+        let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+        let mut missing = None;
+        let result = re.replace_all(text, |caps: &regex::Captures| {
+            match self.variables.get(&caps[1]) {
+                Some(value) => value.clone(),
+                None => {
+                    missing.get_or_insert_with(|| caps[1].to_string());
+                    String::new()
+                }
+            }
+        });
+        match missing {
+            Some(name) => Err(format!("未定义的变量: {}", name)),
+            None => Ok(result.into_owned()),
+        }
+    }
+
+    fn substitute_value(&self, value: &Value) -> Result<Value, String> {
+        let text = serde_json::to_string(value).map_err(|e| e.to_string())?;
+        serde_json::from_str(&self.substitute(&text)?)
+            .map_err(|e| format!("替换变量后不是合法的 JSON: {}", e))
+    }
+
+    fn channel_name(&self, channel: &Option<String>) -> Result<String, String> {
+        match channel {
+            Some(channel) => self
+                .channels
+                .contains_key(channel)
+                .then(|| channel.clone())
+                .ok_or_else(|| format!("通道 {} 未连接", channel)),
+            None => self
+                .current
+                .clone()
+                .ok_or_else(|| "没有已连接的通道".to_string()),
+        }
+    }
+
+    async fn connect(&mut self, step: &ConnectStep) -> StepOutcome {
+        if self.channels.contains_key(&step.channel) {
+            return Err(format!("通道 {} 已连接", step.channel));
+        }
+        let params = self.substitute_value(&step.params)?;
+        let (channel_type, options) = channel_from_params(&step.kind, &params)?;
+        let channel_id = self
+            .manager
+            .add_channel_with_options(channel_type.clone(), options)
+            .await
+            .map_err(|e| format!("连接通道失败: {}", e))?;
+        let channel = self
+            .manager
+            .get_channel(&channel_type)
+            .ok_or("连接通道失败")?;
+        self.channels.insert(
+            step.channel.clone(),
+            ScenarioChannel {
+                channel_type,
+                channel,
+                channel_id: channel_id.clone(),
+                clients: HashSet::new(),
+                collector: FrameCollector::default(),
+                frames: VecDeque::new(),
+            },
+        );
+        self.current = Some(step.channel.clone());
+        Ok(format!("已连接 {}（{}）", step.channel, channel_id))
+    }
+
+    async fn disconnect(&mut self, name: &str) -> StepOutcome {
+        let channel = self
+            .channels
+            .remove(name)
+            .ok_or_else(|| format!("通道 {} 未连接", name))?;
+        if self.current.as_deref() == Some(name) {
+            self.current = self.channels.keys().next().cloned();
+        }
+        self.manager
+            .close(&channel.channel_type)
+            .await
+            .map_err(|e| format!("断开通道失败: {}", e))?;
+        Ok(format!("已断开 {}", name))
+    }
+
+    fn set(&mut self, values: &HashMap<String, Value>) -> StepOutcome {
+        let mut names = Vec::new();
+        for (name, value) in values {
+            let value = self.substitute(&value_text(value))?;
+            self.variables.insert(name.clone(), value);
+            names.push(name.as_str());
+        }
+        names.sort();
+        Ok(format!("设置变量 {}", names.join(", ")))
+    }
+
+    async fn send(&mut self, step: &SendStep, sent: &mut Option<Vec<u8>>) -> StepOutcome {
+        let name = self.channel_name(&step.channel)?;
+        let data = match (&step.frame, &step.build, &step.template) {
+            (Some(frame), None, None) => {
+                let frame = self.substitute(frame)?;
+                parse_hex(&frame).ok_or_else(|| format!("报文不是合法的十六进制: {}", frame))?
+            }
+            (None, Some(build), None) => {
+                let message = self.substitute_value(&build.message)?;
+                build_frame(&build.protocol, &message).await?
+            }
+            (None, None, Some(template)) => {
+                let mut vars = HashMap::new();
+                for (name, value) in &step.vars {
+                    vars.insert(name.clone(), self.substitute(value)?);
+                }
+                expand_template(template, &vars).map_err(|e| e.to_string())?
+            }
+            _ => return Err("send 需要且只能指定 frame、build 或 template 之一".to_string()),
+        };
+
+        let clientid = match &step.clientid {
+            Some(clientid) => Some(self.substitute(clientid)?),
+            None => None,
+        };
+
+        // 发送前收到的数据与本次请求无关，丢弃
+        self.pump_pending().await;
+        let channel = self.channels.get_mut(&name).ok_or("通道不存在")?;
+        channel.frames.clear();
+        channel.collector = FrameCollector::default();
+
+        let message = Message::new(serde_json::json!({ "data": data }));
+        channel
+            .channel
+            .send(&message, clientid)
+            .await
+            .map_err(|e| format!("发送失败: {}", e))?;
+        let text = format!("{} 发送 {} 字节", name, data.len());
+        *sent = Some(data);
+        Ok(text)
+    }
+
+    async fn expect(&mut self, step: &ExpectStep, matched: &mut Option<Vec<u8>>) -> StepOutcome {
+        let name = self.channel_name(&step.channel)?;
+        let pattern = match &step.pattern {
+            Some(pattern) => {
+                let pattern = self.substitute(pattern)?;
+                Some(
+                    parse_pattern(&pattern)
+                        .ok_or_else(|| format!("匹配模式格式错误: {}", pattern))?,
+                )
+            }
+            None => None,
+        };
+        let deadline = Instant::now() + Duration::from_millis(step.timeout_ms);
+        let mut received = 0;
+        // 协议和模式符合但断言不满足时的原因
+        let mut last_failure = None;
+
+        loop {
+            let frame = self
+                .channels
+                .get_mut(&name)
+                .and_then(|c| c.frames.pop_front());
+            let Some(frame) = frame else {
+                if !self.wait_data(&name, deadline).await {
+                    break;
+                }
+                continue;
+            };
+            received += 1;
+            if pattern
+                .as_ref()
+                .is_some_and(|pattern| !pattern_matches(pattern, &frame))
+            {
+                continue;
+            }
+            if step.protocol.is_none() && step.assert.is_empty() && step.save.is_empty() {
+                *matched = Some(frame);
+                return Ok(format!("{} 收到匹配的报文", name));
+            }
+
+            let (protocol, items, _) = FrameAnalisyic::process_frame_safe(&frame, &self.region);
+            if let Some(expected) = &step.protocol {
+                if !protocol.to_lowercase().contains(&expected.to_lowercase()) {
+                    continue;
+                }
+            }
+            match self.check(&step.assert, &items) {
+                Ok(()) => {
+                    let mut saved = Vec::new();
+                    for (var, item) in &step.save {
+                        let item = self.substitute(item)?;
+                        let value = find_item(&items, &item)
+                            .map(item_value)
+                            .ok_or_else(|| format!("报文中没有数据项 {}", item))?;
+                        saved.push(format!("{}={}", var, value));
+                        self.variables.insert(var.clone(), value);
+                    }
+                    *matched = Some(frame);
+                    saved.sort();
+                    let mut message = format!("{} 收到匹配的 {} 报文", name, protocol);
+                    if !saved.is_empty() {
+                        message.push_str(&format!("，保存 {}", saved.join(", ")));
+                    }
+                    return Ok(message);
+                }
+                Err(e) => {
+                    last_failure = Some(e);
+                    *matched = Some(frame);
+                }
+            }
+        }
+
+        match last_failure {
+            Some(failure) => Err(failure),
+            None => {
+                *matched = None;
+                Err(format!(
+                    "{} ms 内未收到匹配的报文（共收到 {} 帧）",
+                    step.timeout_ms, received
+                ))
+            }
+        }
+    }
+
+    fn check(&self, conditions: &[Condition], items: &[Value]) -> Result<(), String> {
+        for condition in conditions {
+            let item = self.substitute(&condition.item)?;
+            let node = find_item(items, &item);
+            if condition.op == ConditionOp::Exists {
+                if node.is_none() {
+                    return Err(format!("报文中没有数据项 {}", item));
+                }
+                continue;
+            }
+            let actual = node
+                .map(item_value)
+                .ok_or_else(|| format!("报文中没有数据项 {}", item))?;
+            let expected = self.substitute(&value_text(&condition.value))?;
+            if !compare(&actual, condition.op, &expected)? {
+                return Err(format!(
+                    "断言失败: {} = {}，不满足 {} {}",
+                    item,
+                    actual,
+                    condition.op.symbol(),
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // 等待通道收到新数据，截止时间到达时返回 false
+    async fn wait_data(&mut self, name: &str, deadline: Instant) -> bool {
+        loop {
+            let Some(channel) = self.channels.get_mut(name) else {
+                return false;
+            };
+            if !channel.frames.is_empty() {
+                return true;
+            }
+            let flush_at = channel.collector.flush_at;
+            if flush_at.is_some_and(|at| at <= Instant::now()) {
+                if let Some(rest) = channel.collector.flush() {
+                    channel.frames.push_back(rest);
+                }
+                continue;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            let wake = flush_at.map_or(deadline, |at| at.min(deadline));
+            match timeout_at(wake, self.records.recv()).await {
+                Ok(Ok(record)) => self.dispatch(record).await,
+                Ok(Err(RecvError::Lagged(n))) => eprintln!("场景订阅消息滞后，丢失 {} 条记录", n),
+                Ok(Err(RecvError::Closed)) => return false,
+                Err(_) => {}
+            }
+        }
+    }
+
+    // 处理已经收到但尚未分配到通道的记录
+    async fn pump_pending(&mut self) {
+        loop {
+            match self.records.try_recv() {
+                Ok(record) => self.dispatch(record).await,
+                Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    async fn dispatch(&mut self, record: MessageRecord) {
+        if !matches!(record.direction(), MessageDirection::Received) {
+            return;
+        }
+        let channel_id = record.channel_id();
+        // 服务端的新客户端不在已知列表中，刷新后再判断
+        if !self.channels.values().any(|c| c.owns(channel_id)) {
+            for channel in self.channels.values_mut() {
+                channel.clients = channel.channel.client_ids().await.into_iter().collect();
+            }
+        }
+        if let Some(channel) = self.channels.values_mut().find(|c| c.owns(channel_id)) {
+            let frames = channel.collector.push(&record.payload_bytes());
+            channel.frames.extend(frames);
+        }
+    }
+
+    async fn start_stand_in(&mut self, stand_in: &StandIn) -> Result<(), String> {
+        let params = self.substitute_value(&stand_in.params)?;
+        let (channel_type, options) = channel_from_params(&stand_in.kind, &params)?;
+        let mut replies = Vec::new();
+        for reply in &stand_in.replies {
+            let pattern = self.substitute(&reply.pattern)?;
+            let pattern =
+                parse_pattern(&pattern).ok_or_else(|| format!("匹配模式格式错误: {}", pattern))?;
+            let data = self.substitute(&reply.reply)?;
+            let data =
+                parse_hex(&data).ok_or_else(|| format!("应答报文不是合法的十六进制: {}", data))?;
+            replies.push((pattern, data, Duration::from_millis(reply.delay_ms)));
+        }
+        // 在打开通道前订阅，避免漏掉最早收到的数据
+        let records = subscribe_records();
+        self.manager
+            .add_channel_with_options(channel_type.clone(), options)
+            .await
+            .map_err(|e| e.to_string())?;
+        let channel = self
+            .manager
+            .get_channel(&channel_type)
+            .ok_or("打开通道失败")?;
+        self.stand_in_types.push(channel_type);
+        self.stand_ins
+            .push(tokio::spawn(stand_in_task(channel, records, replies)));
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) {
+        for handle in self.stand_ins.drain(..) {
+            handle.abort();
+        }
+        let types = self
+            .channels
+            .drain()
+            .map(|(_, channel)| channel.channel_type)
+            .chain(self.stand_in_types.drain(..));
+        for channel_type in types.collect::<Vec<_>>() {
+            if let Err(e) = self.manager.close(&channel_type).await {
+                eprintln!("关闭场景通道失败: {}", e);
+            }
+        }
+    }
+}
+
+// 模拟设备：按来源分帧，匹配规则后应答发送方
+async fn stand_in_task(
+    channel: Arc<Box<dyn CommunicationChannel>>,
+    mut records: Receiver<MessageRecord>,
+    replies: Vec<(Vec<Option<u8>>, Vec<u8>, Duration)>,
+) {
+    let channel_id = channel.get_channel_id();
+    let mut clients: HashSet<String> = HashSet::new();
+    let mut collectors: HashMap<String, FrameCollector> = HashMap::new();
+    loop {
+        let flush_at = collectors.values().filter_map(|c| c.flush_at).min();
+        let flush = async {
+            match flush_at {
+                Some(at) => sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        let mut frames = Vec::new();
+        tokio::select! {
+            received = records.recv() => {
+                let record = match received {
+                    Ok(record) => record,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                if !matches!(record.direction(), MessageDirection::Received) {
+                    continue;
+                }
+                let source = record.channel_id().to_string();
+                if source != channel_id && !clients.contains(&source) {
+                    clients = channel.client_ids().await.into_iter().collect();
+                    if !clients.contains(&source) {
+                        continue;
+                    }
+                }
+                let collector = collectors.entry(source.clone()).or_default();
+                for frame in collector.push(&record.payload_bytes()) {
+                    frames.push((source.clone(), frame));
+                }
+            }
+            _ = flush => {
+                let now = Instant::now();
+                for (source, collector) in collectors.iter_mut() {
+                    if collector.flush_at.is_some_and(|at| at <= now) {
+                        frames.extend(collector.flush().map(|frame| (source.clone(), frame)));
+                    }
+                }
+            }
+        }
+
+        for (source, frame) in frames {
+            let Some((_, reply, delay)) = replies
+                .iter()
+                .find(|(pattern, _, _)| pattern_matches(pattern, &frame))
+            else {
+                continue;
+            };
+            if !delay.is_zero() {
+                sleep(*delay).await;
+            }
+            // 服务端通道应答发送报文的客户端
+            let clientid = (source != channel_id).then_some(source);
+            let message = Message::new(serde_json::json!({ "data": reply }));
+            if let Err(e) = channel.send(&message, clientid).await {
+                eprintln!("模拟设备应答失败: {}", e);
+            }
+        }
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+// 按 . 分隔的路径在解析树中查找数据项，先按编码精确匹配，找不到时按名称包含的文字匹配
+fn find_item<'v>(items: &'v [Value], path: &str) -> Option<&'v Value> {
+    let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return None;
+    }
+    find_path(items, &segments, false).or_else(|| find_path(items, &segments, true))
+}
+
+fn find_path<'v>(items: &'v [Value], segments: &[&str], loose: bool) -> Option<&'v Value> {
+    for item in items {
+        let children = item["children"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        if item_matches(item, segments[0], loose) {
+            if segments.len() == 1 {
+                return Some(item);
+            }
+            if let Some(found) = find_path(children, &segments[1..], loose) {
+                return Some(found);
+            }
+        }
+        if let Some(found) = find_path(children, segments, loose) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+// 数据项的 frameDomain 形如 "00000000_(当前)组合有功总电能"
+fn item_matches(item: &Value, segment: &str, loose: bool) -> bool {
+    let domain = item["frameDomain"].as_str().unwrap_or_default();
+    if loose {
+        domain.contains(segment)
+    } else {
+        domain == segment
+            || domain
+                .strip_prefix(segment)
+                .is_some_and(|rest| rest.starts_with('_'))
+    }
+}
+
+// 数据项的值取描述中 "]: " 之后的部分，如 "[00000000_(当前)组合有功总电能]: 001234.56 kWh"
+fn item_value(item: &Value) -> String {
+    let description = item["description"].as_str().unwrap_or_default();
+    let value = match description.rfind("]: ") {
+        Some(pos) => &description[pos + 3..],
+        None => description,
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        value_text(&item["data"]).trim().to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// 取文本开头的数值，如 "001234.56 kWh" 为 1234.56
+fn leading_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let end = text
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(text.len(), |(i, _)| i);
+    text[..end].parse().ok()
+}
+
+fn compare(actual: &str, op: ConditionOp, expected: &str) -> Result<bool, String> {
+    let numbers = leading_number(actual).zip(expected.trim().parse::<f64>().ok());
+    Ok(match op {
+        ConditionOp::Eq => match numbers {
+            Some((a, b)) => a == b,
+            None => actual == expected,
+        },
+        ConditionOp::Ne => match numbers {
+            Some((a, b)) => a != b,
+            None => actual != expected,
+        },
+        ConditionOp::Gt | ConditionOp::Ge | ConditionOp::Lt | ConditionOp::Le => {
+            let (a, b) =
+                numbers.ok_or_else(|| format!("{} 与 {} 不能按数值比较", actual, expected))?;
+            match op {
+                ConditionOp::Gt => a > b,
+                ConditionOp::Ge => a >= b,
+                ConditionOp::Lt => a < b,
+                _ => a <= b,
+            }
+        }
+        ConditionOp::Contains => actual.contains(expected),
+        ConditionOp::Matches => Regex::new(expected)
+            .map_err(|e| format!("正则表达式错误: {}", e))?
+            .is_match(actual),
+        ConditionOp::Exists => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const REQUEST: &str = "68 10 00 10 00 68 4A 01 02 03 04 05 06 00 0C 61 00 00 00 FF 00 00 CB 16";
+    const REPLY: &str = "68 25 00 25 00 68 88 01 02 03 04 05 06 00 0C 61 00 00 00 FF 00 00 04 \
+                         56 34 12 00 00 01 00 00 00 00 00 00 00 00 00 00 00 00 00 00 AA 16";
+
+    // 场景在名为 link 的虚拟链路上运行，B 端为按请求应答的模拟设备
+    fn scenario(link: &str, continue_on_failure: bool, steps: &str) -> Scenario {
+        let yaml = format!(
+            r#"
+name: 模拟设备
+continue_on_failure: {continue_on_failure}
+variables: {{ seq: "61" }}
+stand_ins:
+  - name: terminal
+    type: virtual
+    params: {{ name: {link}, end: B }}
+    replies:
+      - pattern: "68 10 00 10 00 68 4A"
+        reply: "{REPLY}"
+steps:
+  - connect: {{ channel: master, type: virtual, params: {{ name: {link} }} }}
+{steps}"#
+        );
+        Scenario::from_yaml(&yaml).unwrap()
+    }
+
+    async fn run(scenario: Scenario) -> (ScenarioReport, Vec<String>) {
+        let reported = Mutex::new(Vec::new());
+        let report = run_scenario(scenario, |step| {
+            reported.lock().unwrap().push(step.path.clone())
+        })
+        .await;
+        (report, reported.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn passes_against_stand_in() {
+        let steps = format!(
+            r#"
+  - name: 读电能数据块
+    send: {{ frame: "68 10 00 10 00 68 4A 01 02 03 04 05 06 00 0C ${{seq}} 00 00 00 FF 00 00 CB 16" }}
+  - expect: {{ pattern: "68 25 00 ?? ?? 68 88", timeout_ms: 2000 }}
+  - loop:
+      times: 2
+      var: i
+      steps:
+        - send: {{ frame: "{REQUEST}" }}
+        - expect: {{ pattern: "68 ?? ?? ?? ?? 68 88", timeout_ms: 2000 }}
+  - set: {{ last: "round ${{i}}" }}
+  - disconnect: master
+"#
+        );
+        let (report, reported) = run(scenario("scenario-pass", false, &steps)).await;
+
+        assert!(report.passed, "{}", report.to_text());
+        assert_eq!((report.passed_steps, report.failed_steps), (9, 0));
+        let paths: Vec<&str> = report.steps.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["1", "2", "3", "4[1].1", "4[1].2", "4[2].1", "4[2].2", "5", "6"]
+        );
+        assert_eq!(reported, paths);
+        assert_eq!(report.steps[1].name.as_deref(), Some("读电能数据块"));
+        assert_eq!(report.steps[1].frame.as_deref(), Some(REQUEST));
+        assert_eq!(
+            report.steps[2].frame,
+            Some(FrameFun::get_data_str_with_space(
+                &FrameFun::get_frame_list_from_str(REPLY)
+            ))
+        );
+        assert_eq!(report.variables["last"], "round 2");
+        assert!(report
+            .to_text()
+            .ends_with(&format!("{}\n", report.summary())));
+        assert!(report.summary().starts_with("PASS 模拟设备"));
+    }
+
+    #[tokio::test]
+    async fn reports_failures_and_continues() {
+        let steps = format!(
+            r#"
+  - send: {{ frame: "{REQUEST}" }}
+  - expect: {{ pattern: "68 25 00", timeout_ms: 2000 }}
+  - send: {{ frame: "01 02 03" }}
+  - expect: {{ pattern: "AA BB", timeout_ms: 300 }}
+  - send: {{ frame: "${{nope}}" }}
+"#
+        );
+        let (report, _) = run(scenario("scenario-fail", true, &steps)).await;
+
+        assert!(!report.passed);
+        assert_eq!((report.passed_steps, report.failed_steps), (4, 2));
+        let failed: Vec<(&str, &str)> = report
+            .steps
+            .iter()
+            .filter(|s| !s.passed)
+            .map(|s| (s.path.as_str(), s.kind.as_str()))
+            .collect();
+        assert_eq!(failed, [("5", "expect"), ("6", "send")]);
+        assert!(report.steps[4]
+            .message
+            .contains("300 ms 内未收到匹配的报文"));
+        assert_eq!(report.steps[5].message, "未定义的变量: nope");
+        assert!(report.summary().starts_with("FAIL"));
+    }
+
+    #[tokio::test]
+    async fn stops_at_first_failure() {
+        let steps = r#"
+  - expect: { pattern: "AA BB", timeout_ms: 100 }
+  - send: { frame: "01 02 03" }
+"#;
+        let (report, _) = run(scenario("scenario-stop", false, steps)).await;
+
+        assert!(!report.passed);
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!((report.passed_steps, report.failed_steps), (1, 1));
+    }
+}
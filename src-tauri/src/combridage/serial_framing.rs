@@ -0,0 +1,288 @@
+use crate::basefunc::frame_stream::{FrameSearch, FrameStream};
+use crate::protocol::modbus::parser::ModbusParser;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// 可识别完整帧的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameProtocol {
+    Dlt645,
+    Csg13,
+    Modbus,
+}
+
+/// 串口接收分帧配置，默认不启用，每次读到的数据直接上报
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialFraming {
+    pub enabled: bool,
+    /// 字符间静默超时（毫秒），未指定时按波特率计算 3.5 个字符时间；
+    /// USB 转串口芯片按 1~16ms 的周期上报数据，帧被拆开时可指定更大的值
+    pub inter_byte_timeout_ms: Option<u64>,
+    /// 单帧最大长度，超过后直接作为一帧上报
+    pub max_frame_len: usize,
+    /// 收到这些协议的完整帧时立即上报，不等待静默超时
+    pub protocols: Vec<FrameProtocol>,
+}
+
+impl Default for SerialFraming {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            inter_byte_timeout_ms: None,
+            max_frame_len: 4096,
+            protocols: Vec::new(),
+        }
+    }
+}
+
+impl SerialFraming {
+    /// 按波特率和每个字符的位数（起始位 + 数据位 + 校验位 + 停止位）计算静默超时
+    ///
+    /// 与 Modbus RTU 的 T3.5 一致，波特率高于 19200 时固定为 1.75ms
+    pub fn silence(&self, baud_rate: u32, bits_per_char: u32) -> Duration {
+        if let Some(ms) = self.inter_byte_timeout_ms {
+            return Duration::from_millis(ms.max(1));
+        }
+        if baud_rate == 0 || baud_rate > 19200 {
+            Duration::from_micros(1750)
+        } else {
+            Duration::from_secs_f64(3.5 * bits_per_char as f64 / baud_rate as f64)
+        }
+    }
+}
+
+/// 把串口每次读到的零散数据拼接为完整帧
+///
+/// 帧在以下情况下结束：识别到所配置协议的完整帧、达到最大长度，或静默超时后由调用方 `flush`
+#[derive(Debug)]
+pub struct SerialFramer {
+    config: SerialFraming,
+    silence: Duration,
+    buffer: Vec<u8>,
+    // 当前帧第一个字节的接收时间（毫秒）
+    first_byte_at: i64,
+}
+
+impl SerialFramer {
+    pub fn new(config: SerialFraming, baud_rate: u32, bits_per_char: u32) -> Self {
+        let silence = config.silence(baud_rate, bits_per_char);
+        Self {
+            config,
+            silence,
+            buffer: Vec::new(),
+            first_byte_at: 0,
+        }
+    }
+
+    /// 帧间静默超时
+    pub fn silence(&self) -> Duration {
+        self.silence
+    }
+
+    /// 是否有尚未上报的数据
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// 追加读到的数据，返回已完整的帧及其第一个字节的时间
+    pub fn push(&mut self, data: &[u8], now: i64) -> Vec<(Vec<u8>, i64)> {
+        if !self.config.enabled {
+            return vec![(data.to_vec(), now)];
+        }
+        if self.buffer.is_empty() {
+            self.first_byte_at = now;
+        }
+        self.buffer.extend_from_slice(data);
+
+        let mut frames = Vec::new();
+        while !self.buffer.is_empty() {
+            let (start, len) = match self.complete_frame() {
+                Some(found) => found,
+                None if self.buffer.len() >= self.config.max_frame_len.max(1) => {
+                    (0, self.config.max_frame_len.max(1))
+                }
+                None => break,
+            };
+            // 帧前无法识别的数据单独作为一帧上报
+            if start > 0 {
+                let skipped: Vec<u8> = self.buffer.drain(..start).collect();
+                frames.push((skipped, self.first_byte_at));
+            }
+            let frame: Vec<u8> = self.buffer.drain(..len).collect();
+            frames.push((frame, self.first_byte_at));
+            // 同一次读取中剩余的数据属于下一帧
+            self.first_byte_at = now;
+        }
+        frames
+    }
+
+    /// 静默超时或串口断开时取出缓存的数据作为一帧
+    pub fn flush(&mut self) -> Option<(Vec<u8>, i64)> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        Some((std::mem::take(&mut self.buffer), self.first_byte_at))
+    }
+
+    // 缓存中有所配置协议的完整帧时返回 (帧起始位置, 帧长度)
+    //
+    // 645/南网13 与 FrameStream 一样跳过帧头前的数据；Modbus RTU 没有帧头，只识别缓存开头的帧
+    fn complete_frame(&self) -> Option<(usize, usize)> {
+        let protocols = &self.config.protocols;
+        if protocols.contains(&FrameProtocol::Dlt645) || protocols.contains(&FrameProtocol::Csg13) {
+            if let FrameSearch::Complete { start, len } = FrameStream::find_frame(&self.buffer) {
+                let body: Vec<u8> = self.buffer[start..start + len]
+                    .iter()
+                    .copied()
+                    .skip_while(|&b| b == 0xFE)
+                    .collect();
+                let is_645 = body.len() > 7 && body[7] == 0x68;
+                let is_csg13 =
+                    body.len() > 5 && body[5] == 0x68 && body[1] == body[3] && body[2] == body[4];
+                if (is_645 && protocols.contains(&FrameProtocol::Dlt645))
+                    || (is_csg13 && protocols.contains(&FrameProtocol::Csg13))
+                {
+                    return Some((start, len));
+                }
+            }
+        }
+        if protocols.contains(&FrameProtocol::Modbus) {
+            return modbus_frame_len(&self.buffer).map(|len| (0, len));
+        }
+        None
+    }
+}
+
+// 按功能码推算 Modbus RTU 帧的可能长度（请求或应答），CRC 校验通过的即为完整帧
+pub(crate) fn modbus_frame_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 4 || data[0] > 247 {
+        return None;
+    }
+    let mut candidates = match data[1] {
+        // 读请求固定 8 字节，应答为 地址 功能码 字节数 数据 CRC
+        0x01..=0x04 => vec![8, 5 + data[2] as usize],
+        0x05 | 0x06 => vec![8],
+        // 写多个请求为 地址 功能码 起始地址 数量 字节数 数据 CRC，应答固定 8 字节
+        0x0F | 0x10 => {
+            let mut lens = vec![8];
+            if data.len() > 6 {
+                lens.push(9 + data[6] as usize);
+            }
+            lens
+        }
+        // 异常应答
+        code if code & 0x80 != 0 => vec![5],
+        _ => return None,
+    };
+    candidates.sort_unstable();
+    candidates.into_iter().find(|&len| {
+        if data.len() < len {
+            return false;
+        }
+        let crc = ((data[len - 1] as u16) << 8) | data[len - 2] as u16;
+        ModbusParser::calculate_crc(&data[..len - 2]) == crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basefunc::frame_fun::FrameFun;
+
+    const DLT645: &str = "FE FE 68 01 00 00 00 00 00 68 11 04 33 33 34 33 B3 16";
+
+    fn framer_with(protocols: Vec<FrameProtocol>, max_frame_len: usize) -> SerialFramer {
+        let config = SerialFraming {
+            enabled: true,
+            max_frame_len,
+            protocols,
+            ..Default::default()
+        };
+        SerialFramer::new(config, 9600, 11)
+    }
+
+    fn modbus(body: &[u8]) -> Vec<u8> {
+        let mut frame = body.to_vec();
+        frame.extend_from_slice(&ModbusParser::calculate_crc(body).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn silence_follows_baud_rate() {
+        let config = SerialFraming::default();
+        assert_eq!(
+            config.silence(9600, 11),
+            Duration::from_secs_f64(3.5 * 11.0 / 9600.0)
+        );
+        assert_eq!(config.silence(115200, 10), Duration::from_micros(1750));
+        let config = SerialFraming {
+            inter_byte_timeout_ms: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(config.silence(9600, 11), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn joins_645_frame_split_across_reads() {
+        let frame = FrameFun::get_frame_list_from_str(DLT645);
+        let mut framer = framer_with(vec![FrameProtocol::Dlt645], 4096);
+        assert!(framer.push(&frame[..5], 1).is_empty());
+        assert!(framer.push(&frame[5..12], 2).is_empty());
+        assert_eq!(framer.push(&frame[12..], 3), [(frame.clone(), 1)]);
+        assert!(framer.is_empty());
+
+        // 未配置的协议等待静默超时
+        let mut framer = framer_with(vec![FrameProtocol::Csg13], 4096);
+        assert!(framer.push(&frame, 1).is_empty());
+        assert_eq!(framer.flush(), Some((frame, 1)));
+    }
+
+    #[test]
+    fn completes_modbus_frame_by_crc() {
+        let request = modbus(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]);
+        let reply = modbus(&[0x01, 0x03, 0x04, 0x00, 0x01, 0x00, 0x02]);
+        let mut framer = framer_with(vec![FrameProtocol::Modbus], 4096);
+        assert!(framer.push(&request[..6], 1).is_empty());
+        assert_eq!(framer.push(&request[6..], 2), [(request, 1)]);
+        // 应答长度按字节数计算，9 字节的应答不会在第 8 字节处误判
+        assert!(framer.push(&reply[..8], 3).is_empty());
+        assert_eq!(framer.push(&reply[8..], 4), [(reply, 3)]);
+    }
+
+    #[test]
+    fn reports_leading_garbage_separately() {
+        let frame = FrameFun::get_frame_list_from_str(DLT645);
+        let mut framer = framer_with(vec![FrameProtocol::Dlt645], 4096);
+        let mut data = vec![0x00, 0x68, 0x11];
+        data.extend_from_slice(&frame[..10]);
+        assert!(framer.push(&data, 1).is_empty());
+        assert_eq!(
+            framer.push(&frame[10..], 2),
+            [(vec![0x00, 0x68, 0x11], 1), (frame.clone(), 1)]
+        );
+
+        // 同一次读取中帧后的数据属于下一帧
+        let mut data = frame.clone();
+        data.extend_from_slice(&[0x01, 0x02]);
+        assert_eq!(framer.push(&data, 5), [(frame, 5)]);
+        assert_eq!(framer.flush(), Some((vec![0x01, 0x02], 5)));
+    }
+
+    #[test]
+    fn cuts_off_at_max_frame_len() {
+        let mut framer = framer_with(Vec::new(), 4);
+        let data: Vec<u8> = (0..10).collect();
+        assert_eq!(
+            framer.push(&data, 1),
+            [(vec![0, 1, 2, 3], 1), (vec![4, 5, 6, 7], 1)]
+        );
+        assert_eq!(framer.flush(), Some((vec![8, 9], 1)));
+        assert_eq!(framer.flush(), None);
+
+        // 未启用分帧时每次读到的数据直接上报
+        let mut framer = SerialFramer::new(SerialFraming::default(), 9600, 11);
+        assert_eq!(framer.push(&data, 7), [(data.clone(), 7)]);
+        assert!(framer.is_empty());
+    }
+}
@@ -1,12 +1,14 @@
+use crate::combridage::correlation::Correlator;
 use crate::combridage::messagemanager::{MessageDirection, MessageManager};
-use crate::combridage::{ChannelState, CommunicationChannel, Message};
-use crate::global::get_app_handle;
+use crate::combridage::reconnect::{OutageBuffer, ReconnectPolicy};
+use crate::combridage::serial_framing::{SerialFramer, SerialFraming};
+use crate::combridage::{ChannelOptions, ChannelState, CommunicationChannel, Message};
+use crate::global::emit_event;
 use async_trait::async_trait;
 use serde_json;
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
-use tauri::Emitter;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::broadcast;
 use tokio::sync::{mpsc, Mutex};
@@ -15,19 +17,72 @@ use tokio::time::{sleep, timeout};
 use tokio_serial::SerialStream;
 use uuid::Uuid;
 
+// 打开串口所需的参数，设备重新插入后用于重新打开；串口服务器通道也按它协商参数
+#[derive(Clone)]
+pub(crate) struct SerialSettings {
+    pub(crate) port_name: String,
+    pub(crate) baud_rate: u32,
+    pub(crate) databit: tokio_serial::DataBits,
+    pub(crate) flowctrl: tokio_serial::FlowControl,
+    pub(crate) parity: tokio_serial::Parity,
+    pub(crate) stopbit: tokio_serial::StopBits,
+}
+
+impl SerialSettings {
+    fn open(&self) -> tokio_serial::Result<SerialStream> {
+        let builder = tokio_serial::new(&self.port_name, self.baud_rate)
+            .data_bits(self.databit)
+            .flow_control(self.flowctrl)
+            .parity(self.parity)
+            .stop_bits(self.stopbit);
+        SerialStream::open(&builder)
+    }
+
+    // 每个字符占用的位数：起始位 + 数据位 + 校验位 + 停止位
+    pub(crate) fn bits_per_char(&self) -> u32 {
+        let databits = match self.databit {
+            tokio_serial::DataBits::Five => 5,
+            tokio_serial::DataBits::Six => 6,
+            tokio_serial::DataBits::Seven => 7,
+            tokio_serial::DataBits::Eight => 8,
+        };
+        let parity = match self.parity {
+            tokio_serial::Parity::None => 0,
+            _ => 1,
+        };
+        let stopbits = match self.stopbit {
+            tokio_serial::StopBits::One => 1,
+            tokio_serial::StopBits::Two => 2,
+        };
+        1 + databits + parity + stopbits
+    }
+}
+
+// 接收到的一帧数据及其第一个字节的时间（毫秒）
+type ReceivedFrame = (Vec<u8>, i64);
+
 pub struct SerialPortChannel {
     channeltype: String,
     channelid: String,
     channel_name: String,
     port_name: String,
+    settings: SerialSettings,
+    reconnect: ReconnectPolicy,
+    framing: SerialFraming,
+    // 断线期间缓存的待发送数据
+    pending: Arc<Mutex<OutageBuffer<Vec<u8>>>>,
     writer: Arc<Mutex<Option<tokio::io::WriteHalf<SerialStream>>>>,
     reader: Arc<Mutex<Option<tokio::io::ReadHalf<SerialStream>>>>,
     sender: broadcast::Sender<Vec<u8>>,
-    data_tx: mpsc::Sender<Vec<u8>>,
-    data_rx: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    data_tx: mpsc::Sender<ReceivedFrame>,
+    data_rx: Arc<Mutex<mpsc::Receiver<ReceivedFrame>>>,
+    // 未被等待中的请求认领的帧，由 receive 读取
+    tx_recv: mpsc::Sender<ReceivedFrame>,
+    rx_recv: Arc<Mutex<mpsc::Receiver<ReceivedFrame>>>,
     send_task_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
     receive_task_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
     process_task_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    correlator: Correlator,
 }
 
 impl SerialPortChannel {
@@ -38,9 +93,14 @@ impl SerialPortChannel {
         fowctrl: tokio_serial::FlowControl,
         parity: tokio_serial::Parity,
         stopbit: tokio_serial::StopBits,
+        options: ChannelOptions,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let ChannelOptions {
+            reconnect, framing, ..
+        } = options;
         let (tx, _) = broadcast::channel::<Vec<u8>>(100);
-        let (data_tx, data_rx) = mpsc::channel::<Vec<u8>>(100);
+        let (data_tx, data_rx) = mpsc::channel::<ReceivedFrame>(100);
+        let (tx_recv, rx_recv) = mpsc::channel::<ReceivedFrame>(100);
 
         let writer = Arc::new(Mutex::new(None));
         let reader = Arc::new(Mutex::new(None));
@@ -53,14 +113,28 @@ impl SerialPortChannel {
             channelid: "serial".to_string() + &Uuid::new_v4().to_string(),
             channel_name: "Serial".to_string() + &port_name.to_string(),
             port_name: port_name.to_string(),
+            settings: SerialSettings {
+                port_name: port_name.to_string(),
+                baud_rate,
+                databit,
+                flowctrl: fowctrl,
+                parity,
+                stopbit,
+            },
+            pending: Arc::new(Mutex::new(OutageBuffer::new(reconnect.max_buffered))),
+            reconnect,
+            framing,
             writer,
             reader,
             sender: tx,
             data_tx,
             data_rx: Arc::new(Mutex::new(data_rx)),
+            tx_recv,
+            rx_recv: Arc::new(Mutex::new(rx_recv)),
             send_task_handle,
             receive_task_handle,
             process_task_handle,
+            correlator: Correlator::new(),
         };
 
         channel
@@ -106,8 +180,7 @@ impl SerialPortChannel {
                     *self.reader.lock().await = Some(reader);
                     *self.writer.lock().await = Some(writer);
 
-                    let app_handle = get_app_handle();
-                    let message_manager = MessageManager::new(app_handle)?;
+                    let message_manager = MessageManager::from_global()?;
                     let _ = message_manager.register_channel(&port_name).await;
 
                     let message_send = message_manager.clone();
@@ -142,16 +215,22 @@ impl SerialPortChannel {
         message_manager: MessageManager,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let writer = self.writer.clone();
+        let pending = self.pending.clone();
+        let buffers = self.reconnect.buffers();
         let mut rx = self.sender.subscribe();
-        let channeltype = self.channeltype.clone();
-        let channelid = self.channelid.clone();
 
         let handle = tokio::spawn(async move {
             while let Ok(data) = rx.recv().await {
-                if let Some(writer) = writer.lock().await.as_mut() {
-                    if let Err(e) = writer.write_all(&data).await {
-                        eprintln!("发送数据时发生错误: {:?}", e);
-                        break;
+                // 持有写入端的锁再放入缓存，保证重新打开串口后补发时不会漏掉
+                let mut writer = writer.lock().await;
+                let result = match writer.as_mut() {
+                    Some(writer) => writer.write_all(&data).await,
+                    None => Err(std::io::ErrorKind::NotConnected.into()),
+                };
+                if let Err(e) = result {
+                    eprintln!("发送数据时发生错误: {:?}", e);
+                    if buffers {
+                        pending.lock().await.push(data);
                     }
                 }
             }
@@ -166,31 +245,61 @@ impl SerialPortChannel {
         message_manager: MessageManager,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         let reader = self.reader.clone();
+        let writer = self.writer.clone();
+        let pending = self.pending.clone();
+        let settings = self.settings.clone();
+        let reconnect = self.reconnect.clone();
         let data_sender = self.data_tx.clone();
-        let channeltype = self.channeltype.clone();
-        let channelid = self.channelid.clone();
+        let mut framer = SerialFramer::new(
+            self.framing.clone(),
+            settings.baud_rate,
+            settings.bits_per_char(),
+        );
 
         // 启动数据接收任务
         let receive_handle = tokio::spawn(async move {
             let mut buffer = vec![0; 1024];
-            while let Some(reader) = reader.lock().await.as_mut() {
-                match reader.read(&mut buffer).await {
-                    Ok(n) if n > 0 => {
-                        let received_data = buffer[..n].to_vec();
-                        println!("接收到 {} 字节的数据", n);
-
-                        // 将数据放入队列
-                        if let Err(e) = data_sender.send(received_data).await {
+            loop {
+                while let Some(reader) = reader.lock().await.as_mut() {
+                    // 有未完成的帧时，最多等待一个静默超时
+                    let result = if framer.is_empty() {
+                        Ok(reader.read(&mut buffer).await)
+                    } else {
+                        timeout(framer.silence(), reader.read(&mut buffer)).await
+                    };
+                    let frames = match result {
+                        Ok(Ok(n)) if n > 0 => {
+                            println!("接收到 {} 字节的数据", n);
+                            framer.push(&buffer[..n], chrono::Utc::now().timestamp_millis())
+                        }
+                        Ok(Ok(_)) => Vec::new(),
+                        Ok(Err(e)) => {
+                            eprintln!("读取数据时发生错误: {:?}", e);
+                            break;
+                        }
+                        // 静默超时，缓存的数据作为一帧
+                        Err(_) => framer.flush().into_iter().collect(),
+                    };
+
+                    // 将数据放入队列
+                    for frame in frames {
+                        if let Err(e) = data_sender.send(frame).await {
                             eprintln!("发送数据到队列失败: {:?}", e);
-                            continue;
                         }
                     }
-                    Ok(_) => {}
-                    Err(e) => {
-                        eprintln!("读取数据时发生错误: {:?}", e);
-                        break;
+                }
+
+                // 断开前已收到的不完整数据照常上报
+                if let Some(frame) = framer.flush() {
+                    if let Err(e) = data_sender.send(frame).await {
+                        eprintln!("发送数据到队列失败: {:?}", e);
                     }
                 }
+
+                // 读取出错（如设备被拔出），按策略重新打开串口
+                if !Self::reopen(&settings, &reconnect, &reader, &writer, &pending).await {
+                    break;
+                }
             }
         });
 
@@ -203,12 +312,18 @@ impl SerialPortChannel {
             let channelid = self.channelid.clone();
             let channel_name = self.channel_name.clone();
             let message_manager = message_manager.clone();
+            let correlator = self.correlator.clone();
+            let tx_recv = self.tx_recv.clone();
 
             async move {
-                while let Some(data) = data_rx.lock().await.recv().await {
-                    let message = Message::new(serde_json::json!({
-                        "data": data
-                    }));
+                while let Some((data, timestamp)) = data_rx.lock().await.recv().await {
+                    // 使用帧第一个字节的接收时间
+                    let message = Message::with_timestamp(
+                        serde_json::json!({
+                            "data": data
+                        }),
+                        timestamp,
+                    );
 
                     // 记录消息
                     if let Err(e) = message_manager
@@ -224,6 +339,13 @@ impl SerialPortChannel {
                     {
                         eprintln!("记录消息失败: {:?}", e);
                     }
+                    // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+                    let Some(data) = correlator.offer(data) else {
+                        continue;
+                    };
+                    if let Err(e) = tx_recv.try_send((data, timestamp)) {
+                        eprintln!("SerialPortChannel queue full, dropped frame: {:?}", e);
+                    }
                 }
             }
         });
@@ -232,6 +354,59 @@ impl SerialPortChannel {
         Ok(())
     }
 
+    // 按重连策略重新打开串口，成功后补发缓存的数据；放弃时返回 false
+    async fn reopen(
+        settings: &SerialSettings,
+        reconnect: &ReconnectPolicy,
+        reader: &Mutex<Option<tokio::io::ReadHalf<SerialStream>>>,
+        writer: &Mutex<Option<tokio::io::WriteHalf<SerialStream>>>,
+        pending: &Mutex<OutageBuffer<Vec<u8>>>,
+    ) -> bool {
+        *reader.lock().await = None;
+        *writer.lock().await = None;
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(delay) = reconnect.backoff(attempt) else {
+                let _ = emit_state(&settings.port_name, ChannelState::Disconnected);
+                return false;
+            };
+            let _ = emit_state(&settings.port_name, ChannelState::Reconnecting);
+            println!(
+                "重新打开串口 {}，第 {} 次尝试，等待 {:?}",
+                settings.port_name, attempt, delay
+            );
+            sleep(delay).await;
+
+            let port = match settings.open() {
+                Ok(port) => port,
+                Err(e) => {
+                    eprintln!("重新打开串口失败: {:?}", e);
+                    continue;
+                }
+            };
+            let (new_reader, new_writer) = tokio::io::split(port);
+            *reader.lock().await = Some(new_reader);
+
+            let mut writer = writer.lock().await;
+            let writer = writer.insert(new_writer);
+            let (buffered, dropped) = pending.lock().await.drain();
+            if dropped > 0 {
+                eprintln!("串口断开期间丢弃了 {} 条待发送数据", dropped);
+            }
+            for data in buffered {
+                if let Err(e) = writer.write_all(&data).await {
+                    eprintln!("补发数据时发生错误: {:?}", e);
+                    break;
+                }
+            }
+            println!("串口已重新打开: {}", settings.port_name);
+            let _ = emit_state(&settings.port_name, ChannelState::Connected);
+            return true;
+        }
+    }
+
     pub async fn is_connected(&self) -> bool {
         self.writer.lock().await.is_some() && self.reader.lock().await.is_some()
     }
@@ -253,11 +428,13 @@ impl CommunicationChannel for SerialPortChannel {
         } else {
             return Err("Invalid data format".into());
         };
+        if !self.reconnect.buffers() && self.writer.lock().await.is_none() {
+            return Err(format!("串口未连接: {}", self.port_name).into());
+        }
 
         self.sender.send(data)?;
 
-        let app_handle = get_app_handle();
-        let message_manager = MessageManager::new(app_handle.clone())?;
+        let message_manager = MessageManager::from_global()?;
 
         let mut message_clone = message.clone();
         message_clone.update_timestamp();
@@ -278,11 +455,11 @@ impl CommunicationChannel for SerialPortChannel {
     }
 
     async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
-        if let Some(message) = self.data_rx.lock().await.recv().await {
+        if let Some((message, timestamp)) = self.rx_recv.lock().await.recv().await {
             let payload = serde_json::json!({
                 "data": message
             });
-            Ok(Message::new(payload))
+            Ok(Message::with_timestamp(payload, timestamp))
         } else {
             Err("接收消息失败".into())
         }
@@ -293,11 +470,9 @@ impl CommunicationChannel for SerialPortChannel {
         message: &Message,
         timeout_secs: u64,
     ) -> Result<Message, Box<dyn Error + Send + Sync>> {
-        self.send(message, None).await?;
-        match timeout(Duration::from_secs(timeout_secs), self.receive()).await {
-            Ok(result) => result,
-            Err(_) => Err("等待响应超时".into()),
-        }
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
     }
 
     async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -327,19 +502,7 @@ impl CommunicationChannel for SerialPortChannel {
         &self,
         state: ChannelState,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let app_handle = get_app_handle();
-        let payload = serde_json::json!({
-            "channeltype": "serial",
-            "channelId": self.port_name.clone(),
-            "state": state,
-            "data": serde_json::Value::Null,
-            "reason": "串口已断开连接",
-        });
-        app_handle
-            .emit("channel-state", serde_json::to_string(&payload)?)
-            .unwrap();
-
-        Ok(())
+        emit_state(&self.port_name, state)
     }
 
     fn get_channel_id(&self) -> String {
@@ -358,3 +521,21 @@ impl CommunicationChannel for SerialPortChannel {
         Err("Serial port does not support topic unsubscription".into())
     }
 }
+
+// 发送串口状态变更事件
+fn emit_state(port_name: &str, state: ChannelState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let reason = match state {
+        ChannelState::Connected => "串口已连接",
+        ChannelState::Disconnected => "串口已断开连接",
+        ChannelState::Reconnecting => "串口正在重新连接",
+    };
+    let payload = serde_json::json!({
+        "channeltype": "serial",
+        "channelId": port_name,
+        "state": state,
+        "data": serde_json::Value::Null,
+        "reason": reason,
+    });
+    emit_event("channel-state", serde_json::to_string(&payload)?)?;
+    Ok(())
+}
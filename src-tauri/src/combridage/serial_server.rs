@@ -0,0 +1,452 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::reconnect::{OutageBuffer, ReconnectPolicy};
+use crate::combridage::rfc2217::{self, ComPortSettings, TelnetDecoder, TelnetEvent};
+use crate::combridage::serial_framing::{SerialFramer, SerialFraming};
+use crate::combridage::serial_port::SerialSettings;
+use crate::combridage::{ChannelOptions, ChannelState, CommunicationChannel, Message};
+use crate::global::emit_event;
+use async_trait::async_trait;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::{sleep, timeout, Duration};
+use uuid::Uuid;
+
+// 建立 TCP 连接和 RFC 2217 协商的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// 接收到的一帧数据及其第一个字节的时间（毫秒）
+type ReceivedFrame = (Vec<u8>, i64);
+
+// 新建立的连接：读取端、写入端、Telnet 解码器及协商期间收到的数据
+type Connection = (OwnedReadHalf, OwnedWriteHalf, TelnetDecoder, Vec<u8>);
+
+/// 串口服务器通道
+///
+/// 通过 TCP 连接串口服务器（如 Moxa、有人的 RS-485 转以太网设备）。RFC 2217 模式下
+/// 用 Telnet COM-PORT-OPTION 设置波特率、数据位、校验位、停止位和流控，数据中的 0xFF
+/// 按 Telnet 规则转义；透传模式下直接收发原始字节，串口参数需在设备上配置。
+/// 接收分帧和断线重连与本地串口通道一致。
+#[derive(Clone)]
+pub struct SerialServerChannel {
+    channeltype: String,
+    channelid: String,
+    channel_name: String,
+    address: String,
+    settings: SerialSettings,
+    rfc2217: bool,
+    reconnect: ReconnectPolicy,
+    framing: SerialFraming,
+    // 断线期间缓存的待发送数据
+    pending: Arc<Mutex<OutageBuffer<Vec<u8>>>>,
+    writer: Arc<Mutex<Option<OwnedWriteHalf>>>,
+    shutdown_signal: broadcast::Sender<()>,
+    data_rx: Arc<Mutex<mpsc::Receiver<ReceivedFrame>>>,
+    message_manager: MessageManager,
+    correlator: Correlator,
+}
+
+impl SerialServerChannel {
+    pub(crate) async fn new(
+        ipaddr: &str,
+        port: u16,
+        settings: SerialSettings,
+        rfc2217: bool,
+        options: ChannelOptions,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let ChannelOptions {
+            reconnect, framing, ..
+        } = options;
+        let address = format!("{}:{}", ipaddr, port);
+        let (reader, writer, decoder, leftover) = Self::open(&address, &settings, rfc2217).await?;
+
+        let (shutdown_signal, _) = broadcast::channel(1);
+        let (data_tx, data_rx) = mpsc::channel(100);
+        let message_manager = MessageManager::from_global()?;
+
+        let channel = Self {
+            channeltype: "serialserver".to_string(),
+            channelid: "serialserver".to_string() + &Uuid::new_v4().to_string(),
+            channel_name: "SerialServer".to_string() + &address,
+            address,
+            settings,
+            rfc2217,
+            pending: Arc::new(Mutex::new(OutageBuffer::new(reconnect.max_buffered))),
+            reconnect,
+            framing,
+            writer: Arc::new(Mutex::new(Some(writer))),
+            shutdown_signal,
+            data_rx: Arc::new(Mutex::new(data_rx)),
+            message_manager,
+            correlator: Correlator::new(),
+        };
+        let _ = channel
+            .message_manager
+            .register_channel(&channel.channelid)
+            .await;
+
+        // 启动接收任务
+        let channel_recv = channel.clone();
+        tokio::spawn(async move {
+            channel_recv
+                .receive_task(reader, decoder, leftover, data_tx)
+                .await;
+        });
+
+        channel.on_statechange(ChannelState::Connected).await?;
+        Ok(channel)
+    }
+
+    // 建立 TCP 连接，RFC 2217 模式下协商串口参数
+    async fn open(
+        address: &str,
+        settings: &SerialSettings,
+        rfc2217: bool,
+    ) -> Result<Connection, Box<dyn Error + Send + Sync>> {
+        let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| format!("连接串口服务器超时: {}", address))??;
+        stream.set_nodelay(true)?;
+
+        let mut decoder = TelnetDecoder::default();
+        let leftover = if rfc2217 {
+            let com_port = ComPortSettings::new(settings);
+            rfc2217::negotiate(&mut stream, &com_port, &mut decoder, NEGOTIATE_TIMEOUT).await?
+        } else {
+            Vec::new()
+        };
+        println!(
+            "SerialServerChannel connected to {} (rfc2217: {})",
+            address, rfc2217
+        );
+        let (reader, writer) = stream.into_split();
+        Ok((reader, writer, decoder, leftover))
+    }
+
+    // 接收任务：解析 Telnet 数据流，按分帧配置组帧后记录并放入接收队列
+    async fn receive_task(
+        self,
+        mut reader: OwnedReadHalf,
+        mut decoder: TelnetDecoder,
+        mut data: Vec<u8>,
+        data_tx: mpsc::Sender<ReceivedFrame>,
+    ) {
+        let mut shutdown_receiver = self.shutdown_signal.subscribe();
+        let mut framer = SerialFramer::new(
+            self.framing.clone(),
+            self.settings.baud_rate,
+            self.settings.bits_per_char(),
+        );
+        let mut buffer = vec![0; 1024];
+        let mut events = Vec::new();
+        loop {
+            loop {
+                if !data.is_empty() {
+                    let now = chrono::Utc::now().timestamp_millis();
+                    for frame in framer.push(&std::mem::take(&mut data), now) {
+                        self.deliver(frame, &data_tx).await;
+                    }
+                }
+
+                // 有未完成的帧时，最多等待一个静默超时
+                let silence = (!framer.is_empty()).then(|| framer.silence());
+                let read = async {
+                    match silence {
+                        Some(silence) => timeout(silence, reader.read(&mut buffer)).await,
+                        None => Ok(reader.read(&mut buffer).await),
+                    }
+                };
+                tokio::select! {
+                    _ = shutdown_receiver.recv() => {
+                        println!("SerialServerChannel receive task received shutdown signal");
+                        return;
+                    }
+                    result = read => match result {
+                        Ok(Ok(n)) if n > 0 => {
+                            if self.rfc2217 {
+                                decoder.feed(&buffer[..n], &mut data, &mut events);
+                                self.handle_events(&mut events).await;
+                            } else {
+                                data.extend_from_slice(&buffer[..n]);
+                            }
+                        }
+                        Ok(Ok(_)) => {
+                            println!("串口服务器断开连接: {}", self.address);
+                            break;
+                        }
+                        Ok(Err(e)) => {
+                            eprintln!("读取串口服务器数据时发生错误: {:?}", e);
+                            break;
+                        }
+                        // 静默超时，缓存的数据作为一帧
+                        Err(_) => {
+                            if let Some(frame) = framer.flush() {
+                                self.deliver(frame, &data_tx).await;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // 断开前已收到的不完整数据照常上报
+            if let Some(frame) = framer.flush() {
+                self.deliver(frame, &data_tx).await;
+            }
+            match self.reopen(&mut shutdown_receiver).await {
+                Some((new_reader, new_decoder, leftover)) => {
+                    reader = new_reader;
+                    decoder = new_decoder;
+                    data = leftover;
+                }
+                None => return,
+            }
+        }
+    }
+
+    // 记录收到的一帧并放入接收队列
+    async fn deliver(
+        &self,
+        (data, timestamp): ReceivedFrame,
+        data_tx: &mpsc::Sender<ReceivedFrame>,
+    ) {
+        let message = Message::with_timestamp(serde_json::json!({ "data": data }), timestamp);
+        match timeout(
+            Duration::from_secs(1),
+            self.message_manager.record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &message,
+                MessageDirection::Received,
+                None,
+            ),
+        )
+        .await
+        {
+            Ok(Err(e)) => eprintln!("记录消息失败: {:?}", e),
+            Err(e) => eprintln!("记录消息超时: {:?}", e),
+            Ok(Ok(())) => {}
+        }
+        // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+        let Some(data) = self.correlator.offer(data) else {
+            return;
+        };
+        if let Err(e) = data_tx.try_send((data, timestamp)) {
+            eprintln!("SerialServerChannel queue full, dropped frame: {:?}", e);
+        }
+    }
+
+    // 拒绝服务器在连接过程中请求的其他 Telnet 选项，线路状态通知忽略
+    async fn handle_events(&self, events: &mut Vec<TelnetEvent>) {
+        for event in events.drain(..) {
+            let TelnetEvent::Command { command, option } = event else {
+                continue;
+            };
+            let Some(reply) = rfc2217::reply(command, option) else {
+                continue;
+            };
+            if let Some(writer) = self.writer.lock().await.as_mut() {
+                if let Err(e) = writer.write_all(&reply).await {
+                    eprintln!("应答 Telnet 协商失败: {:?}", e);
+                }
+            }
+        }
+    }
+
+    // 按重连策略重新连接，成功后补发缓存的数据；放弃或收到关闭信号时返回 None
+    async fn reopen(
+        &self,
+        shutdown_receiver: &mut broadcast::Receiver<()>,
+    ) -> Option<(OwnedReadHalf, TelnetDecoder, Vec<u8>)> {
+        *self.writer.lock().await = None;
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(delay) = self.reconnect.backoff(attempt) else {
+                let _ = self.on_statechange(ChannelState::Disconnected).await;
+                return None;
+            };
+            let _ = self.on_statechange(ChannelState::Reconnecting).await;
+            println!(
+                "重新连接串口服务器 {}，第 {} 次尝试，等待 {:?}",
+                self.address, attempt, delay
+            );
+            tokio::select! {
+                _ = shutdown_receiver.recv() => return None,
+                _ = sleep(delay) => {}
+            }
+
+            let (reader, new_writer, decoder, leftover) =
+                match Self::open(&self.address, &self.settings, self.rfc2217).await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        eprintln!("重新连接串口服务器失败: {:?}", e);
+                        continue;
+                    }
+                };
+
+            let mut writer = self.writer.lock().await;
+            let writer = writer.insert(new_writer);
+            let (buffered, dropped) = self.pending.lock().await.drain();
+            if dropped > 0 {
+                eprintln!("串口服务器断开期间丢弃了 {} 条待发送数据", dropped);
+            }
+            for data in buffered {
+                if let Err(e) = writer.write_all(&self.encode(&data)).await {
+                    eprintln!("补发数据时发生错误: {:?}", e);
+                    break;
+                }
+            }
+            let _ = self.on_statechange(ChannelState::Connected).await;
+            return Some((reader, decoder, leftover));
+        }
+    }
+
+    // RFC 2217 模式下转义数据中的 0xFF
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        if self.rfc2217 {
+            rfc2217::escape(data)
+        } else {
+            data.to_vec()
+        }
+    }
+}
+
+#[async_trait]
+impl CommunicationChannel for SerialServerChannel {
+    async fn send(
+        &self,
+        message: &Message,
+        _clientid: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = match message.get_content()["data"].as_array() {
+            Some(arr) => arr
+                .iter()
+                .filter_map(|v| v.as_u64())
+                .map(|v| v as u8)
+                .collect::<Vec<u8>>(),
+            None => return Err("Invalid data format".into()),
+        };
+
+        {
+            // 持有写入端的锁再放入缓存，保证重新连接后补发时不会漏掉
+            let mut writer = self.writer.lock().await;
+            let result = match writer.as_mut() {
+                Some(writer) => writer.write_all(&self.encode(&data)).await,
+                None => Err(std::io::ErrorKind::NotConnected.into()),
+            };
+            if let Err(e) = result {
+                if !self.reconnect.buffers() {
+                    return Err(format!("串口服务器未连接: {}: {}", self.address, e).into());
+                }
+                self.pending.lock().await.push(data);
+                return Ok(());
+            }
+        }
+
+        if let Err(e) = self
+            .message_manager
+            .record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &Message::new(serde_json::json!({ "data": data })),
+                MessageDirection::Sent,
+                None,
+            )
+            .await
+        {
+            eprintln!("记录发送消息失败: {:?}", e);
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let (data, timestamp) = self
+            .data_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or("串口服务器通道已关闭")?;
+        Ok(Message::with_timestamp(
+            serde_json::json!({ "data": data }),
+            timestamp,
+        ))
+    }
+
+    async fn send_and_wait(
+        &self,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("SerialServerChannel closing...");
+        let _ = self.shutdown_signal.send(());
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            let _ = writer.shutdown().await;
+        }
+        self.message_manager
+            .unregister_channel(&self.channelid)
+            .await;
+        self.on_statechange(ChannelState::Disconnected).await
+    }
+
+    async fn on_statechange(
+        &self,
+        state: ChannelState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (ip, port) = self
+            .address
+            .rsplit_once(':')
+            .map(|(ip, port)| (ip.to_string(), port.parse::<u16>().unwrap_or(0)))
+            .unwrap_or_default();
+        let data = serde_json::json!({
+            "ip": ip,
+            "port": port,
+            "baudrate": self.settings.baud_rate,
+            "rfc2217": self.rfc2217,
+        });
+        let reason = match state {
+            ChannelState::Connected => "串口服务器已连接",
+            ChannelState::Disconnected => "串口服务器已断开连接",
+            ChannelState::Reconnecting => "串口服务器正在重新连接",
+        };
+        let payload = serde_json::json!({
+            "channeltype": "serialserver",
+            "channelId": self.address.clone(),
+            "state": state,
+            "data": data,
+            "reason": reason,
+        });
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
+        Ok(())
+    }
+
+    fn get_channel_id(&self) -> String {
+        self.channelid.clone()
+    }
+
+    async fn subscribe_topic(
+        &self,
+        _topic: &str,
+        _qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("Serial server does not support topic subscription".into())
+    }
+
+    async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("Serial server does not support topic unsubscription".into())
+    }
+}
@@ -0,0 +1,488 @@
+use crate::combridage::messagemanager::MessageRecord;
+use crate::config::appconfig::{load_config_value, set_config_value};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// 策略在应用配置中的位置
+const POLICY_SECTION: &str = "storage";
+const POLICY_KEY: &str = "retention";
+
+// 归档目录名，位于应用数据目录下
+pub const ARCHIVE_DIR: &str = "archive";
+
+const MB: u64 = 1024 * 1024;
+
+/// 消息存储的保留策略，数值为 0 表示不限制
+///
+/// 默认不清理任何记录，需要用户显式设置上限
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoragePolicy {
+    /// 记录保留天数
+    pub max_age_days: u32,
+    /// 所有通道的总数据量上限（MB）
+    pub max_total_mb: u64,
+    /// 单个通道的数据量上限（MB）
+    pub max_channel_mb: u64,
+    /// 被清理的记录是否先压缩归档
+    pub archive_evicted: bool,
+    /// 单个归档文件的大小上限（MB），超过后轮转到新文件
+    pub archive_rotate_mb: u64,
+    /// 归档保留天数，按日期目录删除
+    pub archive_max_age_days: u32,
+    /// 归档的总大小上限（MB），超过时从最早的日期开始删除
+    pub archive_max_mb: u64,
+    /// 是否压缩已结束日期目录中的旧版 .log 文件
+    pub compress_closed_days: bool,
+}
+
+impl Default for StoragePolicy {
+    fn default() -> Self {
+        Self {
+            max_age_days: 0,
+            max_total_mb: 0,
+            max_channel_mb: 0,
+            archive_evicted: true,
+            archive_rotate_mb: 64,
+            archive_max_age_days: 0,
+            archive_max_mb: 0,
+            compress_closed_days: true,
+        }
+    }
+}
+
+impl StoragePolicy {
+    /// 从应用配置读取，没有配置时使用默认值
+    pub fn load() -> Self {
+        load_config_value(POLICY_SECTION, POLICY_KEY)
+            .and_then(|value| serde_json::from_value(value).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let value = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        set_config_value(POLICY_SECTION, POLICY_KEY, &value)
+    }
+
+    pub(crate) fn max_total_bytes(&self) -> Option<i64> {
+        (self.max_total_mb > 0).then(|| (self.max_total_mb * MB) as i64)
+    }
+
+    pub(crate) fn max_channel_bytes(&self) -> Option<i64> {
+        (self.max_channel_mb > 0).then(|| (self.max_channel_mb * MB) as i64)
+    }
+
+    pub(crate) fn archive_max_bytes(&self) -> Option<u64> {
+        (self.archive_max_mb > 0).then(|| self.archive_max_mb * MB)
+    }
+}
+
+/// 一次保留策略执行的结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetentionReport {
+    /// 超过保留天数被清理的记录数
+    pub expired: usize,
+    /// 超过总数据量上限被清理的记录数
+    pub over_total: usize,
+    /// 超过单通道数据量上限被清理的记录数
+    pub over_channel: usize,
+    /// 写入归档的记录数
+    pub archived: usize,
+    /// 被压缩的旧版 .log 文件数
+    pub compressed_logs: usize,
+    /// 超过归档保留天数或大小上限被删除的归档文件数
+    pub removed_archives: usize,
+}
+
+/// 单个通道的存储占用
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelUsage {
+    pub channel_name: String,
+    pub channel_type: String,
+    pub records: i64,
+    /// 数据库中的数据量，按记录内容估算
+    pub bytes: i64,
+    pub oldest: Option<i64>,
+    pub newest: Option<i64>,
+    /// 归档文件大小
+    pub archive_bytes: u64,
+}
+
+/// 存储占用汇总
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageUsage {
+    /// 数据库文件大小（含 WAL）
+    pub database_bytes: u64,
+    pub records: i64,
+    pub record_bytes: i64,
+    pub archive_bytes: u64,
+    /// 旧版 .log / .log.gz 文件大小
+    pub legacy_log_bytes: u64,
+    pub channels: Vec<ChannelUsage>,
+}
+
+/// 替换文件名中的非法字符
+pub(crate) fn safe_file_name(channel_name: &str) -> String {
+    channel_name.replace(&['\\', '/', ':', '*', '?', '"', '<', '>', '|'][..], "_")
+}
+
+/// 把记录追加到 archive/{日期}/{通道}.log.gz，每次追加写入一个新的 gzip 成员，
+/// 文件超过大小上限后轮转为 {通道}.1.log.gz、{通道}.2.log.gz ...
+#[derive(Debug, Clone)]
+pub struct ArchiveWriter {
+    root: PathBuf,
+    rotate_bytes: u64,
+}
+
+impl ArchiveWriter {
+    pub fn new(base_path: &Path, rotate_mb: u64) -> Self {
+        Self {
+            root: base_path.join(ARCHIVE_DIR),
+            rotate_bytes: rotate_mb.max(1) * MB,
+        }
+    }
+
+    pub fn write(&self, records: &[MessageRecord]) -> io::Result<usize> {
+        let mut groups: HashMap<(String, String), Vec<&MessageRecord>> = HashMap::new();
+        for record in records {
+            let date = record.timestamp().format("%Y-%m-%d").to_string();
+            groups
+                .entry((date, safe_file_name(record.channel_name())))
+                .or_default()
+                .push(record);
+        }
+
+        for ((date, channel), records) in groups {
+            let dir = self.root.join(&date);
+            fs::create_dir_all(&dir)?;
+            let path = self.current_file(&dir, &channel);
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            for record in records {
+                writeln!(encoder, "{}", record.to_log_line())?;
+            }
+            encoder.finish()?.flush()?;
+        }
+        Ok(records.len())
+    }
+
+    // 找到当前可写入的分卷
+    fn current_file(&self, dir: &Path, channel: &str) -> PathBuf {
+        let mut index = 0;
+        loop {
+            let name = if index == 0 {
+                format!("{}.log.gz", channel)
+            } else {
+                format!("{}.{}.log.gz", channel, index)
+            };
+            let path = dir.join(name);
+            match fs::metadata(&path) {
+                Ok(meta) if meta.len() >= self.rotate_bytes => index += 1,
+                _ => return path,
+            }
+        }
+    }
+}
+
+/// 清理 archive 目录：删除早于 cutoff 日期的日期目录，再从最早的文件开始删除，
+/// 直到总大小不超过 max_bytes，返回删除的文件数
+pub(crate) fn prune_archive(
+    base_path: &Path,
+    cutoff: Option<&str>,
+    max_bytes: Option<u64>,
+) -> io::Result<usize> {
+    let root = base_path.join(ARCHIVE_DIR);
+    let Ok(dates) = fs::read_dir(&root) else {
+        return Ok(0);
+    };
+    let mut dates: Vec<(String, PathBuf)> = dates
+        .flatten()
+        .filter(|d| d.path().is_dir())
+        .map(|d| (d.file_name().to_string_lossy().to_string(), d.path()))
+        .filter(|(name, _)| chrono::NaiveDate::parse_from_str(name, "%Y-%m-%d").is_ok())
+        .collect();
+    dates.sort();
+
+    // 按日期从旧到新排列的归档文件
+    let mut files = Vec::new();
+    for (name, dir) in &dates {
+        let mut entries: Vec<(PathBuf, u64)> = fs::read_dir(dir)?
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(".log.gz"))
+            .map(|e| (e.path(), e.metadata().map(|m| m.len()).unwrap_or(0)))
+            .collect();
+        entries.sort();
+        files.extend(
+            entries
+                .into_iter()
+                .map(|(path, size)| (name.as_str(), path, size)),
+        );
+    }
+
+    let mut total: u64 = files.iter().map(|(_, _, size)| size).sum();
+    let mut removed = 0;
+    for (date, path, size) in files {
+        let expired = cutoff.is_some_and(|cutoff| date < cutoff);
+        let over_size = max_bytes.is_some_and(|max| total > max);
+        if !expired && !over_size {
+            break;
+        }
+        fs::remove_file(&path)?;
+        total -= size;
+        removed += 1;
+    }
+    // 删除清空的日期目录
+    for (_, dir) in dates {
+        if fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_none()) {
+            let _ = fs::remove_dir(&dir);
+        }
+    }
+    Ok(removed)
+}
+
+/// 读取 .log 或压缩后的 .log.gz 文件内容
+pub async fn read_log_file(path: &Path) -> io::Result<String> {
+    let bytes = tokio::fs::read(path).await?;
+    if path.extension().and_then(|e| e.to_str()) != Some("gz") {
+        return String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    let mut content = String::new();
+    MultiGzDecoder::new(bytes.as_slice()).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// 压缩 base_path 下已结束日期目录中已迁移入库的 .log 文件，返回压缩的文件数
+pub(crate) fn compress_closed_logs(
+    base_path: &Path,
+    today: &str,
+    migrated: &[String],
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut count = 0;
+    let Ok(dates) = fs::read_dir(base_path) else {
+        return Ok(0);
+    };
+    for date in dates.flatten() {
+        let name = date.file_name().to_string_lossy().to_string();
+        // 只处理形如 2024-01-02 的日期目录，且不处理当天
+        if !date.path().is_dir() || chrono::NaiveDate::parse_from_str(&name, "%Y-%m-%d").is_err() {
+            continue;
+        }
+        if name.as_str() >= today {
+            continue;
+        }
+        for entry in fs::read_dir(date.path())?.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("log")
+                || !migrated.contains(&path.to_string_lossy().to_string())
+            {
+                continue;
+            }
+            let gz_path = path.with_extension("log.gz");
+            let mut input = File::open(&path)?;
+            let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+            fs::remove_file(&path)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// 统计目录下所有文件的大小，按文件名中的通道名汇总
+pub(crate) fn dir_usage(dir: &Path, suffixes: &[&str]) -> (u64, HashMap<String, u64>) {
+    let mut total = 0;
+    let mut by_channel = HashMap::new();
+    let Ok(dates) = fs::read_dir(dir) else {
+        return (total, by_channel);
+    };
+    for date in dates.flatten().filter(|d| d.path().is_dir()) {
+        let Ok(entries) = fs::read_dir(date.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(stem) = suffixes.iter().find_map(|s| name.strip_suffix(s)) else {
+                continue;
+            };
+            // 去掉分卷序号
+            let channel = match stem.rsplit_once('.') {
+                Some((channel, index)) if index.parse::<u32>().is_ok() => channel,
+                _ => stem,
+            };
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            total += size;
+            *by_channel.entry(channel.to_string()).or_insert(0) += size;
+        }
+    }
+    (total, by_channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combridage::messagemanager::MessageDirection;
+    use crate::combridage::Message;
+
+    // 测试用的临时目录，结束时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("embedtalk-storage-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        // 创建文件，内容为 size 个字节
+        fn file(&self, path: &str, size: usize) -> PathBuf {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, vec![b'x'; size]).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn record(channel: &str, timestamp: &str, data: &[u8]) -> MessageRecord {
+        let timestamp = chrono::DateTime::parse_from_rfc3339(timestamp)
+            .unwrap()
+            .to_utc();
+        let message = Message::with_timestamp(
+            serde_json::json!({ "data": data }),
+            timestamp.timestamp_millis(),
+        );
+        MessageRecord::new(
+            "tcp".to_string(),
+            "id".to_string(),
+            channel.to_string(),
+            timestamp,
+            MessageDirection::Received,
+            message,
+            None,
+        )
+    }
+
+    fn gunzip(path: &Path) -> String {
+        let mut content = String::new();
+        MultiGzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn archive_rotates_by_size() {
+        let dir = TempDir::new();
+        let mut writer = ArchiveWriter::new(&dir.0, 64);
+        let records = [
+            record("TCP/1", "2024-01-02T08:00:00Z", &[0x68, 0x16]),
+            record("TCP/1", "2024-01-02T09:00:00Z", &[0x01]),
+            record("UDP", "2024-01-03T00:00:00Z", &[0x02]),
+        ];
+        assert_eq!(writer.write(&records).unwrap(), 3);
+        // 追加写入新的 gzip 成员
+        writer.write(&records[1..2]).unwrap();
+        let day = dir.0.join(ARCHIVE_DIR).join("2024-01-02");
+        let lines: Vec<_> = gunzip(&day.join("TCP_1.log.gz"))
+            .lines()
+            .map(|line| MessageRecord::from_log_line(line).unwrap().payload_bytes())
+            .collect();
+        assert_eq!(lines, [vec![0x68, 0x16], vec![0x01], vec![0x01]]);
+        assert!(dir
+            .0
+            .join(ARCHIVE_DIR)
+            .join("2024-01-03/UDP.log.gz")
+            .exists());
+
+        // 当前分卷达到上限后写入下一个分卷
+        writer.rotate_bytes = 1;
+        writer.write(&records[..1]).unwrap();
+        writer.write(&records[..1]).unwrap();
+        assert_eq!(gunzip(&day.join("TCP_1.1.log.gz")).lines().count(), 1);
+        assert_eq!(gunzip(&day.join("TCP_1.2.log.gz")).lines().count(), 1);
+    }
+
+    #[test]
+    fn prunes_archive_by_age_and_size() {
+        let dir = TempDir::new();
+        dir.file("archive/2024-01-01/A.log.gz", 100);
+        dir.file("archive/2024-01-02/A.log.gz", 100);
+        dir.file("archive/2024-01-02/B.log.gz", 100);
+        dir.file("archive/2024-01-03/A.log.gz", 100);
+        dir.file("archive/notes/A.log.gz", 100);
+        let archive = dir.0.join(ARCHIVE_DIR);
+
+        assert_eq!(prune_archive(&dir.0, Some("2024-01-02"), None).unwrap(), 1);
+        assert!(!archive.join("2024-01-01").exists());
+        assert!(archive.join("2024-01-02/A.log.gz").exists());
+
+        // 从最早的文件开始删除，直到总大小不超过上限
+        assert_eq!(prune_archive(&dir.0, None, Some(150)).unwrap(), 2);
+        assert!(!archive.join("2024-01-02").exists());
+        assert!(archive.join("2024-01-03/A.log.gz").exists());
+        // 不是日期的目录不处理
+        assert!(archive.join("notes/A.log.gz").exists());
+        assert_eq!(prune_archive(&dir.0, None, Some(150)).unwrap(), 0);
+    }
+
+    #[test]
+    fn compresses_migrated_logs_of_closed_days() {
+        let dir = TempDir::new();
+        let line = "2024-01-01 08:00:00:000 [TCP1] <<< 接收: 68 16\n";
+        let closed = dir.file("2024-01-01/TCP1.log", 0);
+        fs::write(&closed, line).unwrap();
+        let pending = dir.file("2024-01-01/TCP2.log", 10);
+        let today = dir.file("2024-01-05/TCP1.log", 10);
+        let migrated: Vec<String> = [&closed, &today]
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+
+        assert_eq!(
+            compress_closed_logs(&dir.0, "2024-01-05", &migrated).unwrap(),
+            1
+        );
+        assert!(!closed.exists());
+        assert_eq!(gunzip(&closed.with_extension("log.gz")), line);
+        // 未迁移的文件和当天的文件保持不变
+        assert!(pending.exists() && today.exists());
+        let content = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(read_log_file(&closed.with_extension("log.gz")))
+            .unwrap();
+        assert_eq!(content, line);
+    }
+
+    #[test]
+    fn sums_usage_by_channel() {
+        let dir = TempDir::new();
+        dir.file("2024-01-01/TCP1.log.gz", 10);
+        dir.file("2024-01-01/TCP1.1.log.gz", 20);
+        dir.file("2024-01-02/TCP1.log", 5);
+        dir.file("2024-01-02/UDP.v2.log", 7);
+        dir.file("2024-01-02/readme.txt", 100);
+        dir.file("top.log", 100);
+
+        let (total, channels) = dir_usage(&dir.0, &[".log.gz", ".log"]);
+        assert_eq!(total, 42);
+        assert_eq!(
+            channels,
+            HashMap::from([("TCP1".to_string(), 35), ("UDP.v2".to_string(), 7)])
+        );
+        assert_eq!(dir_usage(&dir.0.join("missing"), &[".log"]).0, 0);
+    }
+}
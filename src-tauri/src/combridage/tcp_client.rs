@@ -1,3 +1,6 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::reconnect::{OutageBuffer, ReconnectPolicy};
+use crate::combridage::tls::{self, TcpIoStream, TlsClient, TlsConfig, TlsSession};
 use crate::combridage::CommunicationChannel;
 use crate::combridage::{ChannelState, Message};
 use async_trait::async_trait;
@@ -7,17 +10,15 @@ use std::error::Error;
 use std::io;
 use std::io::Error as IoError;
 use std::sync::Arc;
-use tauri::Emitter;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, watch, Mutex};
 use tokio::time::{sleep, timeout, Duration};
 use uuid::Uuid;
 //global.rs
 use crate::combridage::messagemanager::{MessageDirection, MessageManager};
-use crate::global::get_app_handle;
-use bincode;
+use crate::global::emit_event;
 
 #[derive(Clone, Debug)]
 pub struct TcpClientChannel {
@@ -26,26 +27,33 @@ pub struct TcpClientChannel {
     channel_name: String,
     adress: String,
     stream: Arc<Mutex<TcpStream>>,
+    writer: Arc<Mutex<Option<tokio::io::WriteHalf<TcpIoStream>>>>,
+    tls: Option<TlsClient>,
+    // 当前连接协商出的 TLS 参数，重连后更新
+    tls_session: Arc<std::sync::Mutex<Option<TlsSession>>>,
+    // 当前是否已连接，重连成功后通知发送任务补发缓存的数据
+    connected: Arc<watch::Sender<bool>>,
+    reconnect: ReconnectPolicy,
     shutdown_signal: broadcast::Sender<()>,
     tx_send: mpsc::Sender<Vec<u8>>,
     rx_recv: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    correlator: Correlator,
 }
 
 impl TcpClientChannel {
-    pub async fn new(ipaddr: &str, port: u16) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    pub async fn new(
+        ipaddr: &str,
+        port: u16,
+        reconnect: ReconnectPolicy,
+        tls: TlsConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         let address = format!("{}:{}", ipaddr, port);
-        let stream = TcpStream::connect(address.clone()).await?;
-
-        println!("TcpClientChannel connected to {} {:?}", address, stream);
-        let std_stream = stream.into_std()?;
+        let tls = tls.client(ipaddr)?;
+        let (std_stream, reader, writer, tls_session) =
+            Self::open_stream(&address, tls.as_ref()).await?;
 
         let (shutdown_signal, _) = broadcast::channel(1);
 
-        // 创建一个克隆的流用于读取
-        let split_stream = std_stream.try_clone()?;
-        let tokio_stream = TcpStream::from_std(split_stream)?;
-        let (reader, writer) = tokio::io::split(tokio_stream);
-
         let (tx_send, rx_send) = mpsc::channel(100); // 发送队列
         let (tx_recv, rx_recv) = mpsc::channel(100); // 接收队列
 
@@ -58,12 +66,17 @@ impl TcpClientChannel {
             channel_name: "TCP".to_string() + &address.clone(),
             adress: address.clone(),
             stream: mut_stream.clone(),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            tls,
+            tls_session: Arc::new(std::sync::Mutex::new(tls_session)),
+            connected: Arc::new(watch::channel(true).0),
+            reconnect,
             shutdown_signal: shutdown_signal.clone(),
             tx_send: tx_send,
             rx_recv: Arc::new(Mutex::new(rx_recv)),
+            correlator: Correlator::new(),
         };
-        let app_handle = get_app_handle();
-        let message_manager = MessageManager::new(app_handle)?;
+        let message_manager = MessageManager::from_global()?;
         let _ = message_manager.register_channel(&address).await;
         let subscriber = message_manager.subscribe_to_messages();
 
@@ -76,10 +89,7 @@ impl TcpClientChannel {
         let channelclone = channel.clone();
         let message_manager_send = message_manager.clone();
         tokio::spawn(async move {
-            if let Err(e) = channelclone
-                .send_task(writer, rx_send, message_manager_send)
-                .await
-            {
+            if let Err(e) = channelclone.send_task(rx_send, message_manager_send).await {
                 eprintln!("Send task error: {}", e);
             }
         });
@@ -102,6 +112,98 @@ impl TcpClientChannel {
         Ok(channel)
     }
 
+    // 建立连接并在启用时完成 TLS 握手，返回用于 receive 的流、读写两半及协商出的 TLS 参数
+    async fn open_stream(
+        address: &str,
+        tls: Option<&TlsClient>,
+    ) -> Result<
+        (
+            std::net::TcpStream,
+            tokio::io::ReadHalf<TcpIoStream>,
+            tokio::io::WriteHalf<TcpIoStream>,
+            Option<TlsSession>,
+        ),
+        Box<dyn Error + Send + Sync>,
+    > {
+        let stream = TcpStream::connect(address).await?;
+        println!("TcpClientChannel connected to {} {:?}", address, stream);
+        let std_stream = stream.into_std()?;
+
+        // 创建一个克隆的流用于读写
+        let split_stream = std_stream.try_clone()?;
+        let tokio_stream = TcpStream::from_std(split_stream)?;
+        let (io_stream, tls_session) = tls::connect(tokio_stream, tls).await?;
+        if let Some(session) = &tls_session {
+            println!(
+                "TcpClientChannel TLS {} {}, peer: {:?}",
+                session.protocol, session.cipher, session.peer_subject
+            );
+        }
+        let (reader, writer) = tokio::io::split(io_stream);
+        Ok((std_stream, reader, writer, tls_session))
+    }
+
+    // 连接断开后按策略重连，成功时返回新的读取端；放弃或收到关闭信号时返回 None
+    async fn reconnect_stream(
+        &self,
+        shutdown_receiver: &mut broadcast::Receiver<()>,
+    ) -> Option<tokio::io::ReadHalf<TcpIoStream>> {
+        *self.writer.lock().await = None;
+        self.connected.send_replace(false);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(delay) = self.reconnect.backoff(attempt) else {
+                let _ = timeout(
+                    Duration::from_secs(1),
+                    self.on_statechange(ChannelState::Disconnected),
+                )
+                .await;
+                return None;
+            };
+            let _ = timeout(
+                Duration::from_secs(1),
+                self.on_statechange(ChannelState::Reconnecting),
+            )
+            .await;
+            println!(
+                "TcpClientChannel reconnecting to {} in {:?} (attempt {})",
+                self.adress, delay, attempt
+            );
+            tokio::select! {
+                _ = shutdown_receiver.recv() => return None,
+                _ = sleep(delay) => {}
+            }
+
+            let (std_stream, reader, writer, tls_session) =
+                match Self::open_stream(&self.adress, self.tls.as_ref()).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("TcpClientChannel reconnect failed: {:?}", e);
+                        continue;
+                    }
+                };
+            let _ = self.set_tcp_keepalive(&std_stream).await;
+            match TcpStream::from_std(std_stream) {
+                Ok(stream) => *self.stream.lock().await = stream,
+                Err(e) => {
+                    eprintln!("TcpClientChannel reconnect failed: {:?}", e);
+                    continue;
+                }
+            }
+            *self.writer.lock().await = Some(writer);
+            *self.tls_session.lock().unwrap() = tls_session;
+            self.connected.send_replace(true);
+            let _ = timeout(
+                Duration::from_secs(1),
+                self.on_statechange(ChannelState::Connected),
+            )
+            .await;
+            return Some(reader);
+        }
+    }
+
     pub async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
         println!("TcpClientChannel closing...");
 
@@ -111,6 +213,11 @@ impl TcpClientChannel {
         // 给一些时间让 detect_disconnection_with_timeout 退出
         sleep(Duration::from_millis(100)).await;
 
+        // TLS 连接先发送 close_notify
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            let _ = timeout(Duration::from_secs(1), writer.shutdown()).await;
+        }
+
         // 使用 try_lock 来避免死锁
         let mut attempts = 0;
         let max_attempts = 5;
@@ -183,41 +290,91 @@ impl TcpClientChannel {
         self.rx_recv.lock().await.recv().await
     }
 
-    // 发送任务：从发送队列读取消息并发送到写入器
+    // 发送任务：从发送队列读取消息并发送到写入器，断线期间按策略缓存
     async fn send_task(
         &self,
-        mut writer: tokio::io::WriteHalf<TcpStream>,
         mut rx_send: mpsc::Receiver<Vec<u8>>,
         message_manager: MessageManager,
     ) -> Result<(), IoError> {
-        while let Some(data) = rx_send.recv().await {
-            // 直接写入数据，不尝试解析
-            writer.write_all(&data).await?;
-            writer.flush().await?;
-
-            println!("TcpClientChannel sent data of size: {}", data.len());
-            let payload = serde_json::json!({
-                "data": data
-            });
-            let message = Message::new(payload);
-            let _ = message_manager
-                .record_message(
-                    &self.channeltype.clone(),
-                    &self.channelid.clone(),
-                    &self.channel_name.clone(),
-                    &message,
-                    MessageDirection::Sent,
-                    None,
-                )
-                .await
-                .map_err(|e| IoError::new(io::ErrorKind::Other, e))?; // 修改此行
+        let mut connected = self.connected.subscribe();
+        let mut shutdown_receiver = self.shutdown_signal.subscribe();
+        let mut pending = OutageBuffer::new(self.reconnect.max_buffered);
+        loop {
+            tokio::select! {
+                _ = shutdown_receiver.recv() => return Ok(()),
+                changed = connected.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    if !*connected.borrow_and_update() || pending.is_empty() {
+                        continue;
+                    }
+                    // 重连成功，补发断线期间缓存的数据
+                    let (buffered, dropped) = pending.drain();
+                    if dropped > 0 {
+                        eprintln!("TcpClientChannel dropped {} buffered messages during outage", dropped);
+                    }
+                    println!("TcpClientChannel resending {} buffered messages", buffered.len());
+                    for data in buffered {
+                        self.write_data(data, &message_manager, &mut pending).await;
+                    }
+                }
+                data = rx_send.recv() => {
+                    let Some(data) = data else {
+                        return Ok(());
+                    };
+                    self.write_data(data, &message_manager, &mut pending).await;
+                }
+            }
+        }
+    }
+
+    // 写入一帧数据并记录；未连接或写入失败时按策略缓存
+    async fn write_data(
+        &self,
+        data: Vec<u8>,
+        message_manager: &MessageManager,
+        pending: &mut OutageBuffer<Vec<u8>>,
+    ) {
+        let result = match self.writer.lock().await.as_mut() {
+            Some(writer) => match writer.write_all(&data).await {
+                Ok(_) => writer.flush().await,
+                Err(e) => Err(e),
+            },
+            None => Err(IoError::new(io::ErrorKind::NotConnected, "TCP 连接已断开")),
+        };
+        if let Err(e) = result {
+            if self.reconnect.buffers() {
+                pending.push(data);
+            } else {
+                eprintln!("TcpClientChannel dropped {} bytes: {:?}", data.len(), e);
+            }
+            return;
+        }
+
+        println!("TcpClientChannel sent data of size: {}", data.len());
+        let payload = serde_json::json!({
+            "data": data
+        });
+        let message = Message::new(payload);
+        if let Err(e) = message_manager
+            .record_message(
+                &self.channeltype.clone(),
+                &self.channelid.clone(),
+                &self.channel_name.clone(),
+                &message,
+                MessageDirection::Sent,
+                None,
+            )
+            .await
+        {
+            eprintln!("Error recording message: {:?}", e);
         }
-        Ok(())
     }
     // 接收任务：从读取器读取消息，并检测断开连接
     async fn receive_task(
         self,
-        mut reader: tokio::io::ReadHalf<TcpStream>,
+        mut reader: tokio::io::ReadHalf<TcpIoStream>,
         tx_recv: mpsc::Sender<Vec<u8>>,
         message_manager: MessageManager,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -266,6 +423,11 @@ impl TcpClientChannel {
                                 }
                             }
 
+                            // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+                            let Some(received_data) = self.correlator.offer(received_data) else {
+                                continue;
+                            };
+
                             // 使用 try_send 而不是 send，避免在队列满时阻塞
                             if let Err(e) = tx_recv.try_send(received_data) {
                                 dropped_messages += 1;
@@ -287,26 +449,14 @@ impl TcpClientChannel {
                                     dropped_messages = 0;
                                 }
                             }
+                            continue;
                         }
                         Ok(0) => {
                             println!("Server disconnected (EOF received).");
-                            // 使用 timeout 包装 on_statechange 调用
-                            match timeout(Duration::from_secs(1), self.on_statechange(ChannelState::Disconnected)).await {
-                                Ok(result) => {
-                                    if let Err(e) = result {
-                                        eprintln!("Failed to send disconnect event: {:?}, but continuing...", e);
-                                    }
-                                },
-                                Err(timeout_err) => {
-                                    eprintln!("Timeout sending disconnect event: {:?}", timeout_err);
-                                }
-                            }
-                            return Ok(()); // 正常退出
                         }
                         Ok(_) => {
                             // 处理读取了 0 字节以外的情况
                             eprintln!("Unexpected read result: 0 bytes read but not EOF.");
-                            return Ok(());
                         }
                         Err(e) => {
                             // 区分不同类型的错误
@@ -318,14 +468,17 @@ impl TcpClientChannel {
                             } else {
                                 // 其他错误可能是致命的，如连接断开
                                 eprintln!("Fatal read error: {:?}", e);
-                                // 尝试发送断开连接状态，但不阻塞
-                                let _ = timeout(Duration::from_millis(500), self.on_statechange(ChannelState::Disconnected)).await;
-                                return Err(Box::new(e));
                             }
                         }
                     }
                 }
             }
+
+            // 连接已断开，未启用重连或放弃重连时结束任务
+            match self.reconnect_stream(&mut shutdown_receiver).await {
+                Some(new_reader) => reader = new_reader,
+                None => return Ok(()),
+            }
         }
     }
 }
@@ -337,6 +490,10 @@ impl CommunicationChannel for TcpClientChannel {
         message: &Message,
         clientid: Option<String>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !*self.connected.borrow() && !self.reconnect.buffers() {
+            return Err(format!("TCP 连接已断开: {}", self.adress).into());
+        }
+
         // 获取消息内容
         let content = message.get_content();
 
@@ -400,9 +557,9 @@ impl CommunicationChannel for TcpClientChannel {
         message: &Message,
         timeout_secs: u64,
     ) -> Result<Message, Box<dyn Error + Send + Sync>> {
-        let send_message = bincode::serialize(message)?;
-        self.send(send_message).await?;
-        tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), self.receive()).await?
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
     }
 
     async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -415,27 +572,33 @@ impl CommunicationChannel for TcpClientChannel {
         &self,
         state: ChannelState,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let app_handle = get_app_handle();
-        let stream = self.stream.lock().await;
-        let ip = stream.peer_addr()?.ip().to_string();
-        let port = stream.peer_addr()?.port();
+        // 断线重连期间旧连接可能已经失效，从配置的地址取 IP 和端口
+        let (ip, port) = self
+            .adress
+            .rsplit_once(':')
+            .map(|(ip, port)| (ip.to_string(), port.parse::<u16>().unwrap_or(0)))
+            .unwrap_or_default();
         // Construct the disconnect event payload
         let data = serde_json::json!({
             "ip": ip,
             "port": port,
+            "tls": self.tls_session.lock().unwrap().clone(),
         });
+        let reason = match state {
+            ChannelState::Connected => "The TCP Client has connected",
+            ChannelState::Disconnected => "The TCP Client has disconnected",
+            ChannelState::Reconnecting => "The TCP Client is reconnecting",
+        };
         let payload = serde_json::json!({
             "channeltype": "tcpclient",
             "channelId": self.adress.clone(),
             "state": state,
             "data": data,
-            "reason": "The TCP Client has disconnected",
+            "reason": reason,
         });
         println!("TcpClientChannel disconnected {:?}", payload);
         // Send the disconnect event
-        app_handle
-            .emit("channel-state", serde_json::to_string(&payload)?)
-            .unwrap();
+        emit_event("channel-state", serde_json::to_string(&payload)?).unwrap();
 
         Ok(())
     }
@@ -1,18 +1,21 @@
+use crate::combridage::address_book::{scan_addresses, AddressBook, ClientAddress};
+use crate::combridage::correlation::Correlator;
 use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::tls::{self, TcpIoStream, TlsConfig, TlsServer, TlsSession};
 use crate::combridage::CommunicationChannel;
 use crate::combridage::{ChannelState, Message};
-use crate::global::get_app_handle;
+use crate::global::emit_event;
 use async_trait::async_trait;
 use serde_json;
 use std::collections::HashMap;
 use std::error::Error;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tauri::Emitter;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio::sync::{broadcast, mpsc};
-use tokio::time::{sleep, timeout, Duration};
+use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
 #[derive(Clone, Debug)]
@@ -20,27 +23,29 @@ pub struct TcpClientOfServer {
     channeltype: String,
     channelid: String,
     channel_name: String,
+    peer: String,
+    // 连接建立时间（毫秒）
+    connected_at: i64,
+    tls_session: Option<TlsSession>,
     shutdown_signal: broadcast::Sender<()>,
     tx_send: mpsc::Sender<Vec<u8>>,
     rx_message: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
 }
 
 impl TcpClientOfServer {
-    pub async fn new(stream: TcpStream) -> Result<Self, Box<dyn Error + Send + Sync>> {
+    pub(crate) async fn new(
+        stream: TcpIoStream,
+        peer_addr: SocketAddr,
+        tls_session: Option<TlsSession>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         println!("创建新的 TcpClientOfServer");
-        let peer_addr = stream.peer_addr()?;
         println!("客户端地址: {}", peer_addr);
 
-        let std_stream = stream.into_std()?;
-        std_stream.set_nonblocking(true)?;
-        std_stream.set_nodelay(true)?;
-
         let (shutdown_signal, _) = broadcast::channel(1);
         let (tx_send, mut rx_send) = mpsc::channel::<Vec<u8>>(100); // 发送队列
         let (tx_message, rx_message) = mpsc::channel::<Vec<u8>>(100); // 消息队列
 
-        let tokio_stream = TcpStream::from_std(std_stream)?;
-        let (mut reader, mut writer) = tokio::io::split(tokio_stream);
+        let (mut reader, mut writer) = tokio::io::split(stream);
 
         // 启动发送任务
         let shutdown = shutdown_signal.subscribe();
@@ -62,6 +67,8 @@ impl TcpClientOfServer {
                     }
                     _ = shutdown_rx.recv() => {
                         println!("发送任务收到关闭信号");
+                        // TLS 连接发送 close_notify
+                        let _ = writer.shutdown().await;
                         break;
                     }
                 }
@@ -117,46 +124,16 @@ impl TcpClientOfServer {
             channeltype: "tcpserver".to_string(),
             channelid: "tcpserver".to_string() + &Uuid::new_v4().to_string(),
             channel_name: "TCP".to_string() + &peer_addr.to_string(),
+            peer: peer_addr.to_string(),
+            connected_at: chrono::Utc::now().timestamp_millis(),
+            tls_session,
             shutdown_signal: shutdown_signal.clone(),
             tx_send,
             rx_message: Arc::new(Mutex::new(rx_message)),
         };
 
-        let channeltype = channel.channeltype.clone();
-        let channelid = channel.channelid.clone();
-        let channel_for_spawn = channel.clone();
-        let channel_name = channel.channel_name.clone();
-        tokio::spawn(async move {
-            let app_handle = get_app_handle();
-            let message_manager = match MessageManager::new(app_handle.clone()) {
-                Ok(manager) => manager,
-                Err(e) => {
-                    eprintln!("创建消息管理器失败: {:?}", e);
-                    return;
-                }
-            };
-            let mut channel_clone = channel_for_spawn.clone();
-            while let Some(data) = channel_clone.receive().await {
-                println!("接收到消息，长度: {}", data.len());
-                // 记录发送的消息
-                if let Err(e) = message_manager
-                    .record_message(
-                        &channeltype,
-                        &channelid,
-                        &channel_name,
-                        &Message::new(serde_json::json!({
-                            "data": data
-                        })),
-                        MessageDirection::Received,
-                        None,
-                    )
-                    .await
-                {
-                    eprintln!("记录发送消息失败: {:?}", e);
-                }
-            }
-        });
-
+        // 收到的数据只由 TcpServerChannel 的消息处理器读取、记录并识别地址；
+        // 这里不再另起任务读取同一个接收队列，否则两个任务各取走一部分数据，地址识别会漏掉报文
         println!("TcpClientOfServer 创建完成");
         Ok(channel.clone())
     }
@@ -193,14 +170,20 @@ pub struct TcpServerChannel {
     shutdown_signal: broadcast::Sender<()>,
     listener: Arc<Mutex<Option<TcpListener>>>,
     message_manager: Arc<MessageManager>,
+    // 终端/电表地址到客户端连接的映射
+    addresses: Arc<Mutex<AddressBook>>,
+    tls: Option<TlsServer>,
+    correlator: Correlator,
 }
 
 impl TcpServerChannel {
     pub async fn new(
         ipaddr: &str,
         port: u16,
+        tls: TlsConfig,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let address = format!("{}:{}", ipaddr, port);
+        let tls = tls.server()?;
         let (shutdown_signal, _) = broadcast::channel(1);
         println!("TcpServerChannel listening on: {}", address);
         let channel_name = "TCP".to_string() + &address.clone();
@@ -208,8 +191,7 @@ impl TcpServerChannel {
         std_listener.set_nonblocking(true)?;
         let listener = TcpListener::from_std(std_listener)?;
 
-        let app_handle = get_app_handle();
-        let message_manager = Arc::new(MessageManager::new(app_handle.clone())?);
+        let message_manager = Arc::new(MessageManager::from_global()?);
 
         let server = Self {
             channeltype: "tcpserver".to_string(),
@@ -219,6 +201,9 @@ impl TcpServerChannel {
             shutdown_signal: shutdown_signal.clone(),
             listener: Arc::new(Mutex::new(Some(listener))),
             message_manager,
+            addresses: Arc::new(Mutex::new(AddressBook::new())),
+            tls,
+            correlator: Correlator::new(),
         };
 
         let server_clone = server.clone();
@@ -238,12 +223,32 @@ impl TcpServerChannel {
     ) {
         println!("启动客户端消息处理器: {}", client_addr);
         let message_manager = self.message_manager.clone();
+        let addresses = self.addresses.clone();
+        let correlator = self.correlator.clone();
 
         tokio::spawn(async move {
             println!("开始监听客户端消息: {}", client_addr);
+            let mut pending = Vec::new();
             while let Some(data) = client.receive().await {
                 println!("收到客户端消息: {} 长度: {}", client_addr, data.len());
 
+                // 从上送的报文中识别终端地址
+                pending.extend_from_slice(&data);
+                let now = chrono::Utc::now().timestamp_millis();
+                for (protocol, address) in scan_addresses(&mut pending) {
+                    let learned = addresses.lock().await.learn(
+                        &protocol,
+                        &address,
+                        &client.channelid,
+                        &client.peer,
+                        client.connected_at,
+                        now,
+                    );
+                    if let Some((entry, previous)) = learned {
+                        Self::emit_address_learned(&entry, previous);
+                    }
+                }
+
                 // 创建消息内容
                 let content = serde_json::json!({
                     "data": data
@@ -264,11 +269,98 @@ impl TcpServerChannel {
                 } else {
                     println!("消息已记录并处理: {} -> {:?}", client_addr, content);
                 }
+                // 等待中的请求的应答交给对应请求
+                correlator.offer(data);
             }
+            addresses.lock().await.set_offline(&client.channelid);
             println!("客户端消息处理器停止: {}", client_addr);
         });
     }
 
+    fn emit_address_learned(entry: &ClientAddress, previous_client_id: Option<String>) {
+        println!(
+            "地址 {} ({}) 对应客户端 {}",
+            entry.address, entry.protocol, entry.client_id
+        );
+        let event = serde_json::json!({
+            "channel": "tcpserver",
+            "eventType": "addressLearned",
+            "clientId": entry.client_id,
+            "previousClientId": previous_client_id,
+            "address": entry.address,
+            "protocol": entry.protocol,
+            "peer": entry.peer,
+            "connectedAt": entry.connected_at,
+            "lastSeen": entry.last_seen,
+        });
+        if let Ok(event_payload) = serde_json::to_string(&event) {
+            if let Err(e) = emit_event("tcp-client-event", event_payload) {
+                eprintln!("发送地址识别事件失败: {:?}", e);
+            }
+        }
+    }
+
+    // clientid 可以是客户端 ID，也可以是已识别的终端/电表地址
+    async fn find_client(&self, target: &str) -> Option<(TcpClientOfServer, Option<String>)> {
+        let clients = self.clients.lock().await;
+        if let Some(client) = clients.get(target) {
+            return Some((client.clone(), None));
+        }
+        let addresses = self.addresses.lock().await;
+        let entry = addresses.resolve(target)?;
+        clients
+            .get(&entry.client_id)
+            .map(|client| (client.clone(), Some(entry.address.clone())))
+    }
+
+    // 完成 TLS 握手后登记客户端并通知前端
+    async fn add_client(&self, stream: TcpStream, addr: SocketAddr) {
+        let addr_str = addr.to_string();
+        if let Err(e) = stream.set_nodelay(true) {
+            eprintln!("设置 TCP_NODELAY 失败: {} 错误: {:?}", addr_str, e);
+        }
+        let (stream, tls_session) = match tls::accept(stream, self.tls.as_ref()).await {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("TLS 握手失败: {} 错误: {:?}", addr_str, e);
+                return;
+            }
+        };
+
+        match TcpClientOfServer::new(stream, addr, tls_session).await {
+            Ok(client) => {
+                {
+                    let mut clients = self.clients.lock().await;
+                    clients.insert(client.channelid.clone(), client.clone());
+                }
+
+                let client_clone = client.clone();
+                // 启动消息处理器
+                self.start_client_message_handler(addr_str.clone(), client_clone)
+                    .await;
+
+                // 发送连接事件
+                let channel_info = serde_json::json!({
+                    "channel": "tcpserver",
+                    "eventType": "clientConnected",
+                    "clientId": client.channelid.clone(),
+                    "ip": addr.ip().to_string(),
+                    "port": addr.port(),
+                    "tls": client.tls_session,
+                });
+
+                if let Ok(event_payload) = serde_json::to_string(&channel_info) {
+                    if let Err(e) = emit_event("tcp-client-event", event_payload) {
+                        eprintln!("发送客户端连接事件失败: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("创建客户端对象失败: {} 错误: {:?}", addr_str, e);
+            }
+        }
+    }
+
     async fn accept_loop(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut shutdown_receiver = self.shutdown_signal.subscribe();
 
@@ -295,40 +387,12 @@ impl TcpServerChannel {
                 } => {
                     match accept_result {
                         Ok((stream, addr)) => {
-                            let addr_str = addr.to_string();
-                            println!("New client connected: {}", addr_str);
-
-                            match TcpClientOfServer::new(stream).await {
-                                Ok(client) => {
-                                    {
-                                        let mut clients = self.clients.lock().await;
-                                        clients.insert(client.channelid.clone(), client.clone());
-                                    }
-
-                                    let client_clone = client.clone();
-                                    // 启动消息处理器
-                                    self.start_client_message_handler(addr_str.clone(), client_clone).await;
-
-                                    // 发送连接事件
-                                    let app_handle = get_app_handle();
-                                    let channel_info = serde_json::json!({
-                                        "channel": "tcpserver",
-                                        "eventType": "clientConnected",
-                                        "clientId": client.channelid.clone(),
-                                        "ip": addr.ip().to_string(),
-                                        "port": addr.port()
-                                    });
-
-                                    if let Ok(event_payload) = serde_json::to_string(&channel_info) {
-                                        if let Err(e) = app_handle.emit("tcp-client-event", event_payload) {
-                                            eprintln!("发送客户端连接事件失败: {:?}", e);
-                                        }
-                                    }
-                                },
-                                Err(e) => {
-                                    eprintln!("创建客户端对象失败: {} 错误: {:?}", addr_str, e);
-                                }
-                            }
+                            println!("New client connected: {}", addr);
+                            // TLS 握手可能较慢，不阻塞后续连接的接受
+                            let server = self.clone();
+                            tokio::spawn(async move {
+                                server.add_client(stream, addr).await;
+                            });
                         }
                         Err(e) => {
                             if e.kind() != std::io::ErrorKind::WouldBlock {
@@ -395,19 +459,15 @@ impl CommunicationChannel for TcpServerChannel {
             "TcpServerChannel::send - 发送消息{:?} data: {:?}",
             message, data
         );
-        let app_handle = get_app_handle();
-        let message_manager = MessageManager::new(app_handle.clone())?;
+        let message_manager = MessageManager::from_global()?;
 
         let mut message_clone = message.clone();
         message_clone.update_timestamp();
 
         if let Some(clientid) = clientid {
-            let client_arc = {
-                let clients = self.clients.lock().await;
-                clients.get(clientid.as_str()).cloned()
-            };
+            let client_arc = self.find_client(&clientid).await;
             println!("client_arc: {:?}", client_arc);
-            if let Some(client) = client_arc {
+            if let Some((client, address)) = client_arc {
                 println!("准备发送消息");
                 client.send(data.clone()).await?;
                 println!("消息发送完成");
@@ -420,7 +480,7 @@ impl CommunicationChannel for TcpServerChannel {
                         &client.channel_name,
                         &message_clone,
                         MessageDirection::Sent,
-                        None,
+                        address.map(|address| HashMap::from([("address".to_string(), address)])),
                     )
                     .await
                 {
@@ -428,7 +488,7 @@ impl CommunicationChannel for TcpServerChannel {
                 }
                 Ok(())
             } else {
-                Err(format!("客户端 {} 不存在", clientid).into())
+                Err(format!("客户端或地址 {} 不存在", clientid).into())
             }
         } else {
             let clients = self.clients.lock().await;
@@ -488,8 +548,9 @@ impl CommunicationChannel for TcpServerChannel {
         message: &Message,
         timeout_secs: u64,
     ) -> Result<Message, Box<dyn Error + Send + Sync>> {
-        self.send(message, None).await?;
-        timeout(Duration::from_secs(timeout_secs), self.receive()).await?
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
     }
 
     async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -500,16 +561,13 @@ impl CommunicationChannel for TcpServerChannel {
         &self,
         state: ChannelState,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let app_handle = get_app_handle();
         let payload = serde_json::json!({
             "channel": "tcpserver",
             "state": state,
             "reason": "The tcpserver channel has been disconnected",
         });
 
-        app_handle
-            .emit("channel-state", serde_json::to_string(&payload)?)
-            .unwrap();
+        emit_event("channel-state", serde_json::to_string(&payload)?).unwrap();
 
         Ok(())
     }
@@ -529,4 +587,12 @@ impl CommunicationChannel for TcpServerChannel {
     async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         Err("TCP server does not support topic unsubscription".into())
     }
+
+    async fn client_ids(&self) -> Vec<String> {
+        self.clients.lock().await.keys().cloned().collect()
+    }
+
+    async fn client_addresses(&self) -> Vec<ClientAddress> {
+        self.addresses.lock().await.entries()
+    }
 }
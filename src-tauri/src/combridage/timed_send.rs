@@ -0,0 +1,432 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_template::FrameTemplate;
+use crate::combridage::correlation::ResponseKey;
+use crate::combridage::messagemanager::{subscribe_records, MessageDirection, MessageRecord};
+use crate::combridage::{CommunicationChannel, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep_until, Duration, Instant, MissedTickBehavior};
+
+// 达到发送次数后等待最后一帧应答的最长时间
+const LAST_RESPONSE_WAIT: Duration = Duration::from_secs(3);
+
+/// 定时发送任务的配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimedSendOptions {
+    /// 任务名称，如“心跳”“抄读”，用于区分同一通道上的多个任务
+    pub name: String,
+    /// 发送间隔（毫秒）
+    pub interval_ms: u64,
+    /// 依次循环发送的报文，与 templates 合计至少一帧
+    pub frames: Vec<Vec<u8>>,
+    /// 依次循环发送的报文模板名称，排在 frames 之后，每次发送前展开
+    pub templates: Vec<String>,
+    /// 展开模板使用的变量
+    pub vars: HashMap<String, String>,
+    /// TCP 服务端通道需要指定发往的客户端
+    pub clientid: Option<String>,
+    /// 成功发送指定次数后停止，为空时不限制
+    pub max_sends: Option<u64>,
+    /// 到达该时间（毫秒时间戳）后停止，为空时不限制
+    pub stop_at: Option<i64>,
+}
+
+/// 定时发送任务的配置与运行统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedSendStatus {
+    pub job_id: String,
+    pub channel_id: String,
+    #[serde(flatten)]
+    pub options: TimedSendOptions,
+    /// 启动时间（毫秒时间戳）
+    pub started_at: i64,
+    pub running: bool,
+    /// 任务结束的原因，运行中为空
+    pub stop_reason: Option<String>,
+    pub sent_count: u64,
+    pub failed_count: u64,
+    /// 下一次发送的帧在 frames、templates 中的序号
+    pub next_frame: usize,
+    /// 最近一次成功发送的时间（毫秒时间戳）
+    pub last_sent_at: Option<i64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+    /// 最近一次收到的应答（十六进制），按协议字段与本任务发出的报文匹配，
+    /// 无法识别协议的报文取之后收到的第一条数据
+    pub last_response: Option<String>,
+    pub last_response_at: Option<i64>,
+}
+
+/// 在通道上按固定间隔循环发送一组报文的任务
+///
+/// 任务直接持有通道，发送失败（如通道正在断线重连）只记录错误并继续，
+/// 通道恢复连接后自动恢复发送；任务被丢弃时停止
+pub struct TimedSendJob {
+    status: Arc<Mutex<TimedSendStatus>>,
+    handle: JoinHandle<()>,
+}
+
+impl TimedSendJob {
+    pub fn start(
+        channel: Arc<Box<dyn CommunicationChannel>>,
+        options: TimedSendOptions,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if options.interval_ms == 0 {
+            return Err("发送间隔必须大于 0".into());
+        }
+        if (options.frames.is_empty() && options.templates.is_empty())
+            || options.frames.iter().any(|frame| frame.is_empty())
+        {
+            return Err("定时发送的报文不能为空".into());
+        }
+        // 启动时读取模板并试展开，运行中修改模板不影响已启动的任务
+        let mut templates = Vec::new();
+        for name in &options.templates {
+            let template = FrameTemplate::load(name).ok_or(format!("模板不存在: {}", name))?;
+            template
+                .preview(&options.vars)
+                .map_err(|e| format!("模板 {} 展开失败: {}", name, e))?;
+            templates.push(template);
+        }
+        if options.max_sends == Some(0) {
+            return Err("发送次数必须大于 0".into());
+        }
+        if options
+            .stop_at
+            .is_some_and(|stop_at| stop_at <= chrono::Utc::now().timestamp_millis())
+        {
+            return Err("停止时间已过".into());
+        }
+
+        let status = Arc::new(Mutex::new(TimedSendStatus {
+            job_id: "timer".to_string() + &uuid::Uuid::new_v4().to_string(),
+            channel_id: channel.get_channel_id(),
+            options,
+            started_at: chrono::Utc::now().timestamp_millis(),
+            running: true,
+            stop_reason: None,
+            sent_count: 0,
+            failed_count: 0,
+            next_frame: 0,
+            last_sent_at: None,
+            last_error: None,
+            last_error_at: None,
+            last_response: None,
+            last_response_at: None,
+        }));
+        // 先订阅，避免错过第一帧发送后立即到达的应答
+        let receiver = subscribe_records();
+        let handle = tokio::spawn(Self::run(channel, templates, status.clone(), receiver));
+        Ok(Self { status, handle })
+    }
+
+    pub fn job_id(&self) -> String {
+        self.status.lock().unwrap().job_id.clone()
+    }
+
+    pub fn channel_id(&self) -> String {
+        self.status.lock().unwrap().channel_id.clone()
+    }
+
+    pub fn status(&self) -> TimedSendStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// 停止发送，返回最终的状态
+    pub fn stop(self) -> TimedSendStatus {
+        self.handle.abort();
+        let mut status = self.status.lock().unwrap().clone();
+        if status.running {
+            status.running = false;
+            status.stop_reason = Some("已手动停止".to_string());
+        }
+        status
+    }
+
+    async fn run(
+        channel: Arc<Box<dyn CommunicationChannel>>,
+        templates: Vec<FrameTemplate>,
+        status: Arc<Mutex<TimedSendStatus>>,
+        mut receiver: tokio::sync::broadcast::Receiver<MessageRecord>,
+    ) {
+        let options = status.lock().unwrap().options.clone();
+        // 应答记录所属的通道 ID，TCP 服务端为客户端 ID
+        let response_channel_id = options
+            .clientid
+            .clone()
+            .unwrap_or_else(|| channel.get_channel_id());
+        let mut ticker = interval(Duration::from_millis(options.interval_ms));
+        // 发送较慢时顺延，不连续补发
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let stop_at = options.stop_at.map(|stop_at| {
+            let remaining = stop_at - chrono::Utc::now().timestamp_millis();
+            Instant::now() + Duration::from_millis(remaining.max(0) as u64)
+        });
+        let stop_timer = async move {
+            match stop_at {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(stop_timer);
+
+        let mut index = 0;
+        let mut listening = true;
+        // 本任务最近发出的报文的应答匹配条件，与通道的 send_and_wait 相同按协议字段匹配，
+        // 同一通道上的其他任务的应答和终端主动上报不会记到本任务；
+        // 无法识别协议的报文只能取之后收到的第一条数据，此时不保证归属正确
+        let mut awaiting: Option<ResponseKey> = None;
+        // 达到发送次数后，等待最后一帧应答的截止时间
+        let mut linger: Option<Instant> = None;
+        loop {
+            let linger_timer = async {
+                match linger {
+                    Some(deadline) => sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = &mut stop_timer => {
+                    Self::finish(&status, "到达停止时间");
+                    return;
+                }
+                _ = linger_timer => {
+                    Self::finish(&status, "已达到发送次数");
+                    return;
+                }
+                _ = ticker.tick(), if linger.is_none() => {
+                    let frame = match options.frames.get(index) {
+                        Some(frame) => Ok(frame.clone()),
+                        None => templates[index - options.frames.len()].expand(&options.vars),
+                    };
+                    index = (index + 1) % (options.frames.len() + templates.len());
+                    let result = match frame {
+                        Ok(frame) => {
+                            let message = Message::new(frame_content(&frame));
+                            channel
+                                .send(&message, options.clientid.clone())
+                                .await
+                                .map(|()| ResponseKey::from_request(&frame).unwrap_or(ResponseKey::Any))
+                        }
+                        Err(e) => Err(format!("模板展开失败: {}", e).into()),
+                    };
+                    let now = chrono::Utc::now().timestamp_millis();
+                    let sent_count = {
+                        let mut status = status.lock().unwrap();
+                        status.next_frame = index;
+                        match result {
+                            Ok(key) => {
+                                status.sent_count += 1;
+                                status.last_sent_at = Some(now);
+                                awaiting = Some(key);
+                            }
+                            Err(e) => {
+                                eprintln!("定时发送失败: 通道 {} {}", status.channel_id, e);
+                                status.failed_count += 1;
+                                status.last_error = Some(e.to_string());
+                                status.last_error_at = Some(now);
+                            }
+                        }
+                        status.sent_count
+                    };
+                    if options.max_sends.is_some_and(|max| sent_count >= max) {
+                        if awaiting.is_none() {
+                            Self::finish(&status, "已达到发送次数");
+                            return;
+                        }
+                        let wait = Duration::from_millis(options.interval_ms).min(LAST_RESPONSE_WAIT);
+                        linger = Some(Instant::now() + wait);
+                    }
+                }
+                result = receiver.recv(), if listening => match result {
+                    Ok(record) => {
+                        if record.channel_id() != response_channel_id
+                            || !matches!(record.direction(), MessageDirection::Received)
+                        {
+                            continue;
+                        }
+                        let payload = record.payload_bytes();
+                        if awaiting.as_ref().is_some_and(|key| key.matches(&payload)) {
+                            awaiting = None;
+                            let mut status = status.lock().unwrap();
+                            status.last_response = Some(FrameFun::get_data_str_with_space(&payload));
+                            status.last_response_at = Some(record.timestamp().timestamp_millis());
+                            if linger.is_some() {
+                                status.running = false;
+                                status.stop_reason = Some("已达到发送次数".to_string());
+                                return;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        eprintln!("定时发送订阅消息滞后，丢失 {} 条记录", n);
+                    }
+                    Err(RecvError::Closed) => listening = false,
+                },
+            }
+        }
+    }
+
+    fn finish(status: &Mutex<TimedSendStatus>, reason: &str) {
+        let mut status = status.lock().unwrap();
+        status.running = false;
+        status.stop_reason = Some(reason.to_string());
+    }
+}
+
+impl Drop for TimedSendJob {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// UTF-8 编码的 JSON 对象按消息内容发送（如 MQTT 的主题和负载），否则按二进制发送；
+// 只接受对象，避免 "35 36" 这类报文被当作 JSON 数字
+fn frame_content(frame: &[u8]) -> serde_json::Value {
+    if let Ok(text) = std::str::from_utf8(frame) {
+        if let Ok(json @ serde_json::Value::Object(_)) = serde_json::from_str(text) {
+            return json;
+        }
+    }
+    serde_json::json!({ "data": frame })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combridage::VirtualChannel;
+    use tokio::time::timeout;
+
+    // 68 A0..A5 68 C L 数据(+33) CS 16，表地址 000000000001
+    fn dlt645(control: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x68, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68];
+        frame.push(control);
+        frame.push(data.len() as u8);
+        frame.extend(data.iter().map(|b| b.wrapping_add(0x33)));
+        frame.push(FrameFun::calculate_cs(&frame));
+        frame.push(0x16);
+        frame
+    }
+
+    fn options(frames: Vec<Vec<u8>>, interval_ms: u64) -> TimedSendOptions {
+        TimedSendOptions {
+            interval_ms,
+            frames,
+            ..Default::default()
+        }
+    }
+
+    async fn recv(channel: &VirtualChannel) -> Option<Vec<u8>> {
+        let message = timeout(Duration::from_millis(500), channel.receive())
+            .await
+            .ok()?
+            .unwrap();
+        let data = message.get_content()["data"].as_array()?.clone();
+        Some(data.iter().map(|b| b.as_u64().unwrap() as u8).collect())
+    }
+
+    async fn wait_stopped(job: &TimedSendJob) -> TimedSendStatus {
+        for _ in 0..100 {
+            let status = job.status();
+            if !status.running {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("定时发送任务没有结束: {:?}", job.status());
+    }
+
+    #[tokio::test]
+    async fn cycles_frames_until_max_sends() {
+        let (a, b) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let mut options = options(vec![vec![0x01], vec![0x02]], 20);
+        options.max_sends = Some(3);
+        let job = TimedSendJob::start(Arc::new(Box::new(a)), options).unwrap();
+
+        for expected in [vec![0x01], vec![0x02], vec![0x01]] {
+            assert_eq!(recv(&b).await, Some(expected));
+        }
+        let status = wait_stopped(&job).await;
+        assert_eq!(status.sent_count, 3);
+        assert_eq!(status.next_frame, 1);
+        assert_eq!(status.stop_reason.as_deref(), Some("已达到发送次数"));
+        // 达到次数后不再发送
+        assert_eq!(recv(&b).await, None);
+    }
+
+    #[tokio::test]
+    async fn stops_at_deadline() {
+        let (a, _b) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let mut options = options(vec![vec![0x01]], 30);
+        options.stop_at = Some(chrono::Utc::now().timestamp_millis() + 100);
+        let job = TimedSendJob::start(Arc::new(Box::new(a)), options).unwrap();
+
+        let status = wait_stopped(&job).await;
+        assert_eq!(status.stop_reason.as_deref(), Some("到达停止时间"));
+        assert!(
+            (1..=5).contains(&status.sent_count),
+            "{}",
+            status.sent_count
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_options() {
+        let (a, _b) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let channel: Arc<Box<dyn CommunicationChannel>> = Arc::new(Box::new(a));
+        assert!(TimedSendJob::start(channel.clone(), options(vec![vec![0x01]], 0)).is_err());
+        assert!(TimedSendJob::start(channel.clone(), options(Vec::new(), 10)).is_err());
+        let mut past = options(vec![vec![0x01]], 10);
+        past.stop_at = Some(chrono::Utc::now().timestamp_millis() - 1);
+        assert!(TimedSendJob::start(channel, past).is_err());
+    }
+
+    #[tokio::test]
+    async fn jobs_on_one_channel_keep_their_own_responses() {
+        let (a, b) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        let channel: Arc<Box<dyn CommunicationChannel>> = Arc::new(Box::new(a));
+        // 两个任务读不同的数据标识
+        let energy = dlt645(0x11, &[0x00, 0x00, 0x01, 0x00]);
+        let voltage = dlt645(0x11, &[0x00, 0x01, 0x01, 0x02]);
+        let energy_job = TimedSendJob::start(channel.clone(), options(vec![energy], 1000)).unwrap();
+        let voltage_job =
+            TimedSendJob::start(channel.clone(), options(vec![voltage], 1000)).unwrap();
+        assert!(recv(&b).await.is_some());
+        assert!(recv(&b).await.is_some());
+
+        // 先收到与两个任务都无关的主动上报，再收到电压的应答
+        let report = dlt645(0x9E, &[0x01, 0x02]);
+        let reply = dlt645(0x91, &[0x00, 0x01, 0x01, 0x02, 0x20, 0x02]);
+        b.send(&Message::new(serde_json::json!({ "data": report })), None)
+            .await
+            .unwrap();
+        b.send(&Message::new(serde_json::json!({ "data": reply })), None)
+            .await
+            .unwrap();
+
+        for _ in 0..50 {
+            if voltage_job.status().last_response.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            voltage_job.status().last_response,
+            Some(FrameFun::get_data_str_with_space(&reply))
+        );
+        assert_eq!(energy_job.status().last_response, None);
+    }
+}
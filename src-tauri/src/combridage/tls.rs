@@ -0,0 +1,652 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::WebPkiServerVerifier;
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{
+    ClientConfig, CommonState, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+// 对端不响应握手（如连到了明文端口）时不能一直等待
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TCP 通道的 TLS 配置，证书和私钥均为 PEM 文件路径
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// 客户端用于校验服务端证书、服务端用于校验客户端证书的 CA，
+    /// 客户端未指定时使用内置的公共根证书
+    pub ca_file: Option<String>,
+    /// 本端证书链，服务端必填，客户端仅在服务端要求客户端证书时需要
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    /// 服务端要求客户端提供由 CA 签发的证书
+    pub require_client_cert: bool,
+    /// 客户端不校验服务端证书，仅用于实验室环境
+    pub insecure_skip_verify: bool,
+    /// 校验证书时使用的服务器名称，未指定时使用连接地址
+    pub server_name: Option<String>,
+}
+
+/// 握手完成后协商出的连接参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsSession {
+    pub protocol: String,
+    pub cipher: String,
+    /// 对端证书的主题，对端未提供证书时为空
+    pub peer_subject: Option<String>,
+}
+
+impl TlsSession {
+    fn new(state: &CommonState) -> Self {
+        Self {
+            protocol: state
+                .protocol_version()
+                .map(|version| format!("{:?}", version))
+                .unwrap_or_default(),
+            cipher: state
+                .negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite()))
+                .unwrap_or_default(),
+            peer_subject: state
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| certificate_subject(cert)),
+        }
+    }
+}
+
+/// 明文或 TLS 连接，读写两半通过 `tokio::io::split` 拆分
+pub(crate) trait TcpIo: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Debug> TcpIo for T {}
+
+pub(crate) type TcpIoStream = Box<dyn TcpIo>;
+
+impl TlsConfig {
+    /// 创建连接 `host` 的 TLS 客户端，未启用 TLS 时返回 None
+    pub(crate) fn client(
+        &self,
+        host: &str,
+    ) -> Result<Option<TlsClient>, Box<dyn Error + Send + Sync>> {
+        let server_name = self.server_name(host)?;
+        Ok(self.client_config(host)?.map(|config| TlsClient {
+            connector: TlsConnector::from(config),
+            server_name,
+        }))
+    }
+
+    /// 连接 `host` 的客户端 rustls 配置，未启用 TLS 时返回 None
+    ///
+    /// 证书始终按配置的服务器名称校验，与握手时使用的名称无关
+    pub(crate) fn client_config(
+        &self,
+        host: &str,
+    ) -> Result<Option<Arc<ClientConfig>>, Box<dyn Error + Send + Sync>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let server_name = self.server_name(host)?;
+        let provider = provider();
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .dangerous();
+        let builder = if self.insecure_skip_verify {
+            builder.with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            let mut roots = RootCertStore::empty();
+            match &self.ca_file {
+                Some(ca_file) => {
+                    for cert in load_certs(ca_file)? {
+                        roots.add(cert)?;
+                    }
+                }
+                None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            }
+            let verifier =
+                WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_custom_certificate_verifier(Arc::new(NamedVerifier {
+                verifier,
+                server_name,
+            }))
+        };
+        let config = match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                builder.with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)?
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        Ok(Some(Arc::new(config)))
+    }
+
+    /// 创建 TLS 服务端，未启用 TLS 时返回 None
+    pub(crate) fn server(&self) -> Result<Option<TlsServer>, Box<dyn Error + Send + Sync>> {
+        Ok(self.server_config()?.map(|config| TlsServer {
+            acceptor: TlsAcceptor::from(config),
+        }))
+    }
+
+    /// 服务端的 rustls 配置，未启用 TLS 时返回 None
+    pub fn server_config(&self) -> Result<Option<Arc<ServerConfig>>, Box<dyn Error + Send + Sync>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let (Some(cert_file), Some(key_file)) = (&self.cert_file, &self.key_file) else {
+            return Err("TLS 服务端需要配置证书和私钥".into());
+        };
+        let provider = provider();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = if self.require_client_cert {
+            let ca_file = self
+                .ca_file
+                .as_ref()
+                .ok_or("要求客户端证书时需要配置 CA 证书")?;
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_file)? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder.with_single_cert(load_certs(cert_file)?, load_key(key_file)?)?;
+        Ok(Some(Arc::new(config)))
+    }
+
+    // 校验证书使用的服务器名称
+    fn server_name(&self, host: &str) -> Result<ServerName<'static>, Box<dyn Error + Send + Sync>> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        // IPv6 地址在连接地址中带有方括号
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+        ServerName::try_from(name.to_string())
+            .map_err(|e| format!("无效的 TLS 服务器名称 {}: {}", name, e).into())
+    }
+}
+
+/// TLS 客户端，建立连接后完成握手
+#[derive(Clone)]
+pub(crate) struct TlsClient {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl Debug for TlsClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsClient")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+/// TLS 服务端，接受连接后完成握手
+#[derive(Clone)]
+pub(crate) struct TlsServer {
+    acceptor: TlsAcceptor,
+}
+
+impl Debug for TlsServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsServer").finish_non_exhaustive()
+    }
+}
+
+/// 作为客户端完成 TLS 握手，未启用 TLS 时直接使用明文连接
+pub(crate) async fn connect(
+    stream: TcpStream,
+    tls: Option<&TlsClient>,
+) -> Result<(TcpIoStream, Option<TlsSession>), Box<dyn Error + Send + Sync>> {
+    let Some(tls) = tls else {
+        return Ok((Box::new(stream), None));
+    };
+    let handshake = tls.connector.connect(tls.server_name.clone(), stream);
+    let stream = timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| "TLS 握手超时")??;
+    let session = TlsSession::new(stream.get_ref().1);
+    Ok((Box::new(stream), Some(session)))
+}
+
+/// 作为服务端完成 TLS 握手，未启用 TLS 时直接使用明文连接
+pub(crate) async fn accept(
+    stream: TcpStream,
+    tls: Option<&TlsServer>,
+) -> Result<(TcpIoStream, Option<TlsSession>), Box<dyn Error + Send + Sync>> {
+    let Some(tls) = tls else {
+        return Ok((Box::new(stream), None));
+    };
+    let stream = timeout(HANDSHAKE_TIMEOUT, tls.acceptor.accept(stream))
+        .await
+        .map_err(|_| "TLS 握手超时")??;
+    let session = TlsSession::new(stream.get_ref().1);
+    Ok((Box::new(stream), Some(session)))
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error + Send + Sync>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| format!("读取证书 {} 失败: {}", path, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("解析证书 {} 失败: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("证书文件 {} 中没有证书", path).into());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, Box<dyn Error + Send + Sync>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| format!("读取私钥 {} 失败: {}", path, e).into())
+}
+
+// 跳过证书校验，但仍校验握手签名，保证对端持有证书对应的私钥
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// 按配置的服务器名称校验证书，连接库（如 MQTT）会以连接地址作为握手名称
+#[derive(Debug)]
+struct NamedVerifier {
+    verifier: Arc<WebPkiServerVerifier>,
+    server_name: ServerName<'static>,
+}
+
+impl ServerCertVerifier for NamedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            &self.server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+// DER 编码的 TLV，返回 (标签, 内容, 剩余数据)
+fn der_next(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first & 0x80 == 0 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7F) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// 取证书的主题，格式如 `CN=terminal, O=EmbedTalk`
+pub(crate) fn certificate_subject(cert: &[u8]) -> Option<String> {
+    const SEQUENCE: u8 = 0x30;
+    // Certificate ::= SEQUENCE { tbsCertificate, ... }
+    let (_, certificate, _) = der_next(cert).filter(|(tag, _, _)| *tag == SEQUENCE)?;
+    let (_, tbs, _) = der_next(certificate).filter(|(tag, _, _)| *tag == SEQUENCE)?;
+    // tbsCertificate: [0] 版本（可选）、序列号、签名算法、颁发者、有效期、主题
+    let (tag, _, mut rest) = der_next(tbs)?;
+    if tag == 0xA0 {
+        rest = der_next(rest)?.2;
+    }
+    for _ in 0..3 {
+        rest = der_next(rest)?.2;
+    }
+    let (_, mut names, _) = der_next(rest).filter(|(tag, _, _)| *tag == SEQUENCE)?;
+
+    let mut parts = Vec::new();
+    // Name ::= SEQUENCE OF SET OF SEQUENCE { type OID, value }
+    while !names.is_empty() {
+        let (_, mut set, next) = der_next(names)?;
+        names = next;
+        while !set.is_empty() {
+            let (_, attribute, next) = der_next(set)?;
+            set = next;
+            let (_, oid, value) = der_next(attribute)?;
+            let (_, value, _) = der_next(value)?;
+            parts.push(format!(
+                "{}={}",
+                attribute_name(oid),
+                String::from_utf8_lossy(value)
+            ));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    // 2.5.4.x 编码为 55 04 x
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x05] => "SERIALNUMBER".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0A] => "O".to_string(),
+        [0x55, 0x04, 0x0B] => "OU".to_string(),
+        _ => {
+            let Some((&first, rest)) = oid.split_first() else {
+                return String::new();
+            };
+            let mut arcs = vec![(first / 40) as u64, (first % 40) as u64];
+            let mut value = 0u64;
+            for &byte in rest {
+                value = (value << 7) | (byte & 0x7F) as u64;
+                if byte & 0x80 == 0 {
+                    arcs.push(value);
+                    value = 0;
+                }
+            }
+            arcs.iter()
+                .map(|arc| arc.to_string())
+                .collect::<Vec<_>>()
+                .join(".")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // 测试时生成的证书和私钥，写入临时目录
+    struct TestPki {
+        dir: PathBuf,
+    }
+
+    impl TestPki {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("embedtalk-tls-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        fn write(&self, name: &str, pem: String) -> Option<String> {
+            let path = self.dir.join(name);
+            std::fs::write(&path, pem).unwrap();
+            Some(path.to_string_lossy().to_string())
+        }
+
+        // 生成 CA 及其签发的 localhost 服务端证书、客户端证书，返回 CA 证书路径
+        fn issue(&self) -> Option<String> {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "EmbedTalk Test CA");
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+
+            for (name, common_name) in [("server", "localhost"), ("client", "terminal")] {
+                let key = KeyPair::generate().unwrap();
+                let mut params = CertificateParams::new(vec![common_name.to_string()]).unwrap();
+                params
+                    .distinguished_name
+                    .push(DnType::CommonName, common_name);
+                let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+                self.write(&format!("{}.pem", name), cert.pem());
+                self.write(&format!("{}.key", name), key.serialize_pem());
+            }
+            self.write("ca.pem", ca.pem())
+        }
+
+        // 不由任何 CA 签发的 localhost 证书
+        fn self_signed(&self) {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "localhost");
+            let cert = params.self_signed(&key).unwrap();
+            self.write("server.pem", cert.pem());
+            self.write("server.key", key.serialize_pem());
+        }
+
+        fn path(&self, name: &str) -> Option<String> {
+            Some(self.dir.join(name).to_string_lossy().to_string())
+        }
+
+        fn server_config(&self) -> TlsConfig {
+            TlsConfig {
+                enabled: true,
+                cert_file: self.path("server.pem"),
+                key_file: self.path("server.key"),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Drop for TestPki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    type HandshakeResult = Result<(TcpIoStream, Option<TlsSession>), String>;
+
+    // 在本地端口上完成一次握手，返回服务端和客户端的结果
+    async fn handshake(
+        server: &TlsConfig,
+        client: &TlsConfig,
+    ) -> (HandshakeResult, HandshakeResult) {
+        let server = server.server().unwrap();
+        let client = client.client("127.0.0.1").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let accepted = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept(stream, server.as_ref())
+                .await
+                .map_err(|e| e.to_string())
+        });
+        let stream = TcpStream::connect(address).await.unwrap();
+        let connected = connect(stream, client.as_ref())
+            .await
+            .map_err(|e| e.to_string());
+        (accepted.await.unwrap(), connected)
+    }
+
+    // 握手后客户端发送一段数据，服务端原样收到
+    async fn exchange(server: &mut TcpIoStream, client: &mut TcpIoStream) {
+        client.write_all(&[0x68, 0x16]).await.unwrap();
+        client.flush().await.unwrap();
+        let mut buffer = [0u8; 2];
+        server.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, [0x68, 0x16]);
+    }
+
+    #[tokio::test]
+    async fn client_verifies_server_certificate_with_ca() {
+        let pki = TestPki::new();
+        let ca_file = pki.issue();
+        let client = TlsConfig {
+            enabled: true,
+            ca_file,
+            server_name: Some("localhost".to_string()),
+            ..Default::default()
+        };
+
+        let (accepted, connected) = handshake(&pki.server_config(), &client).await;
+        let (mut server_stream, server_session) = accepted.unwrap();
+        let (mut client_stream, client_session) = connected.unwrap();
+        let client_session = client_session.unwrap();
+        assert_eq!(client_session.peer_subject.as_deref(), Some("CN=localhost"));
+        assert!(!client_session.protocol.is_empty());
+        assert_eq!(server_session.unwrap().peer_subject, None);
+        exchange(&mut server_stream, &mut client_stream).await;
+    }
+
+    #[tokio::test]
+    async fn client_rejects_server_certificate_from_unknown_ca() {
+        let pki = TestPki::new();
+        pki.self_signed();
+        let other = TestPki::new();
+        let client = TlsConfig {
+            enabled: true,
+            ca_file: other.issue(),
+            server_name: Some("localhost".to_string()),
+            ..Default::default()
+        };
+
+        let (_, connected) = handshake(&pki.server_config(), &client).await;
+        assert!(connected.is_err());
+    }
+
+    #[tokio::test]
+    async fn insecure_skip_verify_accepts_self_signed_certificate() {
+        let pki = TestPki::new();
+        pki.self_signed();
+        let client = TlsConfig {
+            enabled: true,
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+
+        let (accepted, connected) = handshake(&pki.server_config(), &client).await;
+        let (mut server_stream, _) = accepted.unwrap();
+        let (mut client_stream, client_session) = connected.unwrap();
+        assert_eq!(
+            client_session.unwrap().peer_subject.as_deref(),
+            Some("CN=localhost")
+        );
+        exchange(&mut server_stream, &mut client_stream).await;
+    }
+
+    #[tokio::test]
+    async fn server_requires_client_certificate() {
+        let pki = TestPki::new();
+        let ca_file = pki.issue();
+        let server = TlsConfig {
+            ca_file: ca_file.clone(),
+            require_client_cert: true,
+            ..pki.server_config()
+        };
+        let client = TlsConfig {
+            enabled: true,
+            ca_file,
+            server_name: Some("localhost".to_string()),
+            ..Default::default()
+        };
+
+        // 没有客户端证书时服务端拒绝连接
+        let (accepted, _) = handshake(&server, &client).await;
+        assert!(accepted.is_err());
+
+        // 提供 CA 签发的客户端证书后握手成功，服务端得到客户端证书的主题
+        let client = TlsConfig {
+            cert_file: pki.path("client.pem"),
+            key_file: pki.path("client.key"),
+            ..client
+        };
+        let (accepted, connected) = handshake(&server, &client).await;
+        let (mut server_stream, server_session) = accepted.unwrap();
+        let (mut client_stream, _) = connected.unwrap();
+        assert_eq!(
+            server_session.unwrap().peer_subject.as_deref(),
+            Some("CN=terminal")
+        );
+        exchange(&mut server_stream, &mut client_stream).await;
+    }
+}
@@ -0,0 +1,472 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::CommunicationChannel;
+use crate::combridage::{ChannelState, Message};
+use crate::global::emit_event;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use socket2::SockRef;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::{sleep, timeout, Duration};
+use uuid::Uuid;
+
+// UDP 数据报的最大长度
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+// 接收出错后的重试间隔，连续出错时加倍
+const RECV_RETRY_MIN: Duration = Duration::from_millis(100);
+const RECV_RETRY_MAX: Duration = Duration::from_secs(5);
+
+// 收到的数据报及其来源地址
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// UDP 组播设置，默认不加入组播组
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UdpMulticast {
+    /// 要加入的 IPv4 组播组，如 239.0.0.1；接收组播数据时本地地址一般绑定为 0.0.0.0
+    pub groups: Vec<Ipv4Addr>,
+    /// 加入组播组和发送组播数据使用的本地网卡地址，未指定时由系统选择
+    pub interface: Option<Ipv4Addr>,
+    /// 发出的组播数据的 TTL，未指定时为系统默认值 1，只在本网段内传播
+    pub ttl: Option<u32>,
+    /// 是否收到本机发出的组播数据，未指定时为系统默认值（接收）
+    pub loopback: Option<bool>,
+}
+
+/// UDP 通道
+///
+/// 绑定本地地址收发数据报，可以配置默认的远端地址。服务端模式下会记录所有发来数据的对端，
+/// 不指定 clientid 时回复最近一个对端；clientid 为 "ip:port" 时发往指定地址，
+/// 也可以是广播地址（如 255.255.255.255:port）或组播地址（如 239.0.0.1:port），用于设备发现。
+#[derive(Clone, Debug)]
+pub struct UdpChannel {
+    channeltype: String,
+    channelid: String,
+    channel_name: String,
+    local_addr: SocketAddr,
+    default_remote: Option<SocketAddr>,
+    server_mode: bool,
+    multicast: UdpMulticast,
+    socket: Arc<UdpSocket>,
+    // 对端地址及最近一次收到数据的时间（毫秒）
+    peers: Arc<Mutex<HashMap<SocketAddr, i64>>>,
+    last_peer: Arc<Mutex<Option<SocketAddr>>>,
+    shutdown_signal: broadcast::Sender<()>,
+    rx_recv: Arc<Mutex<mpsc::Receiver<Datagram>>>,
+    message_manager: Arc<MessageManager>,
+    correlator: Correlator,
+}
+
+impl UdpChannel {
+    pub async fn new(
+        ipaddr: &str,
+        port: u16,
+        remote_ip: &str,
+        remote_port: u16,
+        server_mode: bool,
+        multicast: UdpMulticast,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let address = format!("{}:{}", ipaddr, port);
+        let std_socket = std::net::UdpSocket::bind(&address)?;
+        // 允许向广播地址发送，用于设备发现
+        std_socket.set_broadcast(true)?;
+        Self::apply_multicast(&std_socket, &multicast)?;
+        std_socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(std_socket)?;
+        let local_addr = socket.local_addr()?;
+        println!("UdpChannel bound to {}", local_addr);
+
+        let default_remote = if remote_ip.is_empty() || remote_port == 0 {
+            None
+        } else {
+            Some(Self::resolve(&format!("{}:{}", remote_ip, remote_port)).await?)
+        };
+
+        let (shutdown_signal, _) = broadcast::channel(1);
+        let (tx_recv, rx_recv) = mpsc::channel(100);
+
+        let message_manager = Arc::new(MessageManager::from_global()?);
+
+        let channel = Self {
+            channeltype: "udp".to_string(),
+            channelid: "udp".to_string() + &Uuid::new_v4().to_string(),
+            channel_name: "UDP".to_string() + &local_addr.to_string(),
+            local_addr,
+            default_remote,
+            server_mode,
+            multicast,
+            socket: Arc::new(socket),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            last_peer: Arc::new(Mutex::new(None)),
+            shutdown_signal,
+            rx_recv: Arc::new(Mutex::new(rx_recv)),
+            message_manager,
+            correlator: Correlator::new(),
+        };
+        let _ = channel
+            .message_manager
+            .register_channel(&channel.channelid)
+            .await;
+
+        // 启动接收任务
+        let channel_recv = channel.clone();
+        tokio::spawn(async move {
+            channel_recv.receive_task(tx_recv).await;
+        });
+
+        channel.on_statechange(ChannelState::Connected).await?;
+        Ok(channel)
+    }
+
+    fn apply_multicast(
+        socket: &std::net::UdpSocket,
+        multicast: &UdpMulticast,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let interface = multicast.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        for group in &multicast.groups {
+            if !group.is_multicast() {
+                return Err(format!("{} 不是组播地址", group).into());
+            }
+            socket
+                .join_multicast_v4(group, &interface)
+                .map_err(|e| format!("加入组播组 {} 失败: {}", group, e))?;
+        }
+        if let Some(interface) = multicast.interface {
+            SockRef::from(socket).set_multicast_if_v4(&interface)?;
+        }
+        if let Some(ttl) = multicast.ttl {
+            socket.set_multicast_ttl_v4(ttl)?;
+        }
+        if let Some(loopback) = multicast.loopback {
+            socket.set_multicast_loop_v4(loopback)?;
+        }
+        Ok(())
+    }
+
+    async fn resolve(address: &str) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
+        lookup_host(address)
+            .await?
+            .next()
+            .ok_or_else(|| format!("无法解析地址: {}", address).into())
+    }
+
+    // 确定发送目标：指定的 clientid > 服务端模式下最近的对端 > 默认远端
+    async fn target(
+        &self,
+        clientid: Option<String>,
+    ) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
+        if let Some(clientid) = clientid.filter(|c| !c.is_empty()) {
+            return Self::resolve(&clientid).await;
+        }
+        let last_peer = *self.last_peer.lock().await;
+        let target = if self.server_mode {
+            last_peer.or(self.default_remote)
+        } else {
+            self.default_remote.or(last_peer)
+        };
+        target.ok_or_else(|| "UDP 通道没有可发送的目标地址".into())
+    }
+
+    fn peer_metadata(peer: &SocketAddr) -> Option<HashMap<String, String>> {
+        Some(HashMap::from([("peer".to_string(), peer.to_string())]))
+    }
+
+    // 接收任务：记录收到的数据报并维护对端列表
+    async fn receive_task(self, tx_recv: mpsc::Sender<Datagram>) {
+        let mut shutdown_receiver = self.shutdown_signal.subscribe();
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let mut retry_delay = RECV_RETRY_MIN;
+        loop {
+            tokio::select! {
+                _ = shutdown_receiver.recv() => {
+                    println!("UdpChannel receive task received shutdown signal");
+                    break;
+                }
+                result = self.socket.recv_from(&mut buffer) => {
+                    let (n, peer) = match result {
+                        Ok(result) => result,
+                        // Windows 上对端端口不可达时会返回 ConnectionReset，忽略后继续接收
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                        Err(e) => {
+                            // 其他错误等待一段时间再重试，避免持续出错时空转
+                            eprintln!("UdpChannel recv error: {:?}, retry in {:?}", e, retry_delay);
+                            tokio::select! {
+                                _ = shutdown_receiver.recv() => break,
+                                _ = sleep(retry_delay) => {}
+                            }
+                            retry_delay = (retry_delay * 2).min(RECV_RETRY_MAX);
+                            continue;
+                        }
+                    };
+                    retry_delay = RECV_RETRY_MIN;
+                    let data = buffer[..n].to_vec();
+                    self.track_peer(peer).await;
+
+                    let message = Message::new(serde_json::json!({ "data": data }));
+                    match timeout(Duration::from_secs(1), self.message_manager.record_message(
+                        &self.channeltype,
+                        &self.channelid,
+                        &self.channel_name,
+                        &message,
+                        MessageDirection::Received,
+                        Self::peer_metadata(&peer),
+                    )).await {
+                        Ok(Err(e)) => eprintln!("Error recording message: {:?}, but continuing...", e),
+                        Err(e) => eprintln!("Timeout recording message: {:?}, but continuing...", e),
+                        Ok(Ok(())) => {}
+                    }
+
+                    // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+                    let Some(data) = self.correlator.offer(data) else {
+                        continue;
+                    };
+                    if let Err(e) = tx_recv.try_send((data, peer)) {
+                        eprintln!("UdpChannel queue full, dropped datagram: {:?}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn track_peer(&self, peer: SocketAddr) {
+        *self.last_peer.lock().await = Some(peer);
+        let is_new = self
+            .peers
+            .lock()
+            .await
+            .insert(peer, chrono::Utc::now().timestamp_millis())
+            .is_none();
+        if !is_new {
+            return;
+        }
+
+        let peer_info = serde_json::json!({
+            "channel": "udp",
+            "channelId": self.channelid.clone(),
+            "eventType": "peerDiscovered",
+            "clientId": peer.to_string(),
+            "ip": peer.ip().to_string(),
+            "port": peer.port()
+        });
+        if let Ok(event_payload) = serde_json::to_string(&peer_info) {
+            if let Err(e) = emit_event("udp-peer-event", event_payload) {
+                eprintln!("发送 UDP 对端事件失败: {:?}", e);
+            }
+        }
+    }
+
+    // 从消息中提取要发送的字节：data 为数组时按字节发送，否则发送 JSON
+    fn message_bytes(message: &Message) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let content = message.get_content();
+        match content.get("data") {
+            Some(serde_json::Value::Array(arr)) => Ok(arr
+                .iter()
+                .filter_map(|item| item.as_u64())
+                .map(|byte| byte as u8)
+                .collect()),
+            Some(data) => Ok(serde_json::to_vec(data)?),
+            None => Ok(serde_json::to_vec(content)?),
+        }
+    }
+}
+
+#[async_trait]
+impl CommunicationChannel for UdpChannel {
+    async fn send(
+        &self,
+        message: &Message,
+        clientid: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = Self::message_bytes(message)?;
+        let target = self.target(clientid).await?;
+        self.socket.send_to(&data, target).await?;
+        println!("UdpChannel sent {} bytes to {}", data.len(), target);
+
+        self.message_manager
+            .record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &Message::new(serde_json::json!({ "data": data })),
+                MessageDirection::Sent,
+                Self::peer_metadata(&target),
+            )
+            .await
+    }
+
+    async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let (data, peer) = self
+            .rx_recv
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or("UDP channel closed")?;
+        Ok(Message::new(serde_json::json!({
+            "data": data,
+            "peer": peer.to_string(),
+        })))
+    }
+
+    async fn send_and_wait(
+        &self,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("UdpChannel closing...");
+        let _ = self.shutdown_signal.send(());
+        let interface = self.multicast.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        for group in &self.multicast.groups {
+            let _ = self.socket.leave_multicast_v4(*group, interface);
+        }
+        self.message_manager
+            .unregister_channel(&self.channelid)
+            .await;
+        self.on_statechange(ChannelState::Disconnected).await
+    }
+
+    async fn on_statechange(
+        &self,
+        state: ChannelState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = serde_json::json!({
+            "ip": self.local_addr.ip().to_string(),
+            "port": self.local_addr.port(),
+            "remote": self.default_remote.map(|addr| addr.to_string()),
+            "server": self.server_mode,
+            "multicast": self.multicast.groups,
+        });
+        let reason = match state {
+            ChannelState::Connected => "UDP 通道已打开",
+            ChannelState::Disconnected | ChannelState::Reconnecting => "UDP 通道已关闭",
+        };
+        let payload = serde_json::json!({
+            "channeltype": "udp",
+            "channelId": self.channelid,
+            "state": state,
+            "data": data,
+            "reason": reason,
+        });
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
+        Ok(())
+    }
+
+    fn get_channel_id(&self) -> String {
+        self.channelid.clone()
+    }
+
+    async fn subscribe_topic(
+        &self,
+        _topic: &str,
+        _qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("UDP channel does not support topic subscription".into())
+    }
+
+    async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("UDP channel does not support topic unsubscription".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn recv(channel: &UdpChannel) -> (Vec<u8>, String) {
+        let message = timeout(Duration::from_secs(2), channel.receive())
+            .await
+            .expect("接收超时")
+            .unwrap();
+        let content = message.get_content();
+        let data = content["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b.as_u64().unwrap() as u8)
+            .collect();
+        (data, content["peer"].as_str().unwrap().to_string())
+    }
+
+    fn message(data: &[u8]) -> Message {
+        Message::new(serde_json::json!({ "data": data }))
+    }
+
+    #[tokio::test]
+    async fn loopback_send_and_reply() {
+        let server = UdpChannel::new("127.0.0.1", 0, "", 0, true, Default::default())
+            .await
+            .unwrap();
+        let port = server.local_addr.port();
+        let client = UdpChannel::new("127.0.0.1", 0, "127.0.0.1", port, false, Default::default())
+            .await
+            .unwrap();
+
+        client.send(&message(&[0x68, 0x16]), None).await.unwrap();
+        assert_eq!(
+            recv(&server).await,
+            (vec![0x68, 0x16], client.local_addr.to_string())
+        );
+        // 服务端模式下不指定目标时回复最近的对端
+        server.send(&message(&[0x01]), None).await.unwrap();
+        assert_eq!(
+            recv(&client).await,
+            (vec![0x01], server.local_addr.to_string())
+        );
+        assert!(server.peers.lock().await.contains_key(&client.local_addr));
+
+        server.close().await.unwrap();
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn joins_multicast_group() {
+        let (_, options) = crate::combridage::channel_from_params(
+            "udp",
+            &serde_json::json!({
+                "ip": "0.0.0.0",
+                "port": 0,
+                "multicast": { "groups": ["239.255.42.1"], "interface": "127.0.0.1", "loopback": true },
+            }),
+        )
+        .unwrap();
+        let multicast = options.multicast;
+        assert_eq!(multicast.ttl, None);
+        let receiver = UdpChannel::new("0.0.0.0", 0, "", 0, true, multicast.clone())
+            .await
+            .unwrap();
+        let sender = UdpChannel::new("127.0.0.1", 0, "", 0, false, multicast)
+            .await
+            .unwrap();
+
+        let group = format!("239.255.42.1:{}", receiver.local_addr.port());
+        sender.send(&message(&[0x68]), Some(group)).await.unwrap();
+        assert_eq!(recv(&receiver).await.0, [0x68]);
+
+        receiver.close().await.unwrap();
+        sender.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_non_multicast_group() {
+        let multicast = UdpMulticast {
+            groups: vec![Ipv4Addr::new(192, 168, 1, 1)],
+            ..Default::default()
+        };
+        assert!(UdpChannel::new("0.0.0.0", 0, "", 0, true, multicast)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,600 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::CommunicationChannel;
+use crate::combridage::{ChannelState, Message};
+use crate::global::emit_event;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::{sleep_until, Duration, Instant};
+use uuid::Uuid;
+
+// 按名称打开的虚拟链路，两端都关闭后移除
+static VIRTUAL_LINKS: Lazy<std::sync::Mutex<HashMap<String, Arc<VirtualLink>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// 虚拟链路的一端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VirtualEnd {
+    #[default]
+    A,
+    B,
+}
+
+impl VirtualEnd {
+    fn index(self) -> usize {
+        match self {
+            VirtualEnd::A => 0,
+            VirtualEnd::B => 1,
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            VirtualEnd::A => VirtualEnd::B,
+            VirtualEnd::B => VirtualEnd::A,
+        }
+    }
+}
+
+/// 对一端发出的数据施加的损伤，默认不做任何处理
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualImpairment {
+    /// 固定延迟（毫秒）
+    pub latency_ms: u64,
+    /// 在固定延迟上随机增加 0 ~ jitter_ms 毫秒，数据仍按发送顺序到达
+    pub jitter_ms: u64,
+    /// 一次发送的数据整体丢失的概率，0.0 ~ 1.0
+    pub drop_rate: f64,
+    /// 每个字节随机翻转一位的概率，0.0 ~ 1.0
+    pub corrupt_rate: f64,
+    /// 把一次发送拆分为不超过该长度的多段分别到达，0 表示不拆分
+    pub chunk_size: usize,
+    /// 随机数种子，指定后每次运行的损伤结果相同
+    pub seed: Option<u64>,
+}
+
+// 损伤模拟用的伪随机数（xorshift64*），不需要密码学强度
+#[derive(Debug)]
+struct ImpairmentRng(u64);
+
+impl ImpairmentRng {
+    fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| Uuid::new_v4().as_u64_pair().0);
+        // 状态不能为 0
+        Self(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // [0, 1) 之间的随机数
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // [0, n] 之间的随机整数
+    fn up_to(&mut self, n: u64) -> u64 {
+        self.next_u64() % (n + 1)
+    }
+}
+
+// 发往一端的数据：(到达时间, 数据)
+type Delivery = (Instant, Vec<u8>);
+
+// 链路一端的状态
+#[derive(Debug)]
+struct LinkEnd {
+    // 本端发出数据的损伤设置
+    impairment: std::sync::Mutex<VirtualImpairment>,
+    rng: std::sync::Mutex<ImpairmentRng>,
+    // 发往本端的数据，本端打开时存在
+    inbox: std::sync::Mutex<Option<mpsc::UnboundedSender<Delivery>>>,
+    // 本端发出的最后一段数据的到达时间，保证按发送顺序到达
+    last_due: std::sync::Mutex<Instant>,
+}
+
+impl LinkEnd {
+    fn new() -> Self {
+        Self {
+            impairment: std::sync::Mutex::new(VirtualImpairment::default()),
+            rng: std::sync::Mutex::new(ImpairmentRng::new(None)),
+            inbox: std::sync::Mutex::new(None),
+            last_due: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn set_impairment(&self, impairment: VirtualImpairment) {
+        *self.rng.lock().unwrap() = ImpairmentRng::new(impairment.seed);
+        *self.impairment.lock().unwrap() = impairment;
+    }
+
+    // 按损伤设置处理一次发送，返回各段的延迟和数据；整体丢失时为空
+    fn impair(&self, data: &[u8]) -> Vec<(Duration, Vec<u8>)> {
+        let impairment = self.impairment.lock().unwrap().clone();
+        let mut rng = self.rng.lock().unwrap();
+        if impairment.drop_rate > 0.0 && rng.next_f64() < impairment.drop_rate {
+            return Vec::new();
+        }
+        let mut data = data.to_vec();
+        if impairment.corrupt_rate > 0.0 {
+            for byte in data.iter_mut() {
+                if rng.next_f64() < impairment.corrupt_rate {
+                    *byte ^= 1 << rng.up_to(7);
+                }
+            }
+        }
+        let chunk_size = if impairment.chunk_size == 0 {
+            data.len().max(1)
+        } else {
+            impairment.chunk_size
+        };
+        data.chunks(chunk_size)
+            .map(|chunk| {
+                let delay = impairment.latency_ms + rng.up_to(impairment.jitter_ms);
+                (Duration::from_millis(delay), chunk.to_vec())
+            })
+            .collect()
+    }
+}
+
+// 两端相连的虚拟链路
+#[derive(Debug)]
+struct VirtualLink {
+    name: String,
+    ends: [LinkEnd; 2],
+}
+
+impl VirtualLink {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ends: [LinkEnd::new(), LinkEnd::new()],
+        }
+    }
+}
+
+/// 进程内的虚拟通道，一端发送的数据经过可选的损伤后由另一端收到
+///
+/// 不需要串口、网络或 Broker，也不依赖界面，可用于测试以及没有硬件时调试。
+/// 通过 `pair` 创建一对互联的端点，或通过 `open` 按名称分别打开链路的两端。
+#[derive(Clone, Debug)]
+pub struct VirtualChannel {
+    channeltype: String,
+    channelid: String,
+    channel_name: String,
+    end: VirtualEnd,
+    link: Arc<VirtualLink>,
+    shutdown_signal: broadcast::Sender<()>,
+    rx_recv: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    message_manager: Arc<MessageManager>,
+    correlator: Correlator,
+}
+
+impl VirtualChannel {
+    /// 创建一对互联的端点，分别为 A 端和 B 端
+    pub async fn pair(
+        impairment_a: VirtualImpairment,
+        impairment_b: VirtualImpairment,
+    ) -> Result<(Self, Self), Box<dyn Error + Send + Sync>> {
+        let name = "pair-".to_string() + &Uuid::new_v4().to_string();
+        let link = Arc::new(VirtualLink::new(&name));
+        let a = Self::attach(link.clone(), VirtualEnd::A, impairment_a).await?;
+        let b = Self::attach(link, VirtualEnd::B, impairment_b).await?;
+        Ok((a, b))
+    }
+
+    /// 打开指定名称链路的一端，链路不存在时创建
+    pub async fn open(
+        name: &str,
+        end: VirtualEnd,
+        impairment: VirtualImpairment,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let link = VIRTUAL_LINKS
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(VirtualLink::new(name)))
+            .clone();
+        Self::attach(link, end, impairment).await
+    }
+
+    async fn attach(
+        link: Arc<VirtualLink>,
+        end: VirtualEnd,
+        impairment: VirtualImpairment,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (tx_inbox, rx_inbox) = mpsc::unbounded_channel();
+        {
+            let mut inbox = link.ends[end.index()].inbox.lock().unwrap();
+            if inbox.is_some() {
+                return Err(format!("虚拟通道 {} 的 {:?} 端已打开", link.name, end).into());
+            }
+            *inbox = Some(tx_inbox);
+        }
+        link.ends[end.index()].set_impairment(impairment);
+
+        let (shutdown_signal, _) = broadcast::channel(1);
+        let (tx_recv, rx_recv) = mpsc::channel(100);
+        let channel = Self {
+            channeltype: "virtual".to_string(),
+            channelid: "virtual".to_string() + &Uuid::new_v4().to_string(),
+            channel_name: format!("Virtual{}/{:?}", link.name, end),
+            end,
+            link,
+            shutdown_signal,
+            rx_recv: Arc::new(Mutex::new(rx_recv)),
+            message_manager: Arc::new(MessageManager::from_global()?),
+            correlator: Correlator::new(),
+        };
+        let _ = channel
+            .message_manager
+            .register_channel(&channel.channelid)
+            .await;
+
+        let channel_deliver = channel.clone();
+        tokio::spawn(async move {
+            channel_deliver.deliver_task(rx_inbox, tx_recv).await;
+        });
+
+        channel.on_statechange(ChannelState::Connected).await?;
+        Ok(channel)
+    }
+
+    // 按到达时间把对端发来的数据交给本端
+    async fn deliver_task(
+        &self,
+        mut inbox: mpsc::UnboundedReceiver<Delivery>,
+        tx_recv: mpsc::Sender<Vec<u8>>,
+    ) {
+        let mut shutdown = self.shutdown_signal.subscribe();
+        loop {
+            let (due, data) = tokio::select! {
+                _ = shutdown.recv() => return,
+                item = inbox.recv() => match item {
+                    Some(item) => item,
+                    None => return,
+                },
+            };
+            tokio::select! {
+                _ = shutdown.recv() => return,
+                _ = sleep_until(due) => {}
+            }
+
+            if let Err(e) = self
+                .message_manager
+                .record_message(
+                    &self.channeltype,
+                    &self.channelid,
+                    &self.channel_name,
+                    &Message::new(serde_json::json!({ "data": data })),
+                    MessageDirection::Received,
+                    None,
+                )
+                .await
+            {
+                eprintln!("记录消息失败: {:?}", e);
+            }
+            // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+            let Some(data) = self.correlator.offer(data) else {
+                continue;
+            };
+            if tx_recv.try_send(data).is_err() {
+                eprintln!("VirtualChannel receive queue full, message dropped");
+            }
+        }
+    }
+
+    /// 修改本端发出数据的损伤设置，立即对后续发送生效
+    pub fn set_impairment(&self, impairment: VirtualImpairment) {
+        self.link.ends[self.end.index()].set_impairment(impairment);
+    }
+
+    pub fn impairment(&self) -> VirtualImpairment {
+        self.link.ends[self.end.index()]
+            .impairment
+            .lock()
+            .unwrap()
+            .clone()
+    }
+
+    fn message_bytes(message: &Message) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match message.get_content()["data"].as_array() {
+            Some(arr) => Ok(arr
+                .iter()
+                .filter_map(|item| item.as_u64())
+                .map(|byte| byte as u8)
+                .collect()),
+            None => Err("Invalid data format".into()),
+        }
+    }
+}
+
+/// 修改按名称打开的虚拟链路某一端的损伤设置
+pub fn set_virtual_impairment(
+    name: &str,
+    end: VirtualEnd,
+    impairment: VirtualImpairment,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let links = VIRTUAL_LINKS.lock().unwrap();
+    let link = links
+        .get(name)
+        .ok_or_else(|| format!("虚拟通道 {} 不存在", name))?;
+    link.ends[end.index()].set_impairment(impairment);
+    Ok(())
+}
+
+#[async_trait]
+impl CommunicationChannel for VirtualChannel {
+    async fn send(
+        &self,
+        message: &Message,
+        _clientid: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = Self::message_bytes(message)?;
+        let inbox = self.link.ends[self.end.peer().index()]
+            .inbox
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| format!("虚拟通道 {} 的对端未打开", self.link.name))?;
+
+        self.message_manager
+            .record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &Message::new(serde_json::json!({ "data": data })),
+                MessageDirection::Sent,
+                None,
+            )
+            .await?;
+
+        // 丢失的数据同样视为发送成功
+        let local = &self.link.ends[self.end.index()];
+        let now = Instant::now();
+        for (delay, chunk) in local.impair(&data) {
+            let due = {
+                let mut last_due = local.last_due.lock().unwrap();
+                *last_due = (now + delay).max(*last_due);
+                *last_due
+            };
+            inbox
+                .send((due, chunk))
+                .map_err(|_| format!("虚拟通道 {} 的对端已关闭", self.link.name))?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        let data = self
+            .rx_recv
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or("Virtual channel closed")?;
+        Ok(Message::new(serde_json::json!({ "data": data })))
+    }
+
+    async fn send_and_wait(
+        &self,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _ = self.shutdown_signal.send(());
+        self.link.ends[self.end.index()]
+            .inbox
+            .lock()
+            .unwrap()
+            .take();
+        // 两端都关闭后释放链路名称
+        if self
+            .link
+            .ends
+            .iter()
+            .all(|end| end.inbox.lock().unwrap().is_none())
+        {
+            let mut links = VIRTUAL_LINKS.lock().unwrap();
+            if links
+                .get(&self.link.name)
+                .is_some_and(|link| Arc::ptr_eq(link, &self.link))
+            {
+                links.remove(&self.link.name);
+            }
+        }
+        self.message_manager
+            .unregister_channel(&self.channelid)
+            .await;
+        self.on_statechange(ChannelState::Disconnected).await
+    }
+
+    async fn on_statechange(
+        &self,
+        state: ChannelState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let reason = match state {
+            ChannelState::Connected => "虚拟通道已打开",
+            ChannelState::Disconnected | ChannelState::Reconnecting => "虚拟通道已关闭",
+        };
+        let payload = serde_json::json!({
+            "channeltype": "virtual",
+            "channelId": self.channelid,
+            "state": state,
+            "data": {
+                "name": self.link.name,
+                "end": self.end,
+                "impairment": self.impairment(),
+            },
+            "reason": reason,
+        });
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
+        Ok(())
+    }
+
+    fn get_channel_id(&self) -> String {
+        self.channelid.clone()
+    }
+
+    async fn subscribe_topic(
+        &self,
+        _topic: &str,
+        _qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("Virtual channel does not support topic subscription".into())
+    }
+
+    async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("Virtual channel does not support topic unsubscription".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    fn message(data: &[u8]) -> Message {
+        Message::new(serde_json::json!({ "data": data }))
+    }
+
+    // 等待下一段数据，一段时间内没有收到时返回 None
+    async fn recv(channel: &VirtualChannel) -> Option<Vec<u8>> {
+        let message = timeout(Duration::from_millis(200), channel.receive())
+            .await
+            .ok()?
+            .unwrap();
+        Some(VirtualChannel::message_bytes(&message).unwrap())
+    }
+
+    #[tokio::test]
+    async fn pair_round_trip() {
+        let (a, b) = VirtualChannel::pair(Default::default(), Default::default())
+            .await
+            .unwrap();
+        a.send(&message(&[0x68, 0x01, 0x16]), None).await.unwrap();
+        assert_eq!(recv(&b).await, Some(vec![0x68, 0x01, 0x16]));
+        b.send(&message(&[0x68, 0x81, 0x16]), None).await.unwrap();
+        assert_eq!(recv(&a).await, Some(vec![0x68, 0x81, 0x16]));
+
+        // 对端关闭后发送失败
+        b.close().await.unwrap();
+        assert!(a.send(&message(&[0x00]), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn drop_rate_loses_whole_sends() {
+        let lossy = VirtualImpairment {
+            drop_rate: 0.5,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let (a, b) = VirtualChannel::pair(lossy, Default::default())
+            .await
+            .unwrap();
+        for i in 0..100u8 {
+            a.send(&message(&[i, i]), None).await.unwrap();
+        }
+        let mut received = Vec::new();
+        while let Some(data) = recv(&b).await {
+            received.push(data);
+        }
+        // 每次发送要么完整到达，要么整体丢失，到达的仍保持发送顺序
+        assert!((20..80).contains(&received.len()), "{}", received.len());
+        assert!(received.iter().all(|data| data[0] == data[1]));
+        assert!(received.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+
+        a.set_impairment(VirtualImpairment {
+            drop_rate: 1.0,
+            ..Default::default()
+        });
+        a.send(&message(&[0xFF]), None).await.unwrap();
+        assert_eq!(recv(&b).await, None);
+    }
+
+    #[tokio::test]
+    async fn chunk_size_splits_sends_in_order() {
+        let split = VirtualImpairment {
+            chunk_size: 2,
+            latency_ms: 5,
+            jitter_ms: 20,
+            seed: Some(1),
+            ..Default::default()
+        };
+        let (a, b) = VirtualChannel::pair(split, Default::default())
+            .await
+            .unwrap();
+        a.send(&message(&[1, 2, 3, 4, 5]), None).await.unwrap();
+        a.send(&message(&[6, 7]), None).await.unwrap();
+        // 随机延迟不会打乱到达顺序
+        for expected in [vec![1, 2], vec![3, 4], vec![5], vec![6, 7]] {
+            assert_eq!(recv(&b).await, Some(expected));
+        }
+        assert_eq!(recv(&b).await, None);
+    }
+
+    #[tokio::test]
+    async fn corruption_flips_one_bit_per_byte_reproducibly() {
+        let corrupt = VirtualImpairment {
+            corrupt_rate: 1.0,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let sent = [0x68, 0x00, 0xFF, 0x16];
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let (a, b) = VirtualChannel::pair(corrupt.clone(), Default::default())
+                .await
+                .unwrap();
+            a.send(&message(&sent), None).await.unwrap();
+            let received = recv(&b).await.unwrap();
+            assert_eq!(received.len(), sent.len());
+            assert!(sent
+                .iter()
+                .zip(&received)
+                .all(|(x, y)| (x ^ y).count_ones() == 1));
+            results.push(received);
+        }
+        // 相同的种子得到相同的损伤结果
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[tokio::test]
+    async fn named_link_rejects_second_open_of_same_end() {
+        let name = "test-".to_string() + &Uuid::new_v4().to_string();
+        let a = VirtualChannel::open(&name, VirtualEnd::A, Default::default())
+            .await
+            .unwrap();
+        assert!(
+            VirtualChannel::open(&name, VirtualEnd::A, Default::default())
+                .await
+                .is_err()
+        );
+        let b = VirtualChannel::open(&name, VirtualEnd::B, Default::default())
+            .await
+            .unwrap();
+        b.send(&message(&[0x10]), None).await.unwrap();
+        assert_eq!(recv(&a).await, Some(vec![0x10]));
+    }
+}
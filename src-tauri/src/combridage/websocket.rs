@@ -0,0 +1,410 @@
+use crate::basefunc::frame_fun::FrameFun;
+use crate::combridage::tls::TcpIoStream;
+use crate::combridage::Message;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, timeout, Duration, Instant, Interval};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::WebSocketStream;
+
+// 单条消息（含分片）的最大长度
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+pub(crate) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 一条完整的 WebSocket 消息，分片已合并
+pub(crate) type WsMessage = tokio_tungstenite::tungstenite::Message;
+
+type WsStream = WebSocketStream<TcpIoStream>;
+
+/// 报文在 WebSocket 上的承载方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebSocketMode {
+    /// 二进制帧，原样承载报文
+    #[default]
+    Binary,
+    /// 文本帧，报文为空格分隔的十六进制字符串，如 "68 01 16"
+    Hex,
+}
+
+impl WebSocketMode {
+    pub(crate) fn encode(&self, data: &[u8]) -> WsMessage {
+        match self {
+            WebSocketMode::Binary => WsMessage::Binary(data.to_vec()),
+            WebSocketMode::Hex => WsMessage::Text(FrameFun::get_data_str_with_space(data)),
+        }
+    }
+
+    /// 取出消息承载的报文；十六进制模式下文本不是合法的十六进制时按 UTF-8 字节返回
+    pub(crate) fn decode(&self, message: WsMessage) -> Option<Vec<u8>> {
+        match message {
+            WsMessage::Binary(data) => Some(data),
+            WsMessage::Text(text) => {
+                if *self == WebSocketMode::Hex {
+                    if let Ok(data) = FrameFun::decode_hex_str(&text) {
+                        return Some(data);
+                    }
+                }
+                Some(text.into_bytes())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// ws:// 或 wss:// 地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WsUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+    pub secure: bool,
+}
+
+impl WsUrl {
+    pub(crate) fn parse(url: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("wss://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("ws://") {
+            (false, rest)
+        } else {
+            return Err(format!("WebSocket 地址须以 ws:// 或 wss:// 开头: {}", url).into());
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        let default_port = if secure { 443 } else { 80 };
+        // IPv6 地址写作 [::1]:8080
+        let (host, port) = if let Some(stripped) = authority.strip_prefix('[') {
+            let (host, port) = stripped
+                .split_once(']')
+                .ok_or_else(|| format!("WebSocket 地址格式错误: {}", url))?;
+            (host, port.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(format!("WebSocket 地址缺少主机: {}", url).into());
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| format!("WebSocket 端口无效: {}", url))?,
+            None => default_port,
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            secure,
+        })
+    }
+
+    // 握手请求中的 Host 头，默认端口可省略
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == if self.secure { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    // 握手请求使用的完整地址
+    fn request_url(&self) -> String {
+        let scheme = if self.secure { "wss" } else { "ws" };
+        format!("{}://{}{}", scheme, self.host_header(), self.path)
+    }
+}
+
+fn config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    }
+}
+
+/// 客户端握手，TLS 已在 `stream` 上完成
+pub(crate) async fn client_handshake(
+    stream: TcpIoStream,
+    url: &WsUrl,
+) -> Result<WsStream, Box<dyn Error + Send + Sync>> {
+    let (stream, _) =
+        tokio_tungstenite::client_async_with_config(url.request_url(), stream, Some(config()))
+            .await
+            .map_err(|e| format!("WebSocket 握手失败: {}", e))?;
+    Ok(stream)
+}
+
+/// 服务端握手，`path` 为 "/" 时接受任意路径；返回请求的路径
+pub(crate) async fn server_handshake(
+    stream: TcpIoStream,
+    path: &str,
+) -> Result<(String, WsStream), Box<dyn Error + Send + Sync>> {
+    let mut request_path = String::new();
+    // 回调的错误类型由 tungstenite 规定
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        request_path = request.uri().to_string();
+        if path != "/" && request.uri().path() != path {
+            let mut rejection = ErrorResponse::new(None);
+            *rejection.status_mut() = StatusCode::NOT_FOUND;
+            return Err(rejection);
+        }
+        Ok(response)
+    };
+    let result =
+        tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(config())).await;
+    let stream = result.map_err(|e| format!("拒绝 WebSocket 握手 {}: {}", request_path, e))?;
+    Ok((request_path, stream))
+}
+
+/// 带关闭码的关闭帧
+pub(crate) fn close_message(code: CloseCode) -> WsMessage {
+    WsMessage::Close(Some(CloseFrame {
+        code,
+        reason: "".into(),
+    }))
+}
+
+/// 拆分为读取端和写入任务，`ping_interval` 为 0 时不发送 Ping
+pub(crate) fn split(
+    stream: WsStream,
+    mode: WebSocketMode,
+    ping_interval: u16,
+) -> (WsReader, mpsc::Sender<WsMessage>) {
+    let (sink, stream) = stream.split();
+    (
+        WsReader::new(stream, mode, ping_interval),
+        spawn_writer(sink),
+    )
+}
+
+/// 取出消息中的报文：data 为字节数组或十六进制字符串
+pub(crate) fn payload_bytes(message: &Message) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let data = &message.get_content()["data"];
+    if let Some(array) = data.as_array() {
+        return Ok(array
+            .iter()
+            .filter_map(|v| v.as_u64())
+            .map(|v| v as u8)
+            .collect());
+    }
+    if let Some(text) = data.as_str() {
+        return FrameFun::decode_hex_str(text)
+            .map_err(|e| format!("报文不是合法的十六进制: {}", e).into());
+    }
+    Err("Invalid data format".into())
+}
+
+// 启动写入任务，通过返回的发送端写入消息；发送关闭帧或所有发送端释放后结束
+fn spawn_writer(mut sink: SplitSink<WsStream, WsMessage>) -> mpsc::Sender<WsMessage> {
+    let (tx, mut rx) = mpsc::channel::<WsMessage>(100);
+    tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let closing = message.is_close();
+            if let Err(e) = sink.send(message).await {
+                eprintln!("WebSocket 写入失败: {:?}", e);
+                return;
+            }
+            if closing {
+                return;
+            }
+        }
+        // 对端可能已经断开，忽略错误
+        let _ = timeout(
+            Duration::from_secs(1),
+            sink.send(close_message(CloseCode::Normal)),
+        )
+        .await;
+    });
+    tx
+}
+
+/// 读取端：处理关闭帧，按设定间隔发送 Ping 检测对端是否存活；Ping 的应答和
+/// 关闭帧的回应由 tungstenite 自动发送
+pub(crate) struct WsReader {
+    stream: SplitStream<WsStream>,
+    mode: WebSocketMode,
+    keep_alive: Option<Interval>,
+    // 已发送 Ping 之后还没有收到任何数据
+    awaiting_pong: bool,
+}
+
+impl WsReader {
+    fn new(stream: SplitStream<WsStream>, mode: WebSocketMode, ping_interval: u16) -> Self {
+        let keep_alive = (ping_interval > 0).then(|| {
+            let period = Duration::from_secs(ping_interval as u64);
+            interval_at(Instant::now() + period, period)
+        });
+        Self {
+            stream,
+            mode,
+            keep_alive,
+            awaiting_pong: false,
+        }
+    }
+
+    /// 读取下一条报文，对端关闭连接时返回 None，心跳超时返回错误
+    pub(crate) async fn next(
+        &mut self,
+        writer: &mpsc::Sender<WsMessage>,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        loop {
+            let keep_alive = async {
+                match self.keep_alive.as_mut() {
+                    Some(interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                message = self.stream.next() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        None
+                        | Some(Err(WsError::ConnectionClosed))
+                        | Some(Err(WsError::AlreadyClosed)) => return Ok(None),
+                        Some(Err(e)) => return Err(e.into()),
+                    };
+                    // 收到任何帧都说明对端存活
+                    self.awaiting_pong = false;
+                    match message {
+                        WsMessage::Close(_) => return Ok(None),
+                        message => {
+                            if let Some(data) = self.mode.decode(message) {
+                                return Ok(Some(data));
+                            }
+                        }
+                    }
+                }
+                _ = keep_alive => {
+                    if self.awaiting_pong {
+                        return Err("WebSocket 心跳超时".into());
+                    }
+                    self.awaiting_pong = true;
+                    writer
+                        .send(WsMessage::Ping(Vec::new()))
+                        .await
+                        .map_err(|_| "WebSocket 连接已关闭")?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    type Endpoint = (WsReader, mpsc::Sender<WsMessage>);
+
+    // 在本地端口上完成一次握手，返回服务端（含请求路径）和客户端的结果
+    async fn handshake(
+        server_path: &str,
+        request_path: &str,
+        mode: WebSocketMode,
+    ) -> (Result<(String, Endpoint), String>, Result<Endpoint, String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server_path = server_path.to_string();
+        let accepted = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (path, stream) = server_handshake(Box::new(stream), &server_path)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok((path, split(stream, mode, 0)))
+        });
+
+        let url = WsUrl::parse(&format!("ws://127.0.0.1:{}{}", port, request_path)).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let connected = client_handshake(Box::new(stream), &url)
+            .await
+            .map(|stream| split(stream, mode, 0))
+            .map_err(|e| e.to_string());
+        (accepted.await.unwrap(), connected)
+    }
+
+    #[tokio::test]
+    async fn exchanges_hex_and_binary_messages() {
+        let (accepted, connected) = handshake("/ws", "/ws?id=1", WebSocketMode::Hex).await;
+        let (path, (mut server_reader, server_writer)) = accepted.unwrap();
+        let (mut client_reader, client_writer) = connected.unwrap();
+        assert_eq!(path, "/ws?id=1");
+
+        // 十六进制模式下文本帧按十六进制解析，二进制帧原样承载
+        client_writer
+            .send(WsMessage::Text("68 01 16".to_string()))
+            .await
+            .unwrap();
+        client_writer
+            .send(WsMessage::Binary(vec![0x10, 0x20]))
+            .await
+            .unwrap();
+        assert_eq!(
+            server_reader.next(&server_writer).await.unwrap(),
+            Some(vec![0x68, 0x01, 0x16])
+        );
+        assert_eq!(
+            server_reader.next(&server_writer).await.unwrap(),
+            Some(vec![0x10, 0x20])
+        );
+
+        // 服务端编码为空格分隔的十六进制文本
+        let reply = WebSocketMode::Hex.encode(&[0xAA, 0x55]);
+        assert_eq!(reply, WsMessage::Text("AA 55".to_string()));
+        server_writer.send(reply).await.unwrap();
+        assert_eq!(
+            client_reader.next(&client_writer).await.unwrap(),
+            Some(vec![0xAA, 0x55])
+        );
+    }
+
+    #[tokio::test]
+    async fn close_frame_ends_peer_reader() {
+        let (accepted, connected) = handshake("/", "/any", WebSocketMode::Binary).await;
+        let (_, (mut server_reader, server_writer)) = accepted.unwrap();
+        let (_, client_writer) = connected.unwrap();
+
+        client_writer
+            .send(close_message(CloseCode::Normal))
+            .await
+            .unwrap();
+        assert_eq!(server_reader.next(&server_writer).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_path() {
+        let (accepted, connected) = handshake("/ws", "/other", WebSocketMode::Binary).await;
+        assert!(matches!(&accepted, Err(e) if e.contains("/other")));
+        assert!(matches!(&connected, Err(e) if e.contains("404")));
+    }
+
+    #[test]
+    fn parses_urls() {
+        let url = WsUrl::parse("wss://[::1]/meter").unwrap();
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.request_url(), "wss://[::1]/meter");
+
+        let url = WsUrl::parse("ws://device:8080").unwrap();
+        assert_eq!(url.request_url(), "ws://device:8080/");
+        assert!(WsUrl::parse("http://device").is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::reconnect::{OutageBuffer, ReconnectPolicy};
+use crate::combridage::tls::{self, TlsClient, TlsConfig, TlsSession};
+use crate::combridage::websocket::{
+    self, WebSocketMode, WsMessage, WsReader, WsUrl, HANDSHAKE_TIMEOUT,
+};
+use crate::combridage::CommunicationChannel;
+use crate::combridage::{ChannelState, Message};
+use crate::global::emit_event;
+use async_trait::async_trait;
+use serde_json;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct WebSocketClientChannel {
+    channeltype: String,
+    channelid: String,
+    channel_name: String,
+    url: WsUrl,
+    mode: WebSocketMode,
+    ping_interval: u16,
+    tls: Option<TlsClient>,
+    // 当前连接协商出的 TLS 参数，重连后更新
+    tls_session: Arc<std::sync::Mutex<Option<TlsSession>>>,
+    // 当前连接的写入任务，断线期间为 None
+    writer: Arc<Mutex<Option<mpsc::Sender<WsMessage>>>>,
+    connected: Arc<watch::Sender<bool>>,
+    reconnect: ReconnectPolicy,
+    shutdown_signal: broadcast::Sender<()>,
+    tx_send: mpsc::Sender<Vec<u8>>,
+    rx_recv: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
+    correlator: Correlator,
+}
+
+impl WebSocketClientChannel {
+    /// 连接 ws:// 或 wss:// 地址，是否使用 TLS 由地址决定，`tls` 提供证书等参数
+    pub async fn new(
+        url: &str,
+        mode: WebSocketMode,
+        ping_interval: u16,
+        reconnect: ReconnectPolicy,
+        tls: TlsConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let url = WsUrl::parse(url)?;
+        let tls = TlsConfig {
+            enabled: url.secure,
+            ..tls
+        }
+        .client(&url.host)?;
+        let (reader, writer, tls_session) =
+            Self::open(&url, tls.as_ref(), mode, ping_interval).await?;
+
+        let (shutdown_signal, _) = broadcast::channel(1);
+        let (tx_send, rx_send) = mpsc::channel(100); // 发送队列
+        let (tx_recv, rx_recv) = mpsc::channel(100); // 接收队列
+
+        let address = format!("{}:{}{}", url.host, url.port, url.path);
+        let channel = Self {
+            channeltype: "websocketclient".to_string(),
+            channelid: "websocketclient".to_string() + &Uuid::new_v4().to_string(),
+            channel_name: "WS".to_string() + &address,
+            url,
+            mode,
+            ping_interval,
+            tls,
+            tls_session: Arc::new(std::sync::Mutex::new(tls_session)),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            connected: Arc::new(watch::channel(true).0),
+            reconnect,
+            shutdown_signal,
+            tx_send,
+            rx_recv: Arc::new(Mutex::new(rx_recv)),
+            correlator: Correlator::new(),
+        };
+
+        let message_manager = MessageManager::from_global()?;
+        let _ = message_manager.register_channel(&address).await;
+
+        // 启动发送任务
+        let channel_send = channel.clone();
+        let message_manager_send = message_manager.clone();
+        tokio::spawn(async move {
+            if let Err(e) = channel_send.send_task(rx_send, message_manager_send).await {
+                eprintln!("WebSocket send task error: {}", e);
+            }
+        });
+
+        // 启动接收任务
+        let channel_recv = channel.clone();
+        tokio::spawn(async move {
+            channel_recv
+                .receive_task(reader, tx_recv, message_manager)
+                .await;
+        });
+
+        if let Err(e) = channel.on_statechange(ChannelState::Connected).await {
+            eprintln!("Failed to send connect event: {:?}", e);
+        }
+        Ok(channel)
+    }
+
+    // 建立 TCP 连接、完成 TLS 和 WebSocket 握手，返回读取端、写入任务和 TLS 参数
+    async fn open(
+        url: &WsUrl,
+        tls: Option<&TlsClient>,
+        mode: WebSocketMode,
+        ping_interval: u16,
+    ) -> Result<(WsReader, mpsc::Sender<WsMessage>, Option<TlsSession>), Box<dyn Error + Send + Sync>>
+    {
+        let stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        stream.set_nodelay(true)?;
+        let (stream, tls_session) = tls::connect(stream, tls).await?;
+        let stream = timeout(HANDSHAKE_TIMEOUT, websocket::client_handshake(stream, url))
+            .await
+            .map_err(|_| "WebSocket 握手超时")??;
+        println!(
+            "WebSocketClientChannel connected to {}:{}{}",
+            url.host, url.port, url.path
+        );
+
+        let (reader, writer) = websocket::split(stream, mode, ping_interval);
+        Ok((reader, writer, tls_session))
+    }
+
+    // 连接断开后按策略重连，成功时返回新的读取端；放弃或收到关闭信号时返回 None
+    async fn reconnect_stream(&self, shutdown: &mut broadcast::Receiver<()>) -> Option<WsReader> {
+        *self.writer.lock().await = None;
+        self.connected.send_replace(false);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let Some(delay) = self.reconnect.backoff(attempt) else {
+                let _ = self.on_statechange(ChannelState::Disconnected).await;
+                return None;
+            };
+            let _ = self.on_statechange(ChannelState::Reconnecting).await;
+            println!(
+                "WebSocketClientChannel reconnecting in {:?} (attempt {})",
+                delay, attempt
+            );
+            tokio::select! {
+                _ = shutdown.recv() => return None,
+                _ = sleep(delay) => {}
+            }
+
+            match Self::open(&self.url, self.tls.as_ref(), self.mode, self.ping_interval).await {
+                Ok((reader, writer, tls_session)) => {
+                    *self.writer.lock().await = Some(writer);
+                    *self.tls_session.lock().unwrap() = tls_session;
+                    self.connected.send_replace(true);
+                    let _ = self.on_statechange(ChannelState::Connected).await;
+                    return Some(reader);
+                }
+                Err(e) => eprintln!("WebSocketClientChannel reconnect failed: {:?}", e),
+            }
+        }
+    }
+
+    // 发送任务，断线期间按策略缓存，重连后补发
+    async fn send_task(
+        &self,
+        mut rx_send: mpsc::Receiver<Vec<u8>>,
+        message_manager: MessageManager,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut connected = self.connected.subscribe();
+        let mut shutdown = self.shutdown_signal.subscribe();
+        let mut pending = OutageBuffer::new(self.reconnect.max_buffered);
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => return Ok(()),
+                changed = connected.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                    if !*connected.borrow_and_update() || pending.is_empty() {
+                        continue;
+                    }
+                    let (buffered, dropped) = pending.drain();
+                    if dropped > 0 {
+                        eprintln!("WebSocketClientChannel dropped {} buffered messages during outage", dropped);
+                    }
+                    for data in buffered {
+                        self.write_data(data, &message_manager, &mut pending).await;
+                    }
+                }
+                data = rx_send.recv() => {
+                    let Some(data) = data else {
+                        return Ok(());
+                    };
+                    self.write_data(data, &message_manager, &mut pending).await;
+                }
+            }
+        }
+    }
+
+    // 写入一条报文并记录；未连接时按策略缓存
+    async fn write_data(
+        &self,
+        data: Vec<u8>,
+        message_manager: &MessageManager,
+        pending: &mut OutageBuffer<Vec<u8>>,
+    ) {
+        let writer = self.writer.lock().await.clone();
+        let sent = match writer {
+            Some(writer) => writer.send(self.mode.encode(&data)).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            if self.reconnect.buffers() {
+                pending.push(data);
+            } else {
+                eprintln!("WebSocketClientChannel dropped {} bytes", data.len());
+            }
+            return;
+        }
+
+        let message = Message::new(serde_json::json!({ "data": data }));
+        if let Err(e) = message_manager
+            .record_message(
+                &self.channeltype,
+                &self.channelid,
+                &self.channel_name,
+                &message,
+                MessageDirection::Sent,
+                None,
+            )
+            .await
+        {
+            eprintln!("Error recording message: {:?}", e);
+        }
+    }
+
+    // 接收任务，连接断开时按策略重连
+    async fn receive_task(
+        &self,
+        mut reader: WsReader,
+        tx_recv: mpsc::Sender<Vec<u8>>,
+        message_manager: MessageManager,
+    ) {
+        let mut shutdown = self.shutdown_signal.subscribe();
+        loop {
+            let Some(writer) = self.writer.lock().await.clone() else {
+                return;
+            };
+            let result = tokio::select! {
+                _ = shutdown.recv() => return,
+                result = reader.next(&writer) => result,
+            };
+            match result {
+                Ok(Some(data)) => {
+                    let message = Message::new(serde_json::json!({ "data": data }));
+                    if let Err(e) = timeout(
+                        Duration::from_secs(1),
+                        message_manager.record_message(
+                            &self.channeltype,
+                            &self.channelid,
+                            &self.channel_name,
+                            &message,
+                            MessageDirection::Received,
+                            None,
+                        ),
+                    )
+                    .await
+                    {
+                        eprintln!("Timeout recording received message: {:?}", e);
+                    }
+                    // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+                    let Some(data) = self.correlator.offer(data) else {
+                        continue;
+                    };
+                    if tx_recv.try_send(data).is_err() {
+                        eprintln!("WebSocketClientChannel receive queue full, message dropped");
+                    }
+                    continue;
+                }
+                Ok(None) => println!("WebSocketClientChannel connection closed by server"),
+                Err(e) => eprintln!("WebSocketClientChannel read error: {:?}", e),
+            }
+
+            // 连接已断开，未启用重连或放弃重连时结束任务
+            match self.reconnect_stream(&mut shutdown).await {
+                Some(new_reader) => reader = new_reader,
+                None => return,
+            }
+        }
+    }
+
+    pub async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _ = self.shutdown_signal.send(());
+        // 发送关闭帧，写入任务随后关闭连接
+        if let Some(writer) = self.writer.lock().await.take() {
+            let _ = writer
+                .send(websocket::close_message(CloseCode::Normal))
+                .await;
+        }
+        self.connected.send_replace(false);
+        self.on_statechange(ChannelState::Disconnected).await
+    }
+}
+
+#[async_trait]
+impl CommunicationChannel for WebSocketClientChannel {
+    async fn send(
+        &self,
+        message: &Message,
+        _clientid: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !*self.connected.borrow() && !self.reconnect.buffers() {
+            return Err("WebSocket 连接已断开".into());
+        }
+        let data = websocket::payload_bytes(message)?;
+        self.tx_send.send(data).await.map_err(|e| {
+            Box::<dyn Error + Send + Sync>::from(format!("Failed to send message: {:?}", e))
+        })
+    }
+
+    async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        match self.rx_recv.lock().await.recv().await {
+            Some(data) => Ok(Message::new(serde_json::json!({ "data": data }))),
+            None => Err("WebSocket channel closed".into()),
+        }
+    }
+
+    async fn send_and_wait(
+        &self,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.close().await
+    }
+
+    async fn on_statechange(
+        &self,
+        state: ChannelState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let reason = match state {
+            ChannelState::Connected => "The WebSocket client has connected",
+            ChannelState::Disconnected => "The WebSocket client has disconnected",
+            ChannelState::Reconnecting => "The WebSocket client is reconnecting",
+        };
+        let scheme = if self.url.secure { "wss" } else { "ws" };
+        let payload = serde_json::json!({
+            "channeltype": "websocketclient",
+            "channelId": self.channelid.clone(),
+            "state": state,
+            "data": {
+                "url": format!("{}://{}:{}{}", scheme, self.url.host, self.url.port, self.url.path),
+                "mode": self.mode,
+                "tls": self.tls_session.lock().unwrap().clone(),
+            },
+            "reason": reason,
+        });
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
+        Ok(())
+    }
+
+    fn get_channel_id(&self) -> String {
+        self.channelid.clone()
+    }
+
+    async fn subscribe_topic(
+        &self,
+        _topic: &str,
+        _qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("WebSocket client does not support topic subscription".into())
+    }
+
+    async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("WebSocket client does not support topic unsubscription".into())
+    }
+}
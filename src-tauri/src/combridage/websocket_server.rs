@@ -0,0 +1,364 @@
+use crate::combridage::correlation::Correlator;
+use crate::combridage::messagemanager::{MessageDirection, MessageManager};
+use crate::combridage::tls::{self, TlsConfig, TlsServer, TlsSession};
+use crate::combridage::websocket::{self, WebSocketMode, WsMessage, HANDSHAKE_TIMEOUT};
+use crate::combridage::CommunicationChannel;
+use crate::combridage::{ChannelState, Message};
+use crate::global::emit_event;
+use async_trait::async_trait;
+use serde_json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use uuid::Uuid;
+
+/// WebSocket 服务端上的一个客户端连接
+#[derive(Clone, Debug)]
+pub struct WebSocketClientOfServer {
+    channeltype: String,
+    channelid: String,
+    channel_name: String,
+    peer: SocketAddr,
+    // 握手请求的路径
+    path: String,
+    tls_session: Option<TlsSession>,
+    writer: mpsc::Sender<WsMessage>,
+}
+
+// 客户端收到的报文：(客户端 ID, 数据)
+type Received = (String, Vec<u8>);
+
+#[derive(Clone, Debug)]
+pub struct WebSocketServerChannel {
+    channeltype: String,
+    channelid: String,
+    // 接受握手的路径，"/" 表示任意路径
+    path: String,
+    mode: WebSocketMode,
+    ping_interval: u16,
+    clients: Arc<Mutex<HashMap<String, WebSocketClientOfServer>>>,
+    shutdown_signal: broadcast::Sender<()>,
+    message_manager: Arc<MessageManager>,
+    tls: Option<TlsServer>,
+    // 所有客户端收到的报文
+    tx_recv: mpsc::Sender<Received>,
+    rx_recv: Arc<Mutex<mpsc::Receiver<Received>>>,
+    correlator: Correlator,
+}
+
+impl WebSocketServerChannel {
+    pub async fn new(
+        ipaddr: &str,
+        port: u16,
+        path: &str,
+        mode: WebSocketMode,
+        ping_interval: u16,
+        tls: TlsConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let address = format!("{}:{}", ipaddr, port);
+        let tls = tls.server()?;
+        let listener = TcpListener::bind(&address).await?;
+        println!("WebSocketServerChannel listening on: {}", address);
+
+        let (shutdown_signal, _) = broadcast::channel(1);
+        let (tx_recv, rx_recv) = mpsc::channel(100);
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        };
+        let server = Self {
+            channeltype: "websocketserver".to_string(),
+            channelid: "websocketserver".to_string() + &Uuid::new_v4().to_string(),
+            path,
+            mode,
+            ping_interval,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            shutdown_signal,
+            message_manager: Arc::new(MessageManager::from_global()?),
+            tls,
+            tx_recv,
+            rx_recv: Arc::new(Mutex::new(rx_recv)),
+            correlator: Correlator::new(),
+        };
+
+        let server_clone = server.clone();
+        tokio::spawn(async move {
+            server_clone.accept_loop(listener).await;
+        });
+        Ok(server)
+    }
+
+    async fn accept_loop(&self, listener: TcpListener) {
+        let mut shutdown = self.shutdown_signal.subscribe();
+        loop {
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    println!("WebSocket accept loop received shutdown signal");
+                    // 释放监听端口
+                    return;
+                }
+                result = listener.accept() => match result {
+                    Ok((stream, addr)) => {
+                        println!("New WebSocket connection: {}", addr);
+                        // 握手可能较慢，不阻塞后续连接的接受
+                        let server = self.clone();
+                        tokio::spawn(async move {
+                            server.serve_client(stream, addr).await;
+                        });
+                    }
+                    Err(e) => eprintln!("WebSocket accept error: {:?}", e),
+                }
+            }
+        }
+    }
+
+    // 完成 TLS 和 WebSocket 握手后登记客户端，连接断开前持续接收
+    async fn serve_client(&self, stream: TcpStream, addr: SocketAddr) {
+        if let Err(e) = stream.set_nodelay(true) {
+            eprintln!("设置 TCP_NODELAY 失败: {} 错误: {:?}", addr, e);
+        }
+        let handshake = async {
+            let (stream, tls_session) = tls::accept(stream, self.tls.as_ref()).await?;
+            let (path, stream) = websocket::server_handshake(stream, &self.path).await?;
+            Ok::<_, Box<dyn Error + Send + Sync>>((stream, tls_session, path))
+        };
+        let (stream, tls_session, path) = match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => {
+                eprintln!("WebSocket 握手失败: {} 错误: {:?}", addr, e);
+                return;
+            }
+            Err(_) => {
+                eprintln!("WebSocket 握手超时: {}", addr);
+                return;
+            }
+        };
+
+        let (mut reader, writer) = websocket::split(stream, self.mode, self.ping_interval);
+        let client = WebSocketClientOfServer {
+            channeltype: self.channeltype.clone(),
+            channelid: "websocketserver".to_string() + &Uuid::new_v4().to_string(),
+            channel_name: "WS".to_string() + &addr.to_string(),
+            peer: addr,
+            path,
+            tls_session,
+            writer,
+        };
+        // 先订阅关闭信号，避免登记后、开始接收前错过关闭
+        let mut shutdown = self.shutdown_signal.subscribe();
+        self.clients
+            .lock()
+            .await
+            .insert(client.channelid.clone(), client.clone());
+        Self::emit_client_event("clientConnected", &client);
+
+        loop {
+            let result = tokio::select! {
+                _ = shutdown.recv() => {
+                    let _ = client.writer.send(websocket::close_message(CloseCode::Away)).await;
+                    break;
+                }
+                result = reader.next(&client.writer) => result,
+            };
+            match result {
+                Ok(Some(data)) => {
+                    let message = Message::new(serde_json::json!({ "data": data }));
+                    if let Err(e) = self
+                        .message_manager
+                        .record_message(
+                            &client.channeltype,
+                            &client.channelid,
+                            &client.channel_name,
+                            &message,
+                            MessageDirection::Received,
+                            None,
+                        )
+                        .await
+                    {
+                        eprintln!("记录消息失败: {:?}", e);
+                    }
+                    // 等待中的请求的应答交给对应请求，其余照常进入接收队列
+                    let Some(data) = self.correlator.offer(data) else {
+                        continue;
+                    };
+                    if self
+                        .tx_recv
+                        .try_send((client.channelid.clone(), data))
+                        .is_err()
+                    {
+                        eprintln!("WebSocketServerChannel receive queue full, message dropped");
+                    }
+                }
+                Ok(None) => {
+                    println!("WebSocket 客户端断开: {}", addr);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("WebSocket 客户端 {} 读取错误: {:?}", addr, e);
+                    break;
+                }
+            }
+        }
+
+        self.clients.lock().await.remove(&client.channelid);
+        Self::emit_client_event("clientDisconnected", &client);
+    }
+
+    fn emit_client_event(event_type: &str, client: &WebSocketClientOfServer) {
+        let event = serde_json::json!({
+            "channel": "websocketserver",
+            "eventType": event_type,
+            "clientId": client.channelid,
+            "ip": client.peer.ip().to_string(),
+            "port": client.peer.port(),
+            "path": client.path,
+            "tls": client.tls_session,
+        });
+        if let Ok(event_payload) = serde_json::to_string(&event) {
+            if let Err(e) = emit_event("tcp-client-event", event_payload) {
+                eprintln!("发送客户端事件失败: {:?}", e);
+            }
+        }
+    }
+
+    // 发送给一个客户端并记录
+    async fn send_to(
+        &self,
+        client: &WebSocketClientOfServer,
+        data: &[u8],
+        message: &Message,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        client
+            .writer
+            .send(self.mode.encode(data))
+            .await
+            .map_err(|_| format!("客户端 {} 已断开", client.channelid))?;
+        if let Err(e) = self
+            .message_manager
+            .record_message(
+                &client.channeltype,
+                &client.channelid,
+                &client.channel_name,
+                message,
+                MessageDirection::Sent,
+                None,
+            )
+            .await
+        {
+            eprintln!("记录发送消息失败: {:?}", e);
+        }
+        Ok(())
+    }
+
+    pub async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // 各客户端任务收到关闭信号后发送关闭帧
+        let _ = self.shutdown_signal.send(());
+        self.clients.lock().await.clear();
+        self.on_statechange(ChannelState::Disconnected).await
+    }
+
+    pub async fn is_client_connected(&self, client_id: &str) -> bool {
+        self.clients.lock().await.contains_key(client_id)
+    }
+}
+
+#[async_trait]
+impl CommunicationChannel for WebSocketServerChannel {
+    async fn send(
+        &self,
+        message: &Message,
+        clientid: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data = websocket::payload_bytes(message)?;
+        let mut message = message.clone();
+        message.update_timestamp();
+
+        if let Some(clientid) = clientid {
+            let client = self
+                .clients
+                .lock()
+                .await
+                .get(&clientid)
+                .cloned()
+                .ok_or_else(|| format!("客户端 {} 不存在", clientid))?;
+            return self.send_to(&client, &data, &message).await;
+        }
+        // 未指定客户端时发送给所有客户端
+        let clients: Vec<WebSocketClientOfServer> =
+            self.clients.lock().await.values().cloned().collect();
+        for client in clients {
+            if let Err(e) = self.send_to(&client, &data, &message).await {
+                eprintln!("发送失败: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn receive(&self) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        match self.rx_recv.lock().await.recv().await {
+            Some((clientid, data)) => Ok(Message::new(serde_json::json!({
+                "data": data,
+                "clientId": clientid,
+            }))),
+            None => Err("WebSocket server closed".into()),
+        }
+    }
+
+    async fn send_and_wait(
+        &self,
+        message: &Message,
+        timeout_secs: u64,
+    ) -> Result<Message, Box<dyn Error + Send + Sync>> {
+        self.correlator
+            .send_and_wait(self, message, timeout_secs)
+            .await
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.close().await
+    }
+
+    async fn on_statechange(
+        &self,
+        state: ChannelState,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let payload = serde_json::json!({
+            "channeltype": "websocketserver",
+            "channelId": self.channelid.clone(),
+            "state": state,
+            "data": {
+                "path": self.path,
+                "mode": self.mode,
+            },
+            "reason": "The WebSocket server state has changed",
+        });
+        emit_event("channel-state", serde_json::to_string(&payload)?)?;
+        Ok(())
+    }
+
+    fn get_channel_id(&self) -> String {
+        self.channelid.clone()
+    }
+
+    async fn subscribe_topic(
+        &self,
+        _topic: &str,
+        _qos: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("WebSocket server does not support topic subscription".into())
+    }
+
+    async fn unsubscribe_topic(&self, _topic: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("WebSocket server does not support topic unsubscription".into())
+    }
+
+    async fn client_ids(&self) -> Vec<String> {
+        self.clients.lock().await.keys().cloned().collect()
+    }
+}
@@ -123,7 +123,7 @@ pub async fn set_config_value_async(section: &str, key: &str, value: &str) -> Re
     // Build the path to the config file
     let path = get_config_dir(constants::APP_NAME).join(constants::APP_CONFIG);
     let dir_path = path.parent().unwrap(); // Get the directory path
-                                          // Create the directory if it doesn't exist
+                                           // Create the directory if it doesn't exist
     if !dir_path.exists() {
         if let Err(e) = fs::create_dir_all(dir_path) {
             return Err(format!("Failed to create directory: {}", e));
@@ -169,8 +169,12 @@ pub fn load_config_value(section: &str, key: &str) -> Option<Value> {
     // Load the config
     let config = Config::new(path.to_str().unwrap()).map_err(|e| format!("{}", e));
     if let Ok(config) = config {
-        // 读取 window.theme 字段
-        let sectionvalue = config.get_value(&[section, key]);
+        // key 为空时读取整个配置段
+        let sectionvalue = if key.is_empty() {
+            config.get_value(&[section])
+        } else {
+            config.get_value(&[section, key])
+        };
         // Retrieve the value from the config
         sectionvalue.cloned()
     } else {
@@ -1,15 +1,50 @@
+#[cfg(feature = "desktop")]
 use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::error::Error;
+#[cfg(feature = "desktop")]
 use std::sync::Mutex;
-use tauri::AppHandle;
+#[cfg(feature = "desktop")]
+use tauri::{AppHandle, Emitter};
 
+#[cfg(feature = "desktop")]
 static APP_HANDLE: Lazy<Mutex<Option<AppHandle>>> = Lazy::new(|| Mutex::new(None));
 
+#[cfg(feature = "desktop")]
 pub fn set_app_handle(app_handle: AppHandle) {
     let mut handle = APP_HANDLE.lock().unwrap();
     *handle = Some(app_handle);
 }
 
+#[cfg(feature = "desktop")]
 pub fn get_app_handle() -> AppHandle {
     let handle = APP_HANDLE.lock().unwrap();
     handle.clone().expect("App handle not set")
-}
\ No newline at end of file
+}
+
+/// 获取应用句柄，未设置时（测试、命令行等没有界面的场景）返回 None
+#[cfg(feature = "desktop")]
+pub fn try_get_app_handle() -> Option<AppHandle> {
+    APP_HANDLE.lock().unwrap().clone()
+}
+
+/// 向界面发送事件，没有界面（测试、命令行等）时忽略
+#[cfg(feature = "desktop")]
+pub fn emit_event<S: Serialize + Clone>(
+    event: &str,
+    payload: S,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match try_get_app_handle() {
+        Some(app_handle) => Ok(app_handle.emit(event, payload)?),
+        None => Ok(()),
+    }
+}
+
+/// 不带桌面界面构建时没有事件接收方
+#[cfg(not(feature = "desktop"))]
+pub fn emit_event<S: Serialize + Clone>(
+    _event: &str,
+    _payload: S,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    Ok(())
+}
@@ -0,0 +1,10 @@
+pub mod basefunc;
+pub mod capture;
+pub mod combridage;
+pub mod config;
+pub mod global;
+pub mod protocol;
+#[cfg(feature = "desktop")]
+pub mod taurihandler;
+#[cfg(feature = "web")]
+pub mod web;
@@ -4,47 +4,27 @@
 #![allow(unused_variables)] // 可以允许多个
 use std::panic;
 // use tauri::{CustomMenuItem, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
-use tracing::{error, info};
-pub mod basefunc;
-pub mod config;
-#[cfg(feature = "desktop")]
-pub mod combridage;
-#[cfg(feature = "desktop")]
-pub mod taurihandler;
-#[cfg(feature = "desktop")]
-use tauri::Manager;
-#[cfg(feature = "desktop")]
-pub mod protocol;
 #[cfg(feature = "desktop")]
-pub mod global;
-// use once_cell::sync::OnceCell;
+use embedtalk::config::appconfig;
 #[cfg(feature = "desktop")]
-use crate::config::appconfig::{get_config_value_async, set_config_value_async};
+use embedtalk::global;
 #[cfg(feature = "desktop")]
-use crate::taurihandler::channel_handler::{
-    connect_channel, disconnect_channel, get_timer_status, list_serial_ports, send_message,
-    start_timer_send, stop_timer_send,
-};
+use embedtalk::protocol;
 #[cfg(feature = "desktop")]
-use crate::taurihandler::dlt645_handler::list_channels;
+use embedtalk::taurihandler::{self, channel_handler, dlt645_handler, handler};
+#[cfg(feature = "web")]
+use embedtalk::web;
 #[cfg(feature = "desktop")]
-use crate::taurihandler::handler::{
-    app_close, check_update, get_all_config_item_lists, get_app_info, get_com_list,
-    get_protocol_config_item, get_region_value, get_window_position, prase_frame, open_window,
-    parse_item_data, save_file, save_protocol_config_item, set_region_value,
-    update_window_position, WindowState,
-};
+use tauri::Manager;
 #[cfg(feature = "desktop")]
 use tauri_plugin_log::{Target, TargetKind};
-
-#[cfg(feature = "web")]
-pub mod web;
+use tracing::{error, info};
 
 #[cfg(feature = "desktop")]
 fn main() {
     // Desktop application entry point
     std::env::set_var("RUST_BACKTRACE", "1");
-    
+
     panic::set_hook(Box::new(|info| {
         let backtrace = std::backtrace::Backtrace::capture();
         error!("Panic occurred: {:?}", info);
@@ -72,7 +52,7 @@ fn main() {
                 ])
                 .build(),
         )
-        .manage(WindowState::default()) // 添加窗口位置状态管理
+        .manage(handler::WindowState::default()) // 添加窗口位置状态管理
         .setup(|app| {
             let handle = app.app_handle();
             global::set_app_handle(handle.clone()); // Set the global app handle
@@ -96,8 +76,10 @@ fn main() {
 
                     let state_flags = tauri_plugin_window_state::StateFlags::all();
                     let app = window.app_handle();
-                    let _ =
-                        tauri_plugin_window_state::AppHandleExt::save_window_state(app, state_flags);
+                    let _ = tauri_plugin_window_state::AppHandleExt::save_window_state(
+                        app,
+                        state_flags,
+                    );
 
                     // // 阻止默认关闭行为，让前端处理
                     // api.prevent_close();
@@ -116,31 +98,32 @@ fn main() {
             _ => {}
         })
         .invoke_handler(tauri::generate_handler![
-            prase_frame,
-            app_close,
-            set_config_value_async,
-            get_config_value_async,
-            get_region_value,
-            set_region_value,
-            save_file,
-            get_app_info,
-            check_update,
-            get_com_list,
-            connect_channel,
-            disconnect_channel,
-            get_all_config_item_lists,
-            get_protocol_config_item,
-            save_protocol_config_item,
-            list_serial_ports,
-            send_message,
-            start_timer_send,
-            stop_timer_send,
-            get_timer_status,
-            open_window,
-            update_window_position,
-            get_window_position,
+            handler::prase_frame,
+            handler::app_close,
+            appconfig::set_config_value_async,
+            appconfig::get_config_value_async,
+            handler::get_region_value,
+            handler::set_region_value,
+            handler::save_file,
+            handler::get_app_info,
+            handler::check_update,
+            handler::get_com_list,
+            channel_handler::connect_channel,
+            channel_handler::disconnect_channel,
+            handler::get_all_config_item_lists,
+            handler::get_protocol_config_item,
+            handler::save_protocol_config_item,
+            channel_handler::list_serial_ports,
+            channel_handler::send_message,
+            channel_handler::send_template_message,
+            channel_handler::start_timer_send,
+            channel_handler::stop_timer_send,
+            channel_handler::get_timer_status,
+            handler::open_window,
+            handler::update_window_position,
+            handler::get_window_position,
             // DLT645 相关命令
-            list_channels,
+            dlt645_handler::list_channels,
             // 协议相关命令
             taurihandler::protocol_handler::get_supported_protocols,
             taurihandler::protocol_handler::configure_channel_protocol,
@@ -150,13 +133,43 @@ fn main() {
             taurihandler::protocol_handler::send_protocol_message,
             taurihandler::protocol_handler::handle_protocol_message,
             taurihandler::handler::caculate_pppfcs16,
+            taurihandler::handler::repair_frame,
+            taurihandler::handler::diagnose_frame,
             taurihandler::handler::da_and_measure_point_exchange,
             taurihandler::handler::open_devtools,
             taurihandler::channel_handler::subscribe_mqtt_topic,
             taurihandler::channel_handler::unsubscribe_mqtt_topic,
             taurihandler::handler::export_frames,
-            parse_item_data,
+            handler::parse_item_data,
             taurihandler::handler::export_logs,
+            taurihandler::capture_handler::import_capture_file,
+            taurihandler::capture_handler::export_messages_pcapng,
+            taurihandler::capture_handler::start_live_pcapng,
+            taurihandler::capture_handler::stop_live_pcapng,
+            taurihandler::capture_handler::get_live_pcapng_status,
+            taurihandler::message_handler::query_messages,
+            taurihandler::message_handler::migrate_message_logs,
+            taurihandler::message_handler::get_storage_usage,
+            taurihandler::message_handler::get_storage_policy,
+            taurihandler::message_handler::set_storage_policy,
+            taurihandler::message_handler::apply_storage_retention,
+            taurihandler::channel_handler::start_replay,
+            taurihandler::channel_handler::stop_replay,
+            taurihandler::channel_handler::get_replay_status,
+            taurihandler::channel_handler::start_bridge,
+            taurihandler::channel_handler::stop_bridge,
+            taurihandler::channel_handler::set_bridge_rules,
+            taurihandler::channel_handler::get_bridge_timeline,
+            taurihandler::channel_handler::get_bridge_status,
+            taurihandler::channel_handler::set_virtual_impairment,
+            taurihandler::channel_handler::get_client_addresses,
+            taurihandler::channel_handler::get_reconnect_policy,
+            taurihandler::channel_handler::set_reconnect_policy,
+            taurihandler::channel_handler::list_frame_templates,
+            taurihandler::channel_handler::save_frame_template,
+            taurihandler::channel_handler::delete_frame_template,
+            taurihandler::channel_handler::preview_frame_template,
+            taurihandler::scenario_handler::run_scenario,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -164,7 +177,7 @@ fn main() {
 
 #[cfg(feature = "web")]
 #[tokio::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
     std::env::set_var("RUST_BACKTRACE", "1");
 
     panic::set_hook(Box::new(|info| {
@@ -173,6 +186,18 @@ async fn main() {
         error!("Backtrace: {:?}", backtrace);
     }));
 
+    // 访问日志等通过 tracing 输出到控制台
+    tracing_subscriber::fmt().init();
+
     // Start web server
-    web::start_web_server().await;
+    if let Err(e) = web::start_web_server().await {
+        error!("{}", e);
+        return std::process::ExitCode::FAILURE;
+    }
+    std::process::ExitCode::SUCCESS
+}
+
+#[cfg(not(any(feature = "desktop", feature = "web")))]
+fn main() {
+    eprintln!("未启用 desktop 或 web 功能，命令行工具请使用 embedtalk-cli");
 }
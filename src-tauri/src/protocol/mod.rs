@@ -45,3 +45,17 @@ pub async fn initialize_protocol_stack() -> Result<(), Box<dyn std::error::Error
     println!("Protocol stack initialized with Modbus and DLT645 parsers");
     Ok(())
 }
+
+/// 按协议名组帧，协议栈尚未初始化时（如命令行工具）先完成初始化
+pub async fn build_frame(protocol: &str, message: &serde_json::Value) -> Result<Vec<u8>, String> {
+    let manager = get_protocol_manager();
+    if manager.get_parser(protocol).await.is_none() {
+        initialize_protocol_stack()
+            .await
+            .map_err(|e| format!("初始化协议栈失败: {}", e))?;
+    }
+    manager
+        .build_with_protocol(protocol, message)
+        .await
+        .map_err(|e| format!("组帧失败: {}", e))
+}
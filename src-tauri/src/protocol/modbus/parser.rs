@@ -23,7 +23,7 @@ impl ModbusParser {
     }
 
     /// 计算 Modbus RTU CRC16 校验和
-    fn calculate_crc(data: &[u8]) -> u16 {
+    pub(crate) fn calculate_crc(data: &[u8]) -> u16 {
         let mut crc = 0xFFFF;
         for byte in data {
             crc ^= *byte as u16;
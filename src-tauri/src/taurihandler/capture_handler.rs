@@ -0,0 +1,57 @@
+use crate::capture::{CaptureFilter, CaptureFrame, CaptureImporter};
+use crate::combridage::{
+    export_records, is_live_capture_active, start_live_capture, stop_live_capture, MessageManager,
+    MessageQuery,
+};
+use std::path::Path;
+use tracing::info;
+
+/// 导入 pcap/pcapng 抓包文件，重组 TCP 流并解析其中的报文
+#[tauri::command]
+pub async fn import_capture_file(
+    file_path: String,
+    region: String,
+    filter: Option<CaptureFilter>,
+) -> Result<Vec<CaptureFrame>, String> {
+    info!("Import capture: {} {} {:?}", file_path, region, filter);
+    let filter = filter.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        CaptureImporter::import_file(&file_path, &region, &filter).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("导入抓包文件失败: {}", e))?
+}
+
+/// 把消息库中满足查询条件的收发记录导出为 pcapng 文件，返回导出的报文数
+#[tauri::command]
+pub async fn export_messages_pcapng(
+    app_handle: tauri::AppHandle,
+    file_path: String,
+    query: MessageQuery,
+) -> Result<usize, String> {
+    let message_manager = MessageManager::new(app_handle).map_err(|e| e.to_string())?;
+    let store = message_manager.store().await.map_err(|e| e.to_string())?;
+    let records = store
+        .load_records(&query)
+        .await
+        .map_err(|e| format!("读取通道记录失败: {}", e))?;
+    info!("Export {} records to {}", records.len(), file_path);
+    export_records(&records, Path::new(&file_path)).map_err(|e| e.to_string())
+}
+
+/// 开始把所有通道的收发记录实时写入 pcapng 文件
+#[tauri::command]
+pub async fn start_live_pcapng(file_path: String) -> Result<(), String> {
+    start_live_capture(Path::new(&file_path)).map_err(|e| e.to_string())
+}
+
+/// 停止实时写入 pcapng
+#[tauri::command]
+pub async fn stop_live_pcapng() -> Result<(), String> {
+    stop_live_capture().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_live_pcapng_status() -> bool {
+    is_live_capture_active()
+}
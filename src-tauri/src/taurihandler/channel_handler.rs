@@ -1,11 +1,19 @@
-use crate::combridage::{ChannelType, CommunicationManager, Message};
+use crate::basefunc::frame_template::FrameTemplate;
+use crate::combridage::{
+    build_steps, channel_from_params, read_log_file, records_from_log, Bridge, BridgeFrame,
+    BridgeOptions, BridgeRule, BridgeStats, BridgeStatus, ChannelType, ClientAddress,
+    CommunicationManager, Message, MessageManager, MessageQuery, ReconnectPolicy, ReplayEngine,
+    ReplayOptions, ReplayReport, TimedSendJob, TimedSendOptions, TimedSendStatus,
+    VirtualImpairment,
+};
 use lazy_static::lazy_static;
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::Arc;
+use tauri::Emitter;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
-use tokio::time::{interval, Duration};
 
 // 使用 Lazy 静态变量存储通道管理器
 static CHANNEL_MANAGER: Lazy<Mutex<CommunicationManager>> =
@@ -15,79 +23,51 @@ static CHANNEL_MANAGER: Lazy<Mutex<CommunicationManager>> =
 static CHANNEL_ID_MAP: Lazy<Mutex<std::collections::HashMap<String, ChannelType>>> =
     Lazy::new(|| Mutex::new(std::collections::HashMap::new()));
 
-// 定时发送任务信息
-struct TimerTask {
-    interval: u64,          // 发送间隔（毫秒）
-    message: Vec<u8>,       // 要发送的消息
-    handle: JoinHandle<()>, // 任务句柄
-}
-
-// 定时发送任务管理器
+// 定时发送任务管理器，键为任务 ID
 lazy_static! {
-    static ref TIMER_TASKS: Arc<std::sync::Mutex<HashMap<String, TimerTask>>> =
+    static ref TIMER_TASKS: Arc<std::sync::Mutex<HashMap<String, TimedSendJob>>> =
         Arc::new(std::sync::Mutex::new(HashMap::new()));
 }
 
+// 回放任务信息
+struct ReplayTask {
+    report: Arc<std::sync::Mutex<ReplayReport>>,
+    handle: JoinHandle<()>,
+}
+
+// 回放任务管理器，每个通道同时只有一个回放任务
+static REPLAY_TASKS: Lazy<std::sync::Mutex<HashMap<String, ReplayTask>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// 桥接任务管理器，键为桥接 ID
+static BRIDGES: Lazy<std::sync::Mutex<HashMap<String, Bridge>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// 回放数据来源
+#[derive(Debug, Clone, Deserialize)]
+pub enum ReplaySource {
+    /// 消息库中满足条件的记录
+    Store(MessageQuery),
+    /// 旧版 .log 日志文件，也可以是压缩归档的 .log.gz
+    LogFile {
+        path: String,
+        channel_name: Option<String>,
+    },
+}
+
 /// 连接通道
 #[tauri::command]
 pub async fn connect_channel(channel: &str, params: &str) -> Result<String, String> {
     let values: serde_json::Value = serde_json::from_str(params).map_err(|e| e.to_string())?;
 
-    // 根据通道类型和参数创建 ChannelType
-    let channel_type = match channel.to_uppercase().as_str() {
-        "TCPCLIENT" => {
-            let ip = values["ip"].as_str().ok_or("Missing ip")?;
-            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
-            ChannelType::TcpClient(ip.to_string(), port)
-        }
-        "TCPSERVER" => {
-            let ip = values["ip"].as_str().ok_or("Missing ip")?;
-            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
-            ChannelType::TcpServer(ip.to_string(), port)
-        }
-        "SERIAL" => {
-            let port = values["comname"].as_str().ok_or("Missing comname")?;
-            let baud_rate = values["baurdate"].as_u64().ok_or("Missing baud rate")? as u32;
-            let data_bits = values["databit"].as_u64().ok_or("Missing data bits")? as u8;
-            let flow_control = values["flowctrl"].as_u64().ok_or("Missing flow control")? as u8;
-            let parity = values["parity"].as_str().ok_or("Missing parity")?;
-            let stop_bits = values["stopbit"].as_u64().ok_or("Missing stop bits")? as u8;
-            ChannelType::SerialPort(
-                port.to_string(),
-                baud_rate,
-                flow_control,
-                data_bits,
-                parity.to_string(),
-                stop_bits,
-            )
-        }
-        "MQTT" => {
-            let ip = values["ip"].as_str().ok_or("Missing ip")?;
-            let port = values["port"].as_u64().ok_or("Missing port")? as u16;
-            let username = values["username"].as_str().unwrap_or("");
-            let password = values["password"].as_str().unwrap_or("");
-            let client_id = values["clientid"].as_str().ok_or("Missing client ID")?;
-            let qos = values["qos"].as_u64().unwrap_or(0) as u8;
-            let topic = values["topic"].as_str().unwrap_or("#");
-            ChannelType::Mqtt(
-                ip.to_string(),
-                port,
-                username.to_string(),
-                password.to_string(),
-                client_id.to_string(),
-                qos,
-                topic.to_string(),
-            )
-        }
-        _ => return Err(format!("Unsupported channel type: {}", channel)),
-    };
+    let (channel_type, options) = channel_from_params(channel, &values)?;
 
     // 获取通道管理器的可变引用
     let mut manager = CHANNEL_MANAGER.lock().await;
 
     // 添加通道并获取通道ID
     let channel_id = manager
-        .add_channel(channel_type.clone())
+        .add_channel_with_options(channel_type.clone(), options)
         .await
         .map_err(|e| format!("Failed to add channel: {}", e))?;
 
@@ -126,6 +106,18 @@ pub async fn disconnect_channel(channelid: &str) -> Result<(), String> {
         id_map.remove(channelid);
     }
 
+    // 停止该通道的定时发送任务
+    TIMER_TASKS
+        .lock()
+        .unwrap()
+        .retain(|_, job| job.channel_id() != channelid);
+
+    // 停止使用该通道的桥接
+    BRIDGES
+        .lock()
+        .unwrap()
+        .retain(|_, bridge| !bridge.uses(channelid));
+
     Ok(())
 }
 
@@ -175,6 +167,35 @@ pub async fn send_message(
     }
 }
 
+/// 展开报文模板并发送，返回实际发送的报文
+#[tauri::command]
+pub async fn send_template_message(
+    channelid: String,
+    template: String,
+    vars: Option<HashMap<String, String>>,
+    clientid: Option<String>,
+) -> Result<Vec<u8>, String> {
+    let channel_type = {
+        let id_map = CHANNEL_ID_MAP.lock().await;
+        id_map
+            .get(&channelid)
+            .cloned()
+            .ok_or(format!("Channel ID not found: {}", channelid))?
+    };
+    let frame = FrameTemplate::load(&template)
+        .ok_or(format!("模板不存在: {}", template))?
+        .expand(&vars.unwrap_or_default())
+        .map_err(|e| format!("模板展开失败: {}", e))?;
+    let msg = Message::new(serde_json::json!({ "data": frame }));
+    CHANNEL_MANAGER
+        .lock()
+        .await
+        .send(&channel_type, &msg, clientid)
+        .await
+        .map_err(|e| format!("发送消息失败: {}", e))?;
+    Ok(frame)
+}
+
 #[tauri::command]
 pub async fn list_serial_ports() -> Result<Vec<String>, String> {
     let mut serial_ports = Vec::new();
@@ -184,16 +205,18 @@ pub async fn list_serial_ports() -> Result<Vec<String>, String> {
     Ok(serial_ports)
 }
 
-/// 启动定时发送任务
+/// 启动定时发送任务，返回任务 ID；同一通道可以同时运行多个任务
+///
+/// message、interval_ms、clientid 兼容只发送一帧的旧用法，
+/// 循环发送多帧、限制次数或停止时间通过 options 指定
 #[tauri::command]
 pub async fn start_timer_send(
-    app_handle: tauri::AppHandle,
     channel_id: String,
-    message: Vec<u8>,
-    interval_ms: u64,
+    message: Option<Vec<u8>>,
+    interval_ms: Option<u64>,
     clientid: Option<String>,
-) -> Result<(), String> {
-    // 检查通道是否存在
+    options: Option<TimedSendOptions>,
+) -> Result<String, String> {
     let channel_type = {
         let id_map = CHANNEL_ID_MAP.lock().await;
         id_map
@@ -201,98 +224,306 @@ pub async fn start_timer_send(
             .cloned()
             .ok_or(format!("Channel ID not found: {}", channel_id))?
     };
+    // 任务直接持有通道，不占用通道管理器的锁
+    let channel = CHANNEL_MANAGER
+        .lock()
+        .await
+        .get_channel(&channel_type)
+        .ok_or(format!("Channel not found: {}", channel_id))?;
 
-    // 获取通道管理器
-    let manager = CHANNEL_MANAGER.lock().await;
-
-    // 如果已存在相同通道的定时任务，先停止它
-    stop_timer_send(channel_id.clone()).await?;
-
-    // 解析消息内容
-    let content = parse_message_content(&message)?;
-
-    // 创建消息对象
-    let msg = Message::new(content);
+    let mut options = options.unwrap_or_default();
+    if let Some(message) = message {
+        options.frames.insert(0, message);
+    }
+    if let Some(interval_ms) = interval_ms {
+        options.interval_ms = interval_ms;
+    }
+    if clientid.is_some() {
+        options.clientid = clientid;
+    }
+    let job = TimedSendJob::start(channel, options).map_err(|e| e.to_string())?;
+    let job_id = job.job_id();
+    println!("通道 {} 启动定时发送任务 {}", channel_id, job_id);
+    TIMER_TASKS.lock().unwrap().insert(job_id.clone(), job);
+    Ok(job_id)
+}
 
-    // 创建定时任务
-    let app_handle_clone = app_handle.clone();
-    let channel_id_clone = channel_id.clone();
-    let message_clone = message.clone();
-    let channel_type_clone = channel_type.clone();
-
-    // 启动定时发送任务
-    let task_handle = tokio::spawn(async move {
-        let mut interval_timer = interval(Duration::from_millis(interval_ms));
-
-        loop {
-            interval_timer.tick().await;
-
-            // 解析消息内容
-            let content = match parse_message_content(&message_clone) {
-                Ok(c) => c,
-                Err(e) => {
-                    println!("定时发送任务错误: 解析消息失败 {}", e);
-                    continue;
-                }
-            };
+/// 停止定时发送任务，未指定 jobid 时停止通道上的所有任务，返回停止时的状态
+#[tauri::command]
+pub async fn stop_timer_send(
+    channel_id: String,
+    jobid: Option<String>,
+) -> Result<Vec<TimedSendStatus>, String> {
+    let mut tasks = TIMER_TASKS.lock().unwrap();
+    let job_ids: Vec<String> = tasks
+        .iter()
+        .filter(|(id, job)| {
+            job.channel_id() == channel_id && jobid.as_ref().is_none_or(|jobid| jobid == *id)
+        })
+        .map(|(id, _)| id.clone())
+        .collect();
+    let stopped = job_ids
+        .iter()
+        .filter_map(|id| tasks.remove(id))
+        .map(TimedSendJob::stop)
+        .collect();
+    println!("已停止通道 {} 的定时发送任务 {:?}", channel_id, job_ids);
+    Ok(stopped)
+}
 
-            // 创建消息对象
-            let msg = Message::new(content);
+/// 获取定时发送任务的状态，未指定通道时返回所有任务；已结束的任务保留到被停止为止
+#[tauri::command]
+pub fn get_timer_status(channelid: Option<String>) -> Result<Vec<TimedSendStatus>, String> {
+    let tasks = TIMER_TASKS.lock().unwrap();
+    let mut statuses: Vec<TimedSendStatus> = tasks
+        .values()
+        .map(TimedSendJob::status)
+        .filter(|status| {
+            channelid
+                .as_ref()
+                .is_none_or(|channelid| &status.channel_id == channelid)
+        })
+        .collect();
+    statuses.sort_by_key(|status| status.started_at);
+    Ok(statuses)
+}
 
-            // 发送消息
-            if let Err(e) = manager
-                .send(&channel_type_clone, &msg, clientid.clone())
+/// 在通道上回放录制的发送数据，返回回放的步数；进度通过 replay-progress 事件通知
+#[tauri::command]
+pub async fn start_replay(
+    app_handle: tauri::AppHandle,
+    channelid: String,
+    source: ReplaySource,
+    options: Option<ReplayOptions>,
+) -> Result<usize, String> {
+    let options = options.unwrap_or_default();
+    let channel_type = {
+        let id_map = CHANNEL_ID_MAP.lock().await;
+        id_map
+            .get(&channelid)
+            .cloned()
+            .ok_or(format!("Channel ID not found: {}", channelid))?
+    };
+    if matches!(channel_type, ChannelType::TcpServer(_, _)) && options.clientid.is_none() {
+        return Err("TCP 服务端通道回放需要指定 clientid".to_string());
+    }
+    let channel = CHANNEL_MANAGER
+        .lock()
+        .await
+        .get_channel(&channel_type)
+        .ok_or(format!("Channel not found: {}", channelid))?;
+
+    let records = match source {
+        ReplaySource::Store(query) => {
+            let message_manager =
+                MessageManager::new(app_handle.clone()).map_err(|e| e.to_string())?;
+            let store = message_manager.store().await.map_err(|e| e.to_string())?;
+            store
+                .load_records(&query)
                 .await
-            {
-                println!("定时发送任务错误: 发送消息失败 {}", e);
-            } else {
-                println!("定时发送成功: 通道 {}", channel_id_clone);
-            }
+                .map_err(|e| format!("读取回放记录失败: {}", e))?
         }
+        ReplaySource::LogFile { path, channel_name } => {
+            let content = read_log_file(std::path::Path::new(&path))
+                .await
+                .map_err(|e| format!("读取日志文件 {} 失败: {}", path, e))?;
+            records_from_log(&content, channel_name.as_deref())
+        }
+    };
+    let steps = build_steps(&records);
+    if steps.is_empty() {
+        return Err("记录中没有可回放的发送数据".to_string());
+    }
+    let step_count = steps.len();
+
+    stop_replay(channelid.clone()).await?;
+
+    let report = Arc::new(std::sync::Mutex::new(ReplayReport {
+        total_steps: step_count,
+        ..Default::default()
+    }));
+    let report_clone = report.clone();
+    let channelid_clone = channelid.clone();
+    let handle = tokio::spawn(async move {
+        let engine = ReplayEngine::new(channel, options);
+        let result = engine
+            .run(&steps, |progress| {
+                *report_clone.lock().unwrap() = progress.clone();
+                let payload = serde_json::json!({
+                    "channelId": channelid_clone,
+                    "report": progress,
+                });
+                if let Err(e) = app_handle.emit("replay-progress", payload) {
+                    eprintln!("发送回放进度失败: {:?}", e);
+                }
+            })
+            .await;
+        println!(
+            "通道 {} 回放完成: 发送 {}/{}，一致 {}，差异 {}",
+            channelid_clone,
+            result.sent_steps,
+            result.total_steps,
+            result.matched_steps,
+            result.divergences.len()
+        );
     });
 
-    // 保存任务信息
-    let mut tasks = TIMER_TASKS.lock().unwrap();
-    tasks.insert(
-        channel_id.clone(),
-        TimerTask {
-            interval: interval_ms,
-            message,
-            handle: task_handle,
-        },
-    );
-
-    Ok(())
+    REPLAY_TASKS
+        .lock()
+        .unwrap()
+        .insert(channelid, ReplayTask { report, handle });
+    Ok(step_count)
 }
 
-/// 停止定时发送任务
+/// 停止回放，返回停止时的回放结果
 #[tauri::command]
-pub async fn stop_timer_send(channel_id: String) -> Result<(), String> {
-    let mut tasks = TIMER_TASKS.lock().unwrap();
-
-    if let Some(task) = tasks.remove(&channel_id) {
-        // 中止任务
+pub async fn stop_replay(channelid: String) -> Result<Option<ReplayReport>, String> {
+    let mut tasks = REPLAY_TASKS.lock().unwrap();
+    if let Some(task) = tasks.remove(&channelid) {
         task.handle.abort();
-        println!("已停止通道 {} 的定时发送任务", channel_id);
-        Ok(())
+        let report = task.report.lock().unwrap().clone();
+        Ok(Some(report))
     } else {
-        // 如果没有找到任务，也返回成功
-        Ok(())
+        Ok(None)
     }
 }
 
-/// 获取定时发送任务状态
+/// 获取回放进度与结果
 #[tauri::command]
-pub fn get_timer_status(channelid: String) -> Result<Option<(u64, Vec<u8>)>, String> {
-    let tasks = TIMER_TASKS.lock().unwrap();
+pub fn get_replay_status(channelid: String) -> Result<Option<ReplayReport>, String> {
+    let tasks = REPLAY_TASKS.lock().unwrap();
+    Ok(tasks
+        .get(&channelid)
+        .map(|task| task.report.lock().unwrap().clone()))
+}
 
-    if let Some(task) = tasks.get(&channelid) {
-        Ok(Some((task.interval, task.message.clone())))
-    } else {
-        Ok(None)
+/// 在两个通道之间建立透明桥接，返回桥接 ID；每转发一帧通过 bridge-frame 事件通知
+#[tauri::command]
+pub async fn start_bridge(
+    app_handle: tauri::AppHandle,
+    channel_a: String,
+    channel_b: String,
+    options: Option<BridgeOptions>,
+) -> Result<String, String> {
+    let options = options.unwrap_or_default();
+    let (type_a, type_b) = {
+        let id_map = CHANNEL_ID_MAP.lock().await;
+        let lookup = |id: &String| {
+            id_map
+                .get(id)
+                .cloned()
+                .ok_or(format!("Channel ID not found: {}", id))
+        };
+        (lookup(&channel_a)?, lookup(&channel_b)?)
+    };
+    if BRIDGES
+        .lock()
+        .unwrap()
+        .values()
+        .any(|bridge| bridge.uses(&channel_a) || bridge.uses(&channel_b))
+    {
+        return Err("通道已在其他桥接中使用".to_string());
+    }
+    let (a, b) = {
+        let manager = CHANNEL_MANAGER.lock().await;
+        (
+            manager
+                .get_channel(&type_a)
+                .ok_or(format!("Channel not found: {}", channel_a))?,
+            manager
+                .get_channel(&type_b)
+                .ok_or(format!("Channel not found: {}", channel_b))?,
+        )
+    };
+
+    let bridgeid = "bridge".to_string() + &uuid::Uuid::new_v4().to_string();
+    let bridgeid_clone = bridgeid.clone();
+    let bridge = Bridge::start(a, b, options, move |frame: &BridgeFrame| {
+        let payload = serde_json::json!({
+            "bridgeId": bridgeid_clone,
+            "frame": frame,
+        });
+        if let Err(e) = app_handle.emit("bridge-frame", payload) {
+            eprintln!("发送桥接帧失败: {:?}", e);
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    BRIDGES.lock().unwrap().insert(bridgeid.clone(), bridge);
+    Ok(bridgeid)
+}
+
+/// 停止桥接，返回停止时的统计
+#[tauri::command]
+pub fn stop_bridge(bridgeid: String) -> Result<Option<BridgeStats>, String> {
+    let bridge = BRIDGES.lock().unwrap().remove(&bridgeid);
+    Ok(bridge.map(Bridge::stop))
+}
+
+/// 替换桥接的故障注入规则
+#[tauri::command]
+pub fn set_bridge_rules(bridgeid: String, rules: Vec<BridgeRule>) -> Result<(), String> {
+    let bridges = BRIDGES.lock().unwrap();
+    let bridge = bridges
+        .get(&bridgeid)
+        .ok_or(format!("Bridge not found: {}", bridgeid))?;
+    bridge.set_rules(rules).map_err(|e| e.to_string())
+}
+
+/// 获取桥接时间线中序号不小于 since 的帧
+#[tauri::command]
+pub fn get_bridge_timeline(
+    bridgeid: String,
+    since: Option<usize>,
+) -> Result<Vec<BridgeFrame>, String> {
+    let bridges = BRIDGES.lock().unwrap();
+    let bridge = bridges
+        .get(&bridgeid)
+        .ok_or(format!("Bridge not found: {}", bridgeid))?;
+    Ok(bridge.timeline(since.unwrap_or(0)))
+}
+
+/// 获取桥接状态与统计
+#[tauri::command]
+pub fn get_bridge_status(bridgeid: String) -> Result<Option<BridgeStatus>, String> {
+    let bridges = BRIDGES.lock().unwrap();
+    Ok(bridges.get(&bridgeid).map(Bridge::status))
+}
+
+/// 修改虚拟通道本端发出数据的损伤设置
+#[tauri::command]
+pub async fn set_virtual_impairment(
+    channelid: String,
+    impairment: VirtualImpairment,
+) -> Result<(), String> {
+    let id_map = CHANNEL_ID_MAP.lock().await;
+    match id_map.get(&channelid) {
+        Some(ChannelType::Virtual(name, end)) => {
+            crate::combridage::set_virtual_impairment(name, *end, impairment)
+                .map_err(|e| e.to_string())
+        }
+        Some(_) => Err(format!("Not a virtual channel: {}", channelid)),
+        None => Err(format!("Channel not found: {}", channelid)),
     }
 }
 
+/// 获取 TCP 服务端通道已识别的终端/电表地址，包括所在连接、连接时间和最近收到报文的时间
+#[tauri::command]
+pub async fn get_client_addresses(channelid: String) -> Result<Vec<ClientAddress>, String> {
+    let channel_type = {
+        let id_map = CHANNEL_ID_MAP.lock().await;
+        id_map
+            .get(&channelid)
+            .cloned()
+            .ok_or(format!("Channel ID not found: {}", channelid))?
+    };
+    let channel = CHANNEL_MANAGER
+        .lock()
+        .await
+        .get_channel(&channel_type)
+        .ok_or(format!("Channel not found: {}", channelid))?;
+    Ok(channel.client_addresses().await)
+}
+
 /// 订阅MQTT主题
 #[tauri::command]
 pub async fn subscribe_mqtt_topic(channelid: String, topic: String, qos: u8) -> Result<(), String> {
@@ -383,3 +614,44 @@ fn parse_message_content(message: &[u8]) -> Result<serde_json::Value, String> {
     let bytes = message.to_vec();
     Ok(serde_json::json!({ "data": bytes }))
 }
+
+/// 获取新建通道默认使用的断线重连策略
+#[tauri::command]
+pub fn get_reconnect_policy() -> ReconnectPolicy {
+    ReconnectPolicy::load()
+}
+
+/// 设置新建通道默认使用的断线重连策略，已连接的通道不受影响
+#[tauri::command]
+pub fn set_reconnect_policy(policy: ReconnectPolicy) -> Result<(), String> {
+    policy.save()
+}
+
+/// 获取保存的报文模板
+#[tauri::command]
+pub fn list_frame_templates() -> Vec<FrameTemplate> {
+    FrameTemplate::load_all()
+}
+
+/// 保存报文模板，同名模板被覆盖
+#[tauri::command]
+pub fn save_frame_template(template: FrameTemplate) -> Result<(), String> {
+    template.save()
+}
+
+/// 删除报文模板，模板不存在时返回 false
+#[tauri::command]
+pub fn delete_frame_template(name: String) -> Result<bool, String> {
+    FrameTemplate::delete(&name)
+}
+
+/// 预览模板下一次发送的报文，模板可以未保存，不改变序号和计数器
+#[tauri::command]
+pub fn preview_frame_template(
+    template: FrameTemplate,
+    vars: Option<HashMap<String, String>>,
+) -> Result<Vec<u8>, String> {
+    template
+        .preview(&vars.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
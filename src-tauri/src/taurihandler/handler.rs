@@ -1,12 +1,18 @@
 use crate::basefunc::frame_csg::FrameCsg;
+use crate::basefunc::frame_diagnose::FrameDiagnosis;
 use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_repair::{FixupProtocol, FrameRepair};
 use crate::basefunc::protocol::{FrameAnalisyic, ProtocolInfo};
 use crate::config::appconfig::GLOBAL_CONFIG_MANAGER;
 use crate::config::xmlconfig::{
     ItemConfigList, ProtocolConfigManager, XmlElement, GLOBAL_645, GLOBAL_CSG13, GLOBAL_CSG16,
 };
+use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex,
@@ -15,10 +21,6 @@ use std::thread;
 use std::time::Instant;
 use tauri::{Emitter, LogicalPosition, Manager, State, WebviewUrl, WebviewWindowBuilder};
 use tracing::{error, info};
-use chrono::Local;
-use std::fs::{self, File};
-use std::io::{Write, BufWriter};
-use std::path::PathBuf;
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct WindowPosition {
@@ -100,7 +102,7 @@ pub async fn prase_frame(message: String, region: String) -> Response {
         info!("Result: {:?}", processed_result);
 
         Response {
-            protocol:protocol,
+            protocol: protocol,
             region: region.clone(),
             data: processed_result,
             error: None,
@@ -425,6 +427,24 @@ pub fn caculate_pppfcs16(frame: String) -> Result<u16, String> {
     Ok(fcs)
 }
 
+/// 诊断无法解析的报文：给出各协议识别器拒绝的原因和解析停止的字节位置
+#[tauri::command]
+pub fn diagnose_frame(message: String, region: String) -> Result<FrameDiagnosis, String> {
+    let frame_bytes = FrameFun::decode_hex_str(&message)?;
+    Ok(crate::basefunc::frame_diagnose::diagnose_frame(
+        &frame_bytes,
+        &region,
+    ))
+}
+
+/// 修正手工编辑过的报文，重新计算长度域和校验，返回修正后的报文和改动的字段
+#[tauri::command]
+pub fn repair_frame(frame: String, protocol: Option<FixupProtocol>) -> Result<FrameRepair, String> {
+    let frame_bytes = FrameFun::decode_hex_str(&frame)?;
+    crate::basefunc::frame_repair::repair_frame(&frame_bytes, protocol.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn da_and_measure_point_exchange(
     input: String,
@@ -707,13 +727,13 @@ pub struct FrameEntry {
     pub id: String,
     pub pid: u8,
     pub tag: u8,
-    pub tag_name: Option<String>,  // 添加标签名称字段
+    pub tag_name: Option<String>, // 添加标签名称字段
     pub port: u8,
-    pub port_name: Option<String>,  // 添加端口名称字段
+    pub port_name: Option<String>, // 添加端口名称字段
     pub protocol: u8,
-    pub protocol_name: Option<String>,  // 添加协议名称字段
+    pub protocol_name: Option<String>, // 添加协议名称字段
     pub direction: u8,
-    pub direction_name: Option<String>,  // 添加方向名称字段
+    pub direction_name: Option<String>, // 添加方向名称字段
     pub timestamp: String,
     pub content: String,
     pub raw_data: String,
@@ -733,35 +753,43 @@ pub async fn export_frames(
         .and_then(|n| n.to_str())
         .unwrap_or("export")
         .to_string();
-    
+
     let timestamp = Local::now().format("%Y%m%d_%H%M%S");
     let export_dir_name = format!("{}_{}", file_name, timestamp);
     let export_path = PathBuf::from(&export_dir).join(&export_dir_name);
-    
+
     fs::create_dir_all(&export_path).map_err(|e| e.to_string())?;
 
     let total_entries = entries.len();
     let mut processed_entries = 0;
 
     // 按标签类型分组，使用流式处理避免一次性加载所有数据
-    let mut writers: std::collections::HashMap<String, BufWriter<File>> = std::collections::HashMap::new();
-    
+    let mut writers: std::collections::HashMap<String, BufWriter<File>> =
+        std::collections::HashMap::new();
+
     for entry in &entries {
-        let tag_name = entry.tag_name.as_ref()
+        let tag_name = entry
+            .tag_name
+            .as_ref()
             .map(|s| s.to_string())
             .unwrap_or_else(|| format!("unknown_{}", entry.tag));
-        
-        let safe_tag_name = tag_name.chars()
-            .map(|c| if c.is_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+
+        let safe_tag_name = tag_name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '_' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
             .collect::<String>();
-        
+
         // 获取或创建文件写入器
-        let writer = writers
-            .entry(safe_tag_name.clone())
-            .or_insert_with(|| {
-                let file_path = export_path.join(format!("frame_{}.txt", safe_tag_name));
-                BufWriter::new(File::create(file_path).unwrap())
-            });
+        let writer = writers.entry(safe_tag_name.clone()).or_insert_with(|| {
+            let file_path = export_path.join(format!("frame_{}.txt", safe_tag_name));
+            BufWriter::new(File::create(file_path).unwrap())
+        });
 
         // 写入数据
         let line = format!(
@@ -769,14 +797,24 @@ pub async fn export_frames(
             entry.timestamp,
             entry.pid,
             entry.direction_name.as_ref().unwrap_or(
-                &(if entry.direction == 0 { "发送" } else { "接收" }).to_string()
+                &(if entry.direction == 0 {
+                    "发送"
+                } else {
+                    "接收"
+                })
+                .to_string()
             ),
             entry.port_name.as_ref().unwrap_or(&entry.port.to_string()),
-            entry.protocol_name.as_ref().unwrap_or(&entry.protocol.to_string()),
+            entry
+                .protocol_name
+                .as_ref()
+                .unwrap_or(&entry.protocol.to_string()),
             entry.content
         );
-        
-        writer.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+
+        writer
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())?;
 
         // 更新进度
         processed_entries += 1;
@@ -788,7 +826,9 @@ pub async fn export_frames(
                 percentage: (processed_entries as f32 / total_entries as f32) * 100.0,
             };
             if let Some(main_window) = window.get_webview_window("main") {
-                main_window.emit_to("main", "export-progress", &progress).map_err(|e| e.to_string())?;
+                main_window
+                    .emit_to("main", "export-progress", &progress)
+                    .map_err(|e| e.to_string())?;
             }
         }
     }
@@ -825,7 +865,7 @@ pub async fn export_logs(
     // 创建文件
     let file = File::create(&file_path).map_err(|e| e.to_string())?;
     let mut writer = BufWriter::new(file);
-    
+
     let total_entries = entries.len();
     let mut processed_entries = 0;
 
@@ -845,9 +885,9 @@ pub async fn export_logs(
             (None, None) => String::new(),
         };
 
-        let message = entry.message.unwrap_or_else(|| 
-            entry.raw_data.unwrap_or_default()
-        );
+        let message = entry
+            .message
+            .unwrap_or_else(|| entry.raw_data.unwrap_or_default());
 
         let log_line = format!(
             "{} {} {} {} {}{}\n",
@@ -859,14 +899,18 @@ pub async fn export_logs(
             message
         );
 
-        writer.write_all(log_line.as_bytes()).map_err(|e| e.to_string())?;
+        writer
+            .write_all(log_line.as_bytes())
+            .map_err(|e| e.to_string())?;
 
         // 更新进度
         processed_entries += 1;
         if processed_entries % 100 == 0 || processed_entries == total_entries {
             let progress = (processed_entries as f32 / total_entries as f32) * 100.0;
             if let Some(main_window) = window.get_webview_window("main") {
-                main_window.emit("export-progress", progress).map_err(|e| e.to_string())?;
+                main_window
+                    .emit("export-progress", progress)
+                    .map_err(|e| e.to_string())?;
             }
         }
     }
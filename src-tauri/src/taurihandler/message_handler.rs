@@ -0,0 +1,69 @@
+use crate::combridage::{
+    LogMigrationReport, MessageManager, MessagePage, MessageQuery, RetentionReport, StoragePolicy,
+    StorageUsage,
+};
+use tracing::info;
+
+/// 分页查询通道收发记录，支持按时间、通道、方向、协议、地址过滤及十六进制全文搜索
+#[tauri::command]
+pub async fn query_messages(
+    app_handle: tauri::AppHandle,
+    query: MessageQuery,
+) -> Result<MessagePage, String> {
+    let message_manager = MessageManager::new(app_handle).map_err(|e| e.to_string())?;
+    let store = message_manager.store().await.map_err(|e| e.to_string())?;
+    store.query(&query).await.map_err(|e| e.to_string())
+}
+
+/// 把旧版按日期保存的 .log 文件导入消息库，已导入的文件会被跳过
+#[tauri::command]
+pub async fn migrate_message_logs(
+    app_handle: tauri::AppHandle,
+) -> Result<LogMigrationReport, String> {
+    let message_manager = MessageManager::new(app_handle).map_err(|e| e.to_string())?;
+    let report = message_manager
+        .migrate_log_files()
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("Migrate message logs: {:?}", report);
+    Ok(report)
+}
+
+/// 按通道统计数据库、归档和旧版日志的存储占用
+#[tauri::command]
+pub async fn get_storage_usage(app_handle: tauri::AppHandle) -> Result<StorageUsage, String> {
+    let message_manager = MessageManager::new(app_handle).map_err(|e| e.to_string())?;
+    message_manager
+        .storage_usage()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_storage_policy() -> StoragePolicy {
+    StoragePolicy::load()
+}
+
+/// 保存保留策略并立即执行一次
+#[tauri::command]
+pub async fn set_storage_policy(
+    app_handle: tauri::AppHandle,
+    policy: StoragePolicy,
+) -> Result<RetentionReport, String> {
+    policy.save()?;
+    apply_storage_retention(app_handle).await
+}
+
+/// 立即按当前保留策略清理记录
+#[tauri::command]
+pub async fn apply_storage_retention(
+    app_handle: tauri::AppHandle,
+) -> Result<RetentionReport, String> {
+    let message_manager = MessageManager::new(app_handle).map_err(|e| e.to_string())?;
+    let report = message_manager
+        .apply_storage_policy(&StoragePolicy::load())
+        .await
+        .map_err(|e| e.to_string())?;
+    info!("Apply storage retention: {:?}", report);
+    Ok(report)
+}
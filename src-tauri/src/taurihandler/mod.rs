@@ -1,7 +1,10 @@
+pub mod capture_handler;
 pub mod channel_handler;
 pub mod dlt645_handler;
 pub mod handler;
+pub mod message_handler;
 pub mod protocol_handler;
+pub mod scenario_handler;
 pub use channel_handler::*;
 pub use dlt645_handler::*;
 pub use protocol_handler::*;
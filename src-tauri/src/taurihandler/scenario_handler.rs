@@ -0,0 +1,30 @@
+use crate::combridage::{run_scenario as run, Scenario, ScenarioReport};
+use tauri::Emitter;
+use tracing::info;
+
+/// 执行测试场景，content 为 YAML 内容，未提供时从 path 读取；每完成一步发送 scenario-step 事件
+#[tauri::command]
+pub async fn run_scenario(
+    app_handle: tauri::AppHandle,
+    content: Option<String>,
+    path: Option<String>,
+) -> Result<ScenarioReport, String> {
+    let scenario = match (content, path) {
+        (Some(content), _) => Scenario::from_yaml(&content),
+        (None, Some(path)) => Scenario::load(&path),
+        (None, None) => return Err("需要提供场景内容或文件路径".to_string()),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let report = run(scenario, |step| {
+        if let Err(e) = app_handle.emit("scenario-step", step) {
+            eprintln!("发送场景进度失败: {:?}", e);
+        }
+    })
+    .await;
+    info!(
+        "Scenario {} finished: passed={}, {} ms",
+        report.name, report.passed, report.duration_ms
+    );
+    Ok(report)
+}
@@ -0,0 +1,656 @@
+use super::parse::decode_hex;
+use super::{ApiResponse, ApiResult, AppState, EmptyResponse};
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_stream::FrameStream;
+use crate::basefunc::frame_template::expand_template;
+use crate::basefunc::protocol::FrameAnalisyic;
+use crate::combridage::{
+    channel_from_params, subscribe_records, ChannelType, ClientAddress, CommunicationChannel,
+    CommunicationManager, Message, MessageRecord, TimedSendJob, TimedSendOptions, TimedSendStatus,
+};
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info};
+use utoipa::{IntoParams, ToSchema};
+
+/// 网页端打开的通道，通道 ID 由 CommunicationManager 生成
+pub struct ChannelRegistry {
+    manager: CommunicationManager,
+    channels: HashMap<String, ChannelEntry>,
+}
+
+struct ChannelEntry {
+    channel: String,
+    params: Value,
+    channel_type: ChannelType,
+    // 定时发送任务，键为任务 ID
+    timers: HashMap<String, TimedSendJob>,
+}
+
+impl Default for ChannelRegistry {
+    fn default() -> Self {
+        Self {
+            manager: CommunicationManager::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+impl ChannelRegistry {
+    fn entry(&self, channel_id: &str) -> Result<&ChannelEntry, (StatusCode, String)> {
+        self.channels.get(channel_id).ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Channel ID not found: {}", channel_id),
+        ))
+    }
+
+    fn channel(&self, channel_id: &str) -> Option<Arc<Box<dyn CommunicationChannel>>> {
+        self.channels
+            .get(channel_id)
+            .and_then(|entry| self.manager.get_channel(&entry.channel_type))
+    }
+}
+
+// 服务端通道当前连接的客户端
+async fn client_ids(channel: Option<&Arc<Box<dyn CommunicationChannel>>>) -> Vec<String> {
+    match channel {
+        Some(channel) => channel.client_ids().await,
+        None => Vec::new(),
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ChannelInfo {
+    channel_id: String,
+    channel: String,
+    #[schema(value_type = Object)]
+    params: Value,
+    clients: Vec<String>,
+    #[schema(value_type = Vec<Object>)]
+    timers: Vec<TimedSendStatus>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ConnectRequest {
+    /// 通道类型，与桌面端 connect_channel 相同，如 tcpclient、serial
+    channel: String,
+    /// 通道参数，字段与桌面端相同，如 {"ip": "127.0.0.1", "port": 8080}
+    #[schema(value_type = Object)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ConnectResponse {
+    channel_id: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SendRequest {
+    /// 十六进制报文
+    frame: Option<String>,
+    /// 直接交给通道的消息内容，用于 MQTT 等需要主题的通道
+    #[schema(value_type = Option<Object>)]
+    content: Option<Value>,
+    /// 报文模板名称，展开后发送
+    template: Option<String>,
+    /// 展开模板使用的变量
+    #[serde(default)]
+    vars: HashMap<String, String>,
+    clientid: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct TimerRequest {
+    /// 任务名称，如“心跳”“抄读”
+    #[serde(default)]
+    name: String,
+    /// 十六进制报文，只发送一帧时使用
+    frame: Option<String>,
+    /// 依次循环发送的十六进制报文，排在 frame 之后
+    #[serde(default)]
+    frames: Vec<String>,
+    /// 依次循环发送的报文模板，排在 frames 之后，每次发送前展开
+    #[serde(default)]
+    templates: Vec<String>,
+    /// 展开模板使用的变量
+    #[serde(default)]
+    vars: HashMap<String, String>,
+    interval_ms: u64,
+    clientid: Option<String>,
+    /// 成功发送指定次数后停止
+    max_sends: Option<u64>,
+    /// 到达该时间（毫秒时间戳）后停止
+    stop_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct RecordsQuery {
+    /// 只推送该通道（含服务端通道的客户端）的记录
+    channel: Option<String>,
+    /// 解析使用的省份，默认使用 /api/region 的设置
+    region: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/channels", get(list_channels).post(connect_channel))
+        .route("/api/channels/:channel_id", delete(disconnect_channel))
+        .route("/api/channels/:channel_id/send", post(send_message))
+        .route("/api/channels/:channel_id/addresses", get(client_addresses))
+        .route(
+            "/api/channels/:channel_id/timer",
+            get(list_timers)
+                .post(start_timer_send)
+                .delete(stop_timer_send),
+        )
+        .route(
+            "/api/channels/:channel_id/timer/:job_id",
+            delete(stop_timer_job),
+        )
+        .route("/ws/records", get(records_ws))
+}
+
+// 获取已打开的通道
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    tag = "channel",
+    responses((status = 200, body = ApiResponse<Vec<ChannelInfo>>))
+)]
+async fn list_channels(State(state): State<AppState>) -> ApiResult<Vec<ChannelInfo>> {
+    // 先取出通道信息再查询客户端，查询期间不持有通道表的锁
+    let entries: Vec<_> = {
+        let registry = state.channels.lock().await;
+        registry
+            .channels
+            .iter()
+            .map(|(channel_id, entry)| {
+                let info = ChannelInfo {
+                    channel_id: channel_id.clone(),
+                    channel: entry.channel.clone(),
+                    params: entry.params.clone(),
+                    clients: Vec::new(),
+                    timers: timer_statuses(entry),
+                };
+                (info, registry.channel(channel_id))
+            })
+            .collect()
+    };
+    let mut channels = Vec::new();
+    for (mut info, channel) in entries {
+        info.clients = client_ids(channel.as_ref()).await;
+        channels.push(info);
+    }
+    channels.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+    ApiResponse::ok(channels)
+}
+
+// 连接通道
+#[utoipa::path(
+    post,
+    path = "/api/channels",
+    tag = "channel",
+    request_body = ConnectRequest,
+    responses(
+        (status = 200, body = ApiResponse<ConnectResponse>),
+        (status = 400, description = "通道类型或参数错误", body = EmptyResponse),
+        (status = 409, description = "相同类型和参数的通道已打开", body = EmptyResponse),
+        (status = 502, description = "打开通道失败", body = EmptyResponse),
+    )
+)]
+async fn connect_channel(
+    State(state): State<AppState>,
+    Json(payload): Json<ConnectRequest>,
+) -> ApiResult<ConnectResponse> {
+    let (channel_type, options) = match channel_from_params(&payload.channel, &payload.params) {
+        Ok(result) => result,
+        Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+    };
+    // 管理器按通道类型和参数保存通道，相同的通道只能打开一个
+    if state
+        .channels
+        .lock()
+        .await
+        .manager
+        .get_channel(&channel_type)
+        .is_some()
+    {
+        return duplicate_channel(&payload.channel);
+    }
+    // 连接可能要等待超时，期间不持有通道表的锁，以免阻塞其他请求
+    let channel = match CommunicationManager::open_channel(&channel_type, options).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            return ApiResponse::fail(
+                StatusCode::BAD_GATEWAY,
+                format!("Failed to add channel: {}", e),
+            )
+        }
+    };
+    let mut registry = state.channels.lock().await;
+    // 打开期间其他请求可能已打开相同的通道，关闭这次打开的通道
+    if registry.manager.get_channel(&channel_type).is_some() {
+        drop(registry);
+        if let Err(e) = channel.close().await {
+            error!("关闭重复打开的通道失败: {}", e);
+        }
+        return duplicate_channel(&payload.channel);
+    }
+    let channel_id = registry
+        .manager
+        .insert_channel(channel_type.clone(), channel);
+    info!("web 连接通道 {}: {}", payload.channel, channel_id);
+    registry.channels.insert(
+        channel_id.clone(),
+        ChannelEntry {
+            channel: payload.channel,
+            params: payload.params,
+            channel_type,
+            timers: HashMap::new(),
+        },
+    );
+    ApiResponse::ok(ConnectResponse { channel_id })
+}
+
+fn duplicate_channel(channel: &str) -> ApiResult<ConnectResponse> {
+    ApiResponse::fail(
+        StatusCode::CONFLICT,
+        format!("相同参数的 {} 通道已打开", channel),
+    )
+}
+
+// 断开通道，同时停止定时发送
+#[utoipa::path(
+    delete,
+    path = "/api/channels/{channel_id}",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    responses(
+        (status = 200, body = EmptyResponse),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+    )
+)]
+async fn disconnect_channel(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+) -> ApiResult<()> {
+    let channel = {
+        let mut registry = state.channels.lock().await;
+        let Some(entry) = registry.channels.remove(&channel_id) else {
+            return ApiResponse::fail(
+                StatusCode::NOT_FOUND,
+                format!("Channel ID not found: {}", channel_id),
+            );
+        };
+        drop(entry.timers);
+        registry.manager.remove_channel(&entry.channel_type)
+    };
+    // 关闭可能要等待对端，期间不持有通道表的锁
+    let Some(channel) = channel else {
+        return ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel not found: {}", channel_id),
+        );
+    };
+    if let Err(e) = channel.close().await {
+        return ApiResponse::fail(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to close channel: {}", e),
+        );
+    }
+    info!("web 断开通道 {}", channel_id);
+    ApiResponse::ok(())
+}
+
+// 发送报文
+#[utoipa::path(
+    post,
+    path = "/api/channels/{channel_id}/send",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    request_body = SendRequest,
+    responses(
+        (status = 200, body = EmptyResponse),
+        (status = 400, description = "报文格式错误或模板展开失败", body = EmptyResponse),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+        (status = 502, description = "发送失败", body = EmptyResponse),
+    )
+)]
+async fn send_message(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<SendRequest>,
+) -> ApiResult<()> {
+    let content = match (payload.frame, payload.content, payload.template) {
+        (Some(frame), None, None) => match decode_hex(&frame) {
+            Ok(data) => json!({ "data": data }),
+            Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+        },
+        (None, Some(content), None) => content,
+        (None, None, Some(template)) => match expand_template(&template, &payload.vars) {
+            Ok(data) => json!({ "data": data }),
+            Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e.to_string()),
+        },
+        _ => {
+            return ApiResponse::fail(
+                StatusCode::BAD_REQUEST,
+                "frame、content 和 template 需要且只能指定一个".to_string(),
+            )
+        }
+    };
+    // 发送可能阻塞（如串口、TCP 写缓冲已满），期间不持有通道表的锁
+    let channel = {
+        let registry = state.channels.lock().await;
+        if let Err((status, e)) = registry.entry(&channel_id) {
+            return ApiResponse::fail(status, e);
+        }
+        registry.channel(&channel_id)
+    };
+    let Some(channel) = channel else {
+        return ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel not found: {}", channel_id),
+        );
+    };
+    match channel.send(&Message::new(content), payload.clientid).await {
+        Ok(()) => ApiResponse::ok(()),
+        Err(e) => ApiResponse::fail(StatusCode::BAD_GATEWAY, format!("发送消息失败: {}", e)),
+    }
+}
+
+// 获取 TCP 服务端通道已识别的终端/电表地址
+#[utoipa::path(
+    get,
+    path = "/api/channels/{channel_id}/addresses",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    responses(
+        (status = 200, description = "data 为地址列表，含所在客户端、连接时间和最近收到报文的时间", body = Object),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+    )
+)]
+async fn client_addresses(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+) -> ApiResult<Vec<ClientAddress>> {
+    let channel = {
+        let registry = state.channels.lock().await;
+        let entry = match registry.entry(&channel_id) {
+            Ok(entry) => entry,
+            Err((status, e)) => return ApiResponse::fail(status, e),
+        };
+        registry.manager.get_channel(&entry.channel_type)
+    };
+    match channel {
+        Some(channel) => ApiResponse::ok(channel.client_addresses().await),
+        None => ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel not found: {}", channel_id),
+        ),
+    }
+}
+
+// 启动定时发送，同一通道可以同时运行多个任务
+#[utoipa::path(
+    post,
+    path = "/api/channels/{channel_id}/timer",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    request_body = TimerRequest,
+    responses(
+        (status = 200, description = "data 为任务状态，含任务 ID", body = Object),
+        (status = 400, description = "报文、间隔或停止条件错误", body = EmptyResponse),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+    )
+)]
+async fn start_timer_send(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<TimerRequest>,
+) -> ApiResult<TimedSendStatus> {
+    let mut frames = Vec::new();
+    for frame in payload.frame.iter().chain(&payload.frames) {
+        match decode_hex(frame) {
+            Ok(frame) => frames.push(frame),
+            Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+        }
+    }
+    let mut registry = state.channels.lock().await;
+    let channel = match registry.entry(&channel_id) {
+        Ok(entry) => registry.manager.get_channel(&entry.channel_type),
+        Err((status, e)) => return ApiResponse::fail(status, e),
+    };
+    let Some(channel) = channel else {
+        return ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel not found: {}", channel_id),
+        );
+    };
+
+    let options = TimedSendOptions {
+        name: payload.name,
+        interval_ms: payload.interval_ms,
+        frames,
+        templates: payload.templates,
+        vars: payload.vars,
+        clientid: payload.clientid,
+        max_sends: payload.max_sends,
+        stop_at: payload.stop_at,
+    };
+    // 任务直接持有通道，不占用通道表的锁
+    let job = match TimedSendJob::start(channel, options) {
+        Ok(job) => job,
+        Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let status = job.status();
+    info!("web 通道 {} 启动定时发送任务 {}", channel_id, status.job_id);
+    if let Some(entry) = registry.channels.get_mut(&channel_id) {
+        entry.timers.insert(status.job_id.clone(), job);
+    }
+    ApiResponse::ok(status)
+}
+
+// 获取通道上的定时发送任务
+#[utoipa::path(
+    get,
+    path = "/api/channels/{channel_id}/timer",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    responses(
+        (status = 200, description = "data 为任务状态列表，已结束的任务保留到被停止为止", body = Object),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+    )
+)]
+async fn list_timers(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+) -> ApiResult<Vec<TimedSendStatus>> {
+    let registry = state.channels.lock().await;
+    match registry.entry(&channel_id) {
+        Ok(entry) => ApiResponse::ok(timer_statuses(entry)),
+        Err((status, e)) => ApiResponse::fail(status, e),
+    }
+}
+
+// 停止通道上的所有定时发送任务
+#[utoipa::path(
+    delete,
+    path = "/api/channels/{channel_id}/timer",
+    tag = "channel",
+    params(("channel_id" = String, Path, description = "通道 ID")),
+    responses(
+        (status = 200, description = "data 为停止时的任务状态列表", body = Object),
+        (status = 404, description = "通道不存在", body = EmptyResponse),
+    )
+)]
+async fn stop_timer_send(
+    State(state): State<AppState>,
+    Path(channel_id): Path<String>,
+) -> ApiResult<Vec<TimedSendStatus>> {
+    let mut registry = state.channels.lock().await;
+    match registry.channels.get_mut(&channel_id) {
+        Some(entry) => {
+            let mut stopped: Vec<TimedSendStatus> =
+                entry.timers.drain().map(|(_, job)| job.stop()).collect();
+            stopped.sort_by_key(|status| status.started_at);
+            ApiResponse::ok(stopped)
+        }
+        None => ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel ID not found: {}", channel_id),
+        ),
+    }
+}
+
+// 停止一个定时发送任务
+#[utoipa::path(
+    delete,
+    path = "/api/channels/{channel_id}/timer/{job_id}",
+    tag = "channel",
+    params(
+        ("channel_id" = String, Path, description = "通道 ID"),
+        ("job_id" = String, Path, description = "定时发送任务 ID"),
+    ),
+    responses(
+        (status = 200, description = "data 为停止时的任务状态", body = Object),
+        (status = 404, description = "通道或任务不存在", body = EmptyResponse),
+    )
+)]
+async fn stop_timer_job(
+    State(state): State<AppState>,
+    Path((channel_id, job_id)): Path<(String, String)>,
+) -> ApiResult<TimedSendStatus> {
+    let mut registry = state.channels.lock().await;
+    let Some(entry) = registry.channels.get_mut(&channel_id) else {
+        return ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Channel ID not found: {}", channel_id),
+        );
+    };
+    match entry.timers.remove(&job_id) {
+        Some(job) => ApiResponse::ok(job.stop()),
+        None => ApiResponse::fail(
+            StatusCode::NOT_FOUND,
+            format!("Timer job not found: {}", job_id),
+        ),
+    }
+}
+
+fn timer_statuses(entry: &ChannelEntry) -> Vec<TimedSendStatus> {
+    let mut statuses: Vec<TimedSendStatus> =
+        entry.timers.values().map(TimedSendJob::status).collect();
+    statuses.sort_by_key(|status| status.started_at);
+    statuses
+}
+
+// 推送收发记录及解析结果
+#[utoipa::path(
+    get,
+    path = "/ws/records",
+    tag = "channel",
+    params(RecordsQuery),
+    responses((
+        status = 101,
+        description = "升级为 WebSocket，每条文本消息为 {record, frames}，推送不及时丢弃记录时为 {lagged}"
+    ))
+)]
+async fn records_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<RecordsQuery>,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_records(socket, state, query))
+}
+
+// 记住的非订阅通道来源数量上限，超过后清空重新积累
+const MAX_OTHER_SOURCES: usize = 1024;
+
+async fn stream_records(mut socket: WebSocket, state: AppState, query: RecordsQuery) {
+    let mut records = subscribe_records();
+    // 订阅的通道只查找一次，之后刷新客户端列表不再经过通道表的锁
+    let channel = match &query.channel {
+        Some(channel_id) => state.channels.lock().await.channel(channel_id),
+        None => None,
+    };
+    let mut clients: HashSet<String> = HashSet::new();
+    // 已确认不属于订阅通道的来源。服务端在收到客户端数据之前就已登记该客户端，
+    // 因此每个新来源最多刷新一次客户端列表
+    let mut others: HashSet<String> = HashSet::new();
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                // 客户端发来的其他消息忽略
+                Some(Ok(_)) => {}
+            },
+            record = records.recv() => {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(RecvError::Lagged(n)) => {
+                        // 推送跟不上时告知客户端丢失的记录数
+                        let notice = json!({ "lagged": n }).to_string();
+                        if socket.send(WsMessage::Text(notice)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let Some(channel_id) = &query.channel {
+                    let source = record.channel_id();
+                    if source != channel_id && !clients.contains(source) {
+                        if others.contains(source) {
+                            continue;
+                        }
+                        if let Some(channel) = &channel {
+                            clients = channel.client_ids().await.into_iter().collect();
+                        }
+                        if !clients.contains(source) {
+                            if others.len() >= MAX_OTHER_SOURCES {
+                                others.clear();
+                            }
+                            others.insert(source.to_string());
+                            continue;
+                        }
+                    }
+                }
+                let region = match &query.region {
+                    Some(region) => region.clone(),
+                    None => state.region.read().await.clone(),
+                };
+                let payload = record_payload(&record, &region).to_string();
+                if socket.send(WsMessage::Text(payload)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// 记录本身加上按帧切分后的解析结果
+fn record_payload(record: &MessageRecord, region: &str) -> Value {
+    let frames: Vec<Value> = FrameStream::split(&record.payload_bytes())
+        .into_iter()
+        .map(|frame| {
+            let (protocol, result, _) = FrameAnalisyic::process_frame_safe(&frame, region);
+            json!({
+                "frame": FrameFun::get_data_str_with_space(&frame),
+                "protocol": protocol,
+                "result": result,
+            })
+        })
+        .collect();
+    json!({
+        "record": record,
+        "frames": frames,
+    })
+}
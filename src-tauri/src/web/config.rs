@@ -0,0 +1,118 @@
+use crate::combridage::TlsConfig;
+use crate::config::appconfig::load_config_value;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tracing::warn;
+
+// appconfig.json 中 Web 服务的配置段
+const CONFIG_SECTION: &str = "webserver";
+
+/// Web 服务配置，对应 appconfig.json 的 webserver 段，各项均可省略
+///
+/// 环境变量 HOST、PORT 优先于配置文件中的 host、port；
+/// 默认只监听本机，监听其他地址时必须配置认证
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub auth: AuthConfig,
+    pub rate_limit: RateLimitConfig,
+    /// 请求体的最大字节数
+    pub max_body_bytes: usize,
+    /// 允许跨域访问的来源，为空时不允许跨域访问；
+    /// 默认允许开发时的 vite 页面（src/api/web.ts 的 WEB_API_BASE）
+    pub cors_origins: Vec<String>,
+    /// 反向代理的地址，来自这些地址的请求按 X-Real-IP、X-Forwarded-For 确定客户端 IP，
+    /// 用于访问日志和限流；默认信任本机（deploy/nginx.conf 部署在同一台机器）
+    pub trusted_proxies: Vec<IpAddr>,
+    /// 启用后使用 HTTPS，cert_file、key_file 必填，require_client_cert 时还需 ca_file
+    pub tls: TlsConfig,
+}
+
+/// 接口认证，tokens 与 basic 都未配置时不做认证
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// 接受的 API Token，通过 `Authorization: Bearer <token>`、`X-Api-Token` 请求头
+    /// 或 `token` 查询参数（浏览器的 WebSocket 无法设置请求头）传入
+    pub tokens: Vec<String>,
+    /// HTTP Basic 认证的用户名和密码
+    pub basic: Option<BasicAuth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// 按客户端 IP 限流，允许短时突发 burst 个请求，之后每秒恢复 per_second 个
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// 为 0 时不限流
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl Default for WebServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3000,
+            auth: AuthConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            max_body_bytes: 1024 * 1024,
+            cors_origins: vec![
+                "http://localhost:1420".to_string(),
+                "http://127.0.0.1:1420".to_string(),
+            ],
+            trusted_proxies: vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_second: 20,
+            burst: 50,
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn enabled(&self) -> bool {
+        self.tokens.iter().any(|token| !token.is_empty()) || self.basic.is_some()
+    }
+}
+
+impl WebServerConfig {
+    /// 读取配置文件中的 webserver 段，缺失或格式错误时使用默认值
+    pub fn load() -> Self {
+        let mut config = match load_config_value(CONFIG_SECTION, "") {
+            Some(value) => serde_json::from_value(value).unwrap_or_else(|e| {
+                warn!("Web 服务配置格式错误，使用默认配置: {}", e);
+                Self::default()
+            }),
+            None => Self::default(),
+        };
+        if let Ok(host) = std::env::var("HOST") {
+            config.host = host;
+        }
+        if let Some(port) = std::env::var("PORT").ok().and_then(|p| p.parse().ok()) {
+            config.port = port;
+        }
+        config
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        let ip = self.host.parse::<IpAddr>().unwrap_or_else(|_| {
+            warn!("无效的监听地址 {}，使用 127.0.0.1", self.host);
+            IpAddr::from([127, 0, 0, 1])
+        });
+        SocketAddr::from((ip, self.port))
+    }
+}
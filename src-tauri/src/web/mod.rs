@@ -0,0 +1,479 @@
+mod channel;
+mod config;
+mod parse;
+mod security;
+mod template;
+
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::protocol::FrameAnalisyic;
+use crate::config::xmlconfig::{
+    GLOBAL_Moudle, ItemConfigList, ProtocolConfigManager, GLOBAL_645, GLOBAL_CSG13, GLOBAL_CSG16,
+    GLOBAL_MS,
+};
+#[cfg(feature = "web")]
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderValue, StatusCode},
+    middleware,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use channel::ChannelRegistry;
+use config::WebServerConfig;
+use security::RateLimiter;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Mutex, RwLock};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{error, info};
+use utoipa::{OpenApi, ToSchema};
+
+// 状态管理
+#[derive(Default, Clone)]
+pub struct AppState {
+    region: Arc<RwLock<String>>,
+    channels: Arc<Mutex<ChannelRegistry>>,
+}
+
+/// 接口通用响应，成功时 data 有值，失败时 error 为错误信息
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiResponse<T> {
+    data: Option<T>,
+    error: Option<String>,
+}
+
+pub type ApiResult<T> = (StatusCode, Json<ApiResponse<T>>);
+
+/// 不带数据的响应，data 总为 null，仅用于接口文档
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct EmptyResponse {
+    #[schema(value_type = Option<Object>)]
+    data: Option<()>,
+    error: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn ok(data: T) -> ApiResult<T> {
+        (
+            StatusCode::OK,
+            Json(Self {
+                data: Some(data),
+                error: None,
+            }),
+        )
+    }
+
+    pub fn fail(status: StatusCode, error: String) -> ApiResult<T> {
+        (
+            status,
+            Json(Self {
+                data: None,
+                error: Some(error),
+            }),
+        )
+    }
+}
+
+/// 接口描述文档，由各接口的请求、响应类型生成
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "EmbedTalk Web API",
+        description = "报文解析、协议配置与通道控制接口"
+    ),
+    paths(
+        health_check,
+        parse_text,
+        parse::parse_batch,
+        parse::diagnose,
+        parse::repair,
+        get_region,
+        set_region,
+        get_protocol_config,
+        get_protocol_list,
+        get_csg13_list,
+        get_csg16_list,
+        get_dlt645_list,
+        get_module_list,
+        channel::list_channels,
+        channel::connect_channel,
+        channel::disconnect_channel,
+        channel::send_message,
+        channel::client_addresses,
+        channel::list_timers,
+        channel::start_timer_send,
+        channel::stop_timer_send,
+        channel::stop_timer_job,
+        channel::records_ws,
+        template::list_templates,
+        template::save_template,
+        template::delete_template,
+        template::preview_template,
+    )
+)]
+struct ApiDoc;
+
+// 请求和响应类型
+#[derive(Debug, Deserialize, ToSchema)]
+struct ParseTextRequest {
+    message: String,
+    region: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ParseResponse {
+    #[schema(value_type = Vec<Object>)]
+    data: Vec<serde_json::Value>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct GetProtocolConfigRequest {
+    item_id: String,
+    protocol: String,
+    region: String,
+    dir: Option<u8>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ProtocolListResponse {
+    #[schema(value_type = Vec<Object>)]
+    items: Vec<ItemConfigList>,
+    error: Option<String>,
+}
+
+#[cfg(feature = "web")]
+/// 启动 Web 服务，配置错误或监听失败时返回错误
+pub async fn start_web_server() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config = WebServerConfig::load();
+
+    // 创建应用状态
+    let state = AppState {
+        region: Arc::new(RwLock::new("南网".to_string())),
+        channels: Arc::new(Mutex::new(ChannelRegistry::default())),
+    };
+
+    // 创建CORS中间件，只允许配置的来源跨域访问，未配置时不允许跨域
+    let origins = AllowOrigin::list(
+        config
+            .cors_origins
+            .iter()
+            .filter_map(|origin| origin.parse::<HeaderValue>().ok()),
+    );
+    let cors = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    // 创建路由
+    let mut app = Router::new()
+        .route("/health", get(health_check))
+        .route("/api/parse", post(parse_text))
+        .route("/api/parse/batch", post(parse::parse_batch))
+        .route("/api/parse/diagnose", post(parse::diagnose))
+        .route("/api/frame/repair", post(parse::repair))
+        .route("/api/openapi.json", get(openapi_document))
+        .route("/api/region", get(get_region))
+        .route("/api/region", post(set_region))
+        .route("/api/protocol/config", post(get_protocol_config))
+        .route("/api/protocol/list", get(get_protocol_list))
+        .route("/api/protocol/list/csg13", get(get_csg13_list))
+        .route("/api/protocol/list/csg16", get(get_csg16_list))
+        .route("/api/protocol/list/dlt645", get(get_dlt645_list))
+        .route("/api/protocol/list/module", get(get_module_list))
+        .merge(channel::routes())
+        .merge(template::routes())
+        .with_state(state)
+        .layer(DefaultBodyLimit::max(config.max_body_bytes));
+
+    // 中间件由内向外依次为认证、限流、CORS、访问日志，
+    // 预检请求由 CORS 直接应答，不需要认证
+    if config.auth.enabled() {
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(config.auth.clone()),
+            security::require_auth,
+        ));
+    } else if !config.addr().ip().is_loopback() {
+        // 未认证的接口可以打开串口、连接设备，不能暴露到本机以外
+        return Err(format!(
+            "Web 服务监听 {} 时必须配置认证（webserver.auth），或改为监听 127.0.0.1",
+            config.addr()
+        )
+        .into());
+    }
+    if let Some(limiter) = RateLimiter::new(&config.rate_limit) {
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(limiter),
+            security::rate_limit,
+        ));
+    }
+    let app = app
+        .layer(cors)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(config.trusted_proxies.clone()),
+            security::access_log,
+        ))
+        .into_make_service_with_connect_info::<SocketAddr>();
+
+    let addr = config.addr();
+    let tls = config
+        .tls
+        .server_config()
+        .map_err(|e| format!("Web 服务 TLS 配置错误: {}", e))?;
+
+    // 启动服务器
+    if let Some(tls) = tls {
+        println!("Web server listening on https://{}", addr);
+        axum_server::bind_rustls(addr, RustlsConfig::from_config(tls))
+            .serve(app)
+            .await
+            .map_err(|e| format!("Web 服务启动失败: {}", e))?;
+    } else {
+        println!("Web server listening on http://{}", addr);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| format!("Web 服务绑定 {} 失败: {}", addr, e))?;
+        axum::serve(listener, app)
+            .await
+            .map_err(|e| format!("Web 服务异常退出: {}", e))?;
+    }
+    Ok(())
+}
+
+// 接口描述文档（OpenAPI）
+async fn openapi_document() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// 健康检查接口
+#[utoipa::path(get, path = "/health", responses((status = 200, body = String)))]
+async fn health_check() -> &'static str {
+    "OK"
+}
+
+// 获取协议配置
+#[utoipa::path(
+    post,
+    path = "/api/protocol/config",
+    tag = "protocol",
+    request_body = GetProtocolConfigRequest,
+    responses((status = 200, description = "数据项配置，不存在时为 null", body = Object))
+)]
+async fn get_protocol_config(
+    Json(payload): Json<GetProtocolConfigRequest>,
+) -> Json<serde_json::Value> {
+    if let Some(config) = ProtocolConfigManager::get_config_xml(
+        &payload.item_id,
+        &payload.protocol,
+        &payload.region,
+        payload.dir,
+    ) {
+        Json(serde_json::to_value(config).unwrap_or_default())
+    } else {
+        Json(serde_json::Value::Null)
+    }
+}
+
+// 获取所有协议列表
+#[utoipa::path(
+    get,
+    path = "/api/protocol/list",
+    tag = "protocol",
+    responses((status = 200, description = "所有协议的数据项列表", body = ProtocolListResponse))
+)]
+async fn get_protocol_list() -> Json<ProtocolListResponse> {
+    let mut all_items = Vec::new();
+
+    // 收集CSG13协议项
+    if let Ok(csg13) = &*GLOBAL_CSG13 {
+        let items = csg13.get_all_item().await;
+        all_items.extend(items);
+    }
+
+    // 收集CSG16协议项
+    if let Ok(csg16) = &*GLOBAL_CSG16 {
+        let items = csg16.get_all_item().await;
+        all_items.extend(items);
+    }
+
+    // 收集DLT645协议项
+    if let Ok(dlt645) = &*GLOBAL_645 {
+        let items = dlt645.get_all_item().await;
+        all_items.extend(items);
+    }
+
+    // 收集模块协议项
+    if let Ok(module) = &*GLOBAL_Moudle {
+        let items = module.get_all_item().await;
+        all_items.extend(items);
+    }
+
+    Json(ProtocolListResponse {
+        items: all_items,
+        error: None,
+    })
+}
+
+// 获取CSG13协议列表
+#[utoipa::path(
+    get,
+    path = "/api/protocol/list/csg13",
+    tag = "protocol",
+    responses((status = 200, description = "南网13数据项列表", body = ProtocolListResponse))
+)]
+async fn get_csg13_list() -> Json<ProtocolListResponse> {
+    if let Ok(csg13) = &*GLOBAL_CSG13 {
+        let items = csg13.get_all_item().await;
+        return Json(ProtocolListResponse { items, error: None });
+    }
+    Json(ProtocolListResponse {
+        items: Vec::new(),
+        error: Some("Failed to get CSG13 protocol list".to_string()),
+    })
+}
+
+// 获取CSG16协议列表
+#[utoipa::path(
+    get,
+    path = "/api/protocol/list/csg16",
+    tag = "protocol",
+    responses((status = 200, description = "南网16数据项列表", body = ProtocolListResponse))
+)]
+async fn get_csg16_list() -> Json<ProtocolListResponse> {
+    if let Ok(csg16) = &*GLOBAL_CSG16 {
+        let items = csg16.get_all_item().await;
+        return Json(ProtocolListResponse { items, error: None });
+    }
+    Json(ProtocolListResponse {
+        items: Vec::new(),
+        error: Some("Failed to get CSG16 protocol list".to_string()),
+    })
+}
+
+// 获取DLT645协议列表
+#[utoipa::path(
+    get,
+    path = "/api/protocol/list/dlt645",
+    tag = "protocol",
+    responses((status = 200, description = "DL/T 645 数据项列表", body = ProtocolListResponse))
+)]
+async fn get_dlt645_list() -> Json<ProtocolListResponse> {
+    if let Ok(dlt645) = &*GLOBAL_645 {
+        let items = dlt645.get_all_item().await;
+        return Json(ProtocolListResponse { items, error: None });
+    }
+    Json(ProtocolListResponse {
+        items: Vec::new(),
+        error: Some("Failed to get DLT645 protocol list".to_string()),
+    })
+}
+
+// 获取模块协议列表
+#[utoipa::path(
+    get,
+    path = "/api/protocol/list/module",
+    tag = "protocol",
+    responses((status = 200, description = "模块协议数据项列表", body = ProtocolListResponse))
+)]
+async fn get_module_list() -> Json<ProtocolListResponse> {
+    if let Ok(module) = &*GLOBAL_Moudle {
+        let items = module.get_all_item().await;
+        return Json(ProtocolListResponse { items, error: None });
+    }
+    Json(ProtocolListResponse {
+        items: Vec::new(),
+        error: Some("Failed to get Module protocol list".to_string()),
+    })
+}
+
+// 解析报文
+#[utoipa::path(
+    post,
+    path = "/api/parse",
+    tag = "parse",
+    request_body = ParseTextRequest,
+    responses((status = 200, description = "解析结果，失败时 error 为原因", body = ParseResponse))
+)]
+async fn parse_text(
+    State(state): State<AppState>,
+    Json(payload): Json<ParseTextRequest>,
+) -> Json<ParseResponse> {
+    use std::panic;
+
+    if payload.message.is_empty() {
+        return Json(ParseResponse {
+            data: Vec::new(),
+            error: Some("Invalid hex message".to_string()),
+        });
+    }
+
+    let start_time = Instant::now();
+    info!("Received message: {} {}", payload.message, payload.region);
+
+    let result = panic::catch_unwind(|| {
+        let message_cleaned = payload.message.replace(' ', "").replace('\n', "");
+        if !message_cleaned.chars().all(|c| c.is_digit(16)) || message_cleaned.len() % 2 != 0 {
+            info!("Invalid hex message: {}", payload.message);
+            return ParseResponse {
+                data: Vec::new(),
+                error: Some("Invalid hex message".to_string()),
+            };
+        }
+
+        let frame = hex::decode(&message_cleaned).unwrap_or_default();
+        info!(
+            "Frame: {:?} duration: {:?}",
+            frame,
+            start_time.elapsed().as_millis()
+        );
+        let (_, processed_result) = FrameAnalisyic::process_frame(&frame, &payload.region);
+        info!("Result: {:?}", processed_result);
+
+        ParseResponse {
+            data: processed_result,
+            error: None,
+        }
+    });
+
+    match result {
+        Ok(response) => {
+            let duration = start_time.elapsed();
+            info!("parse_text duration: {:?}", duration.as_millis());
+            Json(response)
+        }
+        Err(e) => {
+            let message = FrameFun::panic_message(&e);
+            error!("parse_text panic: {}", message);
+            Json(ParseResponse {
+                data: Vec::new(),
+                error: Some(format!("解析出错: {}", message)),
+            })
+        }
+    }
+}
+
+// 获取区域值
+#[utoipa::path(get, path = "/api/region", tag = "parse", responses((status = 200, body = String)))]
+async fn get_region(State(state): State<AppState>) -> Json<String> {
+    let region = state.region.read().await;
+    Json(region.to_string())
+}
+
+// 设置区域值
+#[utoipa::path(
+    post,
+    path = "/api/region",
+    tag = "parse",
+    request_body = String,
+    responses((status = 200, description = "设置后的省份", body = String))
+)]
+async fn set_region(State(state): State<AppState>, Json(new_region): Json<String>) -> Json<String> {
+    let mut region = state.region.write().await;
+    *region = new_region;
+    Json(region.to_string())
+}
@@ -0,0 +1,287 @@
+use super::{ApiResponse, ApiResult, AppState, EmptyResponse};
+use crate::basefunc::frame_diagnose::{diagnose_frame, FrameDiagnosis};
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_repair::{repair_frame, FixupProtocol, FrameRepair};
+use crate::basefunc::frame_stream::FrameStream;
+use crate::basefunc::protocol::FrameAnalisyic;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// 批量解析请求，frames 与 text 可同时提供，结果中 frames 在前
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchParseRequest {
+    /// 报文列表，每项按一帧解析
+    #[serde(default)]
+    pub frames: Vec<String>,
+    /// 多行文本，连续的合法行拼接后按帧格式自动切分，一帧可以跨行
+    pub text: Option<String>,
+    /// 解析使用的省份，默认使用 /api/region 的设置
+    pub region: Option<String>,
+}
+
+/// 单帧的解析结果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchParseItem {
+    /// 在结果列表中的序号，从 0 开始
+    pub index: usize,
+    /// 报文来源：frames[序号]，或 text:行号、text:起始行-结束行（行号从 1 开始）
+    pub source: String,
+    /// 报文内容，无法转换为字节时为原文
+    pub frame: String,
+    /// 识别出的协议，报文格式错误时为空
+    pub protocol: Option<String>,
+    /// 解析结果，出错时只包含出错前已解析的部分
+    #[schema(value_type = Vec<Object>)]
+    pub data: Vec<Value>,
+    /// 失败原因，成功时为空
+    pub error: Option<String>,
+}
+
+/// 批量解析结果
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchParseResponse {
+    pub total: usize,
+    pub failed: usize,
+    pub items: Vec<BatchParseItem>,
+}
+
+/// 报文诊断请求
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DiagnoseRequest {
+    /// 按一帧诊断的报文
+    pub message: String,
+    /// 解析使用的省份，默认使用 /api/region 的设置
+    pub region: Option<String>,
+}
+
+/// 报文修正请求
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RepairRequest {
+    /// 手工编辑过的报文
+    pub frame: String,
+    /// auto、csg、dlt645、dlt698、modbus_rtu 或 modbus_tcp，默认按帧结构识别
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub protocol: FixupProtocol,
+}
+
+// 待解析的一段报文，Err 为报文格式错误
+struct Piece {
+    source: String,
+    frame: Result<Vec<u8>, (String, String)>,
+}
+
+/// 批量解析报文，每帧单独给出结果或失败原因
+#[utoipa::path(
+    post,
+    path = "/api/parse/batch",
+    tag = "parse",
+    request_body = BatchParseRequest,
+    responses(
+        (status = 200, description = "每帧的解析结果", body = BatchParseResponse),
+        (status = 400, description = "请求中没有报文", body = EmptyResponse),
+    )
+)]
+pub async fn parse_batch(
+    State(state): State<AppState>,
+    Json(payload): Json<BatchParseRequest>,
+) -> Result<Json<BatchParseResponse>, ApiResult<()>> {
+    let start_time = Instant::now();
+    let mut pieces: Vec<Piece> = payload
+        .frames
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Piece {
+            source: format!("frames[{}]", i),
+            frame: decode_hex(text).map_err(|e| (text.clone(), e)),
+        })
+        .collect();
+    if let Some(text) = &payload.text {
+        pieces.extend(split_text(text));
+    }
+    if pieces.is_empty() {
+        return Err(ApiResponse::fail(
+            StatusCode::BAD_REQUEST,
+            "请求中没有报文".to_string(),
+        ));
+    }
+    let region = match payload.region {
+        Some(region) => region,
+        None => state.region.read().await.clone(),
+    };
+
+    // 解析较耗时，放到阻塞线程中执行
+    let items = tokio::task::spawn_blocking(move || {
+        pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, piece)| parse_piece(index, piece, &region))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| ApiResponse::fail(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let failed = items.iter().filter(|item| item.error.is_some()).count();
+    info!(
+        "parse_batch: {} 帧，失败 {}，耗时 {} ms",
+        items.len(),
+        failed,
+        start_time.elapsed().as_millis()
+    );
+    Ok(Json(BatchParseResponse {
+        total: items.len(),
+        failed,
+        items,
+    }))
+}
+
+fn parse_piece(index: usize, piece: Piece, region: &str) -> BatchParseItem {
+    let frame = match piece.frame {
+        Ok(frame) => frame,
+        Err((text, error)) => {
+            return BatchParseItem {
+                index,
+                source: piece.source,
+                frame: text,
+                protocol: None,
+                data: Vec::new(),
+                error: Some(error),
+            }
+        }
+    };
+    let (protocol, data, error) = match FrameAnalisyic::process_frame_safe(&frame, region) {
+        (protocol, data, error) if protocol == "Unknown" => (
+            None,
+            data,
+            Some(error.unwrap_or_else(|| "无法识别的报文格式".to_string())),
+        ),
+        (protocol, data, error) => (Some(protocol), data, error),
+    };
+    BatchParseItem {
+        index,
+        source: piece.source,
+        frame: FrameFun::get_data_str_with_space(&frame),
+        protocol,
+        data,
+        error,
+    }
+}
+
+// 连续的合法行拼接后切分为帧，格式错误的行单独作为一项并中断拼接
+fn split_text(text: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut chunk: Vec<u8> = Vec::new();
+    let mut lines = (0, 0);
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match decode_hex(line) {
+            Ok(bytes) => {
+                if chunk.is_empty() {
+                    lines.0 = line_no;
+                }
+                lines.1 = line_no;
+                chunk.extend(bytes);
+            }
+            Err(e) => {
+                flush_chunk(&mut pieces, &mut chunk, lines);
+                pieces.push(Piece {
+                    source: format!("text:{}", line_no),
+                    frame: Err((line.trim().to_string(), format!("第 {} 行{}", line_no, e))),
+                });
+            }
+        }
+    }
+    flush_chunk(&mut pieces, &mut chunk, lines);
+    pieces
+}
+
+fn flush_chunk(pieces: &mut Vec<Piece>, chunk: &mut Vec<u8>, lines: (usize, usize)) {
+    if chunk.is_empty() {
+        return;
+    }
+    let source = if lines.0 == lines.1 {
+        format!("text:{}", lines.0)
+    } else {
+        format!("text:{}-{}", lines.0, lines.1)
+    };
+    for frame in FrameStream::split(&std::mem::take(chunk)) {
+        pieces.push(Piece {
+            source: source.clone(),
+            frame: Ok(frame),
+        });
+    }
+}
+
+/// 诊断无法解析的报文，给出各协议识别器拒绝的原因和解析停止的字节位置
+#[utoipa::path(
+    post,
+    path = "/api/parse/diagnose",
+    tag = "parse",
+    request_body = DiagnoseRequest,
+    responses(
+        (status = 200, description = "data 为各识别器的结果、解析停止位置和诊断结论", body = Object),
+        (status = 400, description = "报文格式错误", body = EmptyResponse),
+    )
+)]
+pub async fn diagnose(
+    State(state): State<AppState>,
+    Json(payload): Json<DiagnoseRequest>,
+) -> ApiResult<FrameDiagnosis> {
+    let frame = match decode_hex(&payload.message) {
+        Ok(frame) => frame,
+        Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+    };
+    let region = match payload.region {
+        Some(region) => region,
+        None => state.region.read().await.clone(),
+    };
+    let start_time = Instant::now();
+    let diagnosis = diagnose_frame(&frame, &region);
+    info!(
+        "诊断报文 {} 字节，协议 {}，耗时 {:?}",
+        frame.len(),
+        diagnosis.protocol,
+        start_time.elapsed()
+    );
+    ApiResponse::ok(diagnosis)
+}
+
+/// 修正手工编辑过的报文，重新计算长度域和校验
+#[utoipa::path(
+    post,
+    path = "/api/frame/repair",
+    tag = "parse",
+    request_body = RepairRequest,
+    responses(
+        (status = 200, description = "data 为修正后的报文、使用的协议、FE 前导字节数和改写的字段", body = Object),
+        (status = 400, description = "报文格式错误或无法识别协议", body = EmptyResponse),
+    )
+)]
+pub async fn repair(Json(payload): Json<RepairRequest>) -> ApiResult<FrameRepair> {
+    let frame = match decode_hex(&payload.frame) {
+        Ok(frame) => frame,
+        Err(e) => return ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+    };
+    match repair_frame(&frame, payload.protocol) {
+        Ok(repair) => ApiResponse::ok(repair),
+        Err(e) => ApiResponse::fail(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+// 网页提交的报文，不能为空
+pub(super) fn decode_hex(text: &str) -> Result<Vec<u8>, String> {
+    let frame = FrameFun::decode_hex_str(text)?;
+    if frame.is_empty() {
+        return Err("报文为空".to_string());
+    }
+    Ok(frame)
+}
@@ -0,0 +1,285 @@
+use super::config::{AuthConfig, RateLimitConfig};
+use super::ApiResponse;
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, warn};
+
+// 不需要认证的接口，供负载均衡和监控探测
+const PUBLIC_PATHS: &[&str] = &["/health"];
+
+// 客户端数量超过该值时清理已恢复满额的记录
+const BUCKET_CLEANUP_THRESHOLD: usize = 1024;
+
+/// 通过认证的调用方，记录到访问日志中，不包含 Token 本身
+#[derive(Debug, Clone)]
+struct AuthUser(String);
+
+/// 客户端 IP，经反向代理转发时为代理记录的原始地址，由 access_log 写入请求扩展
+#[derive(Debug, Clone, Copy)]
+struct ClientIp(IpAddr);
+
+/// 访问日志，记录调用方、方法、路径、状态码和耗时，需作为最外层中间件
+///
+/// 不记录查询参数，避免 token 参数写入日志
+pub async fn access_log(
+    State(trusted_proxies): State<Arc<Vec<IpAddr>>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client = client_ip(addr.ip(), request.headers(), &trusted_proxies);
+    request.extensions_mut().insert(ClientIp(client));
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let start_time = Instant::now();
+    let response = next.run(request).await;
+    let user = response
+        .extensions()
+        .get::<AuthUser>()
+        .map(|user| user.0.as_str())
+        .unwrap_or("-");
+    info!(
+        target: "web::access",
+        client = %client,
+        user,
+        method = %method,
+        path,
+        status = response.status().as_u16(),
+        elapsed_ms = start_time.elapsed().as_millis() as u64,
+    );
+    response
+}
+
+// 直连的对端是受信任的代理时，取代理转发的客户端地址；
+// X-Forwarded-For 从右往左跳过受信任的代理，左侧的内容可以被客户端伪造
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let header_ip = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(',')
+                    .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            })
+    };
+    if let Some(ip) = header_ip("x-real-ip").and_then(|mut ips| ips.next()) {
+        return ip;
+    }
+    header_ip("x-forwarded-for")
+        .and_then(|ips| ips.rev().find(|ip| !trusted_proxies.contains(ip)))
+        .unwrap_or(peer)
+}
+
+/// 按客户端 IP 限流的令牌桶
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// 未启用限流时返回 None
+    pub fn new(config: &RateLimitConfig) -> Option<Self> {
+        if config.per_second == 0 {
+            return None;
+        }
+        Some(Self {
+            per_second: config.per_second as f64,
+            burst: config.burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // 取一个令牌，令牌不足时返回需要等待的秒数
+    fn acquire(&self, ip: IpAddr) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > BUCKET_CLEANUP_THRESHOLD {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        bucket.tokens = tokens;
+        bucket.updated = now;
+        if tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - tokens) / self.per_second).ceil() as u64)
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+/// 限流中间件，超出限制时返回 429 和 Retry-After
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = request
+        .extensions()
+        .get::<ClientIp>()
+        .map_or(addr.ip(), |client| client.0);
+    match limiter.acquire(client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            warn!("客户端 {} 请求过于频繁", client);
+            let mut response =
+                ApiResponse::<()>::fail(StatusCode::TOO_MANY_REQUESTS, "请求过于频繁".to_string())
+                    .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+            response
+        }
+    }
+}
+
+/// 认证中间件，校验 API Token 或 Basic 认证
+pub async fn require_auth(
+    State(auth): State<Arc<AuthConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if PUBLIC_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let query_token = query_token(request.uri());
+    match authorize(&auth, request.headers(), query_token.as_deref()) {
+        Some(user) => {
+            let mut response = next.run(request).await;
+            response.extensions_mut().insert(AuthUser(user));
+            response
+        }
+        None => {
+            let mut response =
+                ApiResponse::<()>::fail(StatusCode::UNAUTHORIZED, "未授权的访问".to_string())
+                    .into_response();
+            let challenge = if auth.basic.is_some() {
+                "Basic realm=\"EmbedTalk\""
+            } else {
+                "Bearer"
+            };
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(challenge),
+            );
+            response
+        }
+    }
+}
+
+// 查询参数中的 token，Token 中的 +、/、= 等字符经过 URL 编码
+fn query_token(uri: &Uri) -> Option<String> {
+    let Query(mut params) = Query::<HashMap<String, String>>::try_from_uri(uri).ok()?;
+    params.remove("token")
+}
+
+// 认证通过时返回调用方名称
+fn authorize(auth: &AuthConfig, headers: &HeaderMap, query_token: Option<&str>) -> Option<String> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    let token = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get("x-api-token")
+                .and_then(|value| value.to_str().ok())
+        })
+        .or(query_token);
+    if let Some(token) = token {
+        if auth
+            .tokens
+            .iter()
+            .filter(|expected| !expected.is_empty())
+            .any(|expected| constant_time_eq(expected.as_bytes(), token.trim().as_bytes()))
+        {
+            return Some("token".to_string());
+        }
+    }
+
+    let basic = auth.basic.as_ref()?;
+    let encoded = authorization?.strip_prefix("Basic ")?;
+    let decoded = STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    let valid = constant_time_eq(username.as_bytes(), basic.username.as_bytes())
+        & constant_time_eq(password.as_bytes(), basic.password.as_bytes());
+    valid.then(|| username.to_string())
+}
+
+// 比较耗时与内容无关，避免通过响应时间猜测 Token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_from_trusted_proxy_headers_only() {
+        let proxy = IpAddr::from([127, 0, 0, 1]);
+        let client = IpAddr::from([192, 168, 1, 20]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("10.0.0.1, 192.168.1.20"),
+        );
+
+        assert_eq!(client_ip(proxy, &headers, &[proxy]), client);
+        // 直连的客户端不能通过请求头冒充其他地址
+        let direct = IpAddr::from([192, 168, 1, 30]);
+        assert_eq!(client_ip(direct, &headers, &[proxy]), direct);
+
+        headers.insert("x-real-ip", HeaderValue::from_static("192.168.1.40"));
+        assert_eq!(
+            client_ip(proxy, &headers, &[proxy]),
+            IpAddr::from([192, 168, 1, 40])
+        );
+    }
+
+    #[test]
+    fn query_token_is_url_decoded() {
+        let auth = AuthConfig {
+            tokens: vec!["ab+c/d=".to_string()],
+            basic: None,
+        };
+        let uri: Uri = "/api/ws?channel=1&token=ab%2Bc%2Fd%3D".parse().unwrap();
+        let token = query_token(&uri);
+        assert_eq!(token.as_deref(), Some("ab+c/d="));
+        assert!(authorize(&auth, &HeaderMap::new(), token.as_deref()).is_some());
+        assert!(authorize(&auth, &HeaderMap::new(), Some("ab%2Bc%2Fd%3D")).is_none());
+    }
+}
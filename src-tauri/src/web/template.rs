@@ -0,0 +1,129 @@
+use super::{ApiResponse, ApiResult, AppState, EmptyResponse};
+use crate::basefunc::frame_fun::FrameFun;
+use crate::basefunc::frame_template::FrameTemplate;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::info;
+use utoipa::ToSchema;
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct PreviewRequest {
+    /// 已保存的模板名称
+    name: Option<String>,
+    /// 未保存的模板，字段与保存模板时相同
+    #[schema(value_type = Option<Object>)]
+    template: Option<FrameTemplate>,
+    /// 展开模板使用的变量
+    #[serde(default)]
+    vars: HashMap<String, String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/templates", get(list_templates))
+        .route("/api/templates/preview", post(preview_template))
+        .route(
+            "/api/templates/:name",
+            put(save_template).delete(delete_template),
+        )
+}
+
+// 获取保存的报文模板
+#[utoipa::path(
+    get,
+    path = "/api/templates",
+    tag = "template",
+    responses((status = 200, description = "data 为模板列表，按名称排序", body = Object))
+)]
+async fn list_templates() -> ApiResult<Vec<FrameTemplate>> {
+    ApiResponse::ok(FrameTemplate::load_all())
+}
+
+// 保存报文模板，同名模板被覆盖
+#[utoipa::path(
+    put,
+    path = "/api/templates/{name}",
+    tag = "template",
+    params(("name" = String, Path, description = "模板名称")),
+    request_body(
+        content = Object,
+        description = "text 为带 ${...} 占位符的十六进制报文，protocol 为 auto、csg、dlt645、dlt698、modbus_rtu、modbus_tcp 或 none"
+    ),
+    responses(
+        (status = 200, body = EmptyResponse),
+        (status = 400, description = "模板无效", body = EmptyResponse),
+    )
+)]
+async fn save_template(
+    Path(name): Path<String>,
+    Json(mut template): Json<FrameTemplate>,
+) -> ApiResult<()> {
+    template.name = name;
+    match template.save() {
+        Ok(()) => {
+            info!("web 保存报文模板 {}", template.name);
+            ApiResponse::ok(())
+        }
+        Err(e) => ApiResponse::fail(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+// 删除报文模板
+#[utoipa::path(
+    delete,
+    path = "/api/templates/{name}",
+    tag = "template",
+    params(("name" = String, Path, description = "模板名称")),
+    responses(
+        (status = 200, body = EmptyResponse),
+        (status = 404, description = "模板不存在", body = EmptyResponse),
+    )
+)]
+async fn delete_template(Path(name): Path<String>) -> ApiResult<()> {
+    match FrameTemplate::delete(&name) {
+        Ok(true) => {
+            info!("web 删除报文模板 {}", name);
+            ApiResponse::ok(())
+        }
+        Ok(false) => ApiResponse::fail(StatusCode::NOT_FOUND, format!("模板不存在: {}", name)),
+        Err(e) => ApiResponse::fail(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+// 预览模板下一次发送的报文，不改变序号和计数器
+#[utoipa::path(
+    post,
+    path = "/api/templates/preview",
+    tag = "template",
+    request_body = PreviewRequest,
+    responses(
+        (status = 200, description = "data 为展开并修正长度、校验后的十六进制报文", body = String),
+        (status = 400, description = "模板展开失败", body = EmptyResponse),
+        (status = 404, description = "模板不存在", body = EmptyResponse),
+    )
+)]
+async fn preview_template(Json(payload): Json<PreviewRequest>) -> ApiResult<String> {
+    let template = match (payload.name, payload.template) {
+        (Some(name), None) => match FrameTemplate::load(&name) {
+            Some(template) => template,
+            None => {
+                return ApiResponse::fail(StatusCode::NOT_FOUND, format!("模板不存在: {}", name))
+            }
+        },
+        (None, Some(template)) => template,
+        _ => {
+            return ApiResponse::fail(
+                StatusCode::BAD_REQUEST,
+                "name 和 template 需要且只能指定一个".to_string(),
+            )
+        }
+    };
+    match template.preview(&payload.vars) {
+        Ok(frame) => ApiResponse::ok(FrameFun::get_data_str_with_space(&frame)),
+        Err(e) => ApiResponse::fail(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}